@@ -0,0 +1,42 @@
+//! Compression throughput of `lzss_huff` on text and on base64, the inputs used to decide against
+//! caching Huffman codes per leaf, see `AdaptiveHuffmanTree::code`.  A change to how codes are found
+//! should show up in the `huffman` rows.
+//! Run with `cargo bench --bench huffman_encode`.
+
+use retrocompressor::lzss_huff;
+use std::time::{Duration,Instant};
+
+const PASSES: u32 = 4;
+
+/// About 1.5 MB of text
+fn text() -> Vec<u8> {
+    include_str!("../README.md").repeat(1 + 1500000 / include_str!("../README.md").len()).into_bytes()
+}
+
+/// About 4 MB of base64, which uses the symbols more evenly than text
+fn base64() -> Vec<u8> {
+    const DIGITS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut state: u64 = 0x2545f4914f6cdd1d;
+    (0..4000000).map(|_| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        DIGITS[(state >> 58) as usize]
+    }).collect()
+}
+
+fn main() {
+    // a tiny window and lookahead leave little for the LZSS stage to do, so nearly all the time is Huffman coding
+    let huffman_bound = lzss_huff::Options { window_size: 64, lookahead: 3, ..lzss_huff::STD_OPTIONS };
+    for (name,data) in [("text",text()),("base64",base64())] {
+        for (config,opt) in [("standard",lzss_huff::STD_OPTIONS),("huffman",huffman_bound.clone())] {
+            let mut best = Duration::MAX;
+            for _ in 0..PASSES {
+                let start = Instant::now();
+                let compressed = lzss_huff::compress_slice(&data,&opt).expect("compression failed");
+                best = best.min(start.elapsed());
+                std::hint::black_box(compressed);
+            }
+            let rate = data.len() as f64 / best.as_secs_f64() / 1e6;
+            println!("{:<7} {:<9} {:>8.2} ms {:>7.1} MB/s",name,config,best.as_secs_f64()*1e3,rate);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! Async Adapters
+//!
+//! Available with the `async` feature.  These are not streaming adapters: the codecs need random access
+//! to the data, so the whole input is read from an `AsyncRead` into memory, the codec runs on tokio's
+//! blocking thread pool producing the whole output in memory, and only then is the output sent to an `AsyncWrite`.
+//! The executor is never blocked, but memory use is the size of the input plus the size of the output.
+//! Both are capped by `Limits`, so that a large upload or a decompression bomb fails with `Error::FileTooLarge`
+//! (as an `std::io::Error` of kind `InvalidData`) rather than exhausting memory.
+//!
+//! ```rs
+//! use retrocompressor::async_io::{self,Method};
+//! let (in_size,out_size) = async_io::expand(&mut upload,&mut image,Method::Td0).await?;
+//! ```
+
+use std::io::{Cursor,Write,Seek,SeekFrom};
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncWrite,AsyncWriteExt};
+pub use crate::Method;
+
+/// Largest input and output that are held in memory
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Limits {
+    /// reading stops with an error once the input is longer than this
+    pub max_input: u64,
+    /// the codec stops with an error rather than produce more than this
+    pub max_output: u64
+}
+
+/// Limits used by `compress` and `expand`, 64 MiB each way, the same as the command line's `--max-size`
+pub const STD_LIMITS: Limits = Limits {
+    max_input: 64 << 20,
+    max_output: 64 << 20
+};
+
+fn too_large() -> std::io::Error {
+    crate::to_io_error(Box::new(crate::Error::FileTooLarge))
+}
+
+/// Output held in memory that refuses to go past `limit` bytes
+struct Capped {
+    inner: Cursor<Vec<u8>>,
+    limit: u64
+}
+
+impl Write for Capped {
+    fn write(&mut self,buf: &[u8]) -> std::io::Result<usize> {
+        if self.inner.position() + buf.len() as u64 > self.limit {
+            log::error!("output would exceed {} bytes",self.limit);
+            return Err(too_large());
+        }
+        Write::write(&mut self.inner,buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Capped {
+    fn seek(&mut self,pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// run the codec on the blocking pool, errors become `std::io::Error`
+async fn transform(data: Vec<u8>,method: Method,compress: bool,max_output: u64) -> Result<Vec<u8>,std::io::Error> {
+    let job = move || {
+        let mut out = Capped { inner: Cursor::new(Vec::new()), limit: max_output };
+        let res = match compress {
+            true => method.compress_dyn(&mut Cursor::new(&data),&mut out),
+            false => method.expand_dyn(&mut Cursor::new(&data),&mut out)
+        };
+        res.map(|_| out.inner.into_inner()).map_err(crate::to_io_error)
+    };
+    match tokio::task::spawn_blocking(job).await {
+        Ok(res) => res,
+        Err(e) => Err(std::io::Error::other(e))
+    }
+}
+
+async fn run<R,W>(input: &mut R, output: &mut W, method: Method, compress: bool, limits: Limits) -> Result<(u64,u64),std::io::Error>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    let mut data = Vec::new();
+    input.take(limits.max_input.saturating_add(1)).read_to_end(&mut data).await?;
+    if data.len() as u64 > limits.max_input {
+        log::error!("input exceeds {} bytes",limits.max_input);
+        return Err(too_large());
+    }
+    let in_size = data.len() as u64;
+    let ans = transform(data,method,compress,limits.max_output).await?;
+    output.write_all(&ans).await?;
+    output.flush().await?;
+    Ok((in_size,ans.len() as u64))
+}
+
+/// Compress everything from `input` and write it to `output`, within `STD_LIMITS`, returns (in_size,out_size).
+/// Offsets in the options are relative to the start of the data that is read and written.
+/// Must be called from within a tokio runtime.
+pub async fn compress<R,W>(input: &mut R, output: &mut W, method: Method) -> Result<(u64,u64),std::io::Error>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    run(input,output,method,true,STD_LIMITS).await
+}
+
+/// Expand everything from `input` and write it to `output`, within `STD_LIMITS`, returns (in_size,out_size).
+/// Offsets in the options are relative to the start of the data that is read and written.
+/// Must be called from within a tokio runtime.
+pub async fn expand<R,W>(input: &mut R, output: &mut W, method: Method) -> Result<(u64,u64),std::io::Error>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    run(input,output,method,false,STD_LIMITS).await
+}
+
+/// Same as `compress` with other limits
+pub async fn compress_limited<R,W>(input: &mut R, output: &mut W, method: Method, limits: Limits) -> Result<(u64,u64),std::io::Error>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    run(input,output,method,true,limits).await
+}
+
+/// Same as `expand` with other limits
+pub async fn expand_limited<R,W>(input: &mut R, output: &mut W, method: Method, limits: Limits) -> Result<(u64,u64),std::io::Error>
+where R: AsyncRead + Unpin, W: AsyncWrite + Unpin {
+    run(input,output,method,false,limits).await
+}
+
+#[cfg(all(test,feature="td0"))]
+use crate::{lzw,lzss_huff};
+
+#[cfg(feature="td0")]
+#[test]
+fn async_invertibility() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().expect("no runtime");
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    for method in [Method::Lzw(lzw::STD_OPTIONS),Method::LzssHuff(lzss_huff::STD_OPTIONS)] {
+        let expanded = rt.block_on(async {
+            let mut compressed = Vec::new();
+            compress(&mut &test_data[..],&mut compressed,method.clone()).await.expect("compression failed");
+            let mut expanded = Vec::new();
+            expand(&mut &compressed[..],&mut expanded,method.clone()).await.expect("expansion failed");
+            expanded
+        });
+        assert_eq!(expanded[0..test_data.len()],test_data[..]);
+    }
+    let garbage = [0u8;20];
+    let res = rt.block_on(async { expand(&mut &garbage[..],&mut Vec::new(),Method::Td0).await });
+    assert!(res.is_err());
+}
+
+#[cfg(feature="td0")]
+#[test]
+fn async_limits() {
+    let rt = tokio::runtime::Builder::new_current_thread().build().expect("no runtime");
+    let test_data = vec![b'A';10000];
+    let method = Method::LzssHuff(lzss_huff::STD_OPTIONS);
+    let compressed = lzss_huff::compress_slice(&test_data,&lzss_huff::STD_OPTIONS).expect("compression failed");
+    let limits = Limits { max_input: 1000, max_output: 5000 };
+    let err = rt.block_on(async { compress_limited(&mut &test_data[..],&mut Vec::new(),method.clone(),limits).await }).expect_err("input over the limit");
+    assert_eq!(err.kind(),std::io::ErrorKind::InvalidData);
+    let err = rt.block_on(async { expand_limited(&mut &compressed[..],&mut Vec::new(),method.clone(),limits).await }).expect_err("output over the limit");
+    assert_eq!(err.kind(),std::io::ErrorKind::InvalidData);
+    let limits = Limits { max_input: 1000, max_output: 10000 };
+    let mut expanded = Vec::new();
+    rt.block_on(async { expand_limited(&mut &compressed[..],&mut expanded,method.clone(),limits).await }).expect("expansion failed");
+    assert_eq!(expanded,test_data);
+}
@@ -0,0 +1,82 @@
+//! Parallel Batch Processing
+//!
+//! Available with the `batch` feature.  Converts a list of files using rayon's global thread pool.
+//! Each file is handled independently, so one failure does not stop the others.
+//!
+//! ```rs
+//! use retrocompressor::{batch,Method};
+//! let jobs = vec![("a.td0","a.norm.td0"),("b.td0","b.norm.td0")];
+//! for res in batch::expand_files(&jobs,&Method::Td0) {
+//!     if let Err(e) = res {
+//!         eprintln!("{}",e);
+//!     }
+//! }
+//! ```
+
+use rayon::prelude::*;
+use std::path::Path;
+use crate::{Method,DYNERR};
+
+fn process(path_in: &Path, path_out: &Path, method: &Method, compress: bool) -> Result<(u64,u64),DYNERR> {
+    let mut in_file = std::fs::File::open(path_in)?;
+    let mut out_file = std::fs::File::create(path_out)?;
+    let (in_size,out_size) = match compress {
+        true => method.compress(&mut in_file,&mut out_file)?,
+        false => method.expand(&mut in_file,&mut out_file)?
+    };
+    // output can have a prefix that is not counted in out_size
+    let out_offset = match method {
+        #[cfg(feature="lzw")]
+        Method::Lzw(opt) => opt.out_offset,
+        #[cfg(feature="lzss_huff")]
+        Method::LzssHuff(opt) => opt.out_offset,
+        #[cfg(feature="td0")]
+        Method::Td0 => 0
+    };
+    out_file.set_len(out_size + out_offset)?;
+    Ok((in_size,out_size))
+}
+
+fn run<P: AsRef<Path> + Sync>(jobs: &[(P,P)], method: &Method, compress: bool) -> Vec<Result<(u64,u64),std::io::Error>> {
+    jobs.par_iter().map(|(path_in,path_out)| {
+        log::debug!("process {}",path_in.as_ref().display());
+        process(path_in.as_ref(),path_out.as_ref(),method,compress).map_err(crate::to_io_error)
+    }).collect()
+}
+
+/// Compress each (input path,output path) pair in parallel, output files are created or truncated.
+/// Returns (in_size,out_size) or error for each pair, in the same order as `jobs`.
+pub fn compress_files<P: AsRef<Path> + Sync>(jobs: &[(P,P)], method: &Method) -> Vec<Result<(u64,u64),std::io::Error>> {
+    run(jobs,method,true)
+}
+
+/// Expand each (input path,output path) pair in parallel, output files are created or truncated.
+/// Returns (in_size,out_size) or error for each pair, in the same order as `jobs`.
+pub fn expand_files<P: AsRef<Path> + Sync>(jobs: &[(P,P)], method: &Method) -> Vec<Result<(u64,u64),std::io::Error>> {
+    run(jobs,method,false)
+}
+
+#[cfg(feature="lzss_huff")]
+#[test]
+fn batch_invertibility() {
+    let dir = tempfile::tempdir().expect("no temp dir");
+    let mut names = Vec::new();
+    for i in 1..=6 {
+        let data: Vec<u8> = (0..1000*i).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+        let name = dir.path().join(format!("f{}",i));
+        std::fs::write(&name,&data).expect("write failed");
+        names.push(name);
+    }
+    names.push(dir.path().join("missing"));
+    let method = Method::LzssHuff(crate::lzss_huff::STD_OPTIONS);
+    let jobs: Vec<_> = names.iter().map(|n| (n.clone(),n.with_extension("lzh"))).collect();
+    let results = compress_files(&jobs,&method);
+    assert!(results[0..6].iter().all(|r| r.is_ok()));
+    assert_eq!(results[6].as_ref().unwrap_err().kind(),std::io::ErrorKind::NotFound);
+    let jobs: Vec<_> = names[0..6].iter().map(|n| (n.with_extension("lzh"),n.with_extension("out"))).collect();
+    let results = expand_files(&jobs,&method);
+    for (i,res) in results.iter().enumerate() {
+        assert_eq!(res.as_ref().unwrap().1,1000*(i+1) as u64);
+        assert_eq!(std::fs::read(&names[i]).unwrap(),std::fs::read(names[i].with_extension("out")).unwrap());
+    }
+}
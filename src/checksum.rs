@@ -0,0 +1,264 @@
+//! Table driven CRC calculations, shared by the codecs and available to anyone else.
+//! `Crc16` and `Crc32` take the usual parameters, the polynomial, initial value, reflection, and final XOR,
+//! and build their 256 entry table at compile time.  The variants the retro formats use are ready made statics.
+//! `update` continues a checksum over more data, so the data can be handed over in pieces,
+//! while `checksum` does the whole thing in one call.
+//!
+//! The `Checksum` trait is how a codec takes a checksum it does not know about, such as the additive and XOR
+//! checksums of some vintage formats.  The CRCs implement it, as do `Sum16` and `Xor8`, and anything else can.
+//!
+//! ```rs
+//! use retrocompressor::checksum;
+//! assert_eq!(checksum::XMODEM.checksum(b"123456789"),0x31c3);
+//! let part = checksum::CRC32.checksum(b"12345");
+//! assert_eq!(checksum::CRC32.update(part,b"6789"),0xcbf43926);
+//! // CRC-16/X-25, which is not one of the statics
+//! let x25 = checksum::Crc16::new(0x1021,0xffff,true,0xffff);
+//! assert_eq!(x25.checksum(b"123456789"),0x906e);
+//! ```
+
+/// 16 bit CRC
+pub struct Crc16 {
+    table: [u16;256],
+    reflected: bool,
+    init: u16,
+    xor_out: u16
+}
+
+/// 32 bit CRC
+pub struct Crc32 {
+    table: [u32;256],
+    reflected: bool,
+    init: u32,
+    xor_out: u32
+}
+
+/// CRC used by Teledisk images, polynomial 0xa097, starts from 0
+pub static TELEDISK: Crc16 = Crc16::new(0xa097,0,false,0);
+/// CRC-16/CCITT-FALSE, polynomial 0x1021, starts from 0xffff
+pub static CCITT: Crc16 = Crc16::new(0x1021,0xffff,false,0);
+/// CRC-16/XMODEM, polynomial 0x1021, starts from 0
+pub static XMODEM: Crc16 = Crc16::new(0x1021,0,false,0);
+/// CRC-16/ARC as used by ARC, polynomial 0x8005 reflected, starts from 0
+pub static ARC: Crc16 = Crc16::new(0x8005,0,true,0);
+/// LHA uses the same CRC as ARC
+pub use ARC as LHA;
+/// ZOO uses the same CRC as ARC
+pub use ARC as ZOO;
+/// CRC-32 as used by zip, gzip, and PNG
+pub static CRC32: Crc32 = Crc32::new(0x04c11db7,0xffffffff,true,0xffffffff);
+
+/// A checksum that is worked out a piece at a time, for codecs that let the user choose one,
+/// see `lzw::ChunkChecksum::Custom`.  The running state is held by the caller, so one checksum
+/// can be shared by any number of streams at once.
+pub trait Checksum: Send + Sync {
+    /// Bits in the finished checksum, from 1 to 32
+    fn width(&self) -> u32;
+    /// State before any data
+    fn init(&self) -> u32;
+    /// Continue from `state` over `buf`
+    fn update(&self,state: u32,buf: &[u8]) -> u32;
+    /// The finished checksum, which must fit in `width` bits
+    fn finalize(&self,state: u32) -> u32 {
+        state
+    }
+}
+
+/// 16 bit sum of the bytes, ignoring overflow
+pub struct Sum16;
+
+/// XOR of the bytes
+pub struct Xor8;
+
+impl Checksum for Sum16 {
+    fn width(&self) -> u32 {
+        16
+    }
+    fn init(&self) -> u32 {
+        0
+    }
+    fn update(&self,state: u32,buf: &[u8]) -> u32 {
+        buf.iter().fold(state as u16,|sum,b| sum.wrapping_add(*b as u16)) as u32
+    }
+}
+
+impl Checksum for Xor8 {
+    fn width(&self) -> u32 {
+        8
+    }
+    fn init(&self) -> u32 {
+        0
+    }
+    fn update(&self,state: u32,buf: &[u8]) -> u32 {
+        buf.iter().fold(state as u8,|acc,b| acc ^ b) as u32
+    }
+}
+
+impl Checksum for Crc16 {
+    fn width(&self) -> u32 {
+        16
+    }
+    fn init(&self) -> u32 {
+        Crc16::init(self) as u32
+    }
+    fn update(&self,state: u32,buf: &[u8]) -> u32 {
+        Crc16::update(self,state as u16,buf) as u32
+    }
+}
+
+impl Checksum for Crc32 {
+    fn width(&self) -> u32 {
+        32
+    }
+    fn init(&self) -> u32 {
+        Crc32::init(self)
+    }
+    fn update(&self,state: u32,buf: &[u8]) -> u32 {
+        Crc32::update(self,state,buf)
+    }
+}
+
+/// Table for polynomial `poly` in a register of `width` bits, it is the same code for 16 and 32 bits
+const fn table(poly: u32,width: u32,reflected: bool) -> [u32;256] {
+    let mut table = [0;256];
+    let top = 1 << (width - 1);
+    let mask = match width {
+        32 => u32::MAX,
+        _ => (1 << width) - 1
+    };
+    let rpoly = poly.reverse_bits() >> (32 - width);
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = match reflected {
+            true => i as u32,
+            false => (i as u32) << (width - 8)
+        };
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match reflected {
+                true => (crc >> 1) ^ if crc & 1 != 0 { rpoly } else { 0 },
+                false => ((crc << 1) & mask) ^ if crc & top != 0 { poly } else { 0 }
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc16 {
+    /// Build the table for polynomial `poly`, given in the usual MSB first form.
+    /// The checksum starts from `init`, and is XOR'd with `xor_out` when finished.
+    /// If `reflected` the bits of each byte are taken LSB first, and the checksum comes out that way.
+    pub const fn new(poly: u16,init: u16,reflected: bool,xor_out: u16) -> Self {
+        let wide = table(poly as u32,16,reflected);
+        let mut table = [0;256];
+        let mut i = 0;
+        while i < 256 {
+            table[i] = wide[i] as u16;
+            i += 1;
+        }
+        Self {
+            table,
+            reflected,
+            init,
+            xor_out
+        }
+    }
+    /// Checksum of no data, which is where `update` starts
+    pub fn init(&self) -> u16 {
+        self.init ^ self.xor_out
+    }
+    /// Continue from `crc`, which is the checksum of the preceding data, or `init()` if there is none
+    pub fn update(&self,crc: u16,buf: &[u8]) -> u16 {
+        let mut crc = crc ^ self.xor_out;
+        match self.reflected {
+            true => for b in buf {
+                crc = (crc >> 8) ^ self.table[(crc as u8 ^ b) as usize];
+            },
+            false => for b in buf {
+                crc = (crc << 8) ^ self.table[((crc >> 8) as u8 ^ b) as usize];
+            }
+        }
+        crc ^ self.xor_out
+    }
+    /// Checksum of all of `buf`
+    pub fn checksum(&self,buf: &[u8]) -> u16 {
+        self.update(self.init(),buf)
+    }
+}
+
+impl Crc32 {
+    /// Build the table for polynomial `poly`, given in the usual MSB first form.
+    /// The checksum starts from `init`, and is XOR'd with `xor_out` when finished.
+    /// If `reflected` the bits of each byte are taken LSB first, and the checksum comes out that way.
+    pub const fn new(poly: u32,init: u32,reflected: bool,xor_out: u32) -> Self {
+        Self {
+            table: table(poly,32,reflected),
+            reflected,
+            init,
+            xor_out
+        }
+    }
+    /// Checksum of no data, which is where `update` starts
+    pub fn init(&self) -> u32 {
+        self.init ^ self.xor_out
+    }
+    /// Continue from `crc`, which is the checksum of the preceding data, or `init()` if there is none
+    pub fn update(&self,crc: u32,buf: &[u8]) -> u32 {
+        let mut crc = crc ^ self.xor_out;
+        match self.reflected {
+            true => for b in buf {
+                crc = (crc >> 8) ^ self.table[(crc as u8 ^ b) as usize];
+            },
+            false => for b in buf {
+                crc = (crc << 8) ^ self.table[((crc >> 24) as u8 ^ b) as usize];
+            }
+        }
+        crc ^ self.xor_out
+    }
+    /// Checksum of all of `buf`
+    pub fn checksum(&self,buf: &[u8]) -> u32 {
+        self.update(self.init(),buf)
+    }
+}
+
+#[test]
+fn check_values() {
+    // the check values of the CRC catalogue, each the checksum of the digits 1 to 9
+    let data = b"123456789";
+    assert_eq!(CCITT.checksum(data),0x29b1);
+    assert_eq!(XMODEM.checksum(data),0x31c3);
+    assert_eq!(ARC.checksum(data),0xbb3d);
+    assert_eq!(LHA.checksum(data),0xbb3d);
+    assert_eq!(ZOO.checksum(data),0xbb3d);
+    assert_eq!(CRC32.checksum(data),0xcbf43926);
+    assert_eq!(Crc16::new(0x1021,0xffff,true,0xffff).checksum(data),0x906e);
+    assert_eq!(Crc16::new(0x3d65,0,false,0xffff).checksum(data),0xc2b7);
+    assert_eq!(Crc32::new(0x04c11db7,0xffffffff,false,0xffffffff).checksum(data),0xfc891918);
+    assert_eq!(Crc32::new(0x04c11db7,0xffffffff,false,0).checksum(data),0x0376e6e7);
+    assert_eq!(Crc32::new(0x1edc6f41,0xffffffff,true,0xffffffff).checksum(data),0xe3069283);
+    // in pieces
+    assert_eq!(ARC.update(ARC.checksum(&data[0..4]),&data[4..]),0xbb3d);
+    assert_eq!(CRC32.update(CRC32.checksum(&data[0..4]),&data[4..]),0xcbf43926);
+    assert_eq!(CRC32.update(0,data),0xcbf43926);
+    let x25 = Crc16::new(0x1021,0xffff,true,0xffff);
+    assert_eq!(x25.update(x25.checksum(&data[0..4]),&data[4..]),0x906e);
+    assert_eq!(x25.update(x25.init(),&[]),x25.checksum(&[]));
+    // through the trait, in pieces
+    let all: [(&dyn Checksum,u32);5] = [(&ARC,0xbb3d),(&CRC32,0xcbf43926),(&Sum16,0x01dd),(&Xor8,0x31),(&x25,0x906e)];
+    for (sum,expected) in all {
+        let state = sum.update(sum.init(),&data[0..4]);
+        assert_eq!(sum.finalize(sum.update(state,&data[4..])),expected);
+    }
+    // Teledisk against the bitwise loop
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _bit in 0..8 {
+            crc = (crc << 1) ^ match crc & 0x8000 { 0 => 0, _ => 0xa097 };
+        }
+    }
+    assert_eq!(TELEDISK.checksum(data),crc);
+}
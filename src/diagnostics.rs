@@ -0,0 +1,95 @@
+//! Non-fatal issues that the codecs come across, such as a header CRC that was tolerated or data
+//! left over after the end of a stream.  Each one is logged with `log::warn!` as it happens, and can
+//! also be collected, so a program can show them to its user without scraping the log.
+//!
+//! ```rs
+//! use retrocompressor::*;
+//! let (result,warnings) = diagnostics::collect(|| td0::expand_slice(&image));
+//! for warning in &warnings {
+//!     eprintln!("warning: {}",warning);
+//! }
+//! ```
+//!
+//! Warnings go to the innermost `collect` running on the same thread.  Work that is handed to other
+//! threads, such as `td0::convert_batch`, is only logged.
+
+use std::cell::RefCell;
+
+/// Something that looked wrong but did not stop the work
+#[derive(Clone,Debug,PartialEq)]
+pub enum Warning {
+    /// a header CRC did not match and was corrected, because the options said to go on
+    HeaderCrc { stored: u16, computed: u16 },
+    /// a header field holds a value the format does not define
+    HeaderField { field: &'static str, value: u8 },
+    /// the data ran out and what was expanded was kept, `missing_bits` were needed to finish, or were zero filled
+    Truncated { missing_bits: u64 },
+    /// bytes follow the end of the compressed data
+    TrailingData { bytes: u64 },
+    /// the data goes on past the size in the header at `bit`, it was cut off there, `excess` bytes of data were left over
+    Overlong { expected: u64, bit: u64, excess: u64 },
+    /// bytes of padding were found where the format does not pad
+    Padding { bytes: u64 }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HeaderCrc { stored, computed } => write!(f,"header CRC is {:04x}, should be {:04x}, correcting it",stored,computed),
+            Self::HeaderField { field, value } => write!(f,"header field `{}` has the unexpected value {}",field,value),
+            Self::Truncated { missing_bits } => write!(f,"data ran out {} bits short, keeping what was expanded",missing_bits),
+            Self::TrailingData { bytes } => write!(f,"{} bytes follow the end of the data",bytes),
+            Self::Overlong { expected, bit, excess } => write!(f,"data goes on past the {} bytes in the header at bit {}, {} bytes are left over, ignoring them",expected,bit,excess),
+            Self::Padding { bytes } => write!(f,"{} bytes of padding where none was expected",bytes)
+        }
+    }
+}
+
+thread_local! {
+    /// warnings gathered by each `collect` running on this thread, innermost last
+    static COLLECTORS: RefCell<Vec<Vec<Warning>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Removes the collector if the closure panics, so it does not catch the warnings of a later `collect`
+struct Unwind;
+
+impl Drop for Unwind {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            COLLECTORS.with_borrow_mut(|collectors| collectors.pop());
+        }
+    }
+}
+
+/// Run `f`, returning its result along with the warnings that were raised while it ran
+pub fn collect<T,F: FnOnce() -> T>(f: F) -> (T,Vec<Warning>) {
+    COLLECTORS.with_borrow_mut(|collectors| collectors.push(Vec::new()));
+    let _unwind = Unwind;
+    let ans = f();
+    let warnings = COLLECTORS.with_borrow_mut(|collectors| collectors.pop()).unwrap_or_default();
+    (ans,warnings)
+}
+
+/// Log the warning and hand it to the innermost `collect`, if there is one
+#[allow(dead_code)] // unused if no codecs are enabled
+pub(crate) fn warn(warning: Warning) {
+    log::warn!("{}",warning);
+    COLLECTORS.with_borrow_mut(|collectors| {
+        if let Some(warnings) = collectors.last_mut() {
+            warnings.push(warning);
+        }
+    });
+}
+
+#[test]
+fn nested_collection() {
+    let ((_,inner),outer) = collect(|| {
+        warn(Warning::TrailingData { bytes: 1 });
+        collect(|| warn(Warning::Padding { bytes: 2 }))
+    });
+    assert_eq!(inner,vec![Warning::Padding { bytes: 2 }]);
+    assert_eq!(outer,vec![Warning::TrailingData { bytes: 1 }]);
+    // nothing is kept once collection is over
+    warn(Warning::Padding { bytes: 3 });
+    assert_eq!(COLLECTORS.with_borrow(|collectors| collectors.len()),0);
+}
@@ -11,7 +11,8 @@
 //! * File and bitstream handling is going to look different
 //! * Comments are greatly expanded and some identifiers are given longer names
 //! * Some components are gathered into structs
-//! * The 4 byte header is always little endian
+//! * The 4 byte header is little endian, except with `encode_with_endian` or `decode_with_endian`,
+//!   which also handle the big endian header of the Amiga and 68k ports
 //! 
 //! If you need an equivalent program under MIT license, or need more flexibility in
 //! the parameters, use the module `retrocompressor::lzss_huff`.
@@ -21,16 +22,18 @@
 //! it needs to rebuild the Huffman tree.  This in turn could have to do with C integer types
 //! being interpreted by clang differently from the original intent.
 
-use bit_vec::BitVec;
-use std::io::{Cursor,Read,Write,Seek,SeekFrom,BufReader,BufWriter,Bytes};
-use crate::DYNERR;
+use std::io::{Cursor,Read,Write,Seek,SeekFrom,BufWriter};
+use std::ops::{Index,IndexMut};
+use crate::{DYNERR,Endian};
 
 // LZSS coding constants
 
 const WIN_SIZE: usize = 4096; // sliding buffer
 const LOOKAHEAD: usize = 60; // lookahead buffer size
 const THRESHOLD: usize = 2; // minimum string length that will be tokenized
-const NIL: usize = WIN_SIZE; // pointer value NIL means we have a leaf
+const NIL: u32 = WIN_SIZE as u32; // pointer value NIL means we have a leaf
+const BLOCK_SIZE: usize = 0x4000; // input is read in blocks of this size
+const MAX_PAST_END: usize = 2; // zero bytes allowed after EOF, a whole `get_buf`
 
 // Huffman coding constants
 
@@ -142,6 +145,61 @@ const D_CODE: [u8;256] = [
 	0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
 ];
 
+/// Input read in blocks, standing in for the `getc` calls of LZHUF.C
+struct BlockReader<R: Read> {
+    inner: R,
+    block: Vec<u8>,
+    /// position of the next byte in `block`
+    pos: usize,
+    /// number of valid bytes in `block`
+    end: usize
+}
+
+impl <R: Read> BlockReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: vec![0;BLOCK_SIZE],
+            pos: 0,
+            end: 0
+        }
+    }
+    /// Get the next byte, or `None` at the end of the stream
+    fn next_byte(&mut self) -> Result<Option<u8>,std::io::Error> {
+        if self.pos == self.end {
+            self.end = loop {
+                match self.inner.read(&mut self.block) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind()==std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e)
+                }
+            };
+            self.pos = 0;
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+        self.pos += 1;
+        Ok(Some(self.block[self.pos-1]))
+    }
+}
+
+/// Tree pointers, stored as `u32` rather than `usize` so twice as many fit in the cache
+struct Links(Vec<u32>);
+
+impl Index<u32> for Links {
+    type Output = u32;
+    fn index(&self,i: u32) -> &u32 {
+        &self.0[i as usize]
+    }
+}
+
+impl IndexMut<u32> for Links {
+    fn index_mut(&mut self,i: u32) -> &mut u32 {
+        &mut self.0[i as usize]
+    }
+}
+
 /// Components for the LZSS stage of compression.
 /// The tree pointers `lson`, `rson`, and `dad` are indexed over the length of the ring buffer.
 /// We really have 256 trees.  Each root corresponds to a symbol.
@@ -151,17 +209,26 @@ struct LZSS {
     dictionary: Vec<u8>,
     match_position: i32,
     match_length: usize,
-    lson: Vec<usize>,
-    rson: Vec<usize>,
-    dad: Vec<usize>,
+    lson: Links,
+    rson: Links,
+    dad: Links,
 }
 
 /// Components for the Huffman stage of compression.
 /// The tree is constantly updated during compression/expansion.
 struct AdaptiveHuffman {
-    bits: BitVec,
-    ptr: usize,
+    /// bits that were read but not used yet, aligned to the MSB
+    get_buf: u16,
+    /// number of bits waiting in `get_buf`
+    get_len: u16,
+    /// bytes read from the stream
     count: usize,
+    /// zero bytes shifted in after the stream ended
+    past_end: usize,
+    /// bits waiting to be written, aligned to the MSB
+    put_buf: u16,
+    /// number of bits waiting in `put_buf`
+    put_len: u16,
     /// Frequencies, this is used as a sorting key.
     /// Parent node frequencies are the sum of the child node frequencies.
     freq: Vec<usize>,
@@ -181,17 +248,17 @@ impl LZSS {
             dictionary: vec![0;WIN_SIZE+LOOKAHEAD-1],
             match_position: 0,
             match_length: 0,
-            lson: vec![0;WIN_SIZE+1],
-            rson: vec![0;WIN_SIZE+257],
-            dad: vec![0;WIN_SIZE+1]
+            lson: Links(vec![0;WIN_SIZE+1]),
+            rson: Links(vec![0;WIN_SIZE+257]),
+            dad: Links(vec![0;WIN_SIZE+1])
         }
     }
     fn init_tree(&mut self) {
         for i in WIN_SIZE+1..=WIN_SIZE+256 {
-            self.rson[i] = NIL; // root
+            self.rson.0[i] = NIL; // root
         }
         for i in 0..WIN_SIZE {
-            self.dad[i] = NIL; // node
+            self.dad.0[i] = NIL; // node
         }
     }
     /// This finds a match to the symbol run starting at position `r` by searching
@@ -201,9 +268,10 @@ impl LZSS {
     fn insert_node(&mut self,r: usize) {
         let mut cmp: i16 = 1;
         let key = &self.dictionary[r..];
+        let r = r as u32;
         // At the start, p corresponds to a symbol, not a position in the ring.
         // When we start looping it will become the position of the current match.
-        let mut p = WIN_SIZE + 1 + key[0] as usize;
+        let mut p = NIL + 1 + key[0] as u32;
         self.rson[r] = NIL;
         self.lson[r] = NIL;
         self.match_length = 0;
@@ -233,7 +301,7 @@ impl LZSS {
             // upon exiting this loop, `i` will have the number of matched symbols,
             // and `cmp` will have the difference in first non-matching symbol values.
             while i < LOOKAHEAD {
-                cmp = key[i] as i16 - self.dictionary[p+i] as i16;
+                cmp = key[i] as i16 - self.dictionary[p as usize + i] as i16;
                 if cmp != 0 {
                     break;
                 }
@@ -273,6 +341,7 @@ impl LZSS {
         self.dad[p] = NIL;  // remove p
     }
     fn delete_node(&mut self,p: usize) {
+        let p = p as u32;
         // The big idea here is to delete the node without having to cut a whole branch.
         // If p has only one son, this is easy, that son replaces p.
         // If p has two sons, and the left brother has only a left son, then the right brother's branch
@@ -329,23 +398,17 @@ impl AdaptiveHuffman {
     /// must create a new object for each coding or decoding task
     fn new() -> Self {
         Self {
-            bits: BitVec::new(),
-            ptr: 0,
+            get_buf: 0,
+            get_len: 0,
             count: 0,
+            past_end: 0,
+            put_buf: 0,
+            put_len: 0,
             freq: vec![0;TAB_SIZE+1], // extra element is the frequency backstop
             prnt: vec![0;TAB_SIZE+N_CHAR], // extra N_CHAR elements are the symbol map
             son: vec![0;TAB_SIZE]
         }
     }
-    /// keep the bit vector small, we don't need the bits behind us
-    fn drop_leading_bits(&mut self) {
-        let cpy = self.bits.clone();
-        self.bits = BitVec::new();
-        for i in self.ptr..cpy.len() {
-            self.bits.push(cpy.get(i).unwrap());
-        }
-        self.ptr = 0;
-    }
     /// initialize the Huffman tree (does not reset bitstream)
     fn start_huff(&mut self) {
         // Leaves are stored first, one for each symbol (character)
@@ -483,62 +546,70 @@ impl AdaptiveHuffman {
             }
         }
     }
-    /// Get the next bit reading from the `bytes` iterator as needed.
-    /// When EOF is reached 0 is returned, consistent with original C code.
-    /// Byte iterator should not be advanced outside this function.
-    fn get_bit<R: Read>(&mut self,bytes: &mut Bytes<R>) -> u8 {
-        match self.bits.get(self.ptr) {
-            Some(bit) => {
-                self.ptr += 1;
-                bit as u8
-            },
-            None => {
-                match bytes.next() {
-                    Some(Ok(by)) => {
-                        if self.bits.len()>512 {
-                            self.drop_leading_bits();
-                        }
-                        self.bits.append(&mut BitVec::from_bytes(&[by]));
-                        self.count += 1;
-                        self.get_bit(bytes)
-                    }
-                    Some(Err(e)) => {
-                        panic!("error reading file {}",e)
-                    },
-                    None => 0
-                }
-            }
+    /// Make sure `get_buf` holds at least `num_bits`, at most 8, reading from `input` as needed.
+    /// When EOF is reached zeros are shifted in, consistent with original C code.
+    /// Unlike LZHUF.C, bytes are only read when they are needed, and rather than going on with zeros
+    /// until the size in the header is reached, `UnexpectedEof` is returned after `MAX_PAST_END` of them.
+    fn fill_buf<R: Read>(&mut self,num_bits: u16,input: &mut BlockReader<R>) -> Result<(),std::io::Error> {
+        while self.get_len < num_bits {
+            let by = match input.next_byte()? {
+                Some(by) => {
+                    self.count += 1;
+                    by
+                },
+                None if self.past_end < MAX_PAST_END => {
+                    self.past_end += 1;
+                    0
+                },
+                None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))
+            };
+            self.get_buf |= (by as u16) << (8 - self.get_len);
+            self.get_len += 8;
         }
+        Ok(())
+    }
+    /// Get the next bit, the input should not be advanced outside this function.
+    fn get_bit<R: Read>(&mut self,input: &mut BlockReader<R>) -> Result<u8,std::io::Error> {
+        self.fill_buf(1,input)?;
+        let ans = (self.get_buf >> 15) as u8;
+        self.get_buf <<= 1;
+        self.get_len -= 1;
+        Ok(ans)
     }
     /// get the next 8 bits into a u8, used exlusively to decode the position
-    fn get_byte<R: Read>(&mut self,bytes: &mut Bytes<R>) -> u8 {
-        let mut ans: u8 = 0;
-        for _i in 0..8 {
-            ans <<= 1;
-            ans |= self.get_bit(bytes);
-        }
-        ans
-    }
-    /// output `num_bits` of `code` starting from the MSB, unlike LZHUF.C the bits are always
-    /// written to the output stream (sometimes backing up and rewriting)
-    fn put_code<W: Write + Seek>(&mut self,num_bits: u16,mut code: u16,writer: &mut BufWriter<W>) {
-        for _i in 0..num_bits {
-            self.bits.push(code & 0x8000 > 0);
-            code <<= 1;
-            self.ptr += 1;
-        }
-        let bytes = self.bits.to_bytes();
-        writer.write(&bytes.as_slice()).expect("write err");
-        if self.bits.len() % 8 > 0 {
-            writer.seek(SeekFrom::Current(-1)).expect("seek err");
-            self.ptr = 8 * (self.bits.len() / 8);
-            self.drop_leading_bits();
-        } else {
-            self.bits = BitVec::new();
-            self.ptr = 0;
+    fn get_byte<R: Read>(&mut self,input: &mut BlockReader<R>) -> Result<u8,std::io::Error> {
+        self.fill_buf(8,input)?;
+        let ans = (self.get_buf >> 8) as u8;
+        self.get_buf <<= 8;
+        self.get_len -= 8;
+        Ok(ans)
+    }
+    /// output `num_bits` of `code` starting from the MSB, as in LZHUF.C the bits are
+    /// buffered and only completed bytes are written
+    fn put_code<W: Write>(&mut self,num_bits: u16,code: u16,writer: &mut BufWriter<W>) -> Result<(),std::io::Error> {
+        self.put_buf |= code >> self.put_len;
+        self.put_len += num_bits;
+        if self.put_len >= 8 {
+            writer.write_all(&[(self.put_buf >> 8) as u8])?;
+            self.put_len -= 8;
+            if self.put_len >= 8 {
+                writer.write_all(&[self.put_buf as u8])?;
+                self.put_len -= 8;
+                self.put_buf = code << (num_bits - self.put_len);
+            } else {
+                self.put_buf <<= 8;
+            }
         }
+        Ok(())
     }
-    fn encode_char<W: Write + Seek>(&mut self,c: u16,writer: &mut BufWriter<W>) {
+    /// write the partial byte, if any
+    fn finish<W: Write>(&mut self,writer: &mut BufWriter<W>) -> Result<(),std::io::Error> {
+        if self.put_len > 0 {
+            writer.write_all(&[(self.put_buf >> 8) as u8])?;
+        }
+        Ok(())
+    }
+    fn encode_char<W: Write>(&mut self,c: u16,writer: &mut BufWriter<W>) -> Result<(),std::io::Error> {
         let mut i: u16 = 0;
         let mut j: u16 = 0;
         let mut k: usize = self.prnt[c as usize + TAB_SIZE];
@@ -555,57 +626,70 @@ impl AdaptiveHuffman {
                 break;
             }
         }
-        self.put_code(j,i,writer);
+        self.put_code(j,i,writer)?;
         self.update(c as i16); // TODO: why is input to update signed
+        Ok(())
     }
-    fn encode_position<W: Write + Seek>(&mut self,c: u16,writer: &mut BufWriter<W>) {
+    fn encode_position<W: Write>(&mut self,c: u16,writer: &mut BufWriter<W>) -> Result<(),std::io::Error> {
         // upper 6 bits come from table
         let i = (c >> 6) as usize;
-        self.put_code(P_LEN[i] as u16,(P_CODE[i] as u16) << 8,writer);
+        self.put_code(P_LEN[i] as u16,(P_CODE[i] as u16) << 8,writer)?;
         // lower 6 bits verbatim
-        self.put_code(6,(c & 0x3f) << 10,writer);
+        self.put_code(6,(c & 0x3f) << 10,writer)
     }
-    fn decode_char<R: Read>(&mut self,bytes: &mut Bytes<R>) -> i16 {
+    fn decode_char<R: Read>(&mut self,input: &mut BlockReader<R>) -> Result<i16,std::io::Error> {
         let mut c: usize = self.son[ROOT];
         // travel from root to leaf, choosing the smaller child node (son[])
         // if the read bit is 0, the bigger (son[]+1) if read bit is 1
         while c < TAB_SIZE {
-            c += self.get_bit(bytes) as usize;
+            c += self.get_bit(input)? as usize;
             c = self.son[c];
         }
         c -= TAB_SIZE;
         self.update(c as i16); // TODO: why is input to update signed
-        c as i16
+        Ok(c as i16)
     }
-    fn decode_position<R: Read>(&mut self,bytes: &mut Bytes<R>) -> u16 {
+    fn decode_position<R: Read>(&mut self,input: &mut BlockReader<R>) -> Result<u16,std::io::Error> {
         // get upper 6 bits from table
-        let mut first8 = self.get_byte(bytes) as u16;
+        let mut first8 = self.get_byte(input)? as u16;
         let upper6 = (D_CODE[first8 as usize] as u16) << 6;
         let coded_bits = D_LEN[first8 as usize] as u16;
         // read lower 6 bits verbatim
         // we already got 8 bits, we need another 6 - (8-coded_bits) = coded_bits - 2
         for _i in 0..coded_bits-2 {
             first8 <<= 1;
-            first8 += self.get_bit(bytes) as u16;
+            first8 += self.get_bit(input)? as u16;
         }
-        upper6 | (first8 & 0x3f)
+        Ok(upper6 | (first8 & 0x3f))
     }
 }
 
 /// Main compression function
 pub fn encode<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR> {
-    let mut reader = BufReader::new(expanded_in);
+    encode_with_endian(expanded_in,compressed_out,Endian::Little)
+}
+
+/// Same as `encode`, with the header in the given byte order
+pub fn encode_with_endian<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_out: &mut W, header_endian: Endian) -> Result<(u64,u64),DYNERR> {
     let mut writer = BufWriter::new(compressed_out);
     // write the 32-bit header with length of expanded data
-    let expanded_length = reader.seek(SeekFrom::End(0))?;
+    let expanded_length = expanded_in.seek(SeekFrom::End(0))?;
     if expanded_length >= u32::MAX as u64 {
         return Err(Box::new(crate::Error::FileTooLarge));
     }
-    let header = u32::to_le_bytes(expanded_length as u32);
-    writer.write(&header)?;
-    reader.seek(SeekFrom::Start(0))?;
+    let header = match header_endian {
+        Endian::Little => u32::to_le_bytes(expanded_length as u32),
+        Endian::Big => u32::to_be_bytes(expanded_length as u32)
+    };
+    writer.write_all(&header)?;
+    // as in LZHUF.C, an empty file is only the header
+    if expanded_length == 0 {
+        writer.flush()?;
+        return Ok((0,writer.stream_position()?));
+    }
+    expanded_in.seek(SeekFrom::Start(0))?;
     // init
-    let mut bytes = reader.bytes();
+    let mut input = BlockReader::new(expanded_in);
     let mut lzss = LZSS::new();
     let mut huff = AdaptiveHuffman::new();
     huff.start_huff();
@@ -618,16 +702,13 @@ pub fn encode<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_o
     }
     let mut len = 0;
     while len < LOOKAHEAD {
-        match bytes.next() {
-            Some(Ok(c)) => {
+        match input.next_byte()? {
+            Some(c) => {
                 lzss.dictionary[r+len] = c;
                 len += 1;
             },
             None => {
                 break;
-            },
-            Some(Err(e)) => {
-                return Err(Box::new(e));
             }
         }
     }
@@ -642,18 +723,17 @@ pub fn encode<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_o
         }
         if lzss.match_length <= THRESHOLD {
             lzss.match_length = 1;
-            huff.encode_char(lzss.dictionary[r] as u16,&mut writer);
+            huff.encode_char(lzss.dictionary[r] as u16,&mut writer)?;
         } else {
-            huff.encode_char((255-THRESHOLD+lzss.match_length) as u16,&mut writer);
-            huff.encode_position(lzss.match_position as u16,&mut writer);
+            huff.encode_char((255-THRESHOLD+lzss.match_length) as u16,&mut writer)?;
+            huff.encode_position(lzss.match_position as u16,&mut writer)?;
         }
         let last_match_length = lzss.match_length;
         let mut i = 0;
         while i < last_match_length {
-            let c = match bytes.next() {
-                Some(Ok(c)) => c,
-                None => break,
-                Some(Err(e)) => return Err(Box::new(e))
+            let c = match input.next_byte()? {
+                Some(c) => c,
+                None => break
             };
             lzss.delete_node(s);
             lzss.dictionary[s] = c;
@@ -681,7 +761,7 @@ pub fn encode<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_o
             break;
         }
     }
-    writer.seek(SeekFrom::End(0))?; // coder could be rewound
+    huff.finish(&mut writer)?;
     writer.flush()?;
     Ok((expanded_length,writer.stream_position()?))
 }
@@ -690,14 +770,21 @@ pub fn encode<R: Read + Seek, W: Write + Seek>(expanded_in: &mut R, compressed_o
 /// Returns (compressed size, expanded size) or error.
 pub fn decode<R: Read + Seek , W: Write + Seek>(compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
 {
-    let mut reader = BufReader::new(compressed_in);
-    let mut writer = BufWriter::new(expanded_out);
+    decode_with_endian(compressed_in,expanded_out,Endian::Little)
+}
+
+/// Same as `decode`, with the header in the given byte order
+pub fn decode_with_endian<R: Read + Seek , W: Write + Seek>(compressed_in: &mut R, expanded_out: &mut W, header_endian: Endian) -> Result<(u64,u64),DYNERR>
+{
     // get size of expanded data from 32 bit header
     let mut header: [u8;4] = [0;4];
-    reader.read_exact(&mut header)?;
-    let textsize = u32::from_le_bytes(header);
+    crate::read_header(compressed_in,&mut header)?;
+    let textsize = match header_endian {
+        Endian::Little => u32::from_le_bytes(header),
+        Endian::Big => u32::from_be_bytes(header)
+    };
     // init
-    let mut bytes = reader.bytes();
+    let mut input = BlockReader::new(compressed_in);
     let mut huff = AdaptiveHuffman::new();
     let mut lzss= LZSS::new();
 	huff.start_huff();
@@ -705,28 +792,38 @@ pub fn decode<R: Read + Seek , W: Write + Seek>(compressed_in: &mut R, expanded_
 		lzss.dictionary[i] = b' ';
     }
 	let mut r = WIN_SIZE - LOOKAHEAD;
+    // output is gathered into blocks, `count` stands in for the position that LZHUF.C checks against `textsize`
+    let mut out: Vec<u8> = Vec::with_capacity(BLOCK_SIZE + LOOKAHEAD);
+    let mut count: u64 = 0;
     // start expanding
-	while writer.stream_position()? < textsize as u64 {
-		let c = huff.decode_char(&mut bytes);
+	while count < textsize as u64 {
+		let c = huff.decode_char(&mut input)?;
 		if c < 256 {
-            writer.write(&[c as u8])?;
+            out.push(c as u8);
+            count += 1;
 			lzss.dictionary[r] = c as u8;
             r += 1;
 			r &= WIN_SIZE - 1;
 		} else {
-			let strpos = ((r as i32 - huff.decode_position(&mut bytes) as i32 - 1) & (WIN_SIZE as i32 - 1)) as usize;
+			let strpos = ((r as i32 - huff.decode_position(&mut input)? as i32 - 1) & (WIN_SIZE as i32 - 1)) as usize;
 			let strlen = c as usize + THRESHOLD - 255;
+            count += strlen as u64;
 			for k in 0..strlen {
 				let c8 = lzss.dictionary[(strpos + k) & (WIN_SIZE - 1)];
-                writer.write(&[c8])?;
+                out.push(c8);
 				lzss.dictionary[r] = c8;
                 r += 1;
 				r &= WIN_SIZE - 1;
 			}
 		}
+        if out.len() >= BLOCK_SIZE {
+            expanded_out.write_all(&out)?;
+            out.clear();
+        }
 	}
-    writer.flush()?;
-    Ok((huff.count as u64,writer.stream_position()?))
+    expanded_out.write_all(&out)?;
+    expanded_out.flush()?;
+    Ok((huff.count as u64,count))
 }
 
 /// Convenience function, calls `decode` with a slice returning a Vec
@@ -768,5 +865,73 @@ fn invertibility() {
     let test_data = "1234567".as_bytes();
     let compressed = encode_slice(test_data).expect("encoding failed");
     let expanded = decode_slice(&compressed).expect("decoding failed");
-    assert_eq!(test_data.to_vec(),expanded[0..7]);
-}
\ No newline at end of file
+    assert_eq!(test_data.to_vec(),expanded);
+}
+#[test]
+fn big_endian_header() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let little = encode_slice(test_data).expect("encoding failed");
+    let mut big = Cursor::new(Vec::new());
+    encode_with_endian(&mut Cursor::new(test_data),&mut big,Endian::Big).expect("encoding failed");
+    let big = big.into_inner();
+    assert_eq!(big[0..4],[0,0,0,0x31]);
+    assert_eq!(big[4..],little[4..]);
+    let mut expanded = Cursor::new(Vec::new());
+    decode_with_endian(&mut Cursor::new(&big),&mut expanded,Endian::Big).expect("decoding failed");
+    assert_eq!(expanded.into_inner(),test_data);
+}
+#[test]
+fn tiny_inputs() {
+    for len in 0..5 {
+        let test_data = &b"abcd"[0..len];
+        let compressed = encode_slice(test_data).expect("encoding failed");
+        assert_eq!(decode_slice(&compressed).expect("decoding failed"),test_data);
+    }
+    // LZHUF.C writes only the header for an empty file
+    assert_eq!(encode_slice(&[]).expect("encoding failed"),[0;4]);
+    assert!(decode_slice(&[0;3]).is_err());
+}
+#[test]
+fn read_errors() {
+    /// stream that fails once the 4 byte header has been read
+    struct Failing(Cursor<Vec<u8>>);
+    impl Read for Failing {
+        fn read(&mut self,buf: &mut [u8]) -> std::io::Result<usize> {
+            let avail = usize::min(4usize.saturating_sub(self.0.position() as usize),buf.len());
+            match avail {
+                0 => Err(std::io::Error::other("device error")),
+                n => self.0.read(&mut buf[0..n])
+            }
+        }
+    }
+    impl Seek for Failing {
+        fn seek(&mut self,pos: SeekFrom) -> std::io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+    let compressed = encode_slice("I am Sam. Sam I am.".as_bytes()).expect("encoding failed");
+    let mut out = Cursor::new(Vec::new());
+    assert!(decode(&mut Failing(Cursor::new(compressed.clone())),&mut out).is_err());
+    assert!(encode(&mut Failing(Cursor::new(vec![0;100])),&mut out).is_err());
+}
+#[test]
+fn write_errors() {
+    // the output fills up while codes are going out, well before the end
+    let text = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    let mut buf = vec![0;1000];
+    let res = encode(&mut Cursor::new(&text),&mut Cursor::new(&mut buf[..]));
+    assert_eq!(res.unwrap_err().downcast_ref::<std::io::Error>().map(|e| e.kind()),Some(std::io::ErrorKind::WriteZero));
+}
+
+#[test]
+fn malformed_input() {
+    let text = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    let compressed = encode_slice(&text[0..4000]).expect("encoding failed");
+    for corrupted in crate::tools::mutate::Mutations::new(&compressed,0x5eed).take(200) {
+        let _ = decode_slice(&corrupted);
+    }
+    // LZHUF.C would go on with zeros until it had 4 GB
+    let mut bad_size = compressed.clone();
+    bad_size[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(decode_slice(&bad_size).is_err());
+}
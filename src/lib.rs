@@ -5,10 +5,31 @@
 //! * `lzss_huff` produces output compatible with `LZHUF` using a different implementation
 //! * `lzw` performs LZW compression with various options
 //! * `td0` converts between advanced (compressed) and normal (expanded) TD0 disk image formats
+//! * `sub_block` adds or removes GIF-style sub-block framing around any compressed stream
+//! * `async_io` wraps the codecs for tokio, requires the `async` feature
+//! * `batch` processes many files in parallel, requires the `batch` feature
+//! * `checksum` has table driven CRC-16 and CRC-32 with any parameters, and the variants the formats use, always available
+//! * `diagnostics` collects the warnings the codecs raise, always available
+//! * `sniff` identifies TD0, IMD, DMS, MSA, CopyQM, and raw disk images from their first bytes, always available
+//! * `test_util` checks that a codec and its options round trip, requires the `test-util` feature
+//! 
+//! Each codec module has a cargo feature of the same name, all enabled by default along with
+//! the `cli` feature that builds the command line program.  To build only what is needed, e.g.
+//! just one decoder, use `default-features = false` and select the codecs:
+//! ```toml
+//! retrocompressor = { version = "1", default-features = false, features = ["lzss_huff"] }
+//! ```
+//! The `td0` feature brings in `lzw` and `lzss_huff`.  `async` and `batch` work with whichever codecs are enabled.
+//! The `serde` feature derives `Serialize` and `Deserialize` for the options structs.
+//! The `tracing` feature adds spans for each conversion, and debug events for each LZW chunk, `lzss_huff`
+//! tree rebuild, and TD0 track walked, with offsets and counts as structured fields.  The `log` messages
+//! are still emitted, `tracing-log` can forward them to the same subscriber.
 //! 
 //! The compression/expansion functions are generics that operate on trait objects
 //! with bounds `Read + Seek` or `Write + Seek`.  There are convenience functions for working
-//! directly with buffers.
+//! directly with buffers.  The generics are thin wrappers around `compress_dyn` and `expand_dyn`,
+//! which take `&mut dyn ReadSeek` and `&mut dyn WriteSeek`, so the codecs themselves are only
+//! compiled once no matter how many stream types are used.
 //! 
 //! ## File Example
 //! 
@@ -21,6 +42,46 @@
 //! eprintln!("expanded {} into {}",in_size,out_size);
 //! ```
 //! 
+//! ## Memory
+//! 
+//! The stream functions use memory bounded by the window or dictionary, regardless of how long the
+//! stream is, so huge images can be converted on small machines.  The exceptions are the functions
+//! that work with buffers, such as `compress_slice` or `sub_block::frame`, which hold the whole input
+//! and output, and the trace and index functions, whose results grow with the data.
+//! 
+//! ## Malformed Input
+//! 
+//! Expansion never panics or hangs on damaged data.  Where the format has some redundancy, such as
+//! a stop code, chunk header, or checksum, the damage is reported as an error.  Otherwise the data
+//! expands to whatever it decodes to, so check the result against a checksum from elsewhere if it matters.
+//! Panics are reserved for options that cannot be satisfied, which is a programming error.
+//! 
+//! ## Empty and Tiny Inputs
+//! 
+//! Every codec takes an empty input, or one of a few bytes, and expands it back exactly, with no
+//! padding on the end.  An empty input compresses to whatever the format has around the data, e.g.,
+//! `LZHUF` writes only its 4 byte size header, and a TD0 image that is only the header stays that way.
+//! When expanding, data that ends before the size header, TD0 image header, or initial clear code is
+//! complete is `Error::TruncatedInput`, since nothing can be decoded.  After that, the `truncation`
+//! option decides, so an empty stream for a format without a header is empty data unless truncation is an error.
+//! 
+//! ## Offsets
+//! 
+//! The `in_offset` and `out_offset` options start the codec part way into the streams, as with the
+//! 12 byte header of a TD0 image.  Both are checked before anything is read or written.  An input that
+//! ends before `in_offset` is `Error::OffsetPastEnd`, while an input that ends right at it is empty.
+//! If the output ends before `out_offset`, the `offset_gap` option decides: `ZeroFill` writes zeros
+//! up to the offset, so the header can be filled in afterward, and `Error` returns `Error::OffsetPastEnd`.
+//! 
+//! ## Threads
+//! 
+//! There is no global mutable state, the CRC tables are constants and everything else lives in the
+//! objects passed to each call.  The options structs, `Method`, `Compressor`, `Expander`, and the
+//! errors are all `Send` and `Sync`, so options can be shared by reference across threads, and the
+//! errors, which are boxed as `Box<dyn Error + Send + Sync>`, can be returned from worker threads.
+//! A `Compressor` or `Expander` takes `&mut self`, so each thread needs its own.
+//! The exception is `diagnostics::collect`, which only sees warnings raised on its own thread.
+//! 
 //! ## Buffer Example
 //! 
 //! ```rs
@@ -29,13 +90,127 @@
 //! let compressed = lzw::compress_slice(test_data,&lzw::STD_OPTIONS).expect("compression failed");
 //! ```
 
-mod tools;
+pub(crate) mod tools;
+#[cfg(feature="lzw")]
 pub mod lzw;
+#[cfg(feature="lzss_huff")]
 pub mod lzss_huff;
+#[cfg(feature="td0")]
 pub mod td0;
+pub mod sub_block;
+pub mod diagnostics;
+pub mod checksum;
+pub mod sniff;
+#[cfg(feature="direct_ports")]
 pub mod direct_ports;
+#[cfg(all(feature="async",any(feature="lzw",feature="lzss_huff")))]
+pub mod async_io;
+#[cfg(all(feature="batch",any(feature="lzw",feature="lzss_huff")))]
+pub mod batch;
+#[cfg(all(any(test,feature="test-util"),any(feature="lzw",feature="lzss_huff")))]
+pub mod test_util;
+
+#[allow(dead_code)] // unused if no codecs are enabled
+type DYNERR = Box<dyn std::error::Error + Send + Sync>;
+
+/// Object safe combination of `Read` and `Seek`, implemented for everything that has both
+pub trait ReadSeek: std::io::Read + std::io::Seek {}
+impl<T: std::io::Read + std::io::Seek + ?Sized> ReadSeek for T {}
+
+/// Object safe combination of `Write` and `Seek`, implemented for everything that has both
+pub trait WriteSeek: std::io::Write + std::io::Seek {}
+impl<T: std::io::Write + std::io::Seek + ?Sized> WriteSeek for T {}
+
+/// Items that `compress_iter` accepts, either bare bytes or `std::io::Result<u8>`
+pub trait ByteItem {
+    fn into_byte(self) -> Result<u8,std::io::Error>;
+}
+impl ByteItem for u8 {
+    fn into_byte(self) -> Result<u8,std::io::Error> {
+        Ok(self)
+    }
+}
+impl ByteItem for Result<u8,std::io::Error> {
+    fn into_byte(self) -> Result<u8,std::io::Error> {
+        self
+    }
+}
+
+/// Errors for the batch and async functions are `std::io::Error`, this converts, keeping the original as the source
+#[cfg(any(feature="td0",all(any(feature="async",feature="batch"),any(feature="lzw",feature="lzss_huff"))))]
+fn to_io_error(e: DYNERR) -> std::io::Error {
+    match e.downcast::<std::io::Error>() {
+        Ok(e) => *e,
+        Err(e) => match e.downcast::<Error>() {
+            Ok(e) if matches!(*e,Error::TruncatedInput { .. } | Error::SizeMismatch { .. } | Error::OffsetPastEnd { .. }) => std::io::Error::new(std::io::ErrorKind::UnexpectedEof,e),
+            Ok(e) => std::io::Error::new(std::io::ErrorKind::InvalidData,e),
+            Err(e) => std::io::Error::new(std::io::ErrorKind::InvalidData,e)
+        }
+    }
+}
+
+/// Running out of room in a caller's buffer becomes `Error::FileTooLarge`
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn buffer_full(e: DYNERR) -> DYNERR {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(io) if io.kind()==std::io::ErrorKind::WriteZero => {
+            log::error!("output buffer is too small");
+            Box::new(Error::FileTooLarge)
+        },
+        _ => e
+    }
+}
 
-type DYNERR = Box<dyn std::error::Error>;
+/// Seek the input to `offset`, which is `Error::OffsetPastEnd` if the input ends first.
+/// Works without a known end, as with `IterReader`, which stops at the end rather than seeking past it.
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn seek_input<R: std::io::Seek + ?Sized>(reader: &mut R,offset: u64) -> Result<(),DYNERR> {
+    let len = match tools::iter_reader::known_end(reader)? {
+        Some(end) => end,
+        None => reader.seek(std::io::SeekFrom::Start(offset))?
+    };
+    if offset > len {
+        log::error!("offset {} is past the end of the input at {}",offset,len);
+        return Err(Box::new(Error::OffsetPastEnd { offset, len }));
+    }
+    reader.seek(std::io::SeekFrom::Start(offset))?;
+    Ok(())
+}
+
+/// Seek the output to `offset`, if the output ends first `gap` decides whether to zero fill up to it.
+/// Writers that only seek in place, such as `RangeWriter`, are left to handle the seek themselves.
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn place_output<W: std::io::Write + std::io::Seek + ?Sized>(writer: &mut W,offset: u64,gap: OffsetGap) -> Result<(),DYNERR> {
+    if let Some(len) = tools::iter_reader::known_end(writer)? {
+        if offset > len && gap == OffsetGap::Error {
+            log::error!("offset {} is past the end of the output at {}",offset,len);
+            return Err(Box::new(Error::OffsetPastEnd { offset, len }));
+        }
+        if offset > len {
+            std::io::copy(&mut std::io::Read::take(std::io::repeat(0),offset - len),writer)?;
+        }
+    }
+    writer.seek(std::io::SeekFrom::Start(offset))?;
+    Ok(())
+}
+
+/// Fill `buf` with a header, if the stream ends first it is `Error::TruncatedInput`
+#[cfg(any(feature="lzss_huff",feature="direct_ports"))]
+fn read_header<R: std::io::Read + ?Sized>(reader: &mut R,buf: &mut [u8]) -> Result<(),DYNERR> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => {
+                log::error!("data ended {} bytes into a {} byte header",n,buf.len());
+                return Err(Box::new(Error::TruncatedInput { missing_bits: 8 * (buf.len() - n) as u64, expanded: 0 }));
+            },
+            Ok(count) => n += count,
+            Err(e) if e.kind()==std::io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(Box::new(e))
+        }
+    }
+    Ok(())
+}
 
 #[derive(thiserror::Error,Debug)]
 pub enum Error {
@@ -44,11 +219,244 @@ pub enum Error {
     #[error("file too large")]
     FileTooLarge,
     #[error("checksum failed")]
-    BadChecksum
+    BadChecksum,
+    #[error("input is truncated after {expanded} bytes were expanded, {missing_bits} more bits were needed")]
+    TruncatedInput { missing_bits: u64, expanded: u64 },
+    #[error("header gives {expected} bytes, but the data expands to {actual}")]
+    SizeMismatch { expected: u64, actual: u64 },
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("{bytes} bytes follow the end of the data")]
+    TrailingData { bytes: u64 },
+    #[error("LZW code {code} at bit {bit} is not defined yet, the next code is {next_code}, the dictionary has {dictionary_size} codes")]
+    CodeFromFuture { code: usize, bit: u64, next_code: usize, dictionary_size: usize },
+    #[error("LZW code {code} at bit {bit} is out of range, the next code would be {next_code}, the dictionary has {dictionary_size} codes")]
+    CodeOutOfRange { code: usize, bit: u64, next_code: usize, dictionary_size: usize },
+    #[error("data goes on past the {expected} bytes in the header at bit {bit}, {excess} bytes are left over")]
+    Overlong { expected: u64, bit: u64, excess: u64 },
+    #[error("offset {offset} is past the end of the stream at {len}")]
+    OffsetPastEnd { offset: u64, len: u64 },
+    #[error("invalid options: {0}")]
+    InvalidOptions(String)
+}
+
+/// What expansion does when the compressed data ends before the expanded data is complete
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum Truncation {
+    /// keep what was expanded and stop without an error, the token that ran out is dropped
+    Stop,
+    /// shift in zeros as `LZHUF.C` does, until the expanded size in the header is reached
+    ZeroFill,
+    /// return `Error::TruncatedInput`, with the number of bits the token that ran out was missing
+    Error
+}
+
+/// What expansion does when the expanded size in the header and the compressed data disagree
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum HeaderSize {
+    /// stop at the size in the header, any data left over raises `Warning::Overlong`, if the data runs out first `truncation` decides
+    Limit,
+    /// same as `Limit`, but return `Error::SizeMismatch` if the expanded data falls short of the size in the header,
+    /// or `Error::Overlong` if the data goes on past it
+    Require,
+    /// expand until the data runs out, whatever the header says, the end is found as if there were no header
+    Ignore
+}
+
+/// What to do when the expanded size does not fit in the size header
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum LargeInput {
+    /// return `Error::FileTooLarge`
+    Error,
+    /// split the input into members that each fit, expansion continues through every member
+    MultiMember,
+    /// always use a 64 bit size header, this is not compatible with legacy decoders
+    Header64
+}
+
+/// What to do when `out_offset` is past the end of the output stream
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum OffsetGap {
+    /// write zeros up to the offset, e.g., where the 12 byte TD0 header is to go
+    ZeroFill,
+    /// return `Error::OffsetPastEnd`
+    Error
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
 pub enum BitOrder {
     Msb0,
     Lsb0
 }
+
+#[derive(Clone,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum Endian {
+    Little,
+    Big
+}
+
+/// bytes handed from the expander to the compressor at a time in `Method::transcode`
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+const TRANSCODE_CHUNK: usize = 0x4000;
+/// pieces that can be waiting for the compressor in `Method::transcode`
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+const TRANSCODE_QUEUE: usize = 4;
+
+/// Codec and options, for functions that can work with any of the codecs.
+/// Only the codecs enabled by cargo features are present.
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+#[derive(Clone)]
+pub enum Method {
+    #[cfg(feature="lzw")]
+    Lzw(lzw::Options),
+    #[cfg(feature="lzss_huff")]
+    LzssHuff(lzss_huff::Options),
+    #[cfg(feature="td0")]
+    Td0
+}
+
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+impl Method {
+    /// calls `compress` for the selected codec, returns (in_size,out_size)
+    pub fn compress<R,W>(&self, expanded_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: std::io::Read + std::io::Seek, W: std::io::Write + std::io::Seek {
+        self.compress_dyn(expanded_in,compressed_out)
+    }
+    /// calls `compress_dyn` for the selected codec, returns (in_size,out_size)
+    pub fn compress_dyn(&self, expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::compress_dyn(expanded_in,compressed_out,opt),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::compress_dyn(expanded_in,compressed_out,opt),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::compress_dyn(expanded_in,compressed_out)
+        }
+    }
+    /// calls `expand` for the selected codec, returns (in_size,out_size)
+    pub fn expand<R,W>(&self, compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: std::io::Read + std::io::Seek, W: std::io::Write + std::io::Seek {
+        self.expand_dyn(compressed_in,expanded_out)
+    }
+    /// calls `expand_dyn` for the selected codec, returns (in_size,out_size)
+    pub fn expand_dyn(&self, compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::expand_dyn(compressed_in,expanded_out,opt),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::expand_dyn(compressed_in,expanded_out,opt),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::expand_dyn(compressed_in,expanded_out)
+        }
+    }
+    /// calls `compress_iter` for the selected codec, returns (in_size,out_size)
+    pub fn compress_iter<I,W>(&self, expanded_in: I, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where I: IntoIterator, I::Item: ByteItem, W: std::io::Write + std::io::Seek {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::compress_iter(expanded_in,compressed_out,opt),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::compress_iter(expanded_in,compressed_out,opt),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::compress_iter(expanded_in,compressed_out)
+        }
+    }
+    /// calls `expand_chunked` for the selected codec, returns (in_size,out_size)
+    pub fn expand_chunked<R,F>(&self, compressed_in: &mut R, chunk_size: usize, callback: F) -> Result<(u64,u64),DYNERR>
+    where R: std::io::Read + std::io::Seek, F: FnMut(&[u8]) {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::expand_chunked(compressed_in,opt,chunk_size,callback),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::expand_chunked(compressed_in,opt,chunk_size,callback),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::expand_chunked(compressed_in,chunk_size,callback)
+        }
+    }
+    /// Expand with this method and compress the result with `to` in one pass, the expanded data
+    /// is handed over in pieces, so it is never held in full.  The compression runs on its own thread.
+    /// Returns (in_size,out_size), which are the sizes of the two compressed streams.
+    pub fn transcode<R,W>(&self, to: &Method, compressed_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: std::io::Read + std::io::Seek, W: std::io::Write + std::io::Seek + Send {
+        let (tx,rx) = std::sync::mpsc::sync_channel::<Vec<u8>>(TRANSCODE_QUEUE);
+        std::thread::scope(|scope| {
+            let compressor = scope.spawn(move || {
+                to.compress_iter(rx.into_iter().flatten(),compressed_out)
+            });
+            // once the compressor quits, the rest of the expansion is discarded
+            let mut tx = Some(tx);
+            let expanded = self.expand_chunked(compressed_in,TRANSCODE_CHUNK,|chunk| {
+                if tx.as_ref().is_some_and(|tx| tx.send(chunk.to_vec()).is_err()) {
+                    tx = None;
+                }
+            });
+            drop(tx);
+            let compressed = compressor.join().expect("compressor thread panicked");
+            match (expanded,compressed) {
+                (Err(e),_) => Err(e),
+                (Ok(_),Err(e)) => Err(e),
+                (Ok((in_size,_)),Ok((_,out_size))) => Ok((in_size,out_size))
+            }
+        })
+    }
+    /// calls `compress_slice` for the selected codec
+    pub fn compress_slice(&self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::compress_slice(slice,opt),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::compress_slice(slice,opt),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::compress_slice(slice)
+        }
+    }
+    /// calls `expand_slice` for the selected codec
+    pub fn expand_slice(&self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        match self {
+            #[cfg(feature="lzw")]
+            Self::Lzw(opt) => lzw::expand_slice(slice,opt),
+            #[cfg(feature="lzss_huff")]
+            Self::LzssHuff(opt) => lzss_huff::expand_slice(slice,opt),
+            #[cfg(feature="td0")]
+            Self::Td0 => td0::expand_slice(slice)
+        }
+    }
+}
+
+#[test]
+fn thread_safety() {
+    fn shareable<T: Send + Sync>() {}
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    shareable::<Method>();
+    shareable::<Error>();
+    shareable::<DYNERR>();
+    shareable::<diagnostics::Warning>();
+    #[cfg(feature="lzw")]
+    {
+        shareable::<lzw::Options>();
+        shareable::<lzw::Compressor>();
+        shareable::<lzw::Expander>();
+        shareable::<lzw::State>();
+        shareable::<lzw::Checkpoint>();
+    }
+    #[cfg(feature="lzss_huff")]
+    {
+        shareable::<lzss_huff::Options>();
+        shareable::<lzss_huff::Compressor>();
+        shareable::<lzss_huff::Expander>();
+        shareable::<lzss_huff::Checkpoint>();
+    }
+    #[cfg(feature="td0")]
+    {
+        shareable::<td0::Options>();
+        shareable::<td0::Recovery>();
+        shareable::<td0::ExpandedImage<std::fs::File>>();
+        shareable::<td0::Index>();
+    }
+}
@@ -3,7 +3,7 @@
 //! This can perform compression equivalent to the C program `LZHUF.C` by
 //! Haruyasu Yoshizaki, Haruhiko Okumura, and Kenji Rikitake.  This is not a direct
 //! port, but it will produce the same bit-for-bit output as `LZHUF.C`, assuming the
-//! standard options are chosen.  The header is always treated as little endian.
+//! standard options are chosen.  The header is little endian unless `header_endian` says otherwise.
 //! 
 //! This program appears to work more reliably than `LZHUF.C`.
 //! I found that `LZHUF.C` will hang on large files when compiled with `clang 16`,
@@ -15,18 +15,47 @@
 use crate::tools::node_pool::*;
 use crate::tools::ring_buffer::*;
 use crate::tools::adaptive_huff::*;
-use std::io::{Cursor,Read,Write,Seek,SeekFrom,BufReader,BufWriter,ErrorKind};
-use crate::DYNERR;
+use std::io::{Cursor,Read,BufRead,Write,Seek,SeekFrom,BufReader,BufWriter,ErrorKind};
+use crate::{DYNERR,ReadSeek,WriteSeek,ByteItem,LargeInput,Truncation,HeaderSize,OffsetGap,Endian};
+use crate::diagnostics::{self,Warning};
+use crate::tools::range_writer::RangeWriter;
+use crate::tools::iter_reader::{IterReader,known_end};
+use crate::tools::chunk_writer::{ChunkWriter,DISCARD_CHUNK};
+
+pub use crate::tools::adaptive_huff::UpdateStrategy;
+
+/// Width of the size header
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum HeaderWidth {
+    /// 4 bytes, as `LZHUF.C` writes it
+    Bits32,
+    /// 2 bytes, as some BBS door and packer programs write it
+    Bits16
+}
 
 /// Options controlling compression
 #[derive(Clone)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+#[cfg_attr(feature="serde",serde(deny_unknown_fields))]
 pub struct Options {
     /// whether to include an optional header
     pub header: bool,
+    /// how to handle data too large for the size header, only matters if `header` is true
+    pub large_input: LargeInput,
+    /// width of the size header, only matters if `header` is true, `LargeInput::Header64` overrides it
+    pub header_width: HeaderWidth,
+    /// byte order of the size header, Amiga and 68k ports of `LZHUF` use `Big`
+    pub header_endian: Endian,
+    /// follow each member with a byte holding the sum of the expanded bytes, as some BBS and ham radio
+    /// dialects of `LZHUF` do; expansion checks it, which needs the size from a header or `expand_raw`
+    pub checksum_trailer: bool,
     /// starting position in the input file
     pub in_offset: u64,
     /// starting position in the output file
     pub out_offset: u64,
+    /// what to do if `out_offset` is past the end of the output, as when expanding into a new file
+    pub offset_gap: OffsetGap,
     /// size of sliding window
     pub window_size: usize,
     /// minimum length of match to encode
@@ -35,21 +64,209 @@ pub struct Options {
     pub lookahead: usize,
     /// backfill symbol for LZSS dictionary
     pub precursor: u8,
+    /// bytes to copy into the LZSS dictionary after backfill, can be empty
+    pub preset_dictionary: Vec<u8>,
+    /// position in the LZSS dictionary where the preset bytes start, the copy wraps around the window;
+    /// the preset should end before `window_size - lookahead`, where the input starts
+    pub preset_position: usize,
+    /// algorithm used to update the adaptive Huffman tree, only `Lzhuf` is compatible with `LZHUF.C`
+    pub huffman_update: UpdateStrategy,
+    /// leaf frequencies are divided by this (rounding up) when the Huffman tree is rebuilt, `LZHUF.C` uses 2
+    pub huffman_rescale_divisor: usize,
+    /// the Huffman tree is rebuilt when the total frequency reaches this, `LZHUF.C` uses 0x8000
+    pub huffman_max_freq: usize,
+    /// starting frequency of each Huffman symbol, empty means all are 1; there are
+    /// `256 + lookahead - threshold` symbols, the literal bytes come first
+    pub huffman_initial_freq: Vec<usize>,
+    /// What expansion does if the data runs out, `ZeroFill` only differs from `Stop` if there is a header.
+    /// Without a header, a token that starts in the last byte is taken to be padding, so a cut at a byte
+    /// boundary is only caught if the token in progress started before that.
+    pub truncation: Truncation,
+    /// What expansion does if the size in the header and the data disagree, only matters if `header` is true
+    pub header_size: HeaderSize,
+    /// most index nodes to visit while searching for a match, 0 means no limit as in `LZHUF.C`;
+    /// a position whose search is cut short is left out of the index
+    pub search_depth: usize,
+    /// a match shorter than this is deferred if the next position has a longer one, 0 means
+    /// always take the first match as in `LZHUF.C`
+    pub lazy_length: usize,
     /// return error if file is larger
     pub max_file_size: u64
 }
 
 pub const STD_OPTIONS: Options = Options {
     header: true,
+    large_input: LargeInput::Error,
+    header_width: HeaderWidth::Bits32,
+    header_endian: Endian::Little,
+    checksum_trailer: false,
     in_offset: 0,
     out_offset: 0,
+    offset_gap: OffsetGap::ZeroFill,
     window_size: 4096,
     threshold: 2,
     lookahead: 60,
     precursor: b' ',
+    preset_dictionary: Vec::new(),
+    preset_position: 0,
+    huffman_update: UpdateStrategy::Lzhuf,
+    huffman_rescale_divisor: 2,
+    huffman_max_freq: 0x8000,
+    huffman_initial_freq: Vec::new(),
+    truncation: Truncation::Stop,
+    header_size: HeaderSize::Limit,
+    search_depth: 0,
+    lazy_length: 0,
     max_file_size: u32::MAX as u64/4
 };
 
+/// Names accepted by `Options::preset`
+pub const PRESETS: &[&str] = &["standard","teledisk_v2"];
+
+/// expansion writes in blocks of about this size
+const OUT_BLOCK: usize = 0x1000;
+
+impl Options {
+    /// Teledisk 2.x advanced compression, which is `LZHUF` without the size header.
+    /// The offsets skip the 12 byte image header, this is `td0::TD_V2_OPTIONS`.
+    /// Truncation is an error, since it means part of the disk is lost.
+    pub const fn teledisk_v2() -> Self {
+        Self {
+            header: false,
+            large_input: LargeInput::Error,
+            header_width: HeaderWidth::Bits32,
+            header_endian: Endian::Little,
+            checksum_trailer: false,
+            in_offset: 12,
+            out_offset: 12,
+            offset_gap: OffsetGap::ZeroFill,
+            window_size: 4096,
+            threshold: 2,
+            lookahead: 60,
+            precursor: b' ',
+            preset_dictionary: Vec::new(),
+            preset_position: 0,
+            huffman_update: UpdateStrategy::Lzhuf,
+            huffman_rescale_divisor: 2,
+            huffman_max_freq: 0x8000,
+            huffman_initial_freq: Vec::new(),
+            truncation: Truncation::Error,
+            header_size: HeaderSize::Limit,
+            search_depth: 0,
+            lazy_length: 0,
+            max_file_size: 3_000_000
+        }
+    }
+    /// Look up options by one of the names in `PRESETS`, where `standard` is `STD_OPTIONS`
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(STD_OPTIONS),
+            "teledisk_v2" => Some(Self::teledisk_v2()),
+            _ => None
+        }
+    }
+    /// Set the match finder effort from 1 (fastest) to 9 (smallest output), values outside are clamped.
+    /// Levels 1 through 6 limit the search depth, 7 is the exhaustive greedy search of `LZHUF.C`,
+    /// 8 adds lazy matching for short matches, and 9 for all matches.
+    /// Any level can be expanded with the same options, only the compressor changes.
+    pub fn set_level(&mut self,level: u8) {
+        let level = level.clamp(1,9);
+        (self.search_depth,self.lazy_length) = match level {
+            1..=6 => (2 << level,0),
+            7 => (0,0),
+            8 => (0,16),
+            _ => (0,self.lookahead)
+        };
+    }
+    /// Return `Error::InvalidOptions` for anything `Compressor::new` or `Expander::new` would panic on,
+    /// or that the position code cannot represent.  Check options that come from outside the program first.
+    pub fn validate(&self) -> Result<(),crate::Error> {
+        let num_symbols = 256 + self.lookahead.saturating_sub(self.threshold);
+        let problem = if self.window_size > 4096 {
+            "window size cannot exceed 4096".to_string()
+        } else if self.threshold == 0 || self.threshold >= self.lookahead {
+            "threshold must be positive and less than the lookahead".to_string()
+        } else if 2*self.lookahead > self.window_size {
+            "lookahead cannot exceed half the window size".to_string()
+        } else if self.huffman_update != UpdateStrategy::Lzhuf {
+            return Ok(());
+        } else if self.huffman_rescale_divisor < 2 {
+            "rescale divisor must be at least 2".to_string()
+        } else if self.huffman_max_freq <= num_symbols {
+            format!("maximum frequency must exceed the number of symbols, which is {}",num_symbols)
+        } else if !self.huffman_initial_freq.is_empty() && (self.huffman_initial_freq.len() != num_symbols || self.huffman_initial_freq.contains(&0)) {
+            format!("initial frequencies must be nonzero and given for all {} symbols",num_symbols)
+        } else if self.huffman_initial_freq.iter().fold(0,|sum: usize,f| sum.saturating_add(*f)) > self.huffman_max_freq {
+            format!("initial frequencies cannot total more than {}",self.huffman_max_freq)
+        } else {
+            return Ok(());
+        };
+        Err(crate::Error::InvalidOptions(problem))
+    }
+}
+
+/// Bytes in the size header, 0 if there is none
+fn header_len(opt: &Options) -> usize {
+    match (opt.header,opt.large_input,opt.header_width) {
+        (false,_,_) => 0,
+        (true,LargeInput::Header64,_) => 8,
+        (true,_,HeaderWidth::Bits32) => 4,
+        (true,_,HeaderWidth::Bits16) => 2
+    }
+}
+
+/// Largest expanded size the header can hold, which is the size of a member if there are several
+fn header_max(opt: &Options) -> u64 {
+    match header_len(opt) {
+        8 => u64::MAX,
+        2 => u16::MAX as u64,
+        _ => u32::MAX as u64
+    }
+}
+
+/// The size header for `len` bytes, which should fit
+fn header_bytes(opt: &Options,len: u64) -> Vec<u8> {
+    let n = header_len(opt);
+    match opt.header_endian {
+        Endian::Little => u64::to_le_bytes(len)[0..n].to_vec(),
+        Endian::Big => u64::to_be_bytes(len)[8-n..].to_vec()
+    }
+}
+
+/// The expanded size in a header of `header_len` bytes
+fn header_value(opt: &Options,header: &[u8]) -> u64 {
+    let mut buf = [0;8];
+    let n = header.len();
+    match opt.header_endian {
+        Endian::Little => {
+            buf[0..n].copy_from_slice(header);
+            u64::from_le_bytes(buf)
+        },
+        Endian::Big => {
+            buf[8-n..].copy_from_slice(header);
+            u64::from_be_bytes(buf)
+        }
+    }
+}
+
+/// Add the bytes in `buf` to the trailer checksum
+fn byte_sum(sum: u8,buf: &[u8]) -> u8 {
+    buf.iter().fold(sum,|acc,b| acc.wrapping_add(*b))
+}
+
+/// Length of the common prefix of two slices, comparing a word at a time until they differ
+fn common_prefix(a: &[u8],b: &[u8]) -> usize {
+    let n = usize::min(a.len(),b.len());
+    let mut i = 0;
+    while i + 8 <= n && a[i..i+8] == b[i..i+8] {
+        i += 8;
+    }
+    while i < n && a[i] == b[i] {
+        i += 1;
+    }
+    i
+}
+
 /// Structure to perform the LZSS stage of  compression.
 /// This maintains two components.  First a sliding window containing
 /// the symbols in the order encountered ("dictionary"), and second a
@@ -65,7 +282,8 @@ struct LZSS {
 
 impl LZSS {
     fn create(opt: Options) -> Self {
-        let dictionary = RingBuffer::create(0,opt.window_size);
+        // mirror the lookahead so candidate matches can be compared as slices
+        let dictionary = RingBuffer::create(0,opt.window_size,opt.lookahead);
         let index = Tree::create(opt.window_size,256);
         Self {
             opt,
@@ -75,6 +293,38 @@ impl LZSS {
             match_length: 0
         }
     }
+    /// Clear the dictionary and index without deallocating
+    fn reset(&mut self) {
+        self.dictionary.reset(0);
+        self.index.reset();
+        self.match_offset = 0;
+        self.match_length = 0;
+    }
+    /// Fill the dictionary with the precursor, then overlay the preset dictionary, if any.
+    /// The cursor is left at the starting position for the first input symbol.
+    fn prime_dictionary(&mut self) {
+        let start_pos = self.opt.window_size - self.opt.lookahead;
+        for i in 0..start_pos {
+            self.dictionary.set_abs(i,self.opt.precursor);
+        }
+        for (i,c) in self.opt.preset_dictionary.iter().enumerate() {
+            self.dictionary.set_abs(self.opt.preset_position + i,*c);
+        }
+        self.dictionary.set_pos(start_pos);
+    }
+    /// Index the part of the preset dictionary that precedes the standard initial matches.
+    /// Positions in the last `lookahead` slots before the start are left for the caller,
+    /// which handles them the same way whether or not there is a preset.
+    fn index_preset(&mut self) -> Result<(),Error> {
+        let start_pos = self.opt.window_size - self.opt.lookahead;
+        let end = usize::min(self.opt.preset_position + self.opt.preset_dictionary.len(),start_pos.saturating_sub(self.opt.lookahead));
+        for p in self.opt.preset_position..end {
+            self.dictionary.set_pos(p);
+            self.insert_node()?;
+        }
+        self.dictionary.set_pos(start_pos);
+        Ok(())
+    }
     /// This finds a match to the symbol run starting at position `pos`.
     /// It always exits by inserting a node: either for a match that was found,
     /// or for a prospective match to come.
@@ -97,18 +347,20 @@ impl LZSS {
             }
         };
         self.index.set_cursor(curs)?;
+        let mut depth = 0;
         loop {
-            let mut cmp = 0;
-            let mut i: usize = 1;
-            // upon exiting this loop, `i` will have the number of matched symbols,
-            // and `cmp` will have the difference in first mismatched symbol values.
-            while i < self.opt.lookahead {
-                cmp = self.dictionary.get(i as i64) as i16 - self.dictionary.get_abs(curs+i) as i16;
-                if cmp != 0 {
-                    break;
-                }
-                i += 1;
-            }
+            depth += 1;
+            // `i` is the number of matched symbols,
+            // and `cmp` is the difference in first mismatched symbol values.
+            let span = self.opt.lookahead.saturating_sub(1);
+            let ahead = self.dictionary.slice_abs(pos+1,span);
+            let behind = self.dictionary.slice_abs(curs+1,span);
+            let common = common_prefix(ahead,behind);
+            let i = 1 + common;
+            let cmp = match common < span {
+                true => ahead[common] as i16 - behind[common] as i16,
+                false => 0
+            };
             if i > self.opt.threshold {
                 if i > self.match_length {
                     // we found a better match, take it
@@ -137,6 +389,10 @@ impl LZSS {
                 false => Side::Left
             };
             curs = match self.index.down(side) {
+                Ok(_) if depth == self.opt.search_depth => {
+                    // search limit reached, keep the best match so far, but do not index this position
+                    return Ok(());
+                },
                 Ok(c) => c,
                 Err(Error::NodeMissing) => {
                     // no match, make this position a new node, go out
@@ -149,6 +405,30 @@ impl LZSS {
             };
         }
     }
+    /// Move the dictionary forward one position, reading the next symbol into the lookahead
+    /// if there is one, otherwise shortening the lookahead.  The new position is indexed.
+    fn slide<I>(&mut self,bytes: &mut I,len: &mut usize) -> Result<(),DYNERR>
+    where I: Iterator<Item = std::io::Result<u8>> {
+        let lookahead = self.opt.lookahead as i64;
+        match bytes.next() {
+            Some(Ok(c)) => {
+                self.delete_node(lookahead)?;
+                self.dictionary.set(lookahead,c);
+                self.dictionary.advance();
+                self.insert_node()?;
+            },
+            None => {
+                self.delete_node(lookahead)?;
+                self.dictionary.advance();
+                *len -= 1;
+                if *len > 0 {
+                    self.insert_node()?;
+                }
+            },
+            Some(Err(e)) => return Err(Box::new(e))
+        }
+        Ok(())
+    }
     fn delete_node(&mut self,offset: i64) -> Result<(),Error> {
         // The big idea here is to delete the node without having to cut a whole branch.
         // If p has only one branch, this is easy, the next node down replaces p.
@@ -221,36 +501,470 @@ impl LZSS {
     }
 }
 
+/// Reusable compression state.  The dictionary, index, and Huffman tree are allocated once,
+/// and reset in place at the start of each compression, which helps when there are many small buffers.
+/// `new` panics if the options are inconsistent, `try_new` returns the error.
+pub struct Compressor {
+    opt: Options,
+    lzss: LZSS,
+    huff: AdaptiveHuffmanCoder,
+    /// if present compression records a checkpoint in `index` whenever this many bytes have been encoded since the last one
+    interval: Option<u64>,
+    index: Vec<Checkpoint>
+}
+
+/// Reusable expansion state.  The dictionary and Huffman tree are allocated once,
+/// and reset in place at the start of each expansion, which helps when there are many small buffers.
+/// `new` panics if the options are inconsistent, `try_new` returns the error.
+pub struct Expander {
+    opt: Options,
+    lzss: LZSS,
+    huff: AdaptiveHuffmanDecoder,
+    /// if present expansion records every token here
+    trace: Option<Vec<TraceEvent>>,
+    /// expanded bytes waiting to be written, kept so the allocation carries over between runs
+    out: Vec<u8>,
+    /// where the last expansion stopped short of the end, see `expand_range`
+    stopped: Option<Progress>,
+    /// checkpoints from `compress_indexed`, see `set_index`
+    index: Vec<Checkpoint>
+}
+
+/// Place where expansion can start, recorded by `compress_indexed`.
+/// Both offsets are relative to the respective `in_offset` or `out_offset` used during compression.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Checkpoint {
+    /// offset into the expanded data in bytes
+    pub expanded_offset: u64,
+    /// offset into the compressed data in bits, including the header
+    pub compressed_bit: u64,
+    /// what expansion needs to continue from here, `None` at the start of the data
+    pub state: Option<Snapshot>
+}
+
+/// Expander state partway through a member, this holds a copy of the window
+#[derive(Clone,PartialEq,Debug)]
+pub struct Snapshot {
+    dictionary: RingBuffer<u8>,
+    tree: TreeState,
+    /// sum of the bytes before the checkpoint, for the checksum trailer
+    sum: u8
+}
+
+/// How far the expansion of a member got before it was stopped, so that it can continue from there
+#[derive(Clone,Copy)]
+struct Progress {
+    /// position in the compressed stream, bits already taken from the bytes after it are held by the decoder
+    in_pos: u64,
+    /// bits in the header
+    header_bits: u64,
+    /// size given by the header, or `u64::MAX` if the header does not decide where the data ends
+    declared: u64,
+    /// bytes written so far
+    pos: u64,
+    /// bytes after `pos` that were decoded along with the last match, and are still in the dictionary
+    pending: u64,
+    /// sum of the bytes decoded so far, for the checksum trailer
+    sum: u8
+}
+
+/// Decoded item reported by `expand_traced`
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize),serde(tag="kind",rename_all="snake_case"))]
+pub enum Token {
+    /// a byte copied to the output
+    Literal { byte: u8 },
+    /// copy `length` bytes from `offset` bytes back in the window
+    Match { offset: u16, length: u16 },
+    /// the Huffman tree was rebuilt after the total frequency reached `opt.huffman_max_freq`
+    Rebuild
+}
+
+/// A token and where it was found
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize))]
+pub struct TraceEvent {
+    /// bits consumed starting from the beginning of the member, including the header
+    pub bit: u64,
+    /// bytes written so far by the member
+    pub pos: u64,
+    #[cfg_attr(feature="serde",serde(flatten))]
+    pub token: Token
+}
+
+impl Compressor {
+    /// Returns `Error::InvalidOptions` if the options are inconsistent, see `Options::validate`
+    pub fn try_new(opt: &Options) -> Result<Self,crate::Error> {
+        opt.validate()?;
+        Ok(Self::new(opt))
+    }
+    pub fn new(opt: &Options) -> Self {
+        Self {
+            opt: opt.clone(),
+            lzss: LZSS::create(opt.clone()),
+            huff: AdaptiveHuffmanCoder::create(256 + opt.lookahead - opt.threshold,opt.huffman_update,opt.huffman_rescale_divisor,opt.huffman_max_freq,&opt.huffman_initial_freq),
+            interval: None,
+            index: Vec::new()
+        }
+    }
+    /// Put everything back in the initial state, this happens automatically for each compression
+    pub fn reset(&mut self) {
+        self.lzss.reset();
+        self.huff.reset();
+    }
+    /// Same as the module level `compress`, using the options this was created with
+    pub fn compress<R,W>(&mut self, expanded_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        self.compress_dyn(expanded_in,compressed_out)
+    }
+    /// Same as `compress` with the streams as trait objects
+    pub fn compress_dyn(&mut self, expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        let mut reader = BufReader::new(expanded_in);
+        let mut writer = BufWriter::new(compressed_out);
+        let opt = self.opt.clone();
+        compress_core(&mut reader,&mut writer,&opt,self)
+    }
+    /// Same as the module level `compress_slice`, using the options this was created with
+    pub fn compress_slice(&mut self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        let mut src = Cursor::new(slice);
+        let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(slice.len()));
+        let opt = self.opt.clone();
+        compress_core(&mut src,&mut ans,&opt,self)?;
+        Ok(ans.into_inner())
+    }
+}
+
+impl Expander {
+    /// Returns `Error::InvalidOptions` if the options are inconsistent, see `Options::validate`
+    pub fn try_new(opt: &Options) -> Result<Self,crate::Error> {
+        opt.validate()?;
+        Ok(Self::new(opt))
+    }
+    pub fn new(opt: &Options) -> Self {
+        Self {
+            opt: opt.clone(),
+            lzss: LZSS::create(opt.clone()),
+            huff: AdaptiveHuffmanDecoder::create(256 + opt.lookahead - opt.threshold,opt.huffman_update,opt.huffman_rescale_divisor,opt.huffman_max_freq,&opt.huffman_initial_freq),
+            trace: None,
+            out: Vec::new(),
+            stopped: None,
+            index: Vec::new()
+        }
+    }
+    /// Put everything back in the initial state, this happens automatically for each expansion.
+    /// This also forgets the checkpoints given to `set_index`.
+    pub fn reset(&mut self) {
+        self.lzss.reset();
+        self.huff.reset();
+        self.stopped = None;
+        self.index.clear();
+    }
+    /// Use the checkpoints that `compress_indexed` returned for this stream in the ranges that follow
+    pub fn set_index(&mut self,index: Vec<Checkpoint>) {
+        self.index = index;
+    }
+    /// Same as the module level `expand`, using the options this was created with
+    pub fn expand<R,W>(&mut self, compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        self.expand_dyn(compressed_in,expanded_out)
+    }
+    /// Same as `expand` with the streams as trait objects
+    pub fn expand_dyn(&mut self, compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        let mut reader = BufReader::new(compressed_in);
+        let mut writer = BufWriter::new(expanded_out);
+        let opt = self.opt.clone();
+        expand_core(&mut reader,&mut writer,&opt,u64::MAX,self)
+    }
+    /// Same as the module level `expand_slice`, using the options this was created with
+    pub fn expand_slice(&mut self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        let mut src = Cursor::new(slice);
+        let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(slice_capacity(slice,&self.opt)));
+        let opt = self.opt.clone();
+        expand_core(&mut src,&mut ans,&opt,u64::MAX,self)?;
+        Ok(ans.into_inner())
+    }
+    /// Same as the module level `expand_range`, using the options this was created with and the checkpoints
+    /// given to `set_index`, except that if `start` is not before where the previous range stopped, and there
+    /// is no later checkpoint, decoding continues from there, so reading a stream in order decodes it only once.
+    /// A stream with more than one member always starts over.  Every call has to be on the same stream,
+    /// use `reset` before moving to another.
+    pub fn expand_range<R,W>(&mut self, compressed_in: &mut R, expanded_out: &mut W, start: u64, len: u64) -> Result<u64,DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        let opt = self.opt.clone();
+        let mut range_opt = opt.clone();
+        range_opt.out_offset = 0;
+        let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+        let stopped = self.stopped.take().filter(|progress| progress.pos <= start);
+        let multi = opt.header && opt.large_input == LargeInput::MultiMember;
+        let checkpoint = self.index.iter().rev()
+            .filter(|_| !multi)
+            .find_map(|cp| cp.state.as_ref().filter(|_| cp.expanded_offset <= start).map(|snapshot| (cp,snapshot)))
+            .filter(|(cp,_)| stopped.is_none_or(|progress| cp.expanded_offset > progress.pos));
+        let resume = match checkpoint {
+            Some((checkpoint,snapshot)) => Some(restore(&mut reader,&range_opt,checkpoint,snapshot,&mut self.lzss,&mut self.huff)?),
+            None => stopped
+        };
+        let range = match resume {
+            Some(progress) => {
+                log::debug!("continue expanding at byte {}",progress.pos);
+                let mut window = RangeWriter::new(start - progress.pos,len);
+                let stop = progress.pos.saturating_add(window.end());
+                expand_member(&mut reader,&mut window,&range_opt,stop,None,Some(progress),self)?;
+                window.into_inner()
+            },
+            _ => {
+                let mut window = RangeWriter::new(start,len);
+                let stop = window.end();
+                expand_core(&mut reader,&mut window,&range_opt,stop,self)?;
+                window.into_inner()
+            }
+        };
+        crate::place_output(expanded_out,opt.out_offset,opt.offset_gap)?;
+        expanded_out.write_all(&range)?;
+        Ok(range.len() as u64)
+    }
+    /// Same as the module level `expand_into`, using the options this was created with
+    pub fn expand_into(&mut self, slice: &[u8], buf: &mut [u8]) -> Result<usize,DYNERR> {
+        self.expand_reader_into(&mut Cursor::new(slice),buf)
+    }
+    /// Same as the module level `expand_reader_into`, using the options this was created with.
+    /// The window and Huffman tree are reset in place rather than allocated again.
+    pub fn expand_reader_into<R: Read + Seek>(&mut self, compressed_in: &mut R, buf: &mut [u8]) -> Result<usize,DYNERR> {
+        let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+        let mut ans = Cursor::new(buf);
+        let opt = self.opt.clone();
+        let (_,out_size) = expand_core(&mut reader,&mut ans,&opt,u64::MAX,self).map_err(crate::buffer_full)?;
+        Ok(out_size as usize)
+    }
+}
+
 /// Main compression function.
 /// `expanded_in` is an object with `Read` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<&[u8]>`.
 /// `compressed_out` is an object with `Write` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<Vec<u8>>`.
-/// Returns (in_size,out_size) or error, can panic if offsets are out of range.
+/// Returns (in_size,out_size) or error, which is `Error::InvalidOptions` if the options are inconsistent.
+/// Can panic if offsets are out of range.
 pub fn compress<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
 where R: Read + Seek, W: Write + Seek {
-    let mut reader = BufReader::new(expanded_in);
-    let mut writer = BufWriter::new(compressed_out);
-    let expanded_length = reader.seek(SeekFrom::End(0))? - opt.in_offset;
-    if expanded_length >= opt.max_file_size {
+    compress_dyn(expanded_in,compressed_out,opt)
+}
+
+/// Same as `compress` with the streams as trait objects
+pub fn compress_dyn(expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    Compressor::try_new(opt)?.compress_dyn(expanded_in,compressed_out)
+}
+
+/// Compress bytes from an iterator, items can be `u8` or `std::io::Result<u8>`.
+/// The data is consumed as it is compressed, there is no need to collect it first.
+/// `opt.in_offset` is the number of items to skip.  Returns (in_size,out_size).
+pub fn compress_iter<I,W>(expanded_in: I, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where I: IntoIterator, I::Item: ByteItem, W: Write + Seek {
+    let mut reader = IterReader::new(expanded_in);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+    compress_core(&mut reader,&mut writer,opt,&mut Compressor::try_new(opt)?)
+}
+
+/// Same as `compress`, with the same output, except that a checkpoint is recorded whenever `interval` bytes
+/// of input have been encoded since the last one, so that `expand_range` can start expanding near any position.
+/// Each checkpoint after the first holds a copy of the window and Huffman tree.  Returns (in_size,out_size,index).
+/// Returns `Error::InvalidOptions` if the options are inconsistent, or allow more than one member.
+pub fn compress_indexed<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options, interval: u64) -> Result<(u64,u64,Vec<Checkpoint>),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    if opt.header && opt.large_input == LargeInput::MultiMember {
+        log::error!("indexing needs a single member");
+        return Err(Box::new(crate::Error::InvalidOptions("indexing needs a single member".to_string())));
+    }
+    let mut state = Compressor::try_new(opt)?;
+    state.interval = Some(interval);
+    let (in_size,out_size) = state.compress(expanded_in,compressed_out)?;
+    Ok((in_size,out_size,state.index))
+}
+
+/// Compress to an output that only needs `Write`, such as stdout or a socket, with memory use
+/// that does not depend on the size of the input.  The header is written first, so if there is one,
+/// the length of `expanded_in` has to be found by seeking to its end.
+/// `opt.out_offset` has no effect.  Returns (in_size,out_size).
+pub fn compress_stream<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write {
+    let mut reader = BufReader::new(expanded_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn Write);
+    stream_split(&mut reader,&mut writer,opt,&mut Compressor::try_new(opt)?,header_max(opt))
+}
+
+/// Output that counts the bytes going through it, so the size is known without seeking
+struct Counter<'a,W: Write> {
+    inner: &'a mut W,
+    count: u64
+}
+
+impl <W: Write> Write for Counter<'_,W> {
+    fn write(&mut self,buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Same as `compress_split` with an output that is never rewound, each header goes out ahead of its member.
+fn stream_split<R,W>(reader: &mut R, writer: &mut W, opt: &Options, state: &mut Compressor, member_size: u64) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write {
+    crate::seek_input(reader,opt.in_offset)?;
+    let expanded_length = known_end(reader)?.map(|end| end - opt.in_offset);
+    match expanded_length {
+        Some(len) => {
+            if len >= opt.max_file_size {
+                return Err(Box::new(crate::Error::FileTooLarge));
+            }
+            if opt.header && len > member_size && opt.large_input == LargeInput::Error {
+                log::error!("{} bytes will not fit in the header",len);
+                return Err(Box::new(crate::Error::FileTooLarge));
+            }
+        },
+        None if opt.header => {
+            log::error!("the header needs the length of the input");
+            return Err(Box::new(std::io::Error::new(ErrorKind::Unsupported,"input length is unknown")));
+        },
+        None => {}
+    }
+    let multi = opt.header && opt.large_input == LargeInput::MultiMember;
+    let limit = match multi {
+        true => member_size,
+        false => opt.max_file_size
+    };
+    reader.seek(SeekFrom::Start(opt.in_offset))?;
+    let mut out = Counter { inner: writer, count: 0 };
+    let mut in_size = 0;
+    loop {
+        let remaining = expanded_length.map(|len| u64::min(len - in_size,limit));
+        if let (true,Some(member_len)) = (opt.header,remaining) {
+            out.write_all(&header_bytes(opt,member_len))?;
+        }
+        let mut member_in: u64 = 0;
+        #[cfg(feature="tracing")]
+        let _span = tracing::debug_span!("lzss_huff_compress",in_offset=opt.in_offset+in_size,out_offset=out.count).entered();
+        let mut bytes = reader.by_ref().take(limit).bytes().inspect(|_| member_in += 1);
+        encode_member(&mut bytes,&mut out,opt,state)?;
+        drop(bytes);
+        if opt.header && Some(member_in) != remaining {
+            log::error!("input changed length while it was compressed");
+            return Err(Box::new(std::io::Error::new(ErrorKind::UnexpectedEof,"input changed length")));
+        }
+        log::debug!("member compressed {}",member_in);
+        in_size += member_in;
+        if !multi || Some(in_size) == expanded_length {
+            break;
+        }
+    }
+    if in_size >= opt.max_file_size {
         return Err(Box::new(crate::Error::FileTooLarge));
     }
+    out.flush()?;
+    Ok((in_size,out.count))
+}
+
+/// Compression working directly on the streams, buffering is up to the caller
+fn compress_core<R,W>(reader: &mut R, writer: &mut W, opt: &Options, state: &mut Compressor) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    compress_split(reader,writer,opt,state,header_max(opt))
+}
+
+/// Compress, splitting into members of `member_size` if needed and allowed by the options.
+/// Returns (in_size,out_size) for all members together.
+/// The length of the input is not needed in advance, so this also works with `IterReader`.
+fn compress_split<R,W>(reader: &mut R, writer: &mut W, opt: &Options, state: &mut Compressor, member_size: u64) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    crate::seek_input(reader,opt.in_offset)?;
+    let expanded_length = known_end(reader)?.map(|end| end - opt.in_offset);
+    if let Some(len) = expanded_length {
+        if len >= opt.max_file_size {
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+        if opt.header && len > member_size && opt.large_input == LargeInput::Error {
+            log::error!("{} bytes will not fit in the header",len);
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+    }
+    if !opt.header || opt.large_input != LargeInput::MultiMember {
+        let (in_size,out_size) = compress_member(reader,writer,opt,state,opt.max_file_size)?;
+        if opt.header && in_size > member_size && opt.large_input == LargeInput::Error {
+            log::error!("{} bytes will not fit in the header",in_size);
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+        if in_size >= opt.max_file_size {
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+        return Ok((in_size,out_size));
+    }
+    let mut member_opt = opt.clone();
+    let mut in_size = 0;
+    let mut out_size = 0;
+    loop {
+        reader.seek(SeekFrom::Start(member_opt.in_offset))?;
+        if in_size > 0 && reader.fill_buf()?.is_empty() {
+            break;
+        }
+        if in_size >= opt.max_file_size {
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+        let (member_in,member_out) = compress_member(reader,writer,&member_opt,state,member_size)?;
+        log::debug!("member compressed {} into {}",member_in,member_out);
+        member_opt.in_offset += member_in;
+        member_opt.out_offset += member_out;
+        in_size += member_in;
+        out_size += member_out;
+    }
+    Ok((in_size,out_size))
+}
+
+/// Compress at most `limit` bytes as a single member, returns (in_size,out_size).
+/// The header is filled in after the data is consumed.
+fn compress_member<R,W>(reader: &mut R, writer: &mut W, opt: &Options, state: &mut Compressor, limit: u64) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("lzss_huff_compress",in_offset=opt.in_offset,out_offset=opt.out_offset).entered();
     reader.seek(SeekFrom::Start(opt.in_offset))?;
-    writer.seek(SeekFrom::Start(opt.out_offset))?;
-    // write the 32-bit header with length of expanded data
+    crate::place_output(writer,opt.out_offset,opt.offset_gap)?;
+    // placeholder for the header with length of expanded data
+    writer.write_all(&header_bytes(opt,0))?;
+    let mut expanded_length: u64 = 0;
+    let mut bytes = reader.take(limit).bytes().inspect(|_| expanded_length += 1);
+    encode_member(&mut bytes,writer,opt,state)?;
+    drop(bytes);
     if opt.header {
-        let header = u32::to_le_bytes(expanded_length as u32);
-        writer.write(&header)?;
+        writer.seek(SeekFrom::Start(opt.out_offset))?;
+        writer.write_all(&header_bytes(opt,expanded_length))?;
+    }
+    writer.seek(SeekFrom::End(0))?; // header could have been written behind us
+    writer.flush()?;
+    Ok((expanded_length,writer.stream_position()? - opt.out_offset))
+}
+
+/// Encode everything `bytes` yields as one member without its header, ending with the partial byte.
+/// Only writes go to the output, so this is all that is needed when the header can come first.
+fn encode_member<I,W>(bytes: &mut I, writer: &mut W, opt: &Options, state: &mut Compressor) -> Result<(),DYNERR>
+where I: Iterator<Item = std::io::Result<u8>>, W: Write {
+    // bytes read and their sum, which run ahead of the encoding by the lookahead
+    let read = std::cell::Cell::new(0u64);
+    let sum = std::cell::Cell::new(0u8);
+    let bytes = &mut bytes.inspect(|c| if let Ok(c) = c {
+        read.set(read.get() + 1);
+        sum.set(byte_sum(sum.get(),&[*c]));
+    });
+    let writer = &mut Counter { inner: writer, count: 0 };
+    state.reset();
+    let lzss = &mut state.lzss;
+    let huff = &mut state.huff;
+    let index = &mut state.index;
+    if state.interval.is_some() {
+        index.push(Checkpoint { expanded_offset: 0, compressed_bit: 8 * header_len(opt) as u64, state: None });
     }
-    // init
-    let mut bytes = reader.bytes();
-    let mut lzss = LZSS::create(opt.clone());
-    let mut huff = AdaptiveHuffmanCoder::create(256 + opt.lookahead - opt.threshold);
+    let mut last_checkpoint = 0;
     // setup dictionary
     let start_pos = opt.window_size - opt.lookahead;
-    for i in 0..start_pos {
-        lzss.dictionary.set(i as i64,opt.precursor);
-    }
+    lzss.prime_dictionary();
     let mut len = 0;
-    lzss.dictionary.set_pos(start_pos);
     while len < opt.lookahead {
         match bytes.next() {
             Some(Ok(c)) => {
@@ -265,131 +979,525 @@ where R: Read + Seek, W: Write + Seek {
             }
         }
     }
+    lzss.index_preset()?;
     for _i in 1..=opt.lookahead {
         lzss.dictionary.retreat();
         lzss.insert_node()?;
     }
     lzss.dictionary.set_pos(start_pos);
     lzss.insert_node()?;
-    // main compression loop
-    loop {
+    #[cfg(feature="tracing")]
+    let mut rebuilds = 0;
+    // main compression loop, nothing is encoded if the input is empty
+    while len > 0 {
+        #[cfg(feature="tracing")]
+        if huff.rebuilds() > rebuilds {
+            rebuilds = huff.rebuilds();
+            tracing::debug!(rebuild=rebuilds,"lzss_huff tree rebuilt");
+        }
+        let done = read.get() - len as u64;
+        if state.interval.is_some_and(|n| done - last_checkpoint >= n) {
+            let ahead = (0..len as i64).map(|i| lzss.dictionary.get(i)).fold(0,u8::wrapping_add);
+            index.push(Checkpoint {
+                expanded_offset: done,
+                compressed_bit: 8 * (header_len(opt) as u64 + writer.count) + huff.pending() as u64,
+                state: Some(Snapshot { dictionary: lzss.dictionary.clone(), tree: huff.tree_state(), sum: sum.get().wrapping_sub(ahead) })
+            });
+            last_checkpoint = done;
+        }
         if lzss.match_length > len {
             lzss.match_length = len;
         }
+        let mut steps = lzss.match_length;
         if lzss.match_length <= opt.threshold {
-            lzss.match_length = 1;
-            huff.encode_char(lzss.dictionary.get(0) as u16,&mut writer);
+            steps = 1;
+            huff.encode_char(lzss.dictionary.get(0) as u16,writer)?;
         } else {
-            huff.encode_char((255-opt.threshold+lzss.match_length) as u16,&mut writer);
-            huff.encode_position(lzss.match_offset as u16,&mut writer);
-        }
-        let last_match_length = lzss.match_length;
-        let mut i = 0;
-        while i < last_match_length {
-            let c = match bytes.next() {
-                Some(Ok(c)) => c,
-                None => break,
-                Some(Err(e)) => return Err(Box::new(e))
-            };
-            lzss.delete_node(opt.lookahead as i64)?;
-            lzss.dictionary.set(opt.lookahead as i64,c);
-            lzss.dictionary.advance();
-            lzss.insert_node()?;
-            i += 1;
-        }
-        while i < last_match_length {
-            lzss.delete_node(opt.lookahead as i64)?;
-            lzss.dictionary.advance();
-            len -= 1;
-            if len > 0 {
-                lzss.insert_node()?;
+            let (offset,length) = (lzss.match_offset,lzss.match_length);
+            if length < opt.lazy_length {
+                // see if the next position has a longer match before committing
+                lzss.slide(bytes,&mut len)?;
+                steps -= 1;
+                if lzss.match_length > len {
+                    lzss.match_length = len;
+                }
+                if lzss.match_length > length {
+                    huff.encode_char(lzss.dictionary.get(-1) as u16,writer)?;
+                    continue;
+                }
             }
-            i += 1;
+            huff.encode_char((255-opt.threshold+length) as u16,writer)?;
+            huff.encode_position(offset as u16,writer)?;
         }
-        if len <= 0 {
-            break;
+        for _i in 0..steps {
+            lzss.slide(bytes,&mut len)?;
         }
     }
-    writer.seek(SeekFrom::End(0))?; // coder could be rewound
-    writer.flush()?;
-    Ok((expanded_length,writer.stream_position()? - opt.out_offset))
+    huff.finish(writer)?;
+    if opt.checksum_trailer {
+        writer.write_all(&[sum.get()])?;
+    }
+    Ok(())
+}
+
+/// Compress a file by mapping it into memory, avoiding the seeking and buffering that `compress` does on the input.
+/// Offsets in `opt` are relative to the start of the file.
+/// The file must not be modified or truncated, by this process or any other, while this is running,
+/// the result of that is undefined behavior rather than an error, e.g. truncation can end the process with `SIGBUS`.
+#[cfg(feature="mmap")]
+pub fn compress_mapped<W: Write + Seek>(expanded_in: &std::fs::File, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    // SAFETY: the map is only read, and dropped before returning.  It stays valid only if nothing modifies
+    // or truncates the file while it is mapped, which the caller is required to ensure, see above.
+    let map = unsafe { memmap2::Mmap::map(expanded_in)? };
+    let mut reader = Cursor::new(&map[..]);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+    compress_core(&mut reader,&mut writer,opt,&mut Compressor::try_new(opt)?)
 }
 
 /// Main decompression function.
 /// `compressed_in` is an object with `Read` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<&[u8]>`.
 /// `expanded_out` is an object with `Write` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<Vec<u8>>`.
-/// Returns (in_size,out_size) or error, can panic if offsets are out of range.
+/// Returns (in_size,out_size) or error, which is `Error::InvalidOptions` if the options are inconsistent.
+/// Can panic if offsets are out of range.
+/// The in_size is the number of compressed bytes consumed, which is short of the end of the stream
+/// if the header size is reached with data left over.
 pub fn expand<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
 where R: Read + Seek, W: Write + Seek {
-    let mut reader = BufReader::new(compressed_in);
-    let mut writer = BufWriter::new(expanded_out);
+    expand_dyn(compressed_in,expanded_out,opt)
+}
+
+/// Same as `expand` with the streams as trait objects
+pub fn expand_dyn(compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    Expander::try_new(opt)?.expand_dyn(compressed_in,expanded_out)
+}
+
+/// Expand, handing the output to `callback` in pieces of at most `chunk_size` bytes, rather than writing it.
+/// `opt.out_offset` has no effect.  Returns (in_size,out_size).  Panics if `chunk_size` is 0.
+pub fn expand_chunked<R,F>(compressed_in: &mut R, opt: &Options, chunk_size: usize, mut callback: F) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, F: FnMut(&[u8]) {
+    let mut writer = ChunkWriter::new(opt.out_offset,chunk_size,&mut callback);
+    let ans = expand_dyn(compressed_in,&mut writer,opt)?;
+    writer.flush()?;
+    Ok(ans)
+}
+
+/// Expand and discard the output, recording every token, to find out where and why a stream goes wrong.
+/// The tokens decoded before any error are returned, along with the outcome of the expansion,
+/// which is (in_size,out_size) or the error.
+pub fn expand_traced<R: Read + Seek>(compressed_in: &mut R, opt: &Options) -> (Vec<TraceEvent>,Result<(u64,u64),DYNERR>) {
+    let mut discard = |_: &[u8]| {};
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = ChunkWriter::new(opt.out_offset,DISCARD_CHUNK,&mut discard);
+    let mut state = match Expander::try_new(opt) {
+        Ok(state) => state,
+        Err(e) => return (Vec::new(),Err(Box::new(e)))
+    };
+    state.trace = Some(Vec::new());
+    let res = expand_core(&mut reader,&mut writer,opt,u64::MAX,&mut state);
+    (state.trace.take().unwrap_or_default(),res)
+}
+
+/// Expand `len` bytes starting at `start` (relative to the start of the expanded data).
+/// If `index` is from `compress_indexed`, decoding begins at the last checkpoint at or before `start`,
+/// otherwise (e.g. if `index` is empty) it begins at the start.  Decoding stops as soon as the range is complete,
+/// and only the range is kept.  The bytes are written at `opt.out_offset`, returns the number written, which is less than `len`
+/// if the data ends sooner.
+pub fn expand_range<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options, index: &[Checkpoint], start: u64, len: u64) -> Result<u64,DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut expander = Expander::try_new(opt)?;
+    expander.index = index.iter().rev().find(|cp| cp.expanded_offset <= start).into_iter().cloned().collect();
+    expander.expand_range(compressed_in,expanded_out,start,len)
+}
+
+/// Put the dictionary and tree in the state `snapshot` recorded at `checkpoint`, leaving `reader` where
+/// expansion continues.  Returns the progress for `expand_member` to resume with.
+fn restore<R: BufRead + Seek>(reader: &mut R, opt: &Options, checkpoint: &Checkpoint, snapshot: &Snapshot, lzss: &mut LZSS, huff: &mut AdaptiveHuffmanDecoder) -> Result<Progress,DYNERR> {
+    log::debug!("start expanding at bit {}",checkpoint.compressed_bit);
+    reader.seek(SeekFrom::Start(opt.in_offset))?;
+    let declared = match opt.header && opt.header_size != HeaderSize::Ignore {
+        true => {
+            let mut header: [u8;8] = [0;8];
+            let header = &mut header[0..header_len(opt)];
+            crate::read_header(reader,header)?;
+            header_value(opt,header)
+        },
+        false => u64::MAX
+    };
+    reader.seek(SeekFrom::Start(opt.in_offset + checkpoint.compressed_bit / 8))?;
+    lzss.dictionary.clone_from(&snapshot.dictionary);
+    huff.restore(&snapshot.tree,(checkpoint.compressed_bit % 8) as usize,reader)?;
+    huff.release(reader);
+    Ok(Progress {
+        in_pos: reader.stream_position()?,
+        header_bits: 8 * (checkpoint.compressed_bit / 8),
+        declared,
+        pos: checkpoint.expanded_offset,
+        pending: 0,
+        sum: snapshot.sum
+    })
+}
+
+/// Decompression working directly on the streams, buffering is up to the caller.
+/// Expansion stops once `stop` bytes are written, or sooner if the header or data says so.
+fn expand_core<R,W>(reader: &mut R, writer: &mut W, opt: &Options, stop: u64, state: &mut Expander) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    crate::seek_input(reader,opt.in_offset)?;
+    if !opt.header || opt.large_input != LargeInput::MultiMember {
+        return expand_member(reader,writer,opt,stop,None,None,state);
+    }
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut member_opt = opt.clone();
+    let mut out_size = 0;
+    while member_opt.in_offset < end && out_size < stop {
+        let (member_in,member_out) = expand_member(reader,writer,&member_opt,stop - out_size,None,None,state)?;
+        member_opt.in_offset += member_in;
+        member_opt.out_offset += member_out;
+        out_size += member_out;
+    }
+    // where a member stopped is not where the stream stopped
+    state.stopped = None;
+    Ok((member_opt.in_offset - opt.in_offset,out_size))
+}
+
+/// Expand a single member, stopping once `stop` bytes are written, or sooner if the header or data says so.
+/// If `size` is given it stands in for the header, which is not read.
+/// If `resume` is given, the member continues from there with the dictionary and tree in `state`.
+fn expand_member<R,W>(reader: &mut R, writer: &mut W, opt: &Options, stop: u64, size: Option<u64>, resume: Option<Progress>, state: &mut Expander) -> Result<(u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("lzss_huff_expand",in_offset=opt.in_offset,out_offset=opt.out_offset).entered();
     let compressed_size = reader.seek(SeekFrom::End(0))? - opt.in_offset;
     if compressed_size > opt.max_file_size {
         return Err(Box::new(crate::Error::FileTooLarge));
     }
-    reader.seek(SeekFrom::Start(opt.in_offset))?;
-    writer.seek(SeekFrom::Start(opt.out_offset))?;
-    // get size of expanded data from 32 bit header or set to max
-    let max_expanded_size = match opt.header {
-        true => {
-            let mut header: [u8;4] = [0;4];
-            reader.read_exact(&mut header)?;
-            u32::from_le_bytes(header)
+    crate::place_output(writer,opt.out_offset,opt.offset_gap)?;
+    // whether the header decides where the data ends
+    let sized = (size.is_some() || opt.header) && opt.header_size != HeaderSize::Ignore;
+    if opt.checksum_trailer && !sized {
+        log::error!("the checksum trailer needs the expanded size");
+        return Err(Box::new(std::io::Error::new(ErrorKind::Unsupported,"expanded size is unknown")));
+    }
+    let progress = match resume {
+        Some(progress) => {
+            reader.seek(SeekFrom::Start(progress.in_pos))?;
+            // the last match went past where the expansion stopped, the rest of it is written first
+            let tail: Vec<u8> = (1..=progress.pending as i64).rev().map(|k| state.lzss.dictionary.get(-k)).collect();
+            writer.write_all(&tail)?;
+            progress
+        },
+        None => {
+            reader.seek(SeekFrom::Start(opt.in_offset))?;
+            // get size of expanded data from header or set to max
+            let declared = match (size,opt.header) {
+                (Some(size),_) => size,
+                (None,true) => {
+                    let mut header: [u8;8] = [0;8];
+                    let header = &mut header[0..header_len(opt)];
+                    crate::read_header(reader,header)?;
+                    header_value(opt,header)
+                },
+                (None,false) => u64::MAX
+            };
+            // init
+            state.lzss.reset();
+            state.huff.reset();
+            state.lzss.prime_dictionary();
+            Progress {
+                in_pos: reader.stream_position()?,
+                header_bits: 8*(reader.stream_position()? - opt.in_offset),
+                declared: match sized {
+                    true => declared,
+                    false => u64::MAX
+                },
+                pos: 0,
+                pending: 0,
+                sum: 0
+            }
         }
-       false => u32::MAX
     };
-    // init
-    let mut huff = AdaptiveHuffmanDecoder::create(256 + opt.lookahead - opt.threshold);
-    let mut lzss= LZSS::create(opt.clone());
-    let start_pos = opt.window_size - opt.lookahead;
-	for i in 0..start_pos {
-		lzss.dictionary.set(i as i64,opt.precursor);
-    }
-    lzss.dictionary.set_pos(start_pos);
+    let huff = &mut state.huff;
+    let lzss = &mut state.lzss;
+    let mut trace = state.trace.as_mut();
+    let out = &mut state.out;
+    out.clear();
     // start expanding
-	while writer.stream_position()? < max_expanded_size as u64 {
-		let c = match huff.decode_char(&mut reader) {
-            Ok(c) => c,
-            Err(e) if e.kind()==ErrorKind::UnexpectedEof => break,
-            Err(e) => return Err(Box::new(e))
+    let Progress { header_bits, declared, pos, pending, mut sum, .. } = progress;
+    let mut pos = pos + pending;
+    let max_expanded_size = u64::min(declared,stop);
+    // bit where the data went past the size in the header
+    let mut overlong_bit = None;
+	while pos < max_expanded_size {
+        let bit = header_bits + huff.bits_read();
+        let rebuilds = huff.rebuilds();
+		let c = huff.decode_char(reader)?;
+		let position = match c < 256 {
+            true => None,
+            false => Some(huff.decode_position(reader)?)
         };
-		if c < 256 {
-            writer.write(&[c as u8])?;
-			lzss.dictionary.set(0,c as u8);
-            lzss.dictionary.advance();
-		} else {
-			let offset = match huff.decode_position(&mut reader) {
-                Ok(pos) => - (pos as i64 + 1),
-                Err(e) if e.kind()==ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(Box::new(e))
-            };    
-			let strlen = c as i64 + opt.threshold as i64 - 255;
-			for _k in 0..strlen {
-				let c8 = lzss.dictionary.get(offset);
-                writer.write(&[c8])?;
-                lzss.dictionary.set(0,c8 as u8);
+        let past_end = huff.bits_past_end();
+        if past_end > 0 {
+            // The token needed bits beyond the end, which were taken as zeros.
+            // Without a header the last byte is padded, so a token that starts there is only the end.
+            let padding = !sized && header_bits + huff.bits_read() - bit - past_end < 8;
+            match opt.truncation {
+                Truncation::Error if !padding => {
+                    log::error!("data ran out {} bits short after {} bytes were expanded",past_end,pos);
+                    return Err(Box::new(crate::Error::TruncatedInput { missing_bits: past_end, expanded: pos }));
+                },
+                Truncation::ZeroFill if sized => {},
+                _ if padding => break,
+                _ => {
+                    diagnostics::warn(Warning::Truncated { missing_bits: past_end });
+                    break
+                }
+            }
+        }
+		let (token,len) = match position {
+            None => {
+                out.push(c as u8);
+                lzss.dictionary.set(0,c as u8);
                 lzss.dictionary.advance();
+                (Token::Literal { byte: c as u8 },1)
+            },
+            Some(position) => {
+                let offset = - (position as i64 + 1);
+                let strlen = c as i64 + opt.threshold as i64 - 255;
+                for _k in 0..strlen {
+                    let c8 = lzss.dictionary.get(offset);
+                    out.push(c8);
+                    lzss.dictionary.set(0,c8);
+                    lzss.dictionary.advance();
+                }
+                (Token::Match { offset: (-offset) as u16, length: strlen as u16 },strlen as u64)
+            }
+		};
+        #[cfg(feature="tracing")]
+        if huff.rebuilds() > rebuilds {
+            tracing::debug!(rebuild=huff.rebuilds(),compressed_bit=header_bits+huff.bits_read(),expanded_offset=pos,"lzss_huff tree rebuilt");
+        }
+        if let Some(trace) = trace.as_mut() {
+            let rebuilt = huff.rebuilds() > rebuilds;
+            trace.push(TraceEvent { bit, pos, token });
+            if rebuilt {
+                trace.push(TraceEvent { bit: header_bits + huff.bits_read(), pos, token: Token::Rebuild });
+            }
+        }
+        if sized && pos + len > declared {
+            // a match that runs past the size in the header is cut off there, unless it was made up of zero fill it is reported
+            if past_end == 0 {
+                overlong_bit = Some(bit);
             }
-		}
+            out.truncate(out.len() - (pos + len - declared) as usize);
+            pos = declared;
+            break;
+        }
+        pos += len;
+        if out.len() >= OUT_BLOCK {
+            sum = byte_sum(sum,out);
+            writer.write_all(out)?;
+            out.clear();
+        }
+    }
+    if sized && opt.truncation == Truncation::ZeroFill && huff.bits_past_end() > 0 {
+        diagnostics::warn(Warning::Truncated { missing_bits: huff.bits_past_end() });
+    }
+    // the caller may look for the next member where this one ends
+    huff.sync(reader);
+    sum = byte_sum(sum,out);
+    writer.write_all(out)?;
+    out.clear();
+    writer.flush()?;
+    if opt.checksum_trailer && pos == declared {
+        let mut trailer = [0];
+        match reader.read_exact(&mut trailer) {
+            Ok(()) if trailer[0] == sum => {},
+            Ok(()) => {
+                log::error!("checksum trailer mismatch, expected {}, got {}",sum,trailer[0]);
+                return Err(Box::new(crate::Error::BadChecksum));
+            },
+            Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
+                log::error!("data ended before the checksum trailer");
+                return Err(Box::new(crate::Error::TruncatedInput { missing_bits: 8, expanded: pos }));
+            },
+            Err(e) => return Err(Box::new(e))
+        }
+    }
+    if sized && opt.header_size == HeaderSize::Require && pos < max_expanded_size {
+        log::error!("header gives {} bytes, but the data expands to {}",max_expanded_size,pos);
+        return Err(Box::new(crate::Error::SizeMismatch { expected: max_expanded_size, actual: pos }));
+    }
+    let in_size = reader.stream_position()? - opt.in_offset;
+    // with more than one member, whatever follows is the next member,
+    // and a stream embedded in another format is followed by the rest of that format
+    let excess = match (sized && pos == declared,opt.large_input) {
+        _ if size.is_some() => 0,
+        (true,LargeInput::MultiMember) => 0,
+        (true,_) => compressed_size - in_size,
+        (false,_) => 0
+    };
+    if excess > 0 && overlong_bit.is_none() {
+        overlong_bit = Some(8 * in_size);
+    }
+    if let Some(bit) = overlong_bit {
+        if opt.header_size == HeaderSize::Require {
+            log::error!("data goes on past the {} bytes in the header at bit {}",declared,bit);
+            return Err(Box::new(crate::Error::Overlong { expected: declared, bit, excess }));
+        }
+        diagnostics::warn(Warning::Overlong { expected: declared, bit, excess });
+    }
+    // if it was only stopped, the expansion can continue from here
+    state.stopped = None;
+    if pos >= stop && pos < declared {
+        state.huff.release(reader);
+        state.stopped = Some(Progress { in_pos: reader.stream_position()?, header_bits, declared, pos: stop, pending: pos - stop, sum });
+    }
+    Ok((in_size,pos))
+}
+
+/// Expand a stream that has no header, such as one embedded in a format that stores the expanded size
+/// elsewhere.  `expanded_size` stands in for the header, so `opt.header_size` and `opt.truncation` apply
+/// as if it had been read from one, except that whatever follows the data is left alone; `opt.header` is not used.
+/// Returns (in_size,out_size).
+pub fn expand_raw<R,W>(compressed_in: &mut R, expanded_out: &mut W, expanded_size: u64, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(expanded_out as &mut dyn WriteSeek);
+    crate::seek_input(&mut reader,opt.in_offset)?;
+    expand_member(&mut reader,&mut writer,opt,u64::MAX,Some(expanded_size),None,&mut Expander::try_new(opt)?)
+}
+
+/// Compress without a header, whatever `opt.header` says, for embedding in a format that stores the
+/// expanded size elsewhere.  The in_size that is returned is what `expand_raw` needs.  Returns (in_size,out_size).
+pub fn compress_raw<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    compress(expanded_in,compressed_out,&Options { header: false, ..opt.clone() })
+}
+
+/// Expand and discard the output, to check the integrity of the compressed data.
+/// Returns (in_size,out_size,complete), where `complete` is false if the data ran out before
+/// the size given in the header was reached, which usually means it was truncated.
+/// Without a header (`opt.header` false) the end cannot be detected, and `complete` is always true.
+/// The data is expanded as if `opt.truncation` were `Stop`, so that truncation is reported this way.
+pub fn verify<R: Read + Seek>(compressed_in: &mut R, opt: &Options) -> Result<(u64,u64,bool),DYNERR> {
+    let expected = peek_expanded_size(compressed_in,opt)?;
+    let opt = Options { truncation: Truncation::Stop, ..opt.clone() };
+    let (in_size,out_size) = expand_chunked(compressed_in,&opt,DISCARD_CHUNK,|_| {})?;
+    Ok((in_size,out_size,expected.is_none_or(|n| out_size >= n)))
+}
+
+/// Read the expanded size from the header without decoding anything.
+/// Returns `None` if `opt.header` is false, since then the size is unknown.
+/// With `LargeInput::MultiMember` this is only the size of the first member.
+/// The position of `compressed_in` is restored afterward.
+pub fn peek_expanded_size<R: Read + Seek>(compressed_in: &mut R, opt: &Options) -> Result<Option<u64>,DYNERR> {
+    if !opt.header {
+        return Ok(None);
+    }
+    let save = compressed_in.stream_position()?;
+    compressed_in.seek(SeekFrom::Start(opt.in_offset))?;
+    let mut header: [u8;8] = [0;8];
+    let header = &mut header[0..header_len(opt)];
+    let res = compressed_in.read_exact(header);
+    compressed_in.seek(SeekFrom::Start(save))?;
+    match res {
+        Ok(()) => Ok(Some(header_value(opt,header))),
+        Err(e) if e.kind()==ErrorKind::UnexpectedEof => Err(Box::new(crate::Error::FileFormatMismatch)),
+        Err(e) => Err(Box::new(e))
+    }
+}
+
+/// Compress and append the result to whatever is already in `compressed_out`, forming another member
+/// of a multi-member stream.  The member goes at the end of `compressed_out`, `opt.out_offset` is ignored.
+/// Returns (in_size,out_size) for the new member.
+pub fn compress_append<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut member_opt = opt.clone();
+    member_opt.out_offset = compressed_out.seek(SeekFrom::End(0))?;
+    compress(expanded_in,compressed_out,&member_opt)
+}
+
+/// Expand every member of a multi-member stream, concatenating the expanded data.
+/// The members must have headers, so that the end of each one can be found.
+/// Returns (in_size,out_size) for each member, or an error of kind `InvalidInput` if `opt.header` is false.
+pub fn expand_members<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<Vec<(u64,u64)>,DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    if !opt.header {
+        log::error!("multiple members require headers");
+        return Err(Box::new(std::io::Error::new(ErrorKind::InvalidInput,"multiple members require headers")));
+    }
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(expanded_out as &mut dyn WriteSeek);
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut member_opt = opt.clone();
+    let mut state = Expander::try_new(opt)?;
+    let mut ans = Vec::new();
+    while member_opt.in_offset < end {
+        let (in_size,out_size) = expand_member(&mut reader,&mut writer,&member_opt,u64::MAX,None,None,&mut state)?;
+        log::debug!("member {} expanded {} into {}",ans.len(),in_size,out_size);
+        ans.push((in_size,out_size));
+        member_opt.in_offset += in_size;
+        member_opt.out_offset += out_size;
     }
     writer.flush()?;
-    Ok((compressed_size,writer.stream_position()? - opt.out_offset))
+    Ok(ans)
 }
 
-/// Convenience function, calls `compress` with a slice returning a Vec
+/// Expand a slice into a buffer the caller provides, starting at `opt.out_offset`, so nothing is allocated
+/// for the output.  Returns the number of bytes expanded, or `Error::FileTooLarge` if they do not fit.
+pub fn expand_into(slice: &[u8],buf: &mut [u8],opt: &Options) -> Result<usize,DYNERR> {
+    Expander::try_new(opt)?.expand_into(slice,buf)
+}
+
+/// Same as `expand_into`, reading from a stream.  No buffering is added, so a file should be wrapped
+/// in `BufReader`.  Use `Expander::expand_reader_into` to avoid allocating the window on every call.
+pub fn expand_reader_into<R: Read + Seek>(compressed_in: &mut R,buf: &mut [u8],opt: &Options) -> Result<usize,DYNERR> {
+    Expander::try_new(opt)?.expand_reader_into(compressed_in,buf)
+}
+
+/// Upper bound on the size of the data produced by compressing `input_len` bytes (not counting `opt.out_offset`).
+/// This assumes the worst case for the Huffman code lengths, so it is larger than the input.
+/// The bound saturates at `usize::MAX` if it does not fit, as can happen on 32 bit targets.
+pub fn compress_bound(input_len: usize,opt: &Options) -> usize {
+    // worked in 128 bits so that no input length can overflow
+    let n = input_len as u128;
+    let num_symbols = 256 + opt.lookahead - opt.threshold;
+    // a literal takes one character code, a match takes a character code and a position code
+    // of at most 14 bits, but covers more than `threshold` bytes
+    let char_bits = max_code_len(num_symbols,opt.huffman_update,opt.huffman_max_freq,input_len as u64) as u128;
+    let bits = n*char_bits + 14*n.div_ceil(opt.threshold as u128 + 1);
+    let members = match (opt.header,opt.large_input) {
+        (true,LargeInput::MultiMember) => n.div_ceil(header_max(opt) as u128).max(1),
+        _ => 1
+    };
+    let header = members * (header_len(opt) + opt.checksum_trailer as usize) as u128;
+    usize::try_from(bits.div_ceil(8) + header).unwrap_or(usize::MAX)
+}
+
+/// Convenience function, compresses a slice returning a Vec.
+/// This works on the memory directly, skipping the buffering that `compress` adds.
 pub fn compress_slice(slice: &[u8],opt: &Options) -> Result<Vec<u8>,DYNERR> {
-    let mut src = Cursor::new(slice);
-    let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-    compress(&mut src,&mut ans,opt)?;
-    Ok(ans.into_inner())
+    Compressor::try_new(opt)?.compress_slice(slice)
 }
 
-/// Convenience function, calls `expand` with a slice returning a Vec
+/// Convenience function, expands a slice returning a Vec.
+/// This works on the memory directly, skipping the buffering that `expand` adds.
 pub fn expand_slice(slice: &[u8],opt: &Options) -> Result<Vec<u8>,DYNERR> {
-    let mut src = Cursor::new(slice);
-    let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-    expand(&mut src,&mut ans,opt)?;
-    Ok(ans.into_inner())
+    Expander::try_new(opt)?.expand_slice(slice)
+}
+
+/// guess the expanded size for preallocation, using the header if there is one
+fn slice_capacity(slice: &[u8],opt: &Options) -> usize {
+    let header = usize::try_from(opt.in_offset).ok().and_then(|beg| slice.get(beg..beg.checked_add(header_len(opt))?));
+    let capacity = match (opt.header,header) {
+        (true,Some(header)) => usize::try_from(header_value(opt,header)).unwrap_or(usize::MAX),
+        _ => 2*slice.len()
+    };
+    // the first member of many does not serve as a hint
+    let capacity = match opt.large_input {
+        LargeInput::MultiMember => 2*slice.len(),
+        _ => capacity
+    };
+    usize::min(capacity,slice.len().saturating_mul(8))
 }
 
 
@@ -418,5 +1526,669 @@ fn invertibility() {
     let test_data = "1234567".as_bytes();
     let compressed = compress_slice(test_data,&STD_OPTIONS).expect("compression failed");
     let expanded = expand_slice(&compressed,&STD_OPTIONS).expect("expansion failed");
-    assert_eq!(test_data.to_vec(),expanded[0..7]);
-}
\ No newline at end of file
+    assert_eq!(test_data.to_vec(),expanded);
+}
+
+#[test]
+fn tiny_inputs() {
+    for name in PRESETS {
+        let opt = Options { in_offset: 0, out_offset: 0, ..Options::preset(name).unwrap() };
+        for len in 0..5 {
+            let test_data = &b"abcd"[0..len];
+            let compressed = compress_slice(test_data,&opt).expect("compression failed");
+            assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data);
+        }
+    }
+    assert_eq!(compress_slice(&[],&STD_OPTIONS).expect("compression failed"),[0;4]);
+    // a cut in the size header is always an error, since the size is not known
+    for len in 0..4 {
+        match expand_slice(&[0;4][0..len],&STD_OPTIONS) {
+            Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits, .. }) if *missing_bits == 32 - 8*len as u64)),
+            Ok(_) => panic!("truncation was not detected")
+        }
+    }
+}
+
+#[test]
+fn invertibility_with_preset() {
+    let mut opt = STD_OPTIONS;
+    opt.preset_dictionary = "I do not like this Sam I am.".as_bytes().to_vec();
+    opt.preset_position = 1000;
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    let unprimed = compress_slice(test_data,&STD_OPTIONS).expect("compression failed");
+    assert!(compressed.len() < unprimed.len());
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+}
+
+#[test]
+fn invertibility_with_update_strategies() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    for strategy in [UpdateStrategy::Fgk,UpdateStrategy::Vitter] {
+        let mut opt = STD_OPTIONS;
+        opt.huffman_update = strategy;
+        let compressed = compress_slice(test_data,&opt).expect("compression failed");
+        let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+        assert_eq!(test_data.to_vec(),expanded);
+        let long_data: Vec<u8> = (0..20000).map(|x: u32| ((x*x/7) % 61) as u8 + b'A').collect();
+        let compressed = compress_slice(&long_data,&opt).expect("compression failed");
+        let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+        assert_eq!(long_data,expanded);
+    }
+}
+
+#[test]
+fn invertibility_with_huffman_prior() {
+    let mut opt = STD_OPTIONS;
+    opt.huffman_rescale_divisor = 4;
+    opt.huffman_initial_freq = vec![1;256 + opt.lookahead - opt.threshold];
+    for c in b'a'..=b'z' {
+        opt.huffman_initial_freq[c as usize] = 20;
+    }
+    opt.huffman_initial_freq[b' ' as usize] = 50;
+    let test_data = "i am sam sam i am i do not like this sam i am\n".as_bytes();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    let unprimed = compress_slice(test_data,&STD_OPTIONS).expect("compression failed");
+    assert!(compressed.len() < unprimed.len());
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+    // force several rebuilds
+    let long_data: Vec<u8> = (0..100000).map(|x: u64| ((x*x/7) % 26) as u8 + b'a').collect();
+    let compressed = compress_slice(&long_data,&opt).expect("compression failed");
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(long_data,expanded);
+    // inconsistent priors are an error rather than a panic
+    opt.huffman_initial_freq[0] = 0;
+    assert!(compress_slice(test_data,&opt).is_err());
+    assert!(expand_slice(&compressed,&opt).is_err());
+    assert!(Expander::try_new(&opt).is_err());
+    opt.huffman_initial_freq = Vec::new();
+    opt.huffman_rescale_divisor = 1;
+    assert!(Compressor::try_new(&opt).is_err());
+}
+
+#[test]
+fn slice_matches_stream() {
+    let opt = STD_OPTIONS;
+    let test_data: Vec<u8> = (0..5000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    let mut streamed = Cursor::new(Vec::new());
+    compress(&mut Cursor::new(&test_data),&mut streamed,&opt).expect("compression failed");
+    assert_eq!(compressed,streamed.into_inner());
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    let mut streamed = Cursor::new(Vec::new());
+    expand(&mut Cursor::new(&compressed),&mut streamed,&opt).expect("expansion failed");
+    assert_eq!(expanded,streamed.into_inner());
+    assert_eq!(expanded[0..test_data.len()],test_data);
+}
+
+#[cfg(feature="mmap")]
+#[test]
+fn mapped_matches_slice() {
+    let test_data: Vec<u8> = (0..5000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let mut file = tempfile::tempfile().expect("could not create file");
+    file.write_all(&test_data).expect("could not write file");
+    let mut compressed = Cursor::new(Vec::new());
+    compress_mapped(&file,&mut compressed,&STD_OPTIONS).expect("compression failed");
+    assert_eq!(compressed.into_inner(),compress_slice(&test_data,&STD_OPTIONS).expect("compression failed"));
+}
+
+#[test]
+fn multiple_members() {
+    let data1 = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let data2 = "12345123456789123456789\n".as_bytes();
+    let mut compressed = Cursor::new(Vec::new());
+    let (_,size1) = compress_append(&mut Cursor::new(data1),&mut compressed,&STD_OPTIONS).expect("compression failed");
+    let (_,size2) = compress_append(&mut Cursor::new(data2),&mut compressed,&STD_OPTIONS).expect("compression failed");
+    let mut expanded = Cursor::new(Vec::new());
+    let compressed = compressed.into_inner();
+    let sizes = expand_members(&mut Cursor::new(&compressed),&mut expanded,&STD_OPTIONS).expect("expansion failed");
+    assert_eq!(sizes,vec![(size1,data1.len() as u64),(size2,data2.len() as u64)]);
+    assert_eq!(expanded.into_inner(),[data1,data2].concat());
+    let err = expand_members(&mut Cursor::new(&compressed),&mut Cursor::new(Vec::new()),&Options { header: false, ..STD_OPTIONS }).expect_err("members cannot be found");
+    assert_eq!(err.downcast_ref::<std::io::Error>().map(|e| e.kind()),Some(ErrorKind::InvalidInput));
+}
+
+#[test]
+fn expand_partial_range() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    for (start,len) in [(0,10),(4321,300),(19990,100)] {
+        let mut range = Cursor::new(Vec::new());
+        let n = expand_range(&mut Cursor::new(&compressed),&mut range,&STD_OPTIONS,&[],start,len).expect("expansion failed");
+        let end = usize::min(test_data.len(),(start+len) as usize);
+        assert_eq!(n as usize,end - start as usize);
+        assert_eq!(range.into_inner(),test_data[start as usize..end]);
+    }
+    // ranges in order continue where the last one stopped, going back starts over
+    let mut expander = Expander::new(&STD_OPTIONS);
+    for (start,len) in [(0,1000),(1000,1001),(2001,3),(2500,17500),(10,20)] {
+        let mut range = Cursor::new(Vec::new());
+        let n = expander.expand_range(&mut Cursor::new(&compressed),&mut range,start,len).expect("expansion failed");
+        assert_eq!(n,len);
+        assert_eq!(range.into_inner(),test_data[start as usize..(start+len) as usize]);
+    }
+}
+
+#[test]
+fn expand_indexed_range() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let mut compressed = Cursor::new(Vec::new());
+    let (_,_,index) = compress_indexed(&mut Cursor::new(&test_data),&mut compressed,&STD_OPTIONS,1000).expect("compression failed");
+    assert!(index.len() >= 20);
+    assert_eq!(index[0],Checkpoint { expanded_offset: 0, compressed_bit: 32, state: None });
+    assert!(index.iter().any(|cp| cp.compressed_bit % 8 != 0));
+    let compressed = compressed.into_inner();
+    // indexing does not change the output
+    assert_eq!(compressed,compress_slice(&test_data,&STD_OPTIONS).expect("compression failed"));
+    for (start,len) in [(0,10),(4321,300),(12000,5000),(19990,100)] {
+        let mut range = Cursor::new(Vec::new());
+        let n = expand_range(&mut Cursor::new(&compressed),&mut range,&STD_OPTIONS,&index,start,len).expect("expansion failed");
+        let end = usize::min(test_data.len(),(start+len) as usize);
+        assert_eq!(n as usize,end - start as usize);
+        assert_eq!(range.into_inner(),test_data[start as usize..end]);
+    }
+    // nothing before the checkpoint is decoded
+    let cp = &index[index.len()/2];
+    let mut damaged = compressed.clone();
+    damaged[4..(cp.compressed_bit/8) as usize].fill(0);
+    let mut range = Cursor::new(Vec::new());
+    expand_range(&mut Cursor::new(&damaged),&mut range,&STD_OPTIONS,&index,cp.expanded_offset,100).expect("expansion failed");
+    assert_eq!(range.into_inner(),test_data[cp.expanded_offset as usize..cp.expanded_offset as usize + 100]);
+    // other trees, no header, checksum trailer, lazy matching
+    let mut opts = vec![Options { huffman_update: UpdateStrategy::Vitter, header: false, ..STD_OPTIONS }];
+    opts.push(Options { checksum_trailer: true, lazy_length: 20, ..STD_OPTIONS });
+    for opt in opts {
+        let mut compressed = Cursor::new(Vec::new());
+        let (_,_,index) = compress_indexed(&mut Cursor::new(&test_data),&mut compressed,&opt,700).expect("compression failed");
+        let compressed = compressed.into_inner();
+        assert_eq!(compressed,compress_slice(&test_data,&opt).expect("compression failed"));
+        for start in (0..20000).step_by(611) {
+            let mut range = Cursor::new(Vec::new());
+            expand_range(&mut Cursor::new(&compressed),&mut range,&opt,&index,start,100).expect("expansion failed");
+            assert_eq!(range.into_inner(),test_data[start as usize..usize::min(20000,start as usize+100)]);
+        }
+        // the expander jumps ahead to checkpoints, and continues in order between them
+        let mut expander = Expander::new(&opt);
+        expander.set_index(index);
+        for (start,len) in [(15000,10),(15010,990),(3000,20),(16500,3500)] {
+            let mut range = Cursor::new(Vec::new());
+            expander.expand_range(&mut Cursor::new(&compressed),&mut range,start,len).expect("expansion failed");
+            assert_eq!(range.into_inner(),test_data[start as usize..(start+len) as usize]);
+        }
+    }
+    let multi = Options { large_input: LargeInput::MultiMember, ..STD_OPTIONS };
+    assert!(compress_indexed(&mut Cursor::new(&test_data),&mut Cursor::new(Vec::new()),&multi,700).is_err());
+    let bad = Options { huffman_rescale_divisor: 1, ..STD_OPTIONS };
+    assert!(compress_indexed(&mut Cursor::new(&test_data),&mut Cursor::new(Vec::new()),&bad,700).is_err());
+}
+
+#[test]
+fn peek_size() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let compressed = compress_slice(test_data,&STD_OPTIONS).expect("compression failed");
+    let mut src = Cursor::new(&compressed);
+    src.seek(SeekFrom::Start(5)).expect("seek failed");
+    assert_eq!(peek_expanded_size(&mut src,&STD_OPTIONS).expect("peek failed"),Some(test_data.len() as u64));
+    assert_eq!(src.stream_position().unwrap(),5);
+    assert!(peek_expanded_size(&mut Cursor::new(&compressed[0..3]),&STD_OPTIONS).is_err());
+    let headerless = Options { header: false, ..STD_OPTIONS };
+    assert_eq!(peek_expanded_size(&mut src,&headerless).expect("peek failed"),None);
+}
+
+#[test]
+fn reusable_state() {
+    let mut opt = STD_OPTIONS;
+    opt.preset_dictionary = "I do not like".as_bytes().to_vec();
+    let mut compressor = Compressor::new(&opt);
+    let mut expander = Expander::new(&opt);
+    for test_data in ["I am Sam. Sam I am. I do not like this Sam I am.\n","1234567","12345123456789123456789\n"] {
+        let compressed = compressor.compress_slice(test_data.as_bytes()).expect("compression failed");
+        assert_eq!(compressed,compress_slice(test_data.as_bytes(),&opt).expect("compression failed"));
+        let expanded = expander.expand_slice(&compressed).expect("expansion failed");
+        assert_eq!(expanded[0..test_data.len()],*test_data.as_bytes());
+    }
+}
+
+#[test]
+fn large_input_modes() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(10);
+    let mut opt = STD_OPTIONS;
+    opt.large_input = LargeInput::MultiMember;
+    let mut src = Cursor::new(test_data.as_bytes());
+    let mut compressed = Cursor::new(Vec::new());
+    let (in_size,_) = compress_split(&mut src,&mut compressed,&opt,&mut Compressor::new(&opt),100).expect("compression failed");
+    assert_eq!(in_size,test_data.len() as u64);
+    let compressed = compressed.into_inner();
+    assert_eq!(peek_expanded_size(&mut Cursor::new(&compressed),&opt).expect("peek failed"),Some(100));
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(expanded,test_data.as_bytes());
+    opt.large_input = LargeInput::Error;
+    let res = compress_split(&mut Cursor::new(test_data.as_bytes()),&mut Cursor::new(Vec::new()),&opt,&mut Compressor::new(&opt),100);
+    assert!(res.is_err());
+    opt.large_input = LargeInput::Header64;
+    let compressed = compress_slice(test_data.as_bytes(),&opt).expect("compression failed");
+    assert_eq!(compressed[0..8],u64::to_le_bytes(test_data.len() as u64));
+    assert_eq!(peek_expanded_size(&mut Cursor::new(&compressed),&opt).expect("peek failed"),Some(test_data.len() as u64));
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(expanded,test_data.as_bytes());
+}
+
+#[test]
+fn short_header() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(10);
+    let mut opt = STD_OPTIONS;
+    opt.header_width = HeaderWidth::Bits16;
+    let compressed = compress_slice(test_data.as_bytes(),&opt).expect("compression failed");
+    assert_eq!(compressed[0..2],u16::to_le_bytes(test_data.len() as u16));
+    assert_eq!(compressed[2..],compress_slice(test_data.as_bytes(),&STD_OPTIONS).expect("compression failed")[4..]);
+    assert_eq!(peek_expanded_size(&mut Cursor::new(&compressed),&opt).expect("peek failed"),Some(test_data.len() as u64));
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data.as_bytes());
+    let mut streamed = Vec::new();
+    compress_stream(&mut Cursor::new(test_data.as_bytes()),&mut streamed,&opt).expect("compression failed");
+    assert_eq!(streamed,compressed);
+    // past 65535 bytes the header overflows
+    let long: Vec<u8> = (0..70000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    assert!(compress_slice(&long,&opt).is_err());
+    opt.large_input = LargeInput::MultiMember;
+    let compressed = compress_slice(&long,&opt).expect("compression failed");
+    assert!(compressed.len() <= compress_bound(long.len(),&opt));
+    assert_eq!(peek_expanded_size(&mut Cursor::new(&compressed),&opt).expect("peek failed"),Some(0xffff));
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),long);
+    let members = expand_members(&mut Cursor::new(&compressed),&mut Cursor::new(Vec::new()),&opt).expect("expansion failed");
+    assert_eq!(members.iter().map(|(_,out)| *out).collect::<Vec<u64>>(),vec![0xffff,70000-0xffff]);
+}
+
+#[test]
+fn big_endian_header() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(10);
+    let little = compress_slice(test_data.as_bytes(),&STD_OPTIONS).expect("compression failed");
+    for (width,header) in [(HeaderWidth::Bits32,vec![0,0,1,0xea]),(HeaderWidth::Bits16,vec![1,0xea])] {
+        let opt = Options { header_width: width, header_endian: Endian::Big, ..STD_OPTIONS };
+        let compressed = compress_slice(test_data.as_bytes(),&opt).expect("compression failed");
+        assert_eq!(compressed[0..header.len()],header);
+        assert_eq!(compressed[header.len()..],little[4..]);
+        assert_eq!(peek_expanded_size(&mut Cursor::new(&compressed),&opt).expect("peek failed"),Some(test_data.len() as u64));
+        assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data.as_bytes());
+    }
+    let opt = Options { large_input: LargeInput::Header64, header_endian: Endian::Big, ..STD_OPTIONS };
+    let compressed = compress_slice(test_data.as_bytes(),&opt).expect("compression failed");
+    assert_eq!(compressed[0..8],u64::to_be_bytes(test_data.len() as u64));
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data.as_bytes());
+}
+
+#[test]
+fn raw_streams() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(10);
+    let mut raw = Cursor::new(Vec::new());
+    let (in_size,out_size) = compress_raw(&mut Cursor::new(test_data.as_bytes()),&mut raw,&STD_OPTIONS).expect("compression failed");
+    let raw = raw.into_inner();
+    assert_eq!(in_size,test_data.len() as u64);
+    assert_eq!(raw,compress_slice(test_data.as_bytes(),&STD_OPTIONS).expect("compression failed")[4..]);
+    // embedded between other data, nothing is said about what follows
+    let mut container = b"HEAD".to_vec();
+    container.extend_from_slice(&raw);
+    container.extend_from_slice(b"more records follow");
+    let opt = Options { in_offset: 4, header_size: HeaderSize::Require, ..STD_OPTIONS };
+    let mut expanded = Cursor::new(Vec::new());
+    let (res,warnings) = diagnostics::collect(|| expand_raw(&mut Cursor::new(&container),&mut expanded,in_size,&opt));
+    assert_eq!(res.expect("expansion failed"),(out_size,in_size));
+    assert!(warnings.is_empty());
+    assert_eq!(expanded.into_inner(),test_data.as_bytes());
+    // the size is exact, the padding bits are not taken for data
+    let mut expanded = Cursor::new(Vec::new());
+    expand_raw(&mut Cursor::new(&raw),&mut expanded,100,&STD_OPTIONS).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),test_data[0..100].as_bytes());
+    // data that runs out before the size is reached
+    let res = expand_raw(&mut Cursor::new(&raw[0..raw.len()/2]),&mut Cursor::new(Vec::new()),in_size,&opt);
+    assert!(res.is_err());
+}
+
+#[test]
+fn checksum_trailer() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(10);
+    let sum = test_data.bytes().fold(0u8,|acc,b| acc.wrapping_add(b));
+    let opt = Options { checksum_trailer: true, ..STD_OPTIONS };
+    let plain = compress_slice(test_data.as_bytes(),&STD_OPTIONS).expect("compression failed");
+    let mut compressed = compress_slice(test_data.as_bytes(),&opt).expect("compression failed");
+    assert_eq!(compressed[0..plain.len()],plain);
+    assert_eq!(compressed[plain.len()..],[sum]);
+    assert!(compressed.len() <= compress_bound(test_data.len(),&opt));
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data.as_bytes());
+    let mut streamed = Vec::new();
+    compress_stream(&mut Cursor::new(test_data.as_bytes()),&mut streamed,&opt).expect("compression failed");
+    assert_eq!(streamed,compressed);
+    // a wrong or missing trailer
+    let res = expand_slice(&compressed[0..plain.len()],&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits: 8, .. })));
+    *compressed.last_mut().unwrap() ^= 1;
+    let res = expand_slice(&compressed,&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum)));
+    // every member has its own trailer
+    let multi = Options { large_input: LargeInput::MultiMember, ..opt.clone() };
+    let mut compressed = Cursor::new(Vec::new());
+    compress_split(&mut Cursor::new(test_data.as_bytes()),&mut compressed,&multi,&mut Compressor::new(&multi),100).expect("compression failed");
+    assert_eq!(expand_slice(&compressed.into_inner(),&multi).expect("expansion failed"),test_data.as_bytes());
+    // the end of the data has to be known to find the trailer
+    let mut raw = Cursor::new(Vec::new());
+    compress_raw(&mut Cursor::new(test_data.as_bytes()),&mut raw,&opt).expect("compression failed");
+    let raw = raw.into_inner();
+    assert_eq!(raw[raw.len()-1],sum);
+    let mut expanded = Cursor::new(Vec::new());
+    expand_raw(&mut Cursor::new(&raw),&mut expanded,test_data.len() as u64,&opt).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),test_data.as_bytes());
+    assert!(expand_slice(&raw,&Options { header: false, ..opt }).is_err());
+}
+
+#[test]
+fn dyn_matches_generic() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let mut streams: Vec<Box<dyn ReadSeek>> = vec![Box::new(Cursor::new(test_data)),Box::new(Cursor::new(test_data.to_vec()))];
+    for src in streams.iter_mut() {
+        let mut compressed: Box<dyn WriteSeek> = Box::new(Cursor::new(Vec::new()));
+        let (in_size,out_size) = compress_dyn(src.as_mut(),compressed.as_mut(),&STD_OPTIONS).expect("compression failed");
+        assert_eq!(in_size,test_data.len() as u64);
+        assert_eq!(out_size,compress_slice(test_data,&STD_OPTIONS).expect("compression failed").len() as u64);
+    }
+}
+
+#[test]
+fn iter_matches_slice() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    for opt in [STD_OPTIONS,Options { header: false, in_offset: 12, ..STD_OPTIONS }] {
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        let mut streamed = Cursor::new(Vec::new());
+        let (in_size,_) = compress_iter(test_data.iter().map(|b| Ok(*b)),&mut streamed,&opt).expect("compression failed");
+        assert_eq!(in_size,test_data.len() as u64 - opt.in_offset);
+        assert_eq!(compressed,streamed.into_inner());
+    }
+    let mut opt = STD_OPTIONS;
+    opt.large_input = LargeInput::MultiMember;
+    let mut streamed = Cursor::new(Vec::new());
+    compress_split(&mut IterReader::new(test_data.iter().copied()),&mut streamed,&opt,&mut Compressor::new(&opt),6000).expect("compression failed");
+    let expanded = expand_slice(&streamed.into_inner(),&opt).expect("expansion failed");
+    assert_eq!(expanded,test_data);
+}
+
+#[test]
+fn stream_matches_slice() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let opts = [
+        STD_OPTIONS,
+        Options { header: false, in_offset: 12, ..STD_OPTIONS },
+        Options { large_input: LargeInput::Header64, ..STD_OPTIONS }
+    ];
+    for opt in opts {
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        let mut streamed = Vec::new();
+        let (in_size,out_size) = compress_stream(&mut Cursor::new(&test_data),&mut streamed,&opt).expect("compression failed");
+        assert_eq!(in_size,test_data.len() as u64 - opt.in_offset);
+        assert_eq!(out_size,streamed.len() as u64);
+        assert_eq!(compressed,streamed);
+    }
+    // members come out the same as when the headers are filled in afterward
+    let mut opt = STD_OPTIONS;
+    opt.large_input = LargeInput::MultiMember;
+    let mut split = Cursor::new(Vec::new());
+    compress_split(&mut Cursor::new(&test_data),&mut split,&opt,&mut Compressor::new(&opt),6000).expect("compression failed");
+    let mut streamed = Vec::new();
+    stream_split(&mut Cursor::new(&test_data),&mut streamed,&opt,&mut Compressor::new(&opt),6000).expect("compression failed");
+    assert_eq!(split.into_inner(),streamed);
+    // without a known length the header cannot go first
+    let mut src = IterReader::new(test_data.iter().copied());
+    assert!(stream_split(&mut src,&mut Vec::new(),&STD_OPTIONS,&mut Compressor::new(&STD_OPTIONS),u32::MAX as u64).is_err());
+}
+
+#[test]
+fn bound_and_expand_into() {
+    let mut x: u32 = 1;
+    let noise: Vec<u8> = (0..5000).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); (x >> 16) as u8 }).collect();
+    let text = "I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(100).into_bytes();
+    for strategy in [UpdateStrategy::Lzhuf,UpdateStrategy::Fgk,UpdateStrategy::Vitter] {
+        let mut opt = STD_OPTIONS;
+        opt.huffman_update = strategy;
+        for test_data in [&noise,&text] {
+            let compressed = compress_slice(test_data,&opt).expect("compression failed");
+            assert!(compressed.len() <= compress_bound(test_data.len(),&opt));
+            let mut buf = vec![0;test_data.len()];
+            let n = expand_into(&compressed,&mut buf,&opt).expect("expansion failed");
+            assert_eq!(n,test_data.len());
+            assert_eq!(buf,*test_data);
+            let mut small = vec![0;test_data.len()-1];
+            assert!(expand_into(&compressed,&mut small,&opt).is_err());
+            // the same expander fills the buffer again from a stream
+            let mut expander = Expander::new(&opt);
+            for _ in 0..2 {
+                buf.fill(0);
+                assert_eq!(expander.expand_reader_into(&mut Cursor::new(&compressed),&mut buf).expect("expansion failed"),n);
+                assert_eq!(buf,*test_data);
+            }
+        }
+    }
+    // the bound saturates rather than overflowing, whatever the width of usize
+    assert_eq!(compress_bound(usize::MAX,&STD_OPTIONS),usize::MAX);
+}
+
+#[test]
+fn verify_detects_truncation() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    let (_,out_size,complete) = verify(&mut Cursor::new(&compressed),&STD_OPTIONS).expect("verify failed");
+    assert_eq!(out_size,test_data.len() as u64);
+    assert!(complete);
+    let (_,out_size,complete) = verify(&mut Cursor::new(&compressed[0..compressed.len()/2]),&STD_OPTIONS).expect("verify failed");
+    assert!(out_size < test_data.len() as u64);
+    assert!(!complete);
+}
+
+#[test]
+fn truncation_modes() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    let half = &compressed[0..compressed.len()/2];
+    let (stopped,warnings) = diagnostics::collect(|| expand_slice(half,&STD_OPTIONS));
+    let stopped = stopped.expect("expansion failed");
+    assert!(matches!(warnings[..],[Warning::Truncated { missing_bits }] if missing_bits > 0));
+    assert!(stopped.len() < test_data.len());
+    assert_eq!(stopped,test_data[0..stopped.len()]);
+    let zero_fill = Options { truncation: Truncation::ZeroFill, ..STD_OPTIONS };
+    let (filled,warnings) = diagnostics::collect(|| expand_slice(half,&zero_fill));
+    let filled = filled.expect("expansion failed");
+    assert!(matches!(warnings[..],[Warning::Truncated { missing_bits }] if missing_bits > 8));
+    // unlike LZHUF.C the last match stops at the size in the header
+    assert_eq!(filled.len(),test_data.len());
+    assert_eq!(filled[0..stopped.len()],stopped);
+    let strict = Options { truncation: Truncation::Error, ..STD_OPTIONS };
+    match expand_slice(half,&strict) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits, .. }) if *missing_bits > 0)),
+        Ok(_) => panic!("truncation was not detected")
+    }
+    assert_eq!(diagnostics::collect(|| expand_slice(&compressed,&strict).expect("expansion failed")),(test_data.clone(),vec![]));
+    // without a header, the padding in the last byte is not mistaken for truncation
+    let strict = Options { in_offset: 0, out_offset: 0, ..Options::teledisk_v2() };
+    let compressed = compress_slice(&test_data,&strict).expect("compression failed");
+    assert_eq!(expand_slice(&compressed,&strict).expect("expansion failed"),test_data);
+    assert!(expand_slice(&compressed[0..compressed.len()/2],&strict).is_err());
+}
+
+#[test]
+fn truncated_position() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    let (trace,_) = expand_traced(&mut Cursor::new(&compressed),&STD_OPTIONS);
+    let tokens: Vec<&TraceEvent> = trace.iter().filter(|e| e.token != Token::Rebuild).collect();
+    // find a match whose data ends within the lower 6 bits of its position
+    let (event,end) = tokens.windows(2)
+        .map(|pair| (pair[0],pair[1].bit))
+        .find(|(e,end)| matches!(e.token,Token::Match { .. }) && (1..=5).contains(&(end % 8)))
+        .expect("no suitable match");
+    let cut = &compressed[0..(end / 8) as usize];
+    // the match is dropped rather than taken from zeros, and the error tells where expansion got to
+    let strict = Options { truncation: Truncation::Error, ..STD_OPTIONS };
+    let err = expand_slice(cut,&strict).unwrap_err();
+    assert!(matches!(err.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits, expanded }) if *missing_bits == end % 8 && *expanded == event.pos));
+    assert_eq!(expand_slice(cut,&STD_OPTIONS).expect("expansion failed"),test_data[0..event.pos as usize]);
+}
+
+#[test]
+fn offsets() {
+    // a TD0 image, the 12 byte header is copied over separately, so expanding into a new file leaves a gap
+    let test_data: Vec<u8> = (0..3000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let image = [vec![0xaa;12],test_data.clone()].concat();
+    let opt = Options::teledisk_v2();
+    let mut compressed = Cursor::new(Vec::new());
+    compress(&mut Cursor::new(&image),&mut compressed,&opt).expect("compression failed");
+    let compressed = compressed.into_inner();
+    assert_eq!(compressed[0..12],[0;12]);
+    let mut expanded = Cursor::new(Vec::new());
+    expand(&mut Cursor::new(&compressed),&mut expanded,&opt).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),[vec![0;12],test_data.clone()].concat());
+    // refuse the gap, but writing over a header that is already there is fine
+    let strict = Options { offset_gap: OffsetGap::Error, ..opt.clone() };
+    let res = expand(&mut Cursor::new(&compressed),&mut Cursor::new(vec![0xaa;5]),&strict);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    let mut expanded = Cursor::new(vec![0xaa;12]);
+    expand(&mut Cursor::new(&compressed),&mut expanded,&strict).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),image);
+    // an input that ends before the offset is an error whether or not its end is known
+    let res = expand_slice(&compressed[0..5],&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    let res = compress_iter(image[0..5].iter().copied(),&mut Cursor::new(Vec::new()),&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    let multi = Options { header: true, large_input: LargeInput::MultiMember, ..opt };
+    let res = expand_slice(&compressed[0..5],&multi);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+}
+
+#[test]
+fn header_size_policies() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    let expand_counted = |slice: &[u8],opt: &Options| -> Result<(u64,Vec<u8>),DYNERR> {
+        let mut ans = Cursor::new(Vec::new());
+        let (in_size,_) = expand(&mut Cursor::new(slice),&mut ans,opt)?;
+        Ok((in_size,ans.into_inner()))
+    };
+    let require = Options { header_size: HeaderSize::Require, ..STD_OPTIONS };
+    let ignore = Options { header_size: HeaderSize::Ignore, ..STD_OPTIONS };
+    // extra data after the end is left alone, and not counted, but it is reported
+    let mut padded = compressed.clone();
+    padded.extend_from_slice(&[0x55;100]);
+    let (res,warnings) = diagnostics::collect(|| expand_counted(&padded,&STD_OPTIONS));
+    assert_eq!(res.expect("expansion failed"),(compressed.len() as u64,test_data.clone()));
+    let bit = 8 * compressed.len() as u64;
+    assert_eq!(warnings,vec![Warning::Overlong { expected: 20000, bit, excess: 100 }]);
+    assert!(matches!(expand_counted(&padded,&require).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::Overlong { expected: 20000, bit: b, excess: 100 }) if *b==bit));
+    // header too small
+    let mut short = compressed.clone();
+    short[0..4].copy_from_slice(&1000u32.to_le_bytes());
+    let ((in_size,limited),warnings) = diagnostics::collect(|| expand_counted(&short,&STD_OPTIONS).expect("expansion failed"));
+    assert!(in_size < compressed.len() as u64);
+    assert_eq!(limited,test_data[0..1000]);
+    // the stream is cut off where it passes the header, and the rest is counted
+    assert!(matches!(warnings[..],[Warning::Overlong { expected: 1000, bit, excess }] if bit < 8 * in_size && excess == compressed.len() as u64 - in_size));
+    assert!(matches!(expand_counted(&short,&require).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::Overlong { expected: 1000, .. })));
+    assert_eq!(expand_counted(&short,&ignore).expect("expansion failed"),(compressed.len() as u64,test_data.clone()));
+    // header too large
+    let mut long = compressed.clone();
+    long[0..4].copy_from_slice(&30000u32.to_le_bytes());
+    let (_,stopped) = expand_counted(&long,&STD_OPTIONS).expect("expansion failed");
+    assert!(stopped.starts_with(&test_data));
+    match expand_counted(&long,&require) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::SizeMismatch { expected: 30000, actual }) if *actual < 30000)),
+        Ok(_) => panic!("shortfall was not detected")
+    }
+    assert_eq!(expand_counted(&long,&ignore).expect("expansion failed").1,test_data);
+}
+
+#[test]
+fn invertibility_with_max_freq() {
+    let mut opt = STD_OPTIONS;
+    opt.huffman_max_freq = 0x1000;
+    let mut x: u32 = 1;
+    let long_data: Vec<u8> = (0..50000).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); ((x >> 16) % 26) as u8 + b'a' }).collect();
+    let compressed = compress_slice(&long_data,&opt).expect("compression failed");
+    assert!(compressed.len() <= compress_bound(long_data.len(),&opt));
+    assert!(compressed != compress_slice(&long_data,&STD_OPTIONS).expect("compression failed"));
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(long_data,expanded);
+}
+
+#[test]
+fn presets_by_name() {
+    for name in PRESETS {
+        assert!(Options::preset(name).is_some());
+    }
+    assert!(!Options::preset("teledisk_v2").unwrap().header);
+    assert!(Options::preset("nope").is_none());
+}
+
+#[test]
+fn empty_input() {
+    let compressed = compress_slice(&[],&STD_OPTIONS).expect("compression failed");
+    assert_eq!(compressed,vec![0;4]);
+    assert!(expand_slice(&compressed,&STD_OPTIONS).expect("expansion failed").is_empty());
+}
+
+#[test]
+fn compression_levels() {
+    let test_data = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    let std_compressed = compress_slice(&test_data,&STD_OPTIONS).expect("compression failed");
+    let mut sizes = Vec::new();
+    for level in 1..=9 {
+        let mut opt = STD_OPTIONS;
+        opt.set_level(level);
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        assert_eq!(expand_slice(&compressed,&STD_OPTIONS).expect("expansion failed"),test_data);
+        if level == 7 {
+            assert_eq!(compressed,std_compressed);
+        }
+        sizes.push(compressed.len());
+    }
+    assert!(sizes[0] > sizes[6]);
+    assert!(sizes[8] < sizes[6]);
+}
+
+#[test]
+fn trace_tokens() {
+    let mut opt = STD_OPTIONS;
+    opt.huffman_max_freq = 0x400;
+    let mut x: u32 = 1;
+    let data: Vec<u8> = (0..20000).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); ((x >> 16) % 6) as u8 + b'a' }).collect();
+    let compressed = compress_slice(&data,&opt).expect("compression failed");
+    let (trace,res) = expand_traced(&mut Cursor::new(&compressed),&opt);
+    assert_eq!(res.expect("expansion failed").1,data.len() as u64);
+    assert_eq!(trace[0],TraceEvent { bit: 32, pos: 0, token: Token::Literal { byte: data[0] } });
+    let mut pos = 0;
+    for event in trace.iter().filter(|e| e.token != Token::Rebuild) {
+        assert_eq!(event.pos,pos);
+        pos += match event.token {
+            Token::Match { offset, length } => {
+                assert!(offset > 0);
+                length as u64
+            },
+            _ => 1
+        };
+    }
+    assert_eq!(pos,data.len() as u64);
+    assert!(trace.iter().filter(|e| e.token == Token::Rebuild).count() > 1);
+}
+
+#[test]
+fn malformed_input() {
+    // anything may come out of damaged data, but it must come back as a result
+    let text = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    for name in PRESETS {
+        let opt = Options { in_offset: 0, out_offset: 0, ..Options::preset(name).unwrap() };
+        let compressed = compress_slice(&text[0..4000],&opt).expect("compression failed");
+        for corrupted in crate::tools::mutate::Mutations::new(&compressed,0x5eed).take(200) {
+            let _ = expand_slice(&corrupted,&opt);
+            let _ = verify(&mut Cursor::new(&corrupted),&opt);
+            let _ = peek_expanded_size(&mut Cursor::new(&corrupted),&opt);
+            if opt.header {
+                let _ = expand_members(&mut Cursor::new(&corrupted),&mut Cursor::new(Vec::new()),&opt);
+            }
+        }
+    }
+}
@@ -1,27 +1,108 @@
 //! LZW Compression
 //! 
-//! This currently supports fixed code widths only, other parameters are flexible.
-//! Efficiency is probably not optimal, we rely on `std::collections::HashMap` to perform
-//! fast lookups on keys of the type `(usize,usize)`.
+//! Code widths can be fixed, or grow as the dictionary fills, as in Unix `compress` and GIF.
+//! Other parameters are flexible, and `Options` has constructors for some common profiles.
+//! The dictionary is a trie kept in flat arrays indexed by code, with an open addressing hash
+//! table that finds the child of a code for a given symbol, as in Unix `compress`.
 
-use bit_vec::BitVec;
-use crate::BitOrder;
-use std::io::{Cursor,Read,Write,Seek,SeekFrom,BufReader,BufWriter,ErrorKind};
-use std::collections::HashMap;
-use crate::DYNERR;
+use crate::{BitOrder,Endian,Truncation,OffsetGap};
+use crate::checksum::Checksum;
+use std::sync::Arc;
+use crate::diagnostics::{self,Warning};
+use std::io::{Cursor,Read,BufRead,Write,Seek,SeekFrom,BufReader,BufWriter,ErrorKind};
+use crate::{DYNERR,ReadSeek,WriteSeek,ByteItem};
+use crate::tools::range_writer::RangeWriter;
+use crate::tools::iter_reader::{IterReader,known_end};
+use crate::tools::chunk_writer::{ChunkWriter,DISCARD_CHUNK};
+use crate::tools::bit_writer::BitWriter;
+use crate::tools::bit_reader::BitReader;
+
+/// Quantity stored in the chunk header
+#[derive(Clone,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum HeaderUnit {
+    /// number of bits in the chunk
+    Bits,
+    /// number of codes in the chunk, including any clear or stop code
+    Codes,
+    /// number of bytes in the chunk, rounded up
+    Bytes
+}
+
+/// Where the chunk header goes relative to the chunk
+#[derive(Clone,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum HeaderPlacement {
+    /// header precedes the chunk and determines its length during expansion
+    Before,
+    /// header follows the chunk and is verified during expansion, requires `stop_code`
+    After
+}
+
+/// Checksum of the expanded data that follows each chunk
+#[derive(Clone)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum ChunkChecksum {
+    /// 16 bit sum of the expanded bytes
+    Sum16,
+    /// CRC-16/ARC of the expanded bytes
+    Crc16,
+    /// any checksum of up to 32 bits, e.g., `checksum::Xor8` or one of the user's own,
+    /// this cannot be serialized
+    #[cfg_attr(feature="serde",serde(skip))]
+    Custom(Arc<dyn Checksum>)
+}
+
+impl ChunkChecksum {
+    fn checksum(&self) -> &dyn Checksum {
+        match self {
+            Self::Sum16 => &crate::checksum::Sum16,
+            Self::Crc16 => &crate::checksum::ARC,
+            Self::Custom(sum) => sum.as_ref()
+        }
+    }
+    /// Bits in the checksum
+    pub fn width(&self) -> usize {
+        self.checksum().width() as usize
+    }
+}
+
+/// custom checksums are the same if they are the same object
+impl PartialEq for ChunkChecksum {
+    fn eq(&self,other: &Self) -> bool {
+        match (self,other) {
+            (Self::Custom(a),Self::Custom(b)) => Arc::ptr_eq(a,b),
+            (a,b) => std::mem::discriminant(a) == std::mem::discriminant(b)
+        }
+    }
+}
 
 /// Options controlling compression
 #[derive(Clone)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+#[cfg_attr(feature="serde",serde(deny_unknown_fields))]
 pub struct Options {
-    /// Length in bits of the header preceding each chunk, can be 0.
+    /// Length in bits of the header accompanying each chunk, can be 0.
     /// Can be used with fixed code width in lieu of clear code.
     pub header_bits: usize,
-    /// header contains bit count divided by this number
+    /// header contains the `header_unit` count divided by this number
     pub header_divisor: usize,
+    /// quantity that is counted in the header
+    pub header_unit: HeaderUnit,
+    /// whether the header goes before or after the chunk
+    pub header_placement: HeaderPlacement,
+    /// Byte order of the header, relevant only if `header_bits` is a multiple of 8.
+    /// The natural order is little endian for `Lsb0` and big endian for `Msb0`.
+    pub header_endian: Endian,
+    /// Checksum following each chunk (and its header, if placed after), in the natural byte order.
+    /// Requires a header or a stop code so that the final checksum can be located.
+    pub chunk_checksum: Option<ChunkChecksum>,
     /// starting position in the input file
     pub in_offset: u64,
     /// starting position in the output file
     pub out_offset: u64,
+    /// what to do if `out_offset` is past the end of the output, as when expanding into a new file
+    pub offset_gap: OffsetGap,
     /// number of codes to write before a reset
     pub chunk_size: usize,
     /// minimum value of a symbol, currently must be 0
@@ -32,12 +113,29 @@ pub struct Options {
     pub clear_code: Option<usize>,
     /// stop code, usually max_symbol+1 or max_symbol+2, match codes will skip over
     pub stop_code: Option<usize>,
-    /// min code width in bits, currently must be same as max_code_width
+    /// Emit a clear code at the start of the stream, as GIF does, requires `clear_code`.
+    /// If true, expansion requires the leading clear code, otherwise it is tolerated.
+    pub initial_clear: bool,
+    /// Min code width in bits.  If this is less than `max_code_width`, each code is just wide enough
+    /// to hold the next code the decoder would assign, up to `max_code_width`.
+    /// Variable widths cannot be combined with chunk headers.
     pub min_code_width: usize,
     /// max code with in bits
     pub max_code_width: usize,
+    /// Whenever the code width changes or a clear code is written, pad with zeros to a multiple
+    /// of 8 codes at the old width, counting from the previous padding, as Unix `compress` does.
+    pub pad_code_groups: bool,
     /// bit packing strategy
     pub ord: BitOrder,
+    /// If set, expansion stops as soon as this many bytes are produced, without looking for
+    /// trailers or further codes, useful for streams without a stop code embedded in a larger file.
+    pub expanded_size: Option<u64>,
+    /// What expansion does if the data runs out before a stop code, or before the end of a chunk whose
+    /// length is in a header.  There is nothing to zero-fill, so `ZeroFill` is the same as `Stop`.
+    pub truncation: Truncation,
+    /// If set, the bytes that follow the stop code are counted, a warning is logged if there are any,
+    /// and `Error::TrailingData` is returned if there are more than this.  Otherwise they are ignored.
+    pub trailing_limit: Option<u64>,
     /// return error if file is larger
     pub max_file_size: u64
 }
@@ -45,271 +143,640 @@ pub struct Options {
 pub const STD_OPTIONS: Options = Options {
     header_bits: 0,
     header_divisor: 1,
+    header_unit: HeaderUnit::Bits,
+    header_placement: HeaderPlacement::Before,
+    header_endian: Endian::Little,
+    chunk_checksum: None,
     in_offset: 0,
     out_offset: 0,
+    offset_gap: OffsetGap::ZeroFill,
     chunk_size: 4096,
     min_symbol: 0,
     max_symbol: 255,
     clear_code: Some(256),
     stop_code: Some(257),
+    initial_clear: false,
     min_code_width: 12,
     max_code_width: 12,
+    pad_code_groups: false,
     ord: BitOrder::Lsb0,
+    expanded_size: None,
+    truncation: Truncation::Stop,
+    trailing_limit: None,
     max_file_size: u32::MAX as u64/4
 };
 
-/// bit_vec crate only handles MSB, this assumes starting alignment
-fn bits_to_bytes_lsb0(bits: &BitVec) -> Vec<u8> {
-    let mut ans = Vec::new();
-    let byte_count = bits.len() / 8;
-    let rem = bits.len() % 8;
-    for i in 0..byte_count {
-        let mut val = 0;
-        for b in 0..8 {
-            val |= (bits.get(i*8 + b).unwrap() as u8) << b;
+/// Names accepted by `Options::preset`
+pub const PRESETS: &[&str] = &["standard","unix_compress","arc_crunch","teledisk_v1","gif2","gif3","gif4","gif5","gif6","gif7","gif8"];
+
+impl Options {
+    /// Return `Error::InvalidOptions` for anything `Compressor::new` or `Expander::new` would panic on.
+    /// Check options that come from outside the program first.
+    pub fn validate(&self) -> Result<(),crate::Error> {
+        let problem = if self.min_code_width > self.max_code_width {
+            "min code width exceeds max code width"
+        } else if self.min_code_width != self.max_code_width && self.header_bits > 0 {
+            "variable code width cannot be used with chunk headers"
+        } else if self.min_symbol != 0 {
+            "minimum symbol value must be 0"
+        } else if self.max_symbol > 255 {
+            "maximum symbol value cannot exceed 255"
+        } else if self.max_code_width >= u32::BITS as usize {
+            "max code width does not fit in 32 bits"
+        } else if [Some(self.max_symbol),self.clear_code,self.stop_code].into_iter().flatten().any(|code| code >> self.max_code_width > 0) {
+            "symbols, clear code, and stop code must fit in the max code width"
+        } else if self.header_bits > 0 && self.header_divisor == 0 {
+            "header divisor cannot be 0"
+        } else if self.initial_clear && self.clear_code.is_none() {
+            "initial clear requires a clear code"
+        } else if self.header_bits > 0 && self.header_placement == HeaderPlacement::After && self.stop_code.is_none() {
+            "header after chunk requires a stop code"
+        } else if self.chunk_checksum.is_some() && self.header_bits == 0 && self.stop_code.is_none() {
+            "chunk checksum requires a header or a stop code"
+        } else {
+            return Ok(());
+        };
+        Err(crate::Error::InvalidOptions(problem.to_string()))
+    }
+    /// Look up options by one of the names in `PRESETS`, where `standard` is `STD_OPTIONS`,
+    /// and `gif2` through `gif8` are `gif` with the given minimum code size.
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(STD_OPTIONS),
+            "unix_compress" => Some(Self::unix_compress()),
+            "arc_crunch" => Some(Self::arc_crunch()),
+            "teledisk_v1" => Some(Self::teledisk_v1()),
+            _ => match name.strip_prefix("gif").and_then(|n| n.parse::<usize>().ok()) {
+                Some(min_bits) if (2..=8).contains(&min_bits) => Some(Self::gif(min_bits)),
+                _ => None
+            }
         }
-        ans.push(val);
     }
-    if rem > 0 {
-        let mut val = 0;
-        for b in 0..rem {
-            val |= (bits.get(byte_count*8 + b).unwrap() as u8) << b;
+    /// Unix `compress` with 9 to 16 bit codes in block mode.  The 3 byte signature `1f 9d 90` that
+    /// starts a `.Z` file is not part of the stream, set `in_offset` to 3 to expand a whole file.
+    /// The dictionary is never cleared during compression, which any `compress` decoder accepts.
+    pub const fn unix_compress() -> Self {
+        Self {
+            header_bits: 0,
+            header_divisor: 1,
+            header_unit: HeaderUnit::Bits,
+            header_placement: HeaderPlacement::Before,
+            header_endian: Endian::Little,
+            chunk_checksum: None,
+            in_offset: 0,
+            out_offset: 0,
+            offset_gap: OffsetGap::ZeroFill,
+            chunk_size: usize::MAX,
+            min_symbol: 0,
+            max_symbol: 255,
+            clear_code: Some(256),
+            stop_code: None,
+            initial_clear: false,
+            min_code_width: 9,
+            max_code_width: 16,
+            pad_code_groups: true,
+            ord: BitOrder::Lsb0,
+            expanded_size: None,
+            truncation: Truncation::Stop,
+            trailing_limit: None,
+            max_file_size: u32::MAX as u64
+        }
+    }
+    /// GIF image data, where `min_bits` is the LZW minimum code size byte that precedes the data
+    /// (2 to 8).  The sub-block framing is handled separately, see the `sub_block` module.
+    /// The dictionary is cleared as soon as it fills.
+    pub const fn gif(min_bits: usize) -> Self {
+        let clear = 1 << min_bits;
+        Self {
+            header_bits: 0,
+            header_divisor: 1,
+            header_unit: HeaderUnit::Bits,
+            header_placement: HeaderPlacement::Before,
+            header_endian: Endian::Little,
+            chunk_checksum: None,
+            in_offset: 0,
+            out_offset: 0,
+            offset_gap: OffsetGap::ZeroFill,
+            chunk_size: (1 << 12) - 2 - clear,
+            min_symbol: 0,
+            max_symbol: clear - 1,
+            clear_code: Some(clear),
+            stop_code: Some(clear + 1),
+            initial_clear: true,
+            min_code_width: min_bits + 1,
+            max_code_width: 12,
+            pad_code_groups: false,
+            ord: BitOrder::Lsb0,
+            expanded_size: None,
+            truncation: Truncation::Stop,
+            trailing_limit: None,
+            max_file_size: u32::MAX as u64
+        }
+    }
+    /// ARC method 8 (crunched), which is Unix `compress` limited to 12 bit codes.  The byte that
+    /// gives the maximum code width, and the run length encoding that precedes compression,
+    /// are not part of the stream.
+    pub const fn arc_crunch() -> Self {
+        let mut ans = Self::unix_compress();
+        ans.max_code_width = 12;
+        ans
+    }
+    /// Teledisk 1.x advanced compression, fixed 12 bit codes in chunks with a length header.
+    /// The offsets skip the 12 byte image header, this is `td0::TD_V1_OPTIONS`.
+    /// Truncation is an error, since it means part of the disk is lost.
+    pub const fn teledisk_v1() -> Self {
+        Self {
+            header_bits: 16,
+            header_divisor: 4,
+            header_unit: HeaderUnit::Bits,
+            header_placement: HeaderPlacement::Before,
+            header_endian: Endian::Little,
+            chunk_checksum: None,
+            in_offset: 12,
+            out_offset: 12,
+            offset_gap: OffsetGap::ZeroFill,
+            chunk_size: 4096,
+            min_symbol: 0,
+            max_symbol: 255,
+            clear_code: None,
+            stop_code: None,
+            initial_clear: false,
+            min_code_width: 12,
+            max_code_width: 12,
+            pad_code_groups: false,
+            ord: BitOrder::Lsb0,
+            expanded_size: None,
+            truncation: Truncation::Error,
+            trailing_limit: None,
+            max_file_size: 3_000_000
         }
-        ans.push(val);
     }
-    ans
 }
 
-/// bit_vec crate only handles MSB, this assumes starting alignment
-fn bytes_to_bits_lsb0(bytes: &[u8]) -> BitVec {
-    let mut ans = BitVec::new();
-    for i in 0..bytes.len() {
-        let val = bytes[i];
-        for b in 0..8 {
-            ans.push((val & (1 << b)) != 0);
-        }
+/// Place where expansion can start, recorded at the start of every chunk, and by `compress_indexed`
+/// at intervals within a chunk.  Both offsets are relative to the respective `in_offset` or `out_offset` used during compression.
+#[derive(Clone,PartialEq,Debug)]
+pub struct Checkpoint {
+    /// offset into the expanded data in bytes
+    pub expanded_offset: u64,
+    /// offset into the compressed data in bits
+    pub compressed_bit: u64,
+    /// what expansion needs to continue from inside a chunk, `None` at the start of a chunk
+    pub state: Option<Snapshot>
+}
+
+/// Expander state inside a chunk, the dictionary takes up to 12 bytes per code
+#[derive(Clone,PartialEq,Debug)]
+pub struct Snapshot {
+    /// codes added since the last reset, with their prefix code and final symbol, in order
+    entries: Vec<(u32,u32,u8)>,
+    /// the code most recently added
+    curr_code: Option<usize>,
+    /// the code just read, the next code read adds the dictionary entry that extends it
+    prev_code: usize,
+    /// bits of codes read so far in the chunk, not counting padding
+    bit_count: u64,
+    /// bits read since the last padding
+    run_bits: u64,
+    /// bits of codes in the chunk according to its header, only used if the header goes before the chunk
+    chunk_bits: u64,
+    /// chunk checksum so far
+    sum: u32
+}
+
+/// Swap the bytes in a header value if the header byte order is opposite the natural order
+fn swap_header_bytes(val: usize,opt: &Options) -> usize {
+    let natural = match opt.ord {
+        BitOrder::Msb0 => Endian::Big,
+        BitOrder::Lsb0 => Endian::Little
+    };
+    if natural == opt.header_endian || !opt.header_bits.is_multiple_of(8) {
+        return val;
+    }
+    let mut ans = 0;
+    for i in 0..opt.header_bits/8 {
+        ans = (ans << 8) | ((val >> (8*i)) & 0xff);
     }
     ans
 }
 
+/// Header value for a chunk with `codes` codes, including any clear or stop code,
+/// this is the value as it is to be written to the stream.
+fn header_value(codes: usize,opt: &Options) -> usize {
+    let count = match opt.header_unit {
+        HeaderUnit::Bits => codes * opt.max_code_width,
+        HeaderUnit::Codes => codes,
+        HeaderUnit::Bytes => (codes * opt.max_code_width).div_ceil(8)
+    };
+    swap_header_bytes(count / opt.header_divisor,opt)
+}
+
+/// Maximum number of bits in a chunk given the header value as read from the stream
+fn chunk_bits_from_header(val: usize,opt: &Options) -> u64 {
+    let count = (swap_header_bytes(val,opt) as u64).saturating_mul(opt.header_divisor as u64);
+    match opt.header_unit {
+        HeaderUnit::Bits => count,
+        HeaderUnit::Codes => count.saturating_mul(opt.max_code_width as u64),
+        HeaderUnit::Bytes => count.saturating_mul(8)
+    }
+}
+
+/// Update running checksum with one more byte of expanded data
+fn update_checksum(kind: &ChunkChecksum,sum: u32,byte: u8) -> u32 {
+    kind.checksum().update(sum,&[byte])
+}
+
+/// Checksum state at the start of a chunk, 0 if there is no checksum
+fn init_checksum(opt: &Options) -> u32 {
+    opt.chunk_checksum.as_ref().map_or(0,|kind| kind.checksum().init())
+}
+
 #[derive(Clone)]
 struct LZWCoder {
-    bits: BitVec,
-    ptr: usize,
-    ord: BitOrder,
-    count: usize
+    bits: BitWriter,
+    count: usize,
+    /// bits written since the last padding
+    run_bits: u64
 }
 
 struct LZWDecoder {
-    bits: BitVec,
-    ptr: usize,
-    ord: BitOrder,
-    count: usize
+    bits: BitReader,
+    count: usize,
+    /// total bits consumed by `get_code`
+    bits_read: u64,
+    /// bits consumed since the last padding
+    run_bits: u64
 }
 
 impl LZWCoder {
     pub fn new(ord: BitOrder) -> Self {
         Self {
-            bits: BitVec::new(),
-            ptr: 0,
-            ord,
-            count: 0
-        }
-    }
-    /// keep the bit vector small, we don't need the bits behind us
-    fn drop_leading_bits(&mut self) {
-        let cpy = self.bits.clone();
-        self.bits = BitVec::new();
-        for i in self.ptr..cpy.len() {
-            self.bits.push(cpy.get(i).unwrap());
-        }
-        self.ptr = 0;
-    }
-    /// output `num_bits` of `code` in given bit-order, the bits are always
-    /// written to the output stream (sometimes backing up and rewriting) such that
-    /// the start of the bit vector stays aligned.
-    pub fn put_code<W: Write + Seek>(&mut self,num_bits: usize,mut code: usize,writer: &mut BufWriter<W>) {
-        let bytes = match self.ord {
-            BitOrder::Msb0 => {
-                code <<= usize::BITS as usize - num_bits;
-                let msk = 1 << (usize::BITS - 1);
-                for _i in 0..num_bits {
-                    self.bits.push(code & msk > 0);
-                    code <<= 1;
-                    self.ptr += 1;
-                }
-                self.bits.to_bytes()
-            },
-            BitOrder::Lsb0 => {
-                for _i in 0..num_bits {
-                    self.bits.push(code & 1 > 0);
-                    code >>= 1;
-                    self.ptr += 1;
-                }
-                bits_to_bytes_lsb0(&self.bits)
-            }
-        };
-        writer.write(&bytes.as_slice()).expect("write err");
-        if self.bits.len() % 8 > 0 {
-            writer.seek(SeekFrom::Current(-1)).expect("seek err");
-            self.ptr = 8 * (self.bits.len() / 8);
-            self.drop_leading_bits();
-        } else {
-            self.bits = BitVec::new();
-            self.ptr = 0;
+            bits: BitWriter::new(ord),
+            count: 0,
+            run_bits: 0
         }
+    }
+    /// output `num_bits` of `code` in given bit-order, only completed bytes are written,
+    /// so the stream position is always where the partial byte will go.
+    pub fn put_code<W: Write>(&mut self,num_bits: usize,code: usize,writer: &mut W) -> Result<(),std::io::Error> {
+        self.bits.put_bits(num_bits,code as u64,writer)?;
         self.count += 1;
+        self.run_bits += num_bits as u64;
+        Ok(())
+    }
+    /// write the partial byte, if any, this ends the bit stream
+    pub fn finish<W: Write>(&mut self,writer: &mut W) -> Result<(),std::io::Error> {
+        self.bits.finish(writer)
+    }
+    /// write zeros up to a multiple of 8 codes of `width` bits, see `Options::pad_code_groups`
+    pub fn pad_group<W: Write>(&mut self,width: usize,writer: &mut W) -> Result<(),std::io::Error> {
+        let group = 8 * width as u64;
+        let mut pad = (group - self.run_bits % group) % group;
+        let count = self.count;
+        while pad > 0 {
+            let n = u64::min(pad,8);
+            self.put_code(n as usize,0,writer)?;
+            pad -= n;
+        }
+        self.count = count;
+        self.run_bits = 0;
+        Ok(())
     }
 }
 
 impl LZWDecoder {
     pub fn new(ord: BitOrder) -> Self {
         Self {
-            bits: BitVec::new(),
-            ptr: 0,
-            ord,
-            count: 0
+            bits: BitReader::new(ord),
+            count: 0,
+            bits_read: 0,
+            run_bits: 0
         }
     }
-    /// keep the bit vector small, we don't need the bits behind us
-    fn drop_leading_bits(&mut self) {
-        let cpy = self.bits.clone();
-        self.bits = BitVec::new();
-        for i in self.ptr..cpy.len() {
-            self.bits.push(cpy.get(i).unwrap());
+    /// Get the next code reading from the stream as needed, an error is returned at EOF.
+    /// `reader` should not be advanced outside this function until decoding is done.
+    pub fn get_code<R: BufRead>(&mut self,num_bits: usize,reader: &mut R) -> Result<usize,std::io::Error> {
+        let ans = self.bits.get_bits(num_bits,reader)? as usize;
+        self.count += 1;
+        self.bits_read += num_bits as u64;
+        self.run_bits += num_bits as u64;
+        Ok(ans)
+    }
+    /// skip up to a multiple of 8 codes of `width` bits, see `Options::pad_code_groups`
+    pub fn skip_group<R: BufRead>(&mut self,width: usize,reader: &mut R) -> Result<(),std::io::Error> {
+        let group = 8 * width as u64;
+        let mut pad = (group - self.run_bits % group) % group;
+        let count = self.count;
+        while pad > 0 {
+            let n = u64::min(pad,8);
+            self.get_code(n as usize,reader)?;
+            pad -= n;
         }
-        self.ptr = 0;
+        self.count = count;
+        self.run_bits = 0;
+        Ok(())
     }
-    /// Get the next bit reading from the stream as needed.
-    /// When EOF is reached 0 is returned (behavior comes from LZHUF.C).
-    /// `reader` should not be advanced outside this function until decoding is done.
-    fn get_bit<R: Read>(&mut self,reader: &mut BufReader<R>) -> Result<u8,std::io::Error> {
-        match self.bits.get(self.ptr) {
-            Some(bit) => {
-                self.ptr += 1;
-                Ok(bit as u8)
-            },
-            None => {
-            let mut by: [u8;1] = [0];
-                match reader.read_exact(&mut by) {
-                    Ok(()) => {
-                        if self.bits.len()>512 {
-                            self.drop_leading_bits();
-                        }
-                        match self.ord {
-                            BitOrder::Msb0 => self.bits.append(&mut BitVec::from_bytes(&by)),
-                            BitOrder::Lsb0 => self.bits.append(&mut bytes_to_bits_lsb0(&by))
-                        }
-                        self.get_bit(reader)
-                    }
-                    Err(e) => Err(e)
-                }
+}
+
+/// hash table slot, it is empty unless `generation` is the dictionary's current one
+#[derive(Clone,Default)]
+struct Slot {
+    generation: u32,
+    /// prefix code and symbol packed as `code << 8 | sym`
+    key: u32,
+    code: u32
+}
+
+/// LZW string table.  Each code is stored as its prefix code and final symbol in arrays indexed by code,
+/// which is all expansion needs.  Compression finds the code for a prefix and symbol in a hash table.
+/// Entries are stamped with the generation they were added in, so a reset only starts a new generation.
+/// The symbols themselves are implicit and survive every reset.
+#[derive(Clone)]
+struct Dictionary {
+    min_symbol: usize,
+    max_symbol: usize,
+    prefix: Vec<u32>,
+    sym: Vec<u8>,
+    /// length of the string for each code
+    len: Vec<u32>,
+    /// generation each code was added in
+    added: Vec<u32>,
+    slots: Vec<Slot>,
+    /// shift that takes a hash down to a slot index
+    shift: u32,
+    generation: u32
+}
+
+impl Dictionary {
+    /// Dictionary for codes of up to `max_code_width` bits, the hash table is kept at most half full
+    fn create(max_code_width: usize,min_symbol: usize,max_symbol: usize) -> Self {
+        let codes = 1 << max_code_width;
+        let slot_bits = max_code_width as u32 + 1;
+        Self {
+            min_symbol,
+            max_symbol,
+            prefix: vec![0;codes],
+            sym: vec![0;codes],
+            len: vec![0;codes],
+            added: vec![0;codes],
+            slots: vec![Slot::default();1 << slot_bits],
+            shift: 32 - slot_bits,
+            generation: 1
+        }
+    }
+    /// Forget every code that is not a symbol
+    fn reset(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            // after wrapping around, old stamps could look current
+            self.added.fill(0);
+            self.slots.fill(Slot::default());
+            self.generation = 1;
+        }
+    }
+    fn is_symbol(&self,code: usize) -> bool {
+        code >= self.min_symbol && code <= self.max_symbol
+    }
+    fn contains(&self,code: usize) -> bool {
+        self.is_symbol(code) || (code < self.added.len() && self.added[code] == self.generation)
+    }
+    /// Number of codes that are defined, symbols included, this counts so it is for error reports
+    fn size(&self) -> usize {
+        self.max_symbol + 1 - self.min_symbol + self.added.iter().filter(|g| **g == self.generation).count()
+    }
+    fn slot_for(&self,key: u32) -> usize {
+        (key.wrapping_mul(0x9e3779b1) >> self.shift) as usize
+    }
+    /// Code for the string `prefix` followed by `sym`, if there is one
+    fn child(&self,prefix: usize,sym: u8) -> Option<usize> {
+        let key = (prefix as u32) << 8 | sym as u32;
+        let mask = self.slots.len() - 1;
+        let mut i = self.slot_for(key);
+        loop {
+            let slot = &self.slots[i];
+            if slot.generation != self.generation {
+                return None;
+            }
+            if slot.key == key {
+                return Some(slot.code as usize);
             }
+            i = (i + 1) & mask;
         }
     }
-    pub fn get_code<R: Read>(&mut self,num_bits: usize,reader: &mut BufReader<R>) -> Result<usize,std::io::Error> {
-        let mut ans: usize = 0;
-        match self.ord {
-            BitOrder::Msb0 => {
-                for _i in 0..num_bits {
-                    ans <<= 1;
-                    ans |= self.get_bit(reader)? as usize;
-                }    
-            },
-            BitOrder::Lsb0 => {
-                for i in 0..num_bits {
-                    ans |= (self.get_bit(reader)? as usize) << i;
-                }    
+    /// Add `code` as the string `prefix` followed by `sym`, the code must not be in use
+    fn insert(&mut self,code: usize,prefix: usize,sym: u8) {
+        self.prefix[code] = prefix as u32;
+        self.sym[code] = sym;
+        self.len[code] = self.string_len(prefix) + 1;
+        self.added[code] = self.generation;
+        let key = (prefix as u32) << 8 | sym as u32;
+        let mask = self.slots.len() - 1;
+        let mut i = self.slot_for(key);
+        while self.slots[i].generation == self.generation {
+            i = (i + 1) & mask;
+        }
+        self.slots[i] = Slot { generation: self.generation, key, code: code as u32 };
+    }
+    fn string_len(&self,code: usize) -> u32 {
+        match self.is_symbol(code) {
+            true => 1,
+            false => self.len[code]
+        }
+    }
+    /// Put the string for `code` in `buf`, filling it from the end by walking back through the prefixes
+    fn get_string(&self,mut code: usize,buf: &mut Vec<u8>) {
+        let n = self.string_len(code) as usize;
+        buf.clear();
+        buf.resize(n,0);
+        for i in (0..n).rev() {
+            if self.is_symbol(code) {
+                buf[i] = code as u8;
+                break;
             }
+            buf[i] = self.sym[code];
+            code = self.prefix[code] as usize;
         }
-        self.count += 1;
-        Ok(ans)
     }
 }
 
-/// Dictionary element, can be a key or value.
-/// This stores an LZW code and a symbol, which typically is
-/// what we need to do a lookup during encoding, or reconstruct
-/// a string during decoding.
+/// Recent output of expansion, so that a code whose string was written lately can be copied from
+/// there instead of being spelled out by walking back through its prefixes.
+/// Nothing is allocated until expansion first uses it.
 #[derive(Clone)]
-struct Link {
-    code: usize,
-    sym: usize
+struct History {
+    /// the last `ring.len()` bytes written, byte `i` of the output is at `i % ring.len()`
+    ring: Vec<u8>,
+    /// total bytes written
+    end: u64,
+    /// for each code, where its string starts in the output, counting like `end`
+    start: Vec<u64>
 }
 
-impl Link {
-    fn root(code: usize) -> Self {
-        // root can be identified by setting sym to any consistent
-        // value that is out of range of valid codes 
+impl History {
+    /// bytes of output that are kept
+    const SIZE: usize = 1 << 17;
+    fn new() -> Self {
         Self {
-            code,
-            sym: usize::MAX
+            ring: Vec::new(),
+            end: 0,
+            start: Vec::new()
         }
     }
-    fn create(code: usize, sym: usize) -> Self {
-        Self {
-            code,
-            sym
+    fn allocate(&mut self,codes: usize) {
+        if self.ring.is_empty() {
+            self.ring = vec![0;Self::SIZE];
+            self.start = vec![0;codes];
+        }
+    }
+    /// Make every start recorded so far too old to use, along with 0,
+    /// for when the dictionary is restored without the output that built it
+    fn forget(&mut self) {
+        self.end += Self::SIZE as u64 + 1;
+    }
+    /// Remember that the string for `code` starts at output position `pos`
+    fn set_start(&mut self,code: usize,pos: u64) {
+        self.start[code] = pos;
+    }
+    /// Put the `n` bytes of output starting where `code` was recorded in `buf`, if they are still kept.
+    fn get(&self,code: usize,n: usize,buf: &mut Vec<u8>) -> bool {
+        let beg = self.start[code];
+        if beg + n as u64 > self.end || self.end - beg > Self::SIZE as u64 {
+            return false;
         }
+        let i = (beg % Self::SIZE as u64) as usize;
+        let first = usize::min(n,Self::SIZE - i);
+        buf.clear();
+        buf.extend_from_slice(&self.ring[i..i+first]);
+        buf.extend_from_slice(&self.ring[0..n-first]);
+        true
     }
-    fn hash(&self) -> (usize,usize) {
-        (self.code,self.sym)
+    /// Append to the output that is kept
+    fn push(&mut self,buf: &[u8]) {
+        let mut rest = buf;
+        if rest.len() > Self::SIZE {
+            self.end += (rest.len() - Self::SIZE) as u64;
+            rest = &rest[rest.len()-Self::SIZE..];
+        }
+        let i = (self.end % Self::SIZE as u64) as usize;
+        let first = usize::min(rest.len(),Self::SIZE - i);
+        self.ring[i..i+first].copy_from_slice(&rest[0..first]);
+        self.ring[0..rest.len()-first].copy_from_slice(&rest[first..]);
+        self.end += rest.len() as u64;
     }
 }
 
 /// Structure to perform LZW compression.
+#[derive(Clone)]
 struct LZW {
     opt: Options,
-    /// when used in compression, (base_code,sym) maps to {code,*}.
-    /// when used in expansion, (code,*) maps to {base_code,sym}
-    dictionary: HashMap<(usize,usize),Link>,
+    dictionary: Dictionary,
     /// the code most recently added to the dictionary
     curr_code: Option<usize>,
-    /// the key that has just been matched
-    curr_match: Option<Link>
+    /// code for the string that has just been matched
+    curr_match: Option<usize>,
+    /// If a segment has ended and the stream continues, the number of codes in the current chunk.
+    /// This survives `reset`, since chunks can begin and end within a segment.
+    carry: Option<usize>,
+    /// set by expansion if the data ran out where the format calls for more
+    truncated: bool,
+    /// if present expansion records every token here
+    trace: Option<Vec<TraceEvent>>,
+    /// if present expansion records where each chunk starts here, relative to where it began
+    index: Option<Vec<Checkpoint>>,
+    /// if present expansion continues from here instead of starting a chunk
+    resume: Option<Snapshot>,
+    /// expansion spells out each code here, kept so the allocation carries over between chunks and runs
+    scratch: Vec<u8>,
+    /// recent output, used by expansion only
+    history: History
+}
+
+/// How a call to one of the core functions relates to the whole stream
+#[derive(Clone,Copy,PartialEq)]
+enum Segment {
+    Whole,
+    /// part of a stream, `true` if it is the last part
+    Part(bool)
+}
+
+/// Decoded item reported by `expand_traced`
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize),serde(tag="kind",rename_all="snake_case"))]
+pub enum Token {
+    /// chunk length header, the value as it appears in the stream
+    Header { value: usize },
+    /// a code of `width` bits that expanded to `length` bytes, codes below 256 are literals
+    Code { code: usize, width: usize, length: usize },
+    /// the dictionary is reset
+    Clear,
+    /// end of data
+    Stop
 }
 
+/// A token and where it was found
+#[derive(Clone,Debug,PartialEq)]
+#[cfg_attr(feature="serde",derive(serde::Serialize))]
+pub struct TraceEvent {
+    /// bits consumed starting from `opt.in_offset`, when the token begins
+    pub bit: u64,
+    /// bytes written so far, starting from `opt.out_offset`
+    pub pos: u64,
+    #[cfg_attr(feature="serde",serde(flatten))]
+    pub token: Token
+}
+
+/// Snapshot of the dictionary and code counter between segments, see `Compressor::compress_segment`
+/// and `Expander::expand_segment`.  Only restore to an object created with the same options.
+#[derive(Clone)]
+pub struct State(LZW);
+
 impl LZW {
-    /// Create LZW structures, including initial dictionary, can
-    /// also be used to reset LZW for a new block.
+    /// Create LZW structures, including initial dictionary.
     /// Allowed to panic if options cannot be satisfied.
     fn create(opt: Options) -> Self {
-        if opt.min_code_width != opt.max_code_width {
-            panic!("variable code width not supported");
-        }
-        if opt.min_symbol != 0 {
-            panic!("minimum symbol value must be 0");
+        if let Err(e) = opt.validate() {
+            panic!("{}",e);
         }
         let mut lzw = Self {
             opt: opt.clone(),
-            dictionary: HashMap::new(),
+            dictionary: Dictionary::create(opt.max_code_width,opt.min_symbol,opt.max_symbol),
             curr_code: None,
-            curr_match: None
+            curr_match: None,
+            carry: None,
+            truncated: false,
+            trace: None,
+            index: None,
+            resume: None,
+            scratch: Vec::new(),
+            history: History::new()
         };
-        for i in opt.min_symbol..=opt.max_symbol {
-            lzw.dictionary.insert(Link::root(i).hash(), Link::create(i,i));
-        }
+        lzw.reset();
         lzw
     }
-    /// Walk back through the concatentation sequence to form the string, this does a lookup
-    /// for every symbol, so this may be where we pay the biggest price for sub-optimal hashing.
-    fn get_string(&self,mut code: usize) -> Vec<u8> {
-        let mut rev = Vec::new();
-        loop {
-            let val = self.dictionary.get(&Link::root(code).hash()).unwrap();
-            rev.push(val.sym as u8);
-            if val.sym == val.code && code >= self.opt.min_symbol && code <= self.opt.max_symbol {
-                break;
-            }
-            code = val.code
+    /// Put the dictionary back in its initial state, keeping the allocation
+    fn reset(&mut self) {
+        self.dictionary.reset();
+        self.curr_code = None;
+        self.curr_match = None;
+    }
+    /// Add to the trace, if one is being kept
+    fn record(&mut self,bit: u64,pos: u64,token: Token) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent { bit, pos, token });
         }
-        rev.iter().rev().map(|x| *x).collect()
     }
     /// Return the next available code, or None if bit width would be exceeded,
     /// Also updates `self.curr_code`, unless None is returned, in which case
     /// it retains the maximum value.
     fn advance_code(&mut self) -> Option<usize> {
         let max_code = ((1 as usize) << self.opt.max_code_width) - 1;
+        let ans = self.peek_code();
+        self.curr_code = Some(ans.unwrap_or(max_code));
+        ans
+    }
+    /// The code `advance_code` would return, without changing anything
+    fn peek_code(&self) -> Option<usize> {
+        let max_code: usize = (1 << self.opt.max_code_width) - 1;
         let mut new_code = match self.curr_code {
             None => 0,
             Some(c) => c + 1
@@ -334,12 +801,37 @@ impl LZW {
             }
         }
         if new_code > max_code {
-            self.curr_code = Some(max_code);
             return None;
         }
-        self.curr_code = Some(new_code);
         Some(new_code)
     }
+    /// Width needed to write `code`, within the limits of the options, `None` gives the max width
+    fn width_for(&self,code: Option<usize>) -> usize {
+        match code {
+            Some(c) => (usize::BITS - c.leading_zeros()).clamp(self.opt.min_code_width as u32,self.opt.max_code_width as u32) as usize,
+            None => self.opt.max_code_width
+        }
+    }
+    /// Snapshot for expansion to continue after compression wrote `prev_code`.  Expansion adds the entry that
+    /// extends `prev_code` when it reads the next code, so that entry is left out, and `curr_code` is from before it was added.
+    fn snapshot(&self,curr_code: Option<usize>,prev_code: usize,bit_count: u64,run_bits: u64,sum: u32) -> Snapshot {
+        let dict = &self.dictionary;
+        let pending = self.curr_code.filter(|code| Some(*code) != curr_code);
+        let entries = (0..dict.added.len())
+            .filter(|code| dict.added[*code] == dict.generation && Some(*code) != pending)
+            .map(|code| (code as u32,dict.prefix[code],dict.sym[code]))
+            .collect();
+        Snapshot { entries, curr_code, prev_code, bit_count, run_bits, chunk_bits: 0, sum }
+    }
+    /// Put the dictionary in the state a snapshot was taken in
+    fn restore(&mut self,snapshot: &Snapshot) {
+        self.dictionary.reset();
+        for (code,prefix,sym) in &snapshot.entries {
+            self.dictionary.insert(*code as usize,*prefix as usize,*sym);
+        }
+        self.curr_code = snapshot.curr_code;
+        self.curr_match = None;
+    }
     /// Try to match concatenation of `self.curr_match` with `next_sym`.
     /// If matching, update `self.curr_match` and return `true`, caller should call again with the next symbol.
     /// If not matching, create a new dictionary entry and return `false`, caller should write the code for `self.curr_match`,
@@ -347,23 +839,23 @@ impl LZW {
     /// If not matching and no more symbols available return `None`, caller can proceed as if `false` was returned,
     /// or choose to reset the dictionary.
     /// After calling this, `self.curr_match` should always be `Some`, assuming a valid dictionary.
-    fn check_match(&mut self,next_sym: usize) -> Option<bool> {
-        let search_key = match &self.curr_match {
-            Some(curr_match) => {
-                let base = self.dictionary.get(&curr_match.hash()).unwrap();
-                Link::create(base.code,next_sym)
-            },
-            None => Link::root(next_sym)
+    fn check_match(&mut self,next_sym: u8) -> Option<bool> {
+        let prefix = match self.curr_match {
+            Some(code) => code,
+            None => {
+                self.curr_match = Some(next_sym as usize);
+                return Some(true);
+            }
         };
-        match self.dictionary.contains_key(&search_key.hash()) {
-            true => {
-                self.curr_match = Some(search_key.clone());
+        match self.dictionary.child(prefix,next_sym) {
+            Some(code) => {
+                self.curr_match = Some(code);
                 Some(true)
             },
-            false => {
+            None => {
                 match self.advance_code() {
                     Some(code) => {
-                        self.dictionary.insert(search_key.hash(),Link::create(code,0));
+                        self.dictionary.insert(code,prefix,next_sym);
                         Some(false)
                     },
                     None => None
@@ -373,51 +865,340 @@ impl LZW {
     }
 }
 
+/// Write the header for the chunk that was just closed.  If the header goes before the chunk,
+/// back up to the placeholder and use the coder state that was saved there, then restore the position.
+fn write_header<W: Write + Seek>(opt: &Options,coder: &mut LZWCoder,old_coder_state: &mut LZWCoder,offset: u64,writer: &mut W) -> Result<(),std::io::Error> {
+    if opt.header_bits == 0 {
+        return Ok(());
+    }
+    let val = header_value(coder.count,opt);
+    match opt.header_placement {
+        HeaderPlacement::Before => {
+            let save_offset = writer.stream_position()?;
+            writer.seek(SeekFrom::Start(offset))?;
+            old_coder_state.put_code(opt.header_bits,val,writer)?;
+            old_coder_state.finish(writer)?;
+            writer.seek(SeekFrom::Start(save_offset))?;
+        },
+        HeaderPlacement::After => {
+            coder.put_code(opt.header_bits,val,writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// Put the length of the chunk that was just closed, given `count` codes, in the snapshots taken inside it,
+/// if the header goes before the chunk
+fn set_chunk_bits(opt: &Options,count: usize,index: &mut [Checkpoint]) {
+    if opt.header_bits == 0 || opt.header_placement != HeaderPlacement::Before {
+        return;
+    }
+    let chunk_bits = chunk_bits_from_header(header_value(count,opt),opt);
+    for snapshot in index.iter_mut().filter_map(|cp| cp.state.as_mut()) {
+        snapshot.chunk_bits = chunk_bits;
+    }
+}
+
+/// Write the checksum for the chunk that was just closed, if any
+fn write_checksum<W: Write + Seek>(opt: &Options,coder: &mut LZWCoder,sum: u32,writer: &mut W) -> Result<(),std::io::Error> {
+    if let Some(kind) = &opt.chunk_checksum {
+        coder.put_code(kind.width(),kind.checksum().finalize(sum) as usize,writer)?;
+    }
+    Ok(())
+}
+
+/// Reusable compression state.  The dictionary is allocated once and reset in place,
+/// which helps when there are many small buffers.  Panics if options are inconsistent.
+pub struct Compressor {
+    opt: Options,
+    lzw: LZW
+}
+
+/// Reusable expansion state.  The dictionary is allocated once and reset in place,
+/// which helps when there are many small buffers.  Panics if options are inconsistent.
+pub struct Expander {
+    opt: Options,
+    lzw: LZW,
+    /// checkpoints found by `expand_range` or given to `set_index`
+    index: Vec<Checkpoint>
+}
+
+impl Compressor {
+    pub fn new(opt: &Options) -> Self {
+        Self {
+            opt: opt.clone(),
+            lzw: LZW::create(opt.clone())
+        }
+    }
+    /// Put the dictionary back in the initial state, this happens automatically for each compression
+    pub fn reset(&mut self) {
+        self.lzw.reset();
+        self.lzw.carry = None;
+    }
+    /// Snapshot of the state that the next `compress_segment` would continue from
+    pub fn save_state(&self) -> State {
+        State(self.lzw.clone())
+    }
+    /// Continue from a snapshot, which must come from an object with the same options
+    pub fn restore_state(&mut self, state: &State) {
+        self.lzw = state.0.clone();
+    }
+    /// Compress one segment of a stream that is interrupted by other data, or split across files.
+    /// The dictionary carries over from the previous segment, unless `reset` was called or the
+    /// previous segment was the `last` one.  Each segment is padded to a byte boundary, and the stop
+    /// code is only written after the `last` segment.  Returns (in_size,out_size) for this segment.
+    /// Panics if chunks have headers or checksums.
+    pub fn compress_segment<R,W>(&mut self, expanded_in: &mut R, compressed_out: &mut W, last: bool) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        if self.opt.header_bits > 0 || self.opt.chunk_checksum.is_some() {
+            panic!("segments cannot have chunk headers or checksums");
+        }
+        let mut reader = BufReader::new(expanded_in as &mut dyn ReadSeek);
+        let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+        let (in_size,out_size,_) = compress_core(&mut reader,&mut writer,&self.opt,None,&mut self.lzw,Segment::Part(last))?;
+        Ok((in_size,out_size))
+    }
+    /// Same as the module level `compress`, using the options this was created with
+    pub fn compress<R,W>(&mut self, expanded_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        self.compress_dyn(expanded_in,compressed_out)
+    }
+    /// Same as `compress` with the streams as trait objects
+    pub fn compress_dyn(&mut self, expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        let mut reader = BufReader::new(expanded_in);
+        let mut writer = BufWriter::new(compressed_out);
+        let (in_size,out_size,_) = compress_core(&mut reader,&mut writer,&self.opt,None,&mut self.lzw,Segment::Whole)?;
+        Ok((in_size,out_size))
+    }
+    /// Same as the module level `compress_slice`, using the options this was created with
+    pub fn compress_slice(&mut self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        let mut src = Cursor::new(slice);
+        let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(slice.len()));
+        compress_core(&mut src,&mut ans,&self.opt,None,&mut self.lzw,Segment::Whole)?;
+        Ok(ans.into_inner())
+    }
+}
+
+impl Expander {
+    pub fn new(opt: &Options) -> Self {
+        Self {
+            opt: opt.clone(),
+            lzw: LZW::create(opt.clone()),
+            index: Vec::new()
+        }
+    }
+    /// Put the dictionary back in the initial state, this happens automatically for each expansion.
+    /// This also forgets the checkpoints found by `expand_range` or given to `set_index`.
+    pub fn reset(&mut self) {
+        self.lzw.reset();
+        self.lzw.carry = None;
+        self.index.clear();
+    }
+    /// Use the checkpoints that `compress_indexed` returned for this stream in the ranges that follow
+    pub fn set_index(&mut self,index: Vec<Checkpoint>) {
+        self.index = index;
+    }
+    /// Snapshot of the state that the next `expand_segment` would continue from
+    pub fn save_state(&self) -> State {
+        State(self.lzw.clone())
+    }
+    /// Continue from a snapshot, which must come from an object with the same options
+    pub fn restore_state(&mut self, state: &State) {
+        self.lzw = state.0.clone();
+    }
+    /// Expand one segment produced by `Compressor::compress_segment`, where `len` is the expanded
+    /// length of the segment, which the surrounding format has to provide.  If this is the `last`
+    /// segment and there is a stop code, expansion continues to the stop code instead.
+    /// The dictionary carries over from the previous segment, unless `reset` was called or this was the `last` one.
+    /// Returns (in_size,out_size) for this segment, where `in_size` includes the padding.
+    /// Panics if chunks have headers or checksums.
+    pub fn expand_segment<R,W>(&mut self, compressed_in: &mut R, expanded_out: &mut W, len: u64, last: bool) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        if self.opt.header_bits > 0 || self.opt.chunk_checksum.is_some() {
+            panic!("segments cannot have chunk headers or checksums");
+        }
+        let mut seg_opt = self.opt.clone();
+        seg_opt.expanded_size = match (last,self.opt.stop_code) {
+            (true,Some(_)) => None,
+            _ => Some(len)
+        };
+        let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+        let mut writer = BufWriter::new(expanded_out as &mut dyn WriteSeek);
+        let (_,bits,out_size) = expand_core(&mut reader,&mut writer,&seg_opt,0,&mut self.lzw,Segment::Part(last))?;
+        Ok((bits.div_ceil(8),out_size))
+    }
+    /// Same as the module level `expand`, using the options this was created with
+    pub fn expand<R,W>(&mut self, compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        self.expand_dyn(compressed_in,expanded_out)
+    }
+    /// Same as `expand` with the streams as trait objects
+    pub fn expand_dyn(&mut self, compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+        let mut reader = BufReader::new(compressed_in);
+        let mut writer = BufWriter::new(expanded_out);
+        let (in_size,_,out_size) = expand_core(&mut reader,&mut writer,&self.opt,0,&mut self.lzw,Segment::Whole)?;
+        Ok((in_size,out_size))
+    }
+    /// Same as the module level `expand_slice`, using the options this was created with
+    pub fn expand_slice(&mut self, slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
+        let mut src = Cursor::new(slice);
+        let capacity = match self.opt.expanded_size {
+            Some(sz) => usize::try_from(sz).map_err(|_| crate::Error::FileTooLarge)?,
+            None => 2*slice.len()
+        };
+        let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(capacity));
+        expand_core(&mut src,&mut ans,&self.opt,0,&mut self.lzw,Segment::Whole)?;
+        Ok(ans.into_inner())
+    }
+    /// Same as the module level `expand_range`, using the options this was created with, and the
+    /// checkpoints found while expanding earlier ranges as the index, so that reading a stream in order
+    /// starts each range from the chunk it falls in rather than from the beginning.
+    /// Checkpoints are only found at the start of a chunk, and only if chunks are delimited by `clear_code` or a header before the chunk.
+    /// Every call has to be on the same stream, use `reset` before moving to another.
+    pub fn expand_range<R,W>(&mut self, compressed_in: &mut R, expanded_out: &mut W, start: u64, len: u64) -> Result<u64,DYNERR>
+    where R: Read + Seek, W: Write + Seek {
+        let indexed = self.opt.clear_code.is_some() || (self.opt.header_bits > 0 && self.opt.header_placement == HeaderPlacement::Before);
+        let (count,found) = range_core(compressed_in,expanded_out,&self.opt,&self.index,start,len,indexed)?;
+        let known = self.index.last().map(|cp| cp.expanded_offset);
+        self.index.extend(found.into_iter().filter(|cp| known.is_none_or(|offset| cp.expanded_offset > offset)));
+        Ok(count)
+    }
+    /// Same as the module level `expand_into`, using the options this was created with
+    pub fn expand_into(&mut self, slice: &[u8], buf: &mut [u8]) -> Result<usize,DYNERR> {
+        self.expand_reader_into(&mut Cursor::new(slice),buf)
+    }
+    /// Same as the module level `expand_reader_into`, using the options this was created with.
+    /// Once the object has expanded something, further calls allocate nothing.
+    pub fn expand_reader_into<R: Read + Seek>(&mut self, compressed_in: &mut R, buf: &mut [u8]) -> Result<usize,DYNERR> {
+        let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+        let mut ans = Cursor::new(buf);
+        let (_,_,out_size) = expand_core(&mut reader,&mut ans,&self.opt,0,&mut self.lzw,Segment::Whole).map_err(crate::buffer_full)?;
+        Ok(out_size as usize)
+    }
+}
+
 /// Main compression function.
 /// `expanded_in` is an object with `Read` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<&[u8]>`.
 /// `compressed_out` is an object with `Write` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<Vec<u8>>`.
 /// Returns (in_size,out_size) or error.  Can panic if options are inconsistent.
 pub fn compress<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
 where R: Read + Seek, W: Write + Seek {
-    let mut reader = BufReader::new(expanded_in);
-    let mut writer = BufWriter::new(compressed_out);
+    compress_dyn(expanded_in,compressed_out,opt)
+}
+
+/// Same as `compress` with the streams as trait objects
+pub fn compress_dyn(expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    Compressor::new(opt).compress_dyn(expanded_in,compressed_out)
+}
+
+/// Compress bytes from an iterator, items can be `u8` or `std::io::Result<u8>`.
+/// The data is consumed as it is compressed, there is no need to collect it first.
+/// `opt.in_offset` is the number of items to skip.  Returns (in_size,out_size).
+pub fn compress_iter<I,W>(expanded_in: I, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where I: IntoIterator, I::Item: ByteItem, W: Write + Seek {
+    let mut reader = IterReader::new(expanded_in);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+    let (in_size,out_size,_) = compress_core(&mut reader,&mut writer,opt,None,&mut LZW::create(opt.clone()),Segment::Whole)?;
+    Ok((in_size,out_size))
+}
+
+/// Same as `compress`, with the same output, except that a checkpoint is recorded whenever `interval` bytes
+/// of input have been consumed since the last one, so that `expand_range` can start expanding near any position.
+/// Checkpoints inside a chunk hold a snapshot of the dictionary.  Returns (in_size,out_size,index).
+/// Returns `Error::InvalidOptions` if the options are inconsistent.
+pub fn compress_indexed<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options, interval: u64) -> Result<(u64,u64,Vec<Checkpoint>),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    opt.validate()?;
+    let mut reader = BufReader::new(expanded_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+    compress_core(&mut reader,&mut writer,opt,Some(interval),&mut LZW::create(opt.clone()),Segment::Whole)
+}
+
+/// Compression working directly on the streams, buffering is up to the caller.
+/// Returns (in_size,out_size,index)
+fn compress_core<R,W>(reader: &mut R, writer: &mut W, opt: &Options, interval: Option<u64>, lzw: &mut LZW, seg: Segment) -> Result<(u64,u64,Vec<Checkpoint>),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("lzw_compress",in_offset=opt.in_offset,out_offset=opt.out_offset).entered();
     let mut coder = LZWCoder::new(opt.ord.clone());
 
-    let mut expanded_length = reader.seek(SeekFrom::End(0))?;
-    if opt.in_offset > expanded_length {
-        return Err(Box::new(crate::Error::FileFormatMismatch));
-    }
-    expanded_length -= opt.in_offset;
-    if expanded_length > opt.max_file_size {
-        return Err(Box::new(crate::Error::FileTooLarge));
+    // the end is unknown if the input is an iterator, in which case the size is checked as we go
+    crate::seek_input(reader,opt.in_offset)?;
+    let end = known_end(reader)?;
+    if let Some(end) = end {
+        if end - opt.in_offset > opt.max_file_size {
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
     }
+    crate::place_output(writer,opt.out_offset,opt.offset_gap)?;
     let mut write_offset_header = opt.out_offset;
     let mut read_chunk_offset = opt.in_offset;
     let mut old_coder_state = LZWCoder::new(opt.ord.clone());
     let mut sym_in: [u8;1] = [0];
+    let mut index = Vec::new();
+    let mut last_checkpoint;
+    let mut carry = match seg {
+        Segment::Whole => None,
+        Segment::Part(_) => lzw.carry
+    };
+    lzw.carry = None;
+
+    if let (true,Some(code),None) = (opt.initial_clear,opt.clear_code,carry) {
+        log::debug!("emit initial clear code");
+        lzw.reset();
+        let width = lzw.width_for(lzw.peek_code());
+        writer.seek(SeekFrom::Start(write_offset_header))?;
+        coder.put_code(width,code,writer)?;
+        if opt.pad_code_groups {
+            coder.pad_group(width,writer)?;
+        }
+        write_offset_header = writer.stream_position()?;
+        old_coder_state = coder.clone();
+    }
 
     log::debug!("entering loop over chunks");
     loop {
-        log::debug!("create LZW dictionary");
-        let mut lzw = LZW::create(opt.clone());
+        if carry.is_none() {
+            log::debug!("reset LZW dictionary");
+            lzw.reset();
+        }
         reader.seek(SeekFrom::Start(read_chunk_offset))?;
         writer.seek(SeekFrom::Start(write_offset_header))?;
+        index.push(Checkpoint {
+            expanded_offset: read_chunk_offset - opt.in_offset,
+            compressed_bit: 8*(write_offset_header - opt.out_offset) + coder.bits.pending() as u64,
+            state: None
+        });
+        last_checkpoint = read_chunk_offset;
+        let chunk_index = index.len();
         //placeholder for header 
-        if opt.header_bits > 0 {
-            coder.put_code(opt.header_bits,0,&mut writer);
+        if opt.header_bits > 0 && opt.header_placement == HeaderPlacement::Before {
+            coder.put_code(opt.header_bits,0,writer)?;
         }
-        coder.count = 0;
+        // bits of codes in the chunk, counted the way expansion does
+        let mut chunk_bits: u64 = 0;
+        coder.count = carry.take().unwrap_or(0);
+        let mut width = lzw.width_for(lzw.peek_code());
+        let mut sum = init_checksum(opt);
         //let mut lookahead = 0;
         log::debug!("entering loop over matches");
         loop {
             lzw.curr_match = None;
+            let prev_curr_code = lzw.curr_code;
             // loop to build the longest possible match
             loop {
                 match reader.read_exact(&mut sym_in) {
                     Ok(()) => {
-                        match lzw.check_match(sym_in[0] as usize) {
+                        if sym_in[0] as usize > opt.max_symbol {
+                            log::error!("byte {} exceeds the maximum symbol",sym_in[0]);
+                            return Err(Box::new(crate::Error::FileFormatMismatch));
+                        }
+                        match lzw.check_match(sym_in[0]) {
                             Some(true) => {
                                 // keep matching
+                                if let Some(kind) = &opt.chunk_checksum {
+                                    sum = update_checksum(kind,sum,sym_in[0]);
+                                }
                             },
                             Some(false) => {
                                 // didn't match
@@ -431,42 +1212,75 @@ where R: Read + Seek, W: Write + Seek {
                         }
                     },
                     Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
-                        if let Some(curr) = &lzw.curr_match {
-                            let val = lzw.dictionary.get(&curr.hash()).unwrap(); // should never panic
-                            coder.put_code(opt.max_code_width,val.code,&mut writer);
+                        if let Some(code) = lzw.curr_match {
+                            coder.put_code(width,code,writer)?;
                         }
-                        if let Some(code) = opt.stop_code {
-                            coder.put_code(opt.max_code_width,code,&mut writer);
-                        }
-                        if opt.header_bits > 0 {
-                            writer.seek(SeekFrom::Start(write_offset_header))?;
-                            old_coder_state.put_code(opt.header_bits,coder.count*opt.max_code_width/opt.header_divisor,&mut writer);
+                        match (seg,opt.stop_code) {
+                            (Segment::Part(false),_) => lzw.carry = Some(coder.count),
+                            (_,Some(code)) => coder.put_code(width,code,writer)?,
+                            _ => {}
                         }
+                        set_chunk_bits(opt,coder.count,&mut index[chunk_index..]);
+                        write_header(opt,&mut coder,&mut old_coder_state,write_offset_header,writer)?;
+                        write_checksum(opt,&mut coder,sum,writer)?;
                         log::debug!("last chunk has {} codes",coder.count);
-                        writer.seek(SeekFrom::End(0))?; // coder could be rewound
+                        #[cfg(feature="tracing")]
+                        tracing::debug!(codes=coder.count,expanded_offset=read_chunk_offset-opt.in_offset,compressed_offset=write_offset_header-opt.out_offset,last=true,"lzw chunk");
+                        coder.finish(writer)?;
+                        writer.seek(SeekFrom::End(0))?; // header could have been written behind us
                         writer.flush()?;
-                        return Ok((expanded_length,writer.stream_position()? - opt.out_offset))
+                        let expanded_length = reader.stream_position()? - opt.in_offset;
+                        return Ok((expanded_length,writer.stream_position()? - opt.out_offset,index))
                     },
                     Err(e) => return Err(Box::new(e))
                 }
             }
             // should never panic
-            let curr = lzw.dictionary.get(&lzw.curr_match.as_ref().unwrap().hash()).unwrap();
-            log::trace!("code: {}",curr.code);
-            coder.put_code(opt.max_code_width,curr.code,&mut writer);
+            let curr = lzw.curr_match.unwrap();
+            log::trace!("code: {}",curr);
+            coder.put_code(width,curr,writer)?;
+            chunk_bits += width as u64;
+            // the decoder is one code behind, it will expect the code just added to fit
+            let next_width = lzw.width_for(lzw.curr_code);
+            if next_width != width {
+                log::debug!("code width changes to {}",next_width);
+                if opt.pad_code_groups {
+                    coder.pad_group(width,writer)?;
+                }
+                width = next_width;
+            }
             // backup to try the character that didn't match again
             reader.seek_relative(-1)?;
 
+            if end.is_none() && reader.stream_position()? - opt.in_offset > opt.max_file_size {
+                return Err(Box::new(crate::Error::FileTooLarge));
+            }
+            if let Some(n) = interval {
+                let pos = reader.stream_position()?;
+                if pos - last_checkpoint >= n {
+                    let bit = 8*(writer.stream_position()? - opt.out_offset) + coder.bits.pending() as u64;
+                    index.push(Checkpoint {
+                        expanded_offset: pos - opt.in_offset,
+                        compressed_bit: bit,
+                        state: Some(lzw.snapshot(prev_curr_code,curr,chunk_bits,coder.run_bits,sum))
+                    });
+                    last_checkpoint = pos;
+                }
+            }
             if coder.count >= opt.chunk_size {
                 log::debug!("close chunk with {} codes",coder.count);
+                #[cfg(feature="tracing")]
+                tracing::debug!(codes=coder.count,expanded_offset=read_chunk_offset-opt.in_offset,compressed_offset=write_offset_header-opt.out_offset,last=false,"lzw chunk");
                 if let Some(code) = opt.clear_code {
-                    coder.put_code(opt.max_code_width,code,&mut writer);
+                    coder.put_code(width,code,writer)?;
+                    if opt.pad_code_groups {
+                        coder.pad_group(width,writer)?;
+                    }
                 }
+                set_chunk_bits(opt,coder.count,&mut index[chunk_index..]);
+                write_header(opt,&mut coder,&mut old_coder_state,write_offset_header,writer)?;
+                write_checksum(opt,&mut coder,sum,writer)?;
                 let save_offset = writer.stream_position()?;
-                if opt.header_bits > 0 {
-                    writer.seek(SeekFrom::Start(write_offset_header))?;
-                    old_coder_state.put_code(opt.header_bits,coder.count*opt.max_code_width/opt.header_divisor,&mut writer);
-                }
                 old_coder_state = coder.clone();
                 write_offset_header = save_offset;
                 // read-position should be on the last symbol that didn't match
@@ -477,38 +1291,217 @@ where R: Read + Seek, W: Write + Seek {
     }
 }
 
+/// Compress a file by mapping it into memory, avoiding the seeking and buffering that `compress` does on the input.
+/// Offsets in `opt` are relative to the start of the file.
+/// The file must not be modified or truncated, by this process or any other, while this is running,
+/// the result of that is undefined behavior rather than an error, e.g. truncation can end the process with `SIGBUS`.
+#[cfg(feature="mmap")]
+pub fn compress_mapped<W: Write + Seek>(expanded_in: &std::fs::File, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    // SAFETY: the map is only read, and dropped before returning.  It stays valid only if nothing modifies
+    // or truncates the file while it is mapped, which the caller is required to ensure, see above.
+    let map = unsafe { memmap2::Mmap::map(expanded_in)? };
+    let mut reader = Cursor::new(&map[..]);
+    let mut writer = BufWriter::new(compressed_out as &mut dyn WriteSeek);
+    let (in_size,out_size,_) = compress_core(&mut reader,&mut writer,opt,None,&mut LZW::create(opt.clone()),Segment::Whole)?;
+    Ok((in_size,out_size))
+}
+
 /// Main decompression function.
 /// `compressed_in` is an object with `Read` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<&[u8]>`.
 /// `expanded_out` is an object with `Write` and `Seek` traits, usually `std::fs::File`, or `std::io::Cursor<Vec<u8>>`.
 /// Returns (in_size,out_size) or error.  Can panic if options are inconsistent.
 pub fn expand<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
 where R: Read + Seek, W: Write + Seek {
-    let mut reader = BufReader::new(compressed_in);
-    let mut writer = BufWriter::new(expanded_out);
+    expand_dyn(compressed_in,expanded_out,opt)
+}
+
+/// Same as `expand` with the streams as trait objects
+pub fn expand_dyn(compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    Expander::new(opt).expand_dyn(compressed_in,expanded_out)
+}
+
+/// Expand, handing the output to `callback` in pieces of at most `chunk_size` bytes, rather than writing it.
+/// `opt.out_offset` has no effect.  Returns (in_size,out_size).  Panics if `chunk_size` is 0.
+pub fn expand_chunked<R,F>(compressed_in: &mut R, opt: &Options, chunk_size: usize, mut callback: F) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, F: FnMut(&[u8]) {
+    let mut writer = ChunkWriter::new(opt.out_offset,chunk_size,&mut callback);
+    let ans = expand_dyn(compressed_in,&mut writer,opt)?;
+    writer.flush()?;
+    Ok(ans)
+}
+
+/// Same as `expand`, except returns (bits consumed,out_size).  This is mainly useful in
+/// combination with `opt.expanded_size`, in which case the compressed data ends at bit
+/// offset `8*opt.in_offset + bits`.
+pub fn expand_measured<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(expanded_out as &mut dyn WriteSeek);
+    let (_,bits,out_size) = expand_core(&mut reader,&mut writer,opt,0,&mut LZW::create(opt.clone()),Segment::Whole)?;
+    Ok((bits,out_size))
+}
+
+/// Expand and discard the output, to check the integrity of the compressed data.
+/// Returns (in_size,out_size,complete), where `complete` is false if the data ran out before a stop code,
+/// or before the end of a chunk whose length is in a header, which usually means it was truncated.
+/// Without a stop code or chunk headers the end cannot be detected, and `complete` is always true.
+/// Checksums are verified as usual if `opt.chunk_checksum` is set.
+/// The data is expanded as if `opt.truncation` were `Stop`, so that truncation is reported this way.
+pub fn verify<R: Read + Seek>(compressed_in: &mut R, opt: &Options) -> Result<(u64,u64,bool),DYNERR> {
+    let mut discard = |_: &[u8]| {};
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = ChunkWriter::new(opt.out_offset,DISCARD_CHUNK,&mut discard);
+    let opt = Options { truncation: Truncation::Stop, ..opt.clone() };
+    let mut lzw = LZW::create(opt.clone());
+    let (in_size,_,out_size) = expand_core(&mut reader,&mut writer,&opt,0,&mut lzw,Segment::Whole)?;
+    Ok((in_size,out_size,!lzw.truncated))
+}
+
+/// Expand and discard the output, recording every token, to find out where and why a stream goes wrong.
+/// The tokens decoded before any error are returned, along with the outcome of the expansion,
+/// which is (in_size,out_size) or the error.
+pub fn expand_traced<R: Read + Seek>(compressed_in: &mut R, opt: &Options) -> (Vec<TraceEvent>,Result<(u64,u64),DYNERR>) {
+    let mut discard = |_: &[u8]| {};
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = ChunkWriter::new(opt.out_offset,DISCARD_CHUNK,&mut discard);
+    let mut lzw = LZW::create(opt.clone());
+    lzw.trace = Some(Vec::new());
+    let res = expand_core(&mut reader,&mut writer,opt,0,&mut lzw,Segment::Whole);
+    (lzw.trace.take().unwrap_or_default(),res.map(|(in_size,_,out_size)| (in_size,out_size)))
+}
+
+/// Expand `len` bytes starting at `start` (relative to the start of the expanded data), decoding only as far as needed.
+/// If `index` is from `compress_indexed`, expansion begins at the last checkpoint at or before `start`,
+/// otherwise (e.g. if `index` is empty) expansion begins at the start and the preceding bytes are discarded.
+/// The bytes are written at `opt.out_offset`, returns the number written, which is less than `len`
+/// if the data ends sooner.
+pub fn expand_range<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options, index: &[Checkpoint], start: u64, len: u64) -> Result<u64,DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let (count,_) = range_core(compressed_in,expanded_out,opt,index,start,len,false)?;
+    Ok(count)
+}
+
+/// Expand a range as `expand_range` does.  If `find` is true, the start of every chunk that was
+/// expanded is recorded as a checkpoint.  Returns (bytes written,checkpoints).
+fn range_core<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options, index: &[Checkpoint], start: u64, len: u64, find: bool) -> Result<(u64,Vec<Checkpoint>),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut range_opt = opt.clone();
+    range_opt.out_offset = 0;
+    let mut skip_bits = 0;
+    let base = match index.iter().rev().find(|cp| cp.expanded_offset <= start) {
+        Some(checkpoint) => {
+            log::debug!("start expanding at bit {}",checkpoint.compressed_bit);
+            range_opt.in_offset = opt.in_offset + checkpoint.compressed_bit / 8;
+            range_opt.initial_clear = false;
+            skip_bits = (checkpoint.compressed_bit % 8) as usize;
+            checkpoint.clone()
+        },
+        None => {
+            log::debug!("no checkpoint, start expanding at beginning");
+            Checkpoint { expanded_offset: 0, compressed_bit: 0, state: None }
+        }
+    };
+    let mut window = RangeWriter::new(start - base.expanded_offset,len);
+    range_opt.expanded_size = match opt.expanded_size {
+        Some(sz) => Some(u64::min(sz,window.end())),
+        None => Some(window.end())
+    };
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut lzw = LZW::create(range_opt.clone());
+    lzw.index = find.then(Vec::new);
+    lzw.resume = base.state.clone();
+    expand_core(&mut reader,&mut window,&range_opt,skip_bits,&mut lzw,Segment::Whole)?;
+    let range = window.into_inner();
+    crate::place_output(expanded_out,opt.out_offset,opt.offset_gap)?;
+    expanded_out.write_all(&range)?;
+    // the bits that were skipped are counted by the decoder
+    let found = lzw.index.unwrap_or_default().into_iter().map(|cp| Checkpoint {
+        expanded_offset: base.expanded_offset + cp.expanded_offset,
+        compressed_bit: base.compressed_bit / 8 * 8 + cp.compressed_bit,
+        state: cp.state
+    }).collect();
+    Ok((range.len() as u64,found))
+}
+
+/// Decompression working directly on the streams, buffering is up to the caller.
+/// Expansion starts after skipping `skip_bits` bits at `opt.in_offset`.
+/// Returns (in_size,bits consumed,out_size)
+fn expand_core<R,W>(reader: &mut R, writer: &mut W, opt: &Options, skip_bits: usize, lzw: &mut LZW, seg: Segment) -> Result<(u64,u64,u64),DYNERR>
+where R: BufRead + Seek, W: Write + Seek {
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("lzw_expand",in_offset=opt.in_offset,out_offset=opt.out_offset).entered();
     let mut decoder = LZWDecoder::new(opt.ord.clone());
-    let mut compressed_size = reader.seek(SeekFrom::End(0))?;
-    if opt.in_offset > compressed_size {
-        return Err(Box::new(crate::Error::FileFormatMismatch));
-    }
-    compressed_size -= opt.in_offset;
+    crate::seek_input(reader,opt.in_offset)?;
+    let compressed_size = reader.seek(SeekFrom::End(0))? - opt.in_offset;
     if compressed_size > opt.max_file_size {
         return Err(Box::new(crate::Error::FileTooLarge));
     }
     reader.seek(SeekFrom::Start(opt.in_offset))?;
-    writer.seek(SeekFrom::Start(opt.out_offset))?;
+    crate::place_output(writer,opt.out_offset,opt.offset_gap)?;
+    if skip_bits > 0 {
+        decoder.get_code(skip_bits,reader)?;
+        decoder.run_bits = 0;
+    }
 
-    let mut end_of_data = false;
+    let mut carry = match seg {
+        Segment::Whole => None,
+        Segment::Part(_) => lzw.carry
+    };
+    lzw.carry = None;
+    lzw.truncated = false;
+    if let (true,Some(clear),None) = (opt.initial_clear,opt.clear_code,carry) {
+        lzw.reset();
+        let width = lzw.width_for(lzw.peek_code());
+        let code = match decoder.get_code(width,reader) {
+            Ok(code) => code,
+            Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
+                let missing_bits = width.saturating_sub(decoder.bits.waiting()).max(1) as u64;
+                log::error!("data ran out {} bits short of the initial clear code",missing_bits);
+                return Err(Box::new(crate::Error::TruncatedInput { missing_bits, expanded: 0 }));
+            },
+            Err(e) => return Err(Box::new(e))
+        };
+        if code != clear {
+            log::error!("expected initial clear code {}, got {}",clear,code);
+            return Err(Box::new(crate::Error::FileFormatMismatch));
+        }
+        lzw.record(decoder.bits_read - width as u64,0,Token::Clear);
+        if opt.pad_code_groups {
+            decoder.skip_group(width,reader)?;
+        }
+    }
+
+    let mut end_of_data = opt.expanded_size == Some(0);
+    let mut stopped = false;
+    let mut out_count: u64 = 0;
+    let mut prev_str = std::mem::take(&mut lzw.scratch);
+    lzw.history.allocate(1 << opt.max_code_width);
+    // where the string for `prev_code` starts in the output
+    let mut prev_start = lzw.history.end;
+    let mut resume = lzw.resume.take();
     log::debug!("entering loop over chunks");
-    loop {
-        log::debug!("create LZW dictionary");
-        let mut lzw = LZW::create(opt.clone());
+    while !end_of_data {
+        let snapshot = resume.take();
+        if carry.is_none() && snapshot.is_none() {
+            log::debug!("reset LZW dictionary");
+            lzw.reset();
+            if let Some(index) = &mut lzw.index {
+                index.push(Checkpoint { expanded_offset: out_count, compressed_bit: decoder.bits_read, state: None });
+            }
+        }
     
-        let chunk_bits = match opt.header_bits {
-            0 => usize::MAX,
-            num_bits => {
+        let chunk_bits = match (opt.header_bits,&opt.header_placement,&snapshot) {
+            (0,_,_) => u64::MAX,
+            (_,HeaderPlacement::After,_) => (opt.chunk_size as u64 + opt.clear_code.is_some() as u64).saturating_mul(opt.max_code_width as u64),
+            // the header was before the checkpoint
+            (_,HeaderPlacement::Before,Some(snapshot)) => snapshot.chunk_bits,
+            (num_bits,HeaderPlacement::Before,None) => {
                 log::debug!("read length of chunk");
-                match decoder.get_code(num_bits,&mut reader) {
-                    Ok(code) => opt.header_divisor * code,
+                match decoder.get_code(num_bits,reader) {
+                    Ok(code) => {
+                        lzw.record(decoder.bits_read - num_bits as u64,out_count,Token::Header { value: code });
+                        chunk_bits_from_header(code,opt)
+                    },
                     Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
                         break;
                     },
@@ -516,85 +1509,280 @@ where R: Read + Seek, W: Write + Seek {
                 }
             }
         };
-        lzw.curr_code = None;
+        if carry.take().is_none() {
+            lzw.curr_code = None;
+        }
         let mut prev_code = None;
-        let mut prev_str = Vec::new();
-        let mut bit_count = 0;
+        prev_str.clear();
+        // bits in a chunk are counted in 64 bits, since a chunk can be the whole stream
+        let mut bit_count: u64 = 0;
+        let mut sum = init_checksum(opt);
+        if let Some(snapshot) = &snapshot {
+            log::debug!("restore LZW dictionary with {} codes",snapshot.entries.len());
+            lzw.restore(snapshot);
+            lzw.dictionary.get_string(snapshot.prev_code,&mut prev_str);
+            prev_code = Some(snapshot.prev_code);
+            bit_count = snapshot.bit_count;
+            decoder.run_bits = snapshot.run_bits;
+            sum = snapshot.sum;
+            // the output that built the dictionary is not here to copy from
+            lzw.history.forget();
+            prev_start = 0;
+        }
+        let mut width = lzw.width_for(lzw.peek_code());
+        let mut control_codes = 0;
+        let mut cut_short = false;
     
         log::debug!("enter main LZW loop");
-        while bit_count < chunk_bits {
-            let code = match decoder.get_code(opt.max_code_width,&mut reader) {
+        while bit_count + width as u64 <= chunk_bits {
+            let next_width = lzw.width_for(lzw.peek_code());
+            let padding = match next_width != width && opt.pad_code_groups {
+                true => decoder.skip_group(width,reader),
+                false => Ok(())
+            };
+            width = next_width;
+            let code = match padding.and_then(|_| decoder.get_code(width,reader)) {
                 Ok(c) => c,
                 Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
+                    // a stop code, or the rest of a chunk whose length was given, should have followed
+                    let length_given = opt.header_bits > 0 && opt.header_placement == HeaderPlacement::Before;
+                    lzw.truncated = opt.stop_code.is_some() || length_given;
+                    let needed = match length_given {
+                        true => chunk_bits - bit_count,
+                        false => width as u64
+                    };
+                    let missing_bits = needed.saturating_sub(decoder.bits.waiting() as u64).max(1);
+                    if lzw.truncated && opt.truncation == Truncation::Error {
+                        log::error!("data ran out {} bits short after {} bytes were expanded",missing_bits,out_count);
+                        return Err(Box::new(crate::Error::TruncatedInput { missing_bits, expanded: out_count }));
+                    }
+                    if lzw.truncated {
+                        diagnostics::warn(Warning::Truncated { missing_bits });
+                    }
                     end_of_data = true;
+                    cut_short = true;
                     break;
                 },
                 Err(e) => return Err(Box::new(e))
             };
+            let code_bit = decoder.bits_read - width as u64;
             if let Some(stop) = opt.stop_code {
                 if code == stop {
+                    lzw.record(code_bit,out_count,Token::Stop);
                     end_of_data = true;
+                    stopped = true;
+                    control_codes += 1;
                     break;
                 }
             }
             if let Some(clear) = opt.clear_code {
                 if code == clear {
+                    lzw.record(code_bit,out_count,Token::Clear);
+                    control_codes += 1;
+                    if opt.pad_code_groups {
+                        match decoder.skip_group(width,reader) {
+                            Ok(()) => {},
+                            Err(e) if e.kind()==ErrorKind::UnexpectedEof => end_of_data = true,
+                            Err(e) => return Err(Box::new(e))
+                        }
+                    }
                     break;
                 }
             }
-            bit_count += opt.max_code_width;
+            bit_count += width as u64;
             let next_code = match prev_code {
                 None => None,
                 Some(_) => lzw.advance_code()
             };
-            match lzw.dictionary.contains_key(&Link::root(code).hash()) {
+            match lzw.dictionary.contains(code) {
                 false => {
-                    prev_str.push(prev_str[0]);
-                    if next_code.is_none() {
-                        log::error!("new code was needed but none were available");
-                        return Err(Box::new(crate::Error::FileFormatMismatch));
-                    }
-                    if code != next_code.unwrap() {
-                        log::error!("Bad LZW code, expected {}, got {}",next_code.unwrap(),code);
-                        return Err(Box::new(crate::Error::FileFormatMismatch));
+                    // only the next code can be used before it is defined, and only if there is a previous string
+                    let expected = match prev_code {
+                        None => lzw.peek_code(),
+                        Some(_) => next_code
+                    };
+                    if prev_str.is_empty() || expected != Some(code) {
+                        let dictionary_size = lzw.dictionary.size();
+                        return Err(Box::new(match expected {
+                            Some(next_code) if code >= next_code => {
+                                log::error!("code {} at bit {} is not defined yet, expected {}",code,code_bit,next_code);
+                                crate::Error::CodeFromFuture { code, bit: code_bit, next_code, dictionary_size }
+                            },
+                            _ => {
+                                let next_code = expected.unwrap_or(1 << opt.max_code_width);
+                                log::error!("code {} at bit {} is out of range, expected {}",code,code_bit,next_code);
+                                crate::Error::CodeOutOfRange { code, bit: code_bit, next_code, dictionary_size }
+                            }
+                        }));
                     }
+                    prev_str.push(prev_str[0]);
                 },
                 true => {
-                    prev_str = lzw.get_string(code);
+                    // copying from earlier output is much faster than walking a long chain of prefixes
+                    let n = lzw.dictionary.string_len(code) as usize;
+                    if lzw.dictionary.is_symbol(code) || !lzw.history.get(code,n,&mut prev_str) {
+                        lzw.dictionary.get_string(code,&mut prev_str);
+                    }
                 }
             };
             if let (Some(next_code),Some(prev_code)) = (next_code,prev_code) {
-                lzw.dictionary.insert(Link::root(next_code).hash(),Link::create(prev_code,prev_str[0] as usize));
+                lzw.dictionary.insert(next_code,prev_code,prev_str[0]);
+                // the new string is the previous one followed by the first byte of this one
+                lzw.history.set_start(next_code,prev_start);
                 log::trace!("add {} linking to {}.{}",next_code,prev_code,prev_str[0]);
             }
-            writer.write(&prev_str)?;
+            let mut n = prev_str.len();
+            if let Some(size) = opt.expanded_size {
+                if out_count + n as u64 >= size {
+                    n = (size - out_count) as usize;
+                    end_of_data = true;
+                    cut_short = true;
+                }
+            }
+            lzw.record(code_bit,out_count,Token::Code { code, width, length: n });
+            writer.write_all(&prev_str[0..n])?;
+            prev_start = lzw.history.end;
+            lzw.history.push(&prev_str[0..n]);
+            out_count += n as u64;
+            if let Some(kind) = &opt.chunk_checksum {
+                sum = prev_str[0..n].iter().fold(sum,|acc,b| update_checksum(kind,acc,*b));
+            }
             log::trace!("  write {} as {:?}",code,prev_str);
+            if cut_short {
+                log::debug!("reached expected size");
+                break;
+            }
             prev_code = Some(code);
         }
         log::debug!("chunk completed with {} bits",bit_count);
-        if end_of_data {
-            break;
+        #[cfg(feature="tracing")]
+        tracing::debug!(bits=bit_count,compressed_bit=decoder.bits_read,expanded_offset=out_count,"lzw chunk");
+        if opt.header_bits > 0 && opt.header_placement == HeaderPlacement::After && !cut_short {
+            let expected = header_value((bit_count / opt.max_code_width as u64) as usize + control_codes,opt);
+            match decoder.get_code(opt.header_bits,reader) {
+                Ok(val) if val==expected => {},
+                Ok(val) => {
+                    log::error!("chunk trailer mismatch, expected {}, got {}",expected,val);
+                    return Err(Box::new(crate::Error::FileFormatMismatch));
+                },
+                Err(e) => return Err(Box::new(e))
+            }
+        }
+        if let (Some(kind),false) = (&opt.chunk_checksum,cut_short) {
+            let sum = kind.checksum().finalize(sum);
+            match decoder.get_code(kind.width(),reader) {
+                Ok(val) if val==sum as usize => {},
+                Ok(val) => {
+                    log::error!("chunk checksum mismatch, expected {}, got {}",sum,val);
+                    return Err(Box::new(crate::Error::BadChecksum));
+                },
+                Err(e) => return Err(Box::new(e))
+            }
         }
     }
+    lzw.scratch = prev_str;
     log::debug!("end of data, closing stream");
+    if let (Some(limit),true,Segment::Whole) = (opt.trailing_limit,stopped,seg) {
+        let trailing = compressed_size.saturating_sub(decoder.bits_read.div_ceil(8));
+        if trailing > 0 {
+            diagnostics::warn(Warning::TrailingData { bytes: trailing });
+        }
+        if trailing > limit {
+            return Err(Box::new(crate::Error::TrailingData { bytes: trailing }));
+        }
+    }
+    if let (Segment::Part(false),false) = (seg,stopped) {
+        lzw.carry = Some(0);
+    }
     writer.flush()?;
-    Ok((compressed_size,writer.stream_position()? - opt.out_offset))
+    Ok((compressed_size,decoder.bits_read,writer.stream_position()? - opt.out_offset))
 }
 
-/// Convenience function, calls `compress` with a slice returning a Vec
+/// Compress and append the result to whatever is already in `compressed_out`, forming another member
+/// of a multi-member stream.  The member goes at the end of `compressed_out`, `opt.out_offset` is ignored.
+/// Returns (in_size,out_size) for the new member.
+pub fn compress_append<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let mut member_opt = opt.clone();
+    member_opt.out_offset = compressed_out.seek(SeekFrom::End(0))?;
+    compress(expanded_in,compressed_out,&member_opt)
+}
+
+/// Expand every member of a multi-member stream, concatenating the expanded data.
+/// Each member ends with a stop code, and the next member starts on the following byte.
+/// Returns (in_size,out_size) for each member, or an error of kind `InvalidInput` if there is no `stop_code`.
+pub fn expand_members<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<Vec<(u64,u64)>,DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    if opt.stop_code.is_none() {
+        log::error!("multiple members require a stop code");
+        return Err(Box::new(std::io::Error::new(ErrorKind::InvalidInput,"multiple members require a stop code")));
+    }
+    let mut reader = BufReader::new(compressed_in as &mut dyn ReadSeek);
+    let mut writer = BufWriter::new(expanded_out as &mut dyn WriteSeek);
+    let end = reader.seek(SeekFrom::End(0))?;
+    // the next member follows the stop code, so there is nothing to check
+    let mut member_opt = Options { trailing_limit: None, ..opt.clone() };
+    let mut lzw = LZW::create(opt.clone());
+    let mut ans = Vec::new();
+    while member_opt.in_offset < end {
+        let (_,bits,out_size) = expand_core(&mut reader,&mut writer,&member_opt,0,&mut lzw,Segment::Whole)?;
+        let in_size = bits.div_ceil(8);
+        if in_size == 0 {
+            // too few bits left for a code, nothing more can come out of them
+            break;
+        }
+        log::debug!("member {} expanded {} into {}",ans.len(),in_size,out_size);
+        ans.push((in_size,out_size));
+        member_opt.in_offset += in_size;
+        member_opt.out_offset += out_size;
+    }
+    writer.flush()?;
+    Ok(ans)
+}
+
+/// Expand a slice into a buffer the caller provides, starting at `opt.out_offset`, so nothing is allocated
+/// for the output.  Returns the number of bytes expanded, or `Error::FileTooLarge` if they do not fit.
+pub fn expand_into(slice: &[u8],buf: &mut [u8],opt: &Options) -> Result<usize,DYNERR> {
+    Expander::new(opt).expand_into(slice,buf)
+}
+
+/// Same as `expand_into`, reading from a stream.  No buffering is added, so a file should be wrapped
+/// in `BufReader`.  Use `Expander::expand_reader_into` to avoid allocating the dictionary on every call.
+pub fn expand_reader_into<R: Read + Seek>(compressed_in: &mut R,buf: &mut [u8],opt: &Options) -> Result<usize,DYNERR> {
+    Expander::new(opt).expand_reader_into(compressed_in,buf)
+}
+
+/// Upper bound on the size of the data produced by compressing `input_len` bytes (not counting `opt.out_offset`).
+/// Every code stands for at least one byte, and each chunk can add a clear code, header, and checksum.
+/// The bound saturates at `usize::MAX` if it does not fit, as can happen on 32 bit targets.
+pub fn compress_bound(input_len: usize,opt: &Options) -> usize {
+    // worked in 128 bits so that no input length can overflow
+    let n = input_len as u128;
+    let width = opt.max_code_width as u128;
+    let chunks = n / usize::max(opt.chunk_size,1) as u128 + 1;
+    let per_chunk = opt.header_bits as u128
+        + opt.clear_code.map_or(0,|_| width)
+        + opt.chunk_checksum.as_ref().map_or(0,|kind| kind.width() as u128);
+    let per_stream = (opt.initial_clear as u128 + opt.stop_code.is_some() as u128) * width;
+    // padding happens at most once per width, plus once for each clear code
+    let padding = match opt.pad_code_groups {
+        true => (chunks + opt.initial_clear as u128) * (opt.max_code_width - opt.min_code_width + 1) as u128 * 8 * width,
+        false => 0
+    };
+    let bytes = (n*width + chunks*per_chunk + per_stream + padding).div_ceil(8);
+    usize::try_from(bytes).unwrap_or(usize::MAX)
+}
+
+/// Convenience function, compresses a slice returning a Vec.
+/// This works on the memory directly, skipping the buffering that `compress` adds.
 pub fn compress_slice(slice: &[u8],opt: &Options) -> Result<Vec<u8>,DYNERR> {
-    let mut src = Cursor::new(slice);
-    let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-    compress(&mut src,&mut ans,opt)?;
-    Ok(ans.into_inner())
+    Compressor::new(opt).compress_slice(slice)
 }
 
-/// Convenience function, calls `expand` with a slice returning a Vec
+/// Convenience function, expands a slice returning a Vec.
+/// This works on the memory directly, skipping the buffering that `expand` adds.
 pub fn expand_slice(slice: &[u8],opt: &Options) -> Result<Vec<u8>,DYNERR> {
-    let mut src = Cursor::new(slice);
-    let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::new());
-    expand(&mut src,&mut ans,opt)?;
-    Ok(ans.into_inner())
+    Expander::new(opt).expand_slice(slice)
 }
 
 
@@ -638,11 +1826,33 @@ fn compression_works_with_clear() {
     assert_eq!(compressed,hex::decode(lzw_str.replace(" ","")).unwrap());
 }
 
+#[test]
+fn compression_works_with_initial_clear() {
+    // Same as `compression_works` but with a leading clear code
+    let mut opt = STD_OPTIONS;
+    opt.ord = BitOrder::Msb0;
+    opt.initial_clear = true;
+    let test_data = "TOBEORNOTTOBEORTOBEORNOT#\n".as_bytes();
+    let lzw_str = "100 054 04F 042 045 04F 052 04E 04F 054 102 104 106 10B 105 107 109 023 00A 101";
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    assert_eq!(compressed,hex::decode(lzw_str.replace(" ","")).unwrap());
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+    // leading clear is tolerated if not required
+    opt.initial_clear = false;
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+    // leading clear is required if requested
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    opt.initial_clear = true;
+    assert!(expand_slice(&compressed,&opt).is_err());
+}
+
 #[test]
 fn compression_works_td_mode() {
     // Example adapted from wikipedia; in their example there are 26 symbols and # is a stop code.
     // Here # and newline are symbols, there is a header, and no stop code.
-    let mut opt = super::td0::TD_V1_OPTIONS;
+    let mut opt = Options::teledisk_v1();
     opt.in_offset = 0;
     opt.out_offset = 0;
     let test_data = "TOBEORNOTTOBEORTOBEORNOT#\n".as_bytes();
@@ -675,7 +1885,7 @@ fn invertibility_16() {
 
 #[test]
 fn invertibility_td_mode() {
-    let mut opt = super::td0::TD_V1_OPTIONS;
+    let mut opt = Options::teledisk_v1();
     opt.in_offset = 0;
     opt.out_offset = 0;
     let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
@@ -684,6 +1894,112 @@ fn invertibility_td_mode() {
     assert_eq!(test_data.to_vec(),expanded);
 }
 
+#[test]
+fn compression_works_with_trailer() {
+    // Like `compression_works_with_clear` but each chunk is followed by a big endian 16 bit code count
+    let mut opt = STD_OPTIONS;
+    opt.ord = BitOrder::Lsb0;
+    opt.chunk_size = 14;
+    opt.header_bits = 16;
+    opt.header_unit = HeaderUnit::Codes;
+    opt.header_placement = HeaderPlacement::After;
+    opt.header_endian = Endian::Big;
+    let test_data = "TOBEORNOTTOBEORTOBEORNOT#\n".as_bytes();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    // first chunk has 14 codes and a clear code, 15 * 12 = 180 bits = 22.5 bytes, then the trailer
+    assert_eq!(compressed[22] >> 4,0);
+    assert_eq!(compressed[23],0xf0);
+    assert_eq!(compressed[24] & 0x0f,0);
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+    // corrupt the trailer
+    let mut corrupted = compressed.clone();
+    corrupted[23] = 0xe0;
+    assert!(expand_slice(&corrupted,&opt).is_err());
+}
+
+#[test]
+fn invertibility_with_byte_header() {
+    let mut opt = STD_OPTIONS;
+    opt.chunk_size = 14;
+    opt.header_bits = 16;
+    opt.header_unit = HeaderUnit::Bytes;
+    opt.header_endian = Endian::Big;
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    // first chunk has 14 codes and a clear code, 180 bits rounds up to 23 bytes
+    assert_eq!(compressed[0..2],[0,23]);
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+}
+
+#[test]
+fn chunk_checksums() {
+    assert_eq!("123456789".as_bytes().iter().fold(0,|acc,b| update_checksum(&ChunkChecksum::Crc16,acc,*b)),0xbb3d);
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let xor: Arc<dyn Checksum> = Arc::new(crate::checksum::Xor8);
+    let crc32: Arc<dyn Checksum> = Arc::new(crate::checksum::Crc32::new(0x04c11db7,0xffffffff,true,0xffffffff));
+    for kind in [ChunkChecksum::Sum16,ChunkChecksum::Crc16,ChunkChecksum::Custom(xor),ChunkChecksum::Custom(crc32)] {
+        let mut opt = STD_OPTIONS;
+        opt.chunk_size = 14;
+        opt.chunk_checksum = Some(kind);
+        let compressed = compress_slice(test_data,&opt).expect("compression failed");
+        let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+        assert_eq!(test_data.to_vec(),expanded);
+        // first checksum starts after 14 codes and a clear code, 180 bits
+        let mut corrupted = compressed.clone();
+        corrupted[23] ^= 0x01;
+        assert!(expand_slice(&corrupted,&opt).is_err());
+    }
+    // checksums with a leading header and no stop code
+    let mut opt = Options::teledisk_v1();
+    opt.in_offset = 0;
+    opt.out_offset = 0;
+    opt.chunk_size = 14;
+    opt.chunk_checksum = Some(ChunkChecksum::Crc16);
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),expanded);
+    // a checksum of the user's own, that is not the same as the built in one of the same width
+    struct Sum16Inverted;
+    impl Checksum for Sum16Inverted {
+        fn width(&self) -> u32 { 16 }
+        fn init(&self) -> u32 { 0 }
+        fn update(&self,state: u32,buf: &[u8]) -> u32 { crate::checksum::Sum16.update(state,buf) }
+        fn finalize(&self,state: u32) -> u32 { !state & 0xffff }
+    }
+    let inverted = ChunkChecksum::Custom(Arc::new(Sum16Inverted));
+    assert!(inverted == inverted.clone() && inverted != ChunkChecksum::Custom(Arc::new(Sum16Inverted)) && inverted != ChunkChecksum::Sum16);
+    opt.chunk_checksum = Some(inverted);
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data);
+    opt.chunk_checksum = Some(ChunkChecksum::Sum16);
+    assert!(matches!(expand_slice(&compressed,&opt).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum)));
+}
+
+#[test]
+fn expand_to_size() {
+    // TD mode has no stop code, embed the stream in other data
+    let mut opt = Options::teledisk_v1();
+    opt.in_offset = 0;
+    opt.out_offset = 0;
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    opt.in_offset = 3;
+    let embedded = [vec![1,2,3],compressed.clone(),vec![4,5,6]].concat();
+    opt.expanded_size = Some(test_data.len() as u64);
+    let mut src = Cursor::new(&embedded);
+    let mut ans: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    let (bits,out_size) = expand_measured(&mut src,&mut ans,&opt).expect("expansion failed");
+    assert_eq!(test_data.to_vec(),ans.into_inner());
+    assert_eq!(out_size,test_data.len() as u64);
+    assert_eq!((bits + 7) / 8,compressed.len() as u64);
+    // stopping in the middle of a string
+    opt.expanded_size = Some(20);
+    let expanded = expand_slice(&embedded,&opt).expect("expansion failed");
+    assert_eq!(test_data[0..20].to_vec(),expanded);
+}
+
 #[test]
 fn invertibility_with_clear() {
     let mut opt = STD_OPTIONS;
@@ -693,4 +2009,509 @@ fn invertibility_with_clear() {
     let compressed = compress_slice(test_data,&opt).expect("compression failed");
     let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
     assert_eq!(test_data.to_vec(),expanded);
-}
\ No newline at end of file
+}
+#[test]
+fn slice_matches_stream() {
+    let opt = STD_OPTIONS;
+    let test_data: Vec<u8> = (0..5000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    let mut streamed = Cursor::new(Vec::new());
+    compress(&mut Cursor::new(&test_data),&mut streamed,&opt).expect("compression failed");
+    assert_eq!(compressed,streamed.into_inner());
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    let mut streamed = Cursor::new(Vec::new());
+    expand(&mut Cursor::new(&compressed),&mut streamed,&opt).expect("expansion failed");
+    assert_eq!(expanded,streamed.into_inner());
+    assert_eq!(expanded[0..test_data.len()],test_data);
+}
+
+#[cfg(feature="mmap")]
+#[test]
+fn mapped_matches_slice() {
+    let test_data: Vec<u8> = (0..5000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let mut file = tempfile::tempfile().expect("could not create file");
+    file.write_all(&test_data).expect("could not write file");
+    let mut compressed = Cursor::new(Vec::new());
+    compress_mapped(&file,&mut compressed,&STD_OPTIONS).expect("compression failed");
+    assert_eq!(compressed.into_inner(),compress_slice(&test_data,&STD_OPTIONS).expect("compression failed"));
+}
+
+#[test]
+fn expand_indexed_range() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let mut opt = STD_OPTIONS;
+    opt.max_code_width = 11;
+    opt.min_code_width = 11;
+    let mut compressed = Cursor::new(Vec::new());
+    let (_,_,index) = compress_indexed(&mut Cursor::new(&test_data),&mut compressed,&opt,1000).expect("compression failed");
+    assert!(index.len() >= 20);
+    assert_eq!(index[0],Checkpoint { expanded_offset: 0, compressed_bit: 0, state: None });
+    assert!(index.iter().any(|cp| cp.compressed_bit % 8 != 0));
+    assert!(index.iter().any(|cp| cp.state.is_some()));
+    let compressed = compressed.into_inner();
+    // indexing does not change the output
+    assert_eq!(compressed,compress_slice(&test_data,&opt).expect("compression failed"));
+    for (start,len) in [(0,10),(4321,300),(12000,5000),(19990,100)] {
+        let mut range = Cursor::new(Vec::new());
+        let n = expand_range(&mut Cursor::new(&compressed),&mut range,&opt,&index,start,len).expect("expansion failed");
+        let end = usize::min(test_data.len(),(start+len) as usize);
+        assert_eq!(n as usize,end - start as usize);
+        assert_eq!(range.into_inner(),test_data[start as usize..end]);
+    }
+    // index also works with a header and no clear code
+    let mut opt = Options::teledisk_v1();
+    opt.in_offset = 0;
+    opt.out_offset = 0;
+    let mut compressed = Cursor::new(Vec::new());
+    let (_,_,index) = compress_indexed(&mut Cursor::new(&test_data),&mut compressed,&opt,1000).expect("compression failed");
+    assert!(index.len() >= 20);
+    let compressed = compressed.into_inner();
+    assert_eq!(compressed,compress_slice(&test_data,&opt).expect("compression failed"));
+    for start in (0..20000).step_by(777) {
+        let mut range = Cursor::new(Vec::new());
+        expand_range(&mut Cursor::new(&compressed),&mut range,&opt,&index,start,50).expect("expansion failed");
+        assert_eq!(range.into_inner(),test_data[start as usize..usize::min(20000,start as usize+50)]);
+    }
+    // variable code widths, checksums, and headers after the chunk
+    let mut after = STD_OPTIONS;
+    after.header_bits = 16;
+    after.header_placement = HeaderPlacement::After;
+    after.chunk_checksum = Some(ChunkChecksum::Crc16);
+    after.chunk_size = 3000;
+    for opt in [Options::unix_compress(),after] {
+        let mut compressed = Cursor::new(Vec::new());
+        let (_,_,index) = compress_indexed(&mut Cursor::new(&test_data),&mut compressed,&opt,700).expect("compression failed");
+        let compressed = compressed.into_inner();
+        assert_eq!(compressed,compress_slice(&test_data,&opt).expect("compression failed"));
+        for start in (0..20000).step_by(611) {
+            let mut range = Cursor::new(Vec::new());
+            expand_range(&mut Cursor::new(&compressed),&mut range,&opt,&index,start,100).expect("expansion failed");
+            assert_eq!(range.into_inner(),test_data[start as usize..usize::min(20000,start as usize+100)]);
+        }
+    }
+    let mut bad = STD_OPTIONS;
+    bad.min_code_width = 13;
+    assert!(compress_indexed(&mut Cursor::new(&test_data),&mut Cursor::new(Vec::new()),&bad,700).is_err());
+    // without an index
+    let mut range = Cursor::new(Vec::new());
+    expand_range(&mut Cursor::new(&compressed),&mut range,&opt,&[],15555,50).expect("expansion failed");
+    assert_eq!(range.into_inner(),test_data[15555..15605]);
+    // the expander finds the checkpoints itself as ranges are read in order
+    let mut expander = Expander::new(&opt);
+    for (start,len) in [(0,1000),(1000,1001),(2001,3),(2500,17500),(15555,50)] {
+        let mut range = Cursor::new(Vec::new());
+        let n = expander.expand_range(&mut Cursor::new(&compressed),&mut range,start,len).expect("expansion failed");
+        assert_eq!(n,len);
+        assert_eq!(range.into_inner(),test_data[start as usize..(start+len) as usize]);
+    }
+    // those are the chunk starts, the snapshots inside chunks only come from compression
+    assert_eq!(expander.index,index.into_iter().filter(|cp| cp.state.is_none()).collect::<Vec<_>>());
+}
+
+#[test]
+fn multiple_members() {
+    let data1 = "TOBEORNOTTOBEORTOBEORNOT#\n".as_bytes();
+    let data2 = "12345123456789123456789\n".as_bytes();
+    let mut compressed = Cursor::new(Vec::new());
+    let (_,size1) = compress_append(&mut Cursor::new(data1),&mut compressed,&STD_OPTIONS).expect("compression failed");
+    let (_,size2) = compress_append(&mut Cursor::new(data2),&mut compressed,&STD_OPTIONS).expect("compression failed");
+    let mut expanded = Cursor::new(Vec::new());
+    let compressed = compressed.into_inner();
+    let sizes = expand_members(&mut Cursor::new(&compressed),&mut expanded,&STD_OPTIONS).expect("expansion failed");
+    assert_eq!(sizes,vec![(size1,data1.len() as u64),(size2,data2.len() as u64)]);
+    assert_eq!(expanded.into_inner(),[data1,data2].concat());
+    let err = expand_members(&mut Cursor::new(&compressed),&mut Cursor::new(Vec::new()),&Options { stop_code: None, ..STD_OPTIONS }).expect_err("members cannot be found");
+    assert_eq!(err.downcast_ref::<std::io::Error>().map(|e| e.kind()),Some(ErrorKind::InvalidInput));
+}
+
+#[test]
+fn reusable_state() {
+    let mut compressor = Compressor::new(&STD_OPTIONS);
+    let mut expander = Expander::new(&STD_OPTIONS);
+    for test_data in ["TOBEORNOTTOBEORTOBEORNOT#\n","1234567","12345123456789123456789\n"] {
+        let compressed = compressor.compress_slice(test_data.as_bytes()).expect("compression failed");
+        assert_eq!(compressed,compress_slice(test_data.as_bytes(),&STD_OPTIONS).expect("compression failed"));
+        let expanded = expander.expand_slice(&compressed).expect("expansion failed");
+        assert_eq!(expanded,test_data.as_bytes());
+    }
+}
+
+#[test]
+fn iter_matches_slice() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    for opt in [STD_OPTIONS,Options::teledisk_v1()] {
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        let mut streamed = Cursor::new(Vec::new());
+        let (in_size,_) = compress_iter(test_data.iter().copied(),&mut streamed,&opt).expect("compression failed");
+        assert_eq!(in_size,test_data.len() as u64 - opt.in_offset);
+        assert_eq!(compressed,streamed.into_inner());
+    }
+    let failing = (0..100u32).map(|x| match x {
+        50 => Err(std::io::Error::other("generator failed")),
+        _ => Ok(x as u8)
+    });
+    assert!(compress_iter(failing,&mut Cursor::new(Vec::new()),&STD_OPTIONS).is_err());
+}
+
+#[test]
+fn segments_with_saved_state() {
+    let test_data: Vec<u8> = (0..30000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let segments = [&test_data[0..7000],&test_data[7000..7001],&test_data[7001..19000],&test_data[19000..]];
+    let mut opt = STD_OPTIONS;
+    opt.chunk_size = 2000;
+    // compress each segment, interleaving raw records, and keep a snapshot after the first
+    let mut compressor = Compressor::new(&opt);
+    let mut stream = Cursor::new(Vec::new());
+    let mut snapshot = None;
+    for (i,seg) in segments.iter().enumerate() {
+        let mut compressed = Cursor::new(Vec::new());
+        compressor.compress_segment(&mut Cursor::new(seg),&mut compressed,i==segments.len()-1).expect("compression failed");
+        stream.write_all(&compressed.into_inner()).expect("write failed");
+        stream.write_all(b"RAW").expect("write failed");
+        if i==0 {
+            snapshot = Some(compressor.save_state());
+        }
+    }
+    let stream = stream.into_inner();
+    let mut expander = Expander::new(&opt);
+    let mut pos = 0;
+    let mut expanded = Vec::new();
+    let mut resume_at = (0,None);
+    for (i,seg) in segments.iter().enumerate() {
+        let mut src = Cursor::new(&stream[pos..]);
+        let mut out = Cursor::new(Vec::new());
+        let (in_size,out_size) = expander.expand_segment(&mut src,&mut out,seg.len() as u64,i==segments.len()-1).expect("expansion failed");
+        assert_eq!(out_size,seg.len() as u64);
+        pos += in_size as usize;
+        assert_eq!(&stream[pos..pos+3],b"RAW");
+        pos += 3;
+        expanded.append(&mut out.into_inner());
+        if i==0 {
+            resume_at = (pos,Some(expander.save_state()));
+        }
+    }
+    assert_eq!(expanded,test_data);
+    // restoring the snapshot gives the same second segment
+    let mut compressed = Cursor::new(Vec::new());
+    compressor.restore_state(&snapshot.unwrap());
+    compressor.compress_segment(&mut Cursor::new(segments[1]),&mut compressed,false).expect("compression failed");
+    let compressed = compressed.into_inner();
+    assert_eq!(stream[resume_at.0..resume_at.0+compressed.len()],compressed);
+    let mut out = Cursor::new(Vec::new());
+    expander.restore_state(&resume_at.1.unwrap());
+    expander.expand_segment(&mut Cursor::new(&compressed),&mut out,1,false).expect("expansion failed");
+    assert_eq!(out.into_inner(),segments[1]);
+}
+
+#[test]
+fn bound_and_expand_into() {
+    let mut x: u32 = 1;
+    let noise: Vec<u8> = (0..5000).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); (x >> 16) as u8 }).collect();
+    let mut opt = Options::teledisk_v1();
+    opt.in_offset = 0;
+    opt.out_offset = 0;
+    for opt in [STD_OPTIONS,opt] {
+        let compressed = compress_slice(&noise,&opt).expect("compression failed");
+        assert!(compressed.len() <= compress_bound(noise.len(),&opt));
+        let mut buf = vec![0;noise.len()];
+        assert_eq!(expand_into(&compressed,&mut buf,&opt).expect("expansion failed"),noise.len());
+        assert_eq!(buf,noise);
+        let mut small = vec![0;noise.len()-1];
+        assert!(expand_into(&compressed,&mut small,&opt).is_err());
+        let mut expander = Expander::new(&opt);
+        for _ in 0..2 {
+            buf.fill(0);
+            assert_eq!(expander.expand_reader_into(&mut Cursor::new(&compressed),&mut buf).expect("expansion failed"),noise.len());
+            assert_eq!(buf,noise);
+        }
+        assert!(expand_reader_into(&mut Cursor::new(&compressed),&mut small,&opt).is_err());
+    }
+    // the bound saturates rather than overflowing, whatever the width of usize
+    assert_eq!(compress_bound(usize::MAX,&STD_OPTIONS),usize::MAX);
+}
+
+#[test]
+fn unix_compress_profile() {
+    // checked against `uncompress`
+    let test_data = "TOBEORNOTTOBEORTOBEORNOT#\n".as_bytes();
+    let lzw_str = "549e0829f2448a932754020e2ca890a04184231400";
+    let opt = Options::unix_compress();
+    let compressed = compress_slice(test_data,&opt).expect("compression failed");
+    assert_eq!(compressed,hex::decode(lzw_str).unwrap());
+    // expand a whole file, long enough for the width to change and the dictionary to fill
+    let mut x: u32 = 1;
+    let mut test_data: Vec<u8> = (0..60000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    test_data.extend((0..60000).map(|_| { x = x.wrapping_mul(1103515245).wrapping_add(12345); (x >> 16) as u8 }));
+    for mut opt in [Options::unix_compress(),Options::arc_crunch()] {
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        assert!(compressed.len() <= compress_bound(test_data.len(),&opt));
+        let file = [vec![0x1f,0x9d,0x90],compressed].concat();
+        opt.in_offset = 3;
+        assert_eq!(expand_slice(&file,&opt).expect("expansion failed"),test_data);
+    }
+}
+
+#[test]
+fn gif_profile() {
+    // sample image from the wikipedia article on GIF
+    let pixels = hex::decode("28ffffff28ffffffffffffffffffff").unwrap();
+    let lzw_str = "0051fc1b2870a0c1830101";
+    let opt = Options::gif(8);
+    assert_eq!(compress_slice(&pixels,&opt).expect("compression failed"),hex::decode(lzw_str).unwrap());
+    assert_eq!(expand_slice(&hex::decode(lzw_str).unwrap(),&opt).expect("expansion failed"),pixels);
+    let test_data: Vec<u8> = (0..30000).map(|x: u64| ((x*x/7) % 61) as u8).collect();
+    for min_bits in 2..=8 {
+        let opt = Options::gif(min_bits);
+        let pixels: Vec<u8> = test_data.iter().map(|p| (*p as usize % (1 << min_bits)) as u8).collect();
+        let compressed = compress_slice(&pixels,&opt).expect("compression failed");
+        assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),pixels);
+    }
+    // pixels that do not fit the symbol size
+    assert!(compress_slice(&[1,2,16],&Options::gif(4)).is_err());
+}
+
+#[test]
+fn dictionary_generations() {
+    let mut dict = Dictionary::create(9,0,255);
+    dict.insert(300,65,b'B');
+    dict.insert(301,300,b'C');
+    assert_eq!(dict.child(65,b'B'),Some(300));
+    assert_eq!(dict.child(300,b'C'),Some(301));
+    let mut buf = Vec::new();
+    dict.get_string(301,&mut buf);
+    assert_eq!(buf,b"ABC");
+    dict.reset();
+    assert!(dict.contains(65));
+    assert!(!dict.contains(300));
+    assert_eq!(dict.child(65,b'B'),None);
+    // stamps from before the generation counter wrapped must not come back
+    dict.generation = u32::MAX;
+    dict.insert(300,65,b'B');
+    dict.reset();
+    dict.generation = u32::MAX;
+    assert!(!dict.contains(300));
+}
+
+#[test]
+fn history_ring() {
+    let mut hist = History::new();
+    hist.allocate(512);
+    hist.push(&vec![b'x';History::SIZE - 2]);
+    hist.set_start(300,hist.end);
+    hist.push(b"ABCD");
+    let mut buf = Vec::new();
+    assert!(hist.get(300,4,&mut buf));
+    assert_eq!(buf,b"ABCD");
+    assert!(!hist.get(300,5,&mut buf));
+    hist.push(&vec![b'y';History::SIZE]);
+    assert!(!hist.get(300,4,&mut buf));
+    // strings first seen long ago must come out of the dictionary, recent ones out of the history
+    let mut opt = STD_OPTIONS;
+    opt.min_code_width = 16;
+    opt.max_code_width = 16;
+    let block: Vec<u8> = (0..400).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let test_data = [block.clone(),vec![b'z';2*History::SIZE],block.repeat(20)].concat();
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    let expanded = expand_slice(&compressed,&opt).expect("expansion failed");
+    assert_eq!(test_data,expanded);
+}
+
+#[test]
+fn verify_detects_truncation() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let mut td_opt = Options::teledisk_v1();
+    td_opt.in_offset = 0;
+    td_opt.out_offset = 0;
+    for opt in [STD_OPTIONS,td_opt,Options::gif(8)] {
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        let (_,out_size,complete) = verify(&mut Cursor::new(&compressed),&opt).expect("verify failed");
+        assert_eq!(out_size,test_data.len() as u64);
+        assert!(complete);
+        let (_,out_size,complete) = verify(&mut Cursor::new(&compressed[0..compressed.len()/2]),&opt).expect("verify failed");
+        assert!(out_size < test_data.len() as u64);
+        assert!(!complete);
+    }
+    // without a stop code or headers the end is unknown
+    let opt = Options::unix_compress();
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    let (_,_,complete) = verify(&mut Cursor::new(&compressed[0..compressed.len()/2]),&opt).expect("verify failed");
+    assert!(complete);
+}
+
+#[test]
+fn bad_codes() {
+    // 12 bit codes packed LSB first, as in `STD_OPTIONS`
+    let pack = |codes: &[u64]| -> Vec<u8> {
+        let acc = codes.iter().enumerate().fold(0u128,|acc,(i,c)| acc | (*c as u128) << (12*i));
+        acc.to_le_bytes()[0..(codes.len()*12).div_ceil(8)].to_vec()
+    };
+    assert_eq!(expand_slice(&pack(&[65,66,258,257]),&STD_OPTIONS).expect("expansion failed"),b"ABAB");
+    // the code that is next can be used, once there is a previous string
+    assert_eq!(expand_slice(&pack(&[65,66,259,257]),&STD_OPTIONS).expect("expansion failed"),b"ABBB");
+    match expand_slice(&pack(&[65,66,4000,257]),&STD_OPTIONS) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),
+            Some(crate::Error::CodeFromFuture { code: 4000, bit: 24, next_code: 259, dictionary_size: 257 }))),
+        Ok(_) => panic!("bad code was not detected")
+    }
+    match expand_slice(&pack(&[258,257]),&STD_OPTIONS) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),
+            Some(crate::Error::CodeFromFuture { code: 258, bit: 0, next_code: 258, dictionary_size: 256 }))),
+        Ok(_) => panic!("bad code was not detected")
+    }
+}
+
+#[test]
+fn tiny_inputs() {
+    for name in PRESETS {
+        let opt = Options { in_offset: 0, out_offset: 0, ..Options::preset(name).unwrap() };
+        for len in 0..5 {
+            // small enough for every symbol size
+            let test_data = &[0,1,2,3][0..len];
+            let compressed = compress_slice(test_data,&opt).expect("compression failed");
+            assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data);
+        }
+    }
+    // nothing at all, not even the stop code, is up to `truncation`
+    assert_eq!(expand_slice(&[],&STD_OPTIONS).expect("expansion failed"),b"");
+    let strict = Options { truncation: Truncation::Error, ..STD_OPTIONS };
+    assert!(matches!(expand_slice(&[],&strict).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { .. })));
+    // without the initial clear code nothing can be decoded, so that is always an error
+    let gif = Options::preset("gif8").unwrap();
+    assert!(matches!(expand_slice(&[],&gif).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits: 9, .. })));
+}
+
+#[test]
+fn write_errors() {
+    // the output fills up while codes are going out, well before the end
+    let text = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    for opt in [STD_OPTIONS,Options { pad_code_groups: true, ..Options::preset("unix_compress").unwrap() }] {
+        let mut buf = vec![0;1000];
+        let res = compress(&mut Cursor::new(&text),&mut Cursor::new(&mut buf[..]),&opt);
+        assert_eq!(res.unwrap_err().downcast_ref::<std::io::Error>().map(|e| e.kind()),Some(std::io::ErrorKind::WriteZero));
+    }
+}
+
+#[test]
+fn truncation_is_an_error() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let opt = Options { in_offset: 0, out_offset: 0, ..Options::teledisk_v1() };
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    assert_eq!(expand_slice(&compressed,&opt).expect("expansion failed"),test_data);
+    // the chunk header tells how much is missing
+    let cut = compressed.len() - 10;
+    match expand_slice(&compressed[0..cut],&opt) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits, .. }) if *missing_bits > 64)),
+        Ok(_) => panic!("truncation was not detected")
+    }
+    let lenient = Options { truncation: Truncation::Stop, ..opt };
+    assert!(expand_slice(&compressed[0..cut],&lenient).expect("expansion failed").len() < test_data.len());
+}
+
+#[test]
+fn offsets() {
+    // a TD0 image, the 12 byte header is copied over separately, so expanding into a new file leaves a gap
+    let test_data: Vec<u8> = (0..3000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let image = [vec![0xaa;12],test_data.clone()].concat();
+    let opt = Options::teledisk_v1();
+    let mut compressed = Cursor::new(Vec::new());
+    compress(&mut Cursor::new(&image),&mut compressed,&opt).expect("compression failed");
+    let compressed = compressed.into_inner();
+    assert_eq!(compressed[0..12],[0;12]);
+    let mut expanded = Cursor::new(Vec::new());
+    expand(&mut Cursor::new(&compressed),&mut expanded,&opt).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),[vec![0;12],test_data.clone()].concat());
+    // refuse the gap, but writing over a header that is already there is fine
+    let strict = Options { offset_gap: OffsetGap::Error, ..opt.clone() };
+    let res = expand(&mut Cursor::new(&compressed),&mut Cursor::new(vec![0xaa;5]),&strict);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    let mut expanded = Cursor::new(vec![0xaa;12]);
+    expand(&mut Cursor::new(&compressed),&mut expanded,&strict).expect("expansion failed");
+    assert_eq!(expanded.into_inner(),image);
+    // an input that ends before the offset is an error whether or not its end is known
+    let res = expand_slice(&compressed[0..5],&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    let res = compress_iter(image[0..5].iter().copied(),&mut Cursor::new(Vec::new()),&opt);
+    assert!(matches!(res.unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::OffsetPastEnd { offset: 12, len: 5 })));
+    // an offset at the very end is an empty input
+    assert_eq!(expand_slice(&compressed[0..12],&Options { truncation: Truncation::Stop, ..opt }).expect("expansion failed"),[0;12]);
+}
+
+#[test]
+fn trailing_data() {
+    let test_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes();
+    let compressed = compress_slice(test_data,&STD_OPTIONS).expect("compression failed");
+    let mut padded = compressed.clone();
+    padded.extend_from_slice(&[0;3]);
+    // ignored by default
+    assert_eq!(expand_slice(&padded,&STD_OPTIONS).expect("expansion failed"),test_data);
+    let strict = Options { trailing_limit: Some(0), ..STD_OPTIONS };
+    assert_eq!(expand_slice(&compressed,&strict).expect("expansion failed"),test_data);
+    match expand_slice(&padded,&strict) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TrailingData { bytes: 3 }))),
+        Ok(_) => panic!("trailing data was not detected")
+    }
+    let tolerant = Options { trailing_limit: Some(3), ..STD_OPTIONS };
+    let (ans,warnings) = diagnostics::collect(|| expand_slice(&padded,&tolerant));
+    assert_eq!(ans.expect("expansion failed"),test_data);
+    assert_eq!(warnings,vec![Warning::TrailingData { bytes: 3 }]);
+    // each member is followed by the next, so they are not checked
+    let mut members = compressed.clone();
+    members.extend_from_slice(&compressed);
+    let mut out = Cursor::new(Vec::new());
+    assert_eq!(expand_members(&mut Cursor::new(&members),&mut out,&strict).expect("expansion failed").len(),2);
+}
+
+#[test]
+fn presets_by_name() {
+    for name in PRESETS {
+        assert!(Options::preset(name).is_some());
+    }
+    assert_eq!(Options::preset("gif4").unwrap().clear_code,Some(16));
+    assert!(Options::preset("gif9").is_none());
+    assert!(Options::preset("nope").is_none());
+}
+
+#[test]
+fn trace_tokens() {
+    let test_data: Vec<u8> = (0..20000).map(|x: u64| ((x*x/7) % 61) as u8 + b'A').collect();
+    let opt = Options::gif(8);
+    let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+    let (trace,res) = expand_traced(&mut Cursor::new(&compressed),&opt);
+    assert_eq!(res.expect("expansion failed").1,test_data.len() as u64);
+    assert_eq!(trace[0].token,Token::Clear);
+    assert_eq!(trace[1],TraceEvent { bit: 9, pos: 0, token: Token::Code { code: test_data[0] as usize, width: 9, length: 1 } });
+    assert_eq!(trace.last().unwrap().token,Token::Stop);
+    let lengths: usize = trace.iter().map(|e| match e.token { Token::Code { length, .. } => length, _ => 0 }).sum();
+    assert_eq!(lengths,test_data.len());
+    // a bad code stops the trace
+    let mut bad = compressed.clone();
+    bad[20..24].fill(0xff);
+    let (trace,res) = expand_traced(&mut Cursor::new(&bad),&opt);
+    assert!(res.is_err());
+    assert!(trace.len() > 10 && trace.last().unwrap().bit < 8*24);
+    // a first code that is not in the dictionary
+    assert!(expand_slice(&[0xff;8],&STD_OPTIONS).is_err());
+}
+
+#[test]
+fn malformed_input() {
+    // anything may come out of damaged data, but it must come back as a result
+    let text = std::fs::read("tests/hamlet_act_1.txt").unwrap();
+    for name in PRESETS {
+        let opt = Options { in_offset: 0, out_offset: 0, ..Options::preset(name).unwrap() };
+        let test_data: Vec<u8> = text[0..4000].iter().map(|b| b & opt.max_symbol as u8).collect();
+        let compressed = compress_slice(&test_data,&opt).expect("compression failed");
+        for corrupted in crate::tools::mutate::Mutations::new(&compressed,0x5eed).take(200) {
+            let _ = expand_slice(&corrupted,&opt);
+            let _ = verify(&mut Cursor::new(&corrupted),&opt);
+            if opt.stop_code.is_some() {
+                let _ = expand_members(&mut Cursor::new(&corrupted),&mut Cursor::new(Vec::new()),&opt);
+            }
+        }
+    }
+    // a stray byte after the last member is too short to hold a code
+    let mut compressed = compress_slice(&text[0..4000],&STD_OPTIONS).expect("compression failed");
+    compressed.push(0);
+    let sizes = expand_members(&mut Cursor::new(&compressed),&mut Cursor::new(Vec::new()),&STD_OPTIONS).expect("expansion failed");
+    assert_eq!(sizes.len(),1);
+}
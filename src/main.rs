@@ -1,99 +1,1882 @@
-use clap::{arg,crate_version,Command};
-use retrocompressor::{lzw,lzss_huff, td0, direct_ports};
-type STDRESULT = Result<(),Box<dyn std::error::Error>>;
+use clap::{arg,crate_version,value_parser,Arg,ArgMatches,Command};
+use std::io::{Read,Write,Seek,SeekFrom,Cursor,IsTerminal};
+use std::path::{Path,PathBuf};
+use std::ffi::OsString;
+use retrocompressor::ReadSeek;
+use retrocompressor::sub_block;
+#[cfg(feature="lzw")]
+use retrocompressor::lzw;
+#[cfg(feature="lzss_huff")]
+use retrocompressor::lzss_huff;
+#[cfg(feature="td0")]
+use retrocompressor::td0;
+#[cfg(feature="direct_ports")]
+use retrocompressor::direct_ports;
+#[path="tools/workers.rs"]
+mod workers;
+use workers::on_workers;
+type DYNERR = Box<dyn std::error::Error + Send + Sync>;
+type STDRESULT = Result<(),DYNERR>;
 
 const RCH: &str = "unreachable was reached";
 
-fn ok_to_overwrite(path_out: &str) -> bool {
-    if let Ok(_f) = std::fs::File::open(path_out) {
-        let mut ans = String::new();
-        eprint!("{} exists, overwrite? (y/n) ",path_out);
-        std::io::stdin().read_line(&mut ans).expect("could not read stdin");
-        if ans.trim_end()=="y" || ans.trim_end()=="Y" {
-            log::warn!("existing file will not be truncated");
-            return true;
+/// Why the program failed, the value is the exit code
+#[derive(Clone,Copy,PartialEq,Debug)]
+enum Cause {
+    /// none of the others, or a mix of them
+    Failed = 1,
+    /// bad arguments, clap uses the same code for the problems it finds
+    Usage = 2,
+    /// the data is not in the expected format
+    Format = 3,
+    /// a checksum or CRC did not match
+    Checksum = 4,
+    /// the data ended too soon
+    Truncated = 5,
+    /// reading or writing failed
+    Io = 6,
+    /// a size limit was reached
+    TooLarge = 7
+}
+
+impl Cause {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Failed => "failed",
+            Self::Usage => "usage",
+            Self::Format => "format",
+            Self::Checksum => "checksum",
+            Self::Truncated => "truncated",
+            Self::Io => "io",
+            Self::TooLarge => "too-large"
+        }
+    }
+    /// Work out the cause of an error from the codecs, the file system, or this program
+    fn of(e: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(failure) = e.downcast_ref::<Failure>() {
+            return failure.cause;
+        }
+        if let Some(e) = e.downcast_ref::<retrocompressor::Error>() {
+            return match e {
+                retrocompressor::Error::FileFormatMismatch |
+                retrocompressor::Error::UnsupportedVersion(_) |
+                retrocompressor::Error::TrailingData { .. } |
+                retrocompressor::Error::Overlong { .. } |
+                retrocompressor::Error::CodeFromFuture { .. } |
+                retrocompressor::Error::CodeOutOfRange { .. } => Self::Format,
+                retrocompressor::Error::BadChecksum => Self::Checksum,
+                retrocompressor::Error::FileTooLarge => Self::TooLarge,
+                retrocompressor::Error::TruncatedInput { .. } |
+                retrocompressor::Error::SizeMismatch { .. } |
+                retrocompressor::Error::OffsetPastEnd { .. } => Self::Truncated,
+                retrocompressor::Error::InvalidOptions(_) => Self::Usage
+            };
+        }
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            // errors can be passed along inside an I/O error
+            if let Some(inner) = e.get_ref() {
+                match Self::of(inner) {
+                    Self::Failed => {},
+                    cause => return cause
+                }
+            }
+            return match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => Self::Truncated,
+                std::io::ErrorKind::InvalidData => Self::Format,
+                _ => Self::Io
+            };
+        }
+        Self::Failed
+    }
+    /// The cause shared by all of `causes`, or `Failed` if they differ
+    fn common<I: IntoIterator<Item=Cause>>(causes: I) -> Self {
+        let mut causes = causes.into_iter();
+        let first = causes.next().unwrap_or(Self::Failed);
+        match causes.all(|c| c == first) {
+            true => first,
+            false => Self::Failed
+        }
+    }
+}
+
+/// Error with the message for the final diagnostic and the cause that sets the exit code
+#[derive(Debug)]
+struct Failure {
+    cause: Cause,
+    msg: String
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"{}",self.msg)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+fn fail<S: Into<String>>(cause: Cause,msg: S) -> DYNERR {
+    Box::new(Failure { cause, msg: msg.into() })
+}
+
+/// `-` in place of a path means stdin or stdout
+const STDIO: &str = "-";
+
+/// expanded data goes to stdout in pieces of this size
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+const STDOUT_CHUNK: usize = 0x4000;
+
+#[cfg(not(any(feature="lzw",feature="lzss_huff",feature="direct_ports")))]
+compile_error!("the cli feature needs at least one codec feature");
+
+/// methods offered, depending on the codecs that were enabled at build time
+const METHODS: &[&str] = &[
+    #[cfg(feature="lzw")]
+    "lzw",
+    #[cfg(feature="direct_ports")]
+    "lzhuf-port",
+    #[cfg(feature="lzss_huff")]
+    "lzss_huff",
+    #[cfg(feature="td0")]
+    "td0"
+];
+
+/// stages that only frame the data, they can be chained with the methods
+const FRAMINGS: &[&str] = &["sub_block"];
+
+/// separates the stages of a chained method, which are applied left to right when compressing
+const CHAIN: char = '+';
+
+/// Accept a method, or several methods and framings chained together
+fn parse_method(s: &str) -> Result<String,String> {
+    for stage in s.split(CHAIN) {
+        if !METHODS.contains(&stage) && !FRAMINGS.contains(&stage) {
+            let choices: Vec<&str> = METHODS.iter().chain(FRAMINGS).copied().collect();
+            return Err(format!("{} is not a method, choose from {}, chained with {}",stage,choices.join(", "),CHAIN));
         }
-        return false;
     }
-    true
+    Ok(s.to_string())
+}
+
+/// methods built on the library codecs, which can be inspected or converted
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+const STREAM_METHODS: &[&str] = &[
+    #[cfg(feature="lzw")]
+    "lzw",
+    #[cfg(feature="lzss_huff")]
+    "lzss_huff",
+    #[cfg(feature="td0")]
+    "td0"
+];
+
+/// flags that override codec options, and the methods they apply to
+const LZW_FLAGS: &[&str] = &["options","code-width","bit-order","in-offset","out-offset"];
+const LZSS_HUFF_FLAGS: &[&str] = &["options","window-size","lookahead","threshold","max-freq","level","in-offset","out-offset"];
+
+/// Method chosen on the command line, with the codec options after any overrides
+struct Codec<'a> {
+    method: &'a str,
+    #[cfg(feature="lzw")]
+    lzw: lzw::Options,
+    #[cfg(feature="lzss_huff")]
+    lzss_huff: lzss_huff::Options,
+    #[cfg(feature="td0")]
+    td0: td0::Options
 }
 
-fn main() -> STDRESULT
+impl Codec<'_> {
+    /// true if the data passes through several stages, or a stage other than a method
+    fn is_chain(&self) -> bool {
+        !METHODS.contains(&self.method)
+    }
+    /// where the codec starts writing, the sizes it returns do not include this
+    fn out_offset(&self) -> u64 {
+        match self.method {
+            #[cfg(feature="lzw")]
+            "lzw" => self.lzw.out_offset,
+            #[cfg(feature="lzss_huff")]
+            "lzss_huff" => self.lzss_huff.out_offset,
+            _ => 0
+        }
+    }
+}
+
+/// Read a TOML profile with the options for a codec.  If there is a `preset` key, the named options
+/// are the starting point and the other keys override fields, otherwise the file gives the full options.
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn load_profile<T>(path: &Path,preset: fn(&str) -> Option<T>) -> Result<T,DYNERR>
+where T: serde::Serialize + serde::de::DeserializeOwned {
+    let mut table: toml::Table = std::fs::read_to_string(path)?.parse()?;
+    let base = match table.remove("preset") {
+        None => return Ok(table.try_into()?),
+        Some(toml::Value::String(name)) => match preset(&name) {
+            Some(opt) => opt,
+            None => return Err(format!("unknown preset {}",name).into())
+        },
+        Some(_) => return Err("preset should be a name".into())
+    };
+    let mut merged = toml::Table::try_from(base)?;
+    merged.extend(table);
+    Ok(merged.try_into()?)
+}
+
+/// Flag that sets the check signature byte of a converted TD0 image
+fn check_signature_arg() -> Arg {
+    arg!(--"check-signature" <BYTE> "td0: check signature byte for the output, shared by the volumes of a set")
+        .value_parser(value_parser!(u8)).required(false)
+}
+
+/// Add the flags that override codec options to a subcommand
+fn codec_args(cmd: Command) -> Command {
+    cmd.arg(arg!(--options <PROFILE> "TOML file with the options for the method, flags override it")
+            .required(false))
+        .arg(arg!(--"window-size" <BYTES> "lzss_huff: size of the sliding window, at most 4096")
+            .value_parser(value_parser!(usize)).required(false))
+        .arg(arg!(--lookahead <BYTES> "lzss_huff: longest match")
+            .value_parser(value_parser!(usize)).required(false))
+        .arg(arg!(--threshold <BYTES> "lzss_huff: matches must be longer than this to be encoded")
+            .value_parser(value_parser!(usize)).required(false))
+        .arg(arg!(--"max-freq" <COUNT> "lzss_huff: total frequency that triggers a Huffman tree rebuild")
+            .value_parser(value_parser!(usize)).required(false))
+        .arg(arg!(--level <N> "lzss_huff: match finder effort, 1 is fastest, 9 is smallest, 7 matches LZHUF.C")
+            .value_parser(value_parser!(u8).range(1..=9)).required(false))
+        .arg(arg!(--"code-width" <BITS> "lzw: fixed code width")
+            .value_parser(value_parser!(u8).range(9..=16)).required(false))
+        .arg(arg!(--"bit-order" <ORDER> "lzw: order in which code bits are packed").value_parser(["msb0","lsb0"])
+            .required(false))
+        .arg(arg!(--"in-offset" <BYTES> "lzw, lzss_huff: where the data starts in the input")
+            .value_parser(value_parser!(u64)).required(false))
+        .arg(arg!(--"out-offset" <BYTES> "lzw, lzss_huff: where the data starts in the output")
+            .value_parser(value_parser!(u64)).required(false))
+}
+
+/// Get the method and start from its standard options, applying the overrides.
+/// Overrides that do not apply to the method, or that are inconsistent, are an error.
+fn codec_from(cmd: &ArgMatches) -> Result<Codec<'_>,DYNERR> {
+    let method = cmd.get_one::<String>("method").expect(RCH);
+    let stages: Vec<&str> = method.split(CHAIN).collect();
+    let allowed: Vec<&str> = stages.iter().flat_map(|stage| match *stage {
+        "lzw" => LZW_FLAGS,
+        "lzss_huff" => LZSS_HUFF_FLAGS,
+        _ => &[]
+    }).copied().collect();
+    if cmd.contains_id("options") && stages.contains(&"lzw") && stages.contains(&"lzss_huff") {
+        return Err(fail(Cause::Usage,"--options is ambiguous when both lzw and lzss_huff are chained"));
+    }
+    for flag in LZW_FLAGS.iter().chain(LZSS_HUFF_FLAGS) {
+        if cmd.contains_id(flag) && !allowed.contains(flag) {
+            return Err(fail(Cause::Usage,format!("--{} does not apply to {}",flag,method)));
+        }
+    }
+    if matches!(cmd.try_get_one::<bool>("trim-padding"),Ok(Some(true))) && !stages.contains(&"td0") {
+        return Err(fail(Cause::Usage,format!("--trim-padding does not apply to {}",method)));
+    }
+    if matches!(cmd.try_get_one::<u8>("check-signature"),Ok(Some(_))) && !stages.contains(&"td0") {
+        return Err(fail(Cause::Usage,format!("--check-signature does not apply to {}",method)));
+    }
+    for flag in ["pad-to","pad-min"] {
+        if matches!(cmd.try_get_one::<u32>(flag),Ok(Some(_))) && !stages.contains(&"td0") {
+            return Err(fail(Cause::Usage,format!("--{} does not apply to {}",flag,method)));
+        }
+    }
+    #[cfg(feature="lzw")]
+    let mut lzw_opt = lzw::STD_OPTIONS;
+    #[cfg(feature="lzss_huff")]
+    let mut lzss_huff_opt = lzss_huff::STD_OPTIONS;
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    let profile = cmd.get_one::<String>("options").map(Path::new);
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    let bad_profile = |path: &Path,e: DYNERR| -> DYNERR {
+        fail(Cause::Usage,format!("{}: {}",path.display(),e))
+    };
+    for stage in stages {
+        match stage {
+            #[cfg(feature="lzw")]
+            "lzw" => {
+                if let Some(path) = profile {
+                    // TOML integers are signed 64 bit, the larger chunk sizes in the presets all mean unlimited
+                    let preset: fn(&str) -> Option<lzw::Options> = |name| lzw::Options::preset(name).map(|mut opt| {
+                        opt.chunk_size = usize::min(opt.chunk_size,i64::MAX as usize);
+                        opt
+                    });
+                    lzw_opt = load_profile(path,preset).map_err(|e| bad_profile(path,e))?;
+                }
+                if let Some(width) = cmd.get_one::<u8>("code-width") {
+                    lzw_opt.min_code_width = *width as usize;
+                    lzw_opt.max_code_width = *width as usize;
+                }
+                if let Some(ord) = cmd.get_one::<String>("bit-order") {
+                    lzw_opt.ord = match ord.as_str() {
+                        "msb0" => retrocompressor::BitOrder::Msb0,
+                        _ => retrocompressor::BitOrder::Lsb0
+                    };
+                }
+                lzw_opt.in_offset = *cmd.get_one::<u64>("in-offset").unwrap_or(&lzw_opt.in_offset);
+                lzw_opt.out_offset = *cmd.get_one::<u64>("out-offset").unwrap_or(&lzw_opt.out_offset);
+                lzw_opt.validate().map_err(|e| fail(Cause::Usage,e.to_string()))?;
+            },
+            #[cfg(feature="lzss_huff")]
+            "lzss_huff" => {
+                if let Some(path) = profile {
+                    lzss_huff_opt = load_profile(path,lzss_huff::Options::preset).map_err(|e| bad_profile(path,e))?;
+                }
+                let opt = &mut lzss_huff_opt;
+                opt.window_size = *cmd.get_one::<usize>("window-size").unwrap_or(&opt.window_size);
+                opt.lookahead = *cmd.get_one::<usize>("lookahead").unwrap_or(&opt.lookahead);
+                opt.threshold = *cmd.get_one::<usize>("threshold").unwrap_or(&opt.threshold);
+                opt.huffman_max_freq = *cmd.get_one::<usize>("max-freq").unwrap_or(&opt.huffman_max_freq);
+                opt.in_offset = *cmd.get_one::<u64>("in-offset").unwrap_or(&opt.in_offset);
+                opt.out_offset = *cmd.get_one::<u64>("out-offset").unwrap_or(&opt.out_offset);
+                if let Some(level) = cmd.get_one::<u8>("level") {
+                    opt.set_level(*level);
+                }
+                opt.validate().map_err(|e| fail(Cause::Usage,e.to_string()))?;
+            },
+            _ => {}
+        }
+    }
+    Ok(Codec {
+        method,
+        #[cfg(feature="lzw")]
+        lzw: lzw_opt,
+        #[cfg(feature="lzss_huff")]
+        lzss_huff: lzss_huff_opt,
+        // only some subcommands have these flags, --force also gets past a bad image header CRC
+        #[cfg(feature="td0")]
+        td0: td0::Options {
+            tolerate_bad_crc: matches!(cmd.try_get_one::<bool>("force"),Ok(Some(true))),
+            trim_padding: matches!(cmd.try_get_one::<bool>("trim-padding"),Ok(Some(true))),
+            check_signature: cmd.try_get_one::<u8>("check-signature").ok().flatten().copied(),
+            padding: match (cmd.try_get_one::<u32>("pad-to"),cmd.try_get_one::<u32>("pad-min")) {
+                (Ok(Some(n)),_) => td0::Padding::Boundary(*n),
+                (_,Ok(Some(n))) => td0::Padding::AtLeast(*n),
+                _ => td0::Padding::None
+            }
+        }
+    })
+}
+
+/// What to do when an output file already exists
+#[derive(Clone,Copy,PartialEq)]
+enum Clobber {
+    /// ask, if stdin is a terminal, otherwise refuse
+    Ask,
+    /// overwrite without asking
+    Force,
+    /// never overwrite
+    Never
+}
+
+impl Clobber {
+    fn from(cmd: &ArgMatches) -> Self {
+        match (cmd.get_flag("force"),cmd.get_flag("no-clobber")) {
+            (true,_) => Self::Force,
+            (_,true) => Self::Never,
+            _ => Self::Ask
+        }
+    }
+}
+
+/// Decide whether `path_out` can be written.  Returns false if the file should be skipped,
+/// or an error if we would have to ask but cannot.
+fn ok_to_overwrite(path_in: &Path,path_out: &Path,clobber: Clobber) -> Result<bool,DYNERR> {
+    if is_stdio(path_out) || clobber == Clobber::Force || !path_out.exists() {
+        return Ok(true);
+    }
+    if clobber == Clobber::Never {
+        eprintln!("{} exists, not overwriting",path_out.display());
+        return Ok(false);
+    }
+    if is_stdio(path_in) || !std::io::stdin().is_terminal() {
+        return Err(fail(Cause::Usage,format!("{} exists, use --force to overwrite or --no-clobber to skip",path_out.display())));
+    }
+    let mut ans = String::new();
+    eprint!("{} exists, overwrite? (y/n) ",path_out.display());
+    std::io::stdin().read_line(&mut ans)?;
+    Ok(ans.trim_end()=="y" || ans.trim_end()=="Y")
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO)
+}
+
+/// File output goes to a temporary file next to the destination, which is renamed over the
+/// destination only when the conversion succeeds.  If it is dropped unfinished it is removed.
+struct TempFile {
+    file: std::fs::File,
+    path: PathBuf,
+    dest: PathBuf,
+    done: bool
+}
+
+impl TempFile {
+    /// Create the temporary file, if the destination exists its permissions and
+    /// its first `keep` bytes are carried over
+    fn create(dest: &Path,keep: u64) -> Result<Self,DYNERR> {
+        let mut name = OsString::from(".");
+        name.push(dest.file_name().ok_or("output path has no file name")?);
+        name.push(format!(".{}.tmp",std::process::id()));
+        let path = dest.with_file_name(name);
+        let file = std::fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        let mut ans = Self { file, path, dest: dest.to_path_buf(), done: false };
+        if let Ok(existing) = std::fs::File::open(dest) {
+            ans.file.set_permissions(existing.metadata()?.permissions())?;
+            std::io::copy(&mut existing.take(keep),&mut ans.file)?;
+        }
+        Ok(ans)
+    }
+    /// set the length and replace the destination
+    fn persist(mut self,size: u64) -> STDRESULT {
+        self.file.set_len(size)?;
+        self.file.sync_all()?;
+        std::fs::rename(&self.path,&self.dest)?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Compression output is a file, or a buffer that goes to stdout when done, since the codecs seek
+enum Output {
+    File(TempFile),
+    Stdout(Cursor<Vec<u8>>)
+}
+
+impl Output {
+    /// `keep` is how much of an existing file to leave in place ahead of the output
+    fn create(path: &Path,keep: u64) -> Result<Self,DYNERR> {
+        match is_stdio(path) {
+            true => Ok(Self::Stdout(Cursor::new(Vec::new()))),
+            false => Ok(Self::File(TempFile::create(path,keep)?))
+        }
+    }
+    /// put the file in place with the given length, or send the buffer to stdout
+    fn finish(self,size: u64) -> STDRESULT {
+        match self {
+            Self::File(f) => f.persist(size)?,
+            Self::Stdout(buf) => {
+                let buf = buf.into_inner();
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&buf[0..usize::try_from(size).map_or(buf.len(),|size| usize::min(size,buf.len()))])?;
+                stdout.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self,buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(f) => f.file.write(buf),
+            Self::Stdout(c) => c.write(buf)
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::File(f) => f.file.flush(),
+            Self::Stdout(c) => c.flush()
+        }
+    }
+}
+
+impl Seek for Output {
+    fn seek(&mut self,pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            Self::File(f) => f.file.seek(pos),
+            Self::Stdout(c) => c.seek(pos)
+        }
+    }
+}
+
+/// Writer that refuses to go past `limit` bytes, which guards against decompression bombs
+struct Limited<'a,W: Write + Seek> {
+    inner: &'a mut W,
+    limit: u64,
+    pos: u64
+}
+
+impl<'a,W: Write + Seek> Limited<'a,W> {
+    fn new(inner: &'a mut W,limit: u64) -> Self {
+        Self { inner, limit, pos: 0 }
+    }
+}
+
+/// Error for output that would exceed `limit`
+fn too_large(limit: u64) -> std::io::Error {
+    std::io::Error::other(Failure { cause: Cause::TooLarge, msg: format!("expanded size would exceed {} bytes, see --max-size",limit) })
+}
+
+impl<W: Write + Seek> Write for Limited<'_,W> {
+    fn write(&mut self,buf: &[u8]) -> std::io::Result<usize> {
+        if self.pos + buf.len() as u64 > self.limit {
+            return Err(too_large(self.limit));
+        }
+        let n = self.inner.write(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write + Seek> Seek for Limited<'_,W> {
+    fn seek(&mut self,pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// Parse a byte count with an optional binary suffix, `k`, `M`, or `G`, which may be followed by `iB`
+fn parse_size(s: &str) -> Result<u64,String> {
+    let lower = s.to_ascii_lowercase();
+    let num = lower.trim_end_matches("ib").trim_end_matches('b');
+    let (num,scale) = match num.strip_suffix(['k','m','g']) {
+        Some(n) => (n,match num.chars().last() {
+            Some('k') => 1 << 10,
+            Some('m') => 1 << 20,
+            _ => 1 << 30
+        }),
+        None => (num,1)
+    };
+    match num.trim().parse::<u64>().ok().and_then(|n| n.checked_mul(scale)) {
+        Some(n) => Ok(n),
+        None => Err(format!("{} is not a size, use bytes or a number with k, M, or G",s))
+    }
+}
+
+/// Open the input file, or read all of stdin, since expansion needs to seek.
+fn open_input(path: &Path) -> Result<Box<dyn ReadSeek>,DYNERR> {
+    match is_stdio(path) {
+        true => {
+            let mut buf = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut buf)?;
+            Ok(Box::new(Cursor::new(buf)))
+        },
+        false => Ok(Box::new(std::fs::File::open(path)?))
+    }
+}
+
+fn unsupported(method: &str) -> DYNERR {
+    fail(Cause::Usage,format!("{} not supported",method))
+}
+
+/// Pass the data through one stage of a chained method
+#[allow(unused_variables)]
+fn chain_stage(compress: bool,codec: &Codec,stage: &str,data: &[u8]) -> Result<Vec<u8>,DYNERR> {
+    match (stage,compress) {
+        ("sub_block",true) => Ok(sub_block::frame(data)),
+        ("sub_block",false) => Ok(sub_block::unframe(data)?.0),
+        #[cfg(feature="lzw")]
+        ("lzw",true) => lzw::compress_slice(data,&codec.lzw),
+        #[cfg(feature="lzw")]
+        ("lzw",false) => lzw::expand_slice(data,&codec.lzw),
+        #[cfg(feature="direct_ports")]
+        ("lzhuf-port",true) => direct_ports::lzhuf::encode_slice(data),
+        #[cfg(feature="direct_ports")]
+        ("lzhuf-port",false) => direct_ports::lzhuf::decode_slice(data),
+        #[cfg(feature="lzss_huff")]
+        ("lzss_huff",true) => lzss_huff::compress_slice(data,&codec.lzss_huff),
+        #[cfg(feature="lzss_huff")]
+        ("lzss_huff",false) => lzss_huff::expand_slice(data,&codec.lzss_huff),
+        #[cfg(feature="td0")]
+        ("td0",true) => {
+            let mut ans = Cursor::new(Vec::new());
+            td0::compress_with_options(&mut Cursor::new(data),&mut ans,&codec.td0)?;
+            Ok(ans.into_inner())
+        },
+        #[cfg(feature="td0")]
+        ("td0",false) => {
+            let mut ans = Cursor::new(Vec::new());
+            td0::expand_with_options(&mut Cursor::new(data),&mut ans,&codec.td0)?;
+            Ok(ans.into_inner())
+        },
+        _ => Err(unsupported(stage))
+    }
+}
+
+/// Pass all of the input through the stages of a chained method, in reverse order when expanding,
+/// each stage works on the whole output of the one before, returns (in_size,out_size).
+fn run_chain<W: Write>(compress: bool,codec: &Codec,in_file: &mut Box<dyn ReadSeek>,out: &mut W) -> Result<(u64,u64),DYNERR> {
+    let mut data = Vec::new();
+    in_file.read_to_end(&mut data)?;
+    let in_size = data.len() as u64;
+    let mut stages: Vec<&str> = codec.method.split(CHAIN).collect();
+    if !compress {
+        stages.reverse();
+    }
+    for stage in stages {
+        data = chain_stage(compress,codec,stage,&data)?;
+    }
+    out.write_all(&data)?;
+    Ok((in_size,data.len() as u64))
+}
+
+/// Codecs that accept an iterator compress stdin as it arrives, others read it all first.
+fn compress(codec: &Codec,path_in: &Path,out: &mut Output) -> Result<(u64,u64),DYNERR> {
+    if codec.is_chain() {
+        return run_chain(true,codec,&mut open_input(path_in)?,out);
+    }
+    if is_stdio(path_in) {
+        match codec.method {
+            #[cfg(feature="lzw")]
+            "lzw" => return lzw::compress_iter(std::io::stdin().lock().bytes(),out,&codec.lzw),
+            #[cfg(feature="lzss_huff")]
+            "lzss_huff" => return lzss_huff::compress_iter(std::io::stdin().lock().bytes(),out,&codec.lzss_huff),
+            _ => {}
+        }
+    }
+    compress_from(codec,&mut open_input(path_in)?,out)
+}
+
+fn compress_from(codec: &Codec,in_file: &mut Box<dyn ReadSeek>,out: &mut Output) -> Result<(u64,u64),DYNERR> {
+    match codec.method {
+        #[cfg(feature="lzw")]
+        "lzw" => lzw::compress(in_file,out,&codec.lzw),
+        #[cfg(feature="direct_ports")]
+        "lzhuf-port" => direct_ports::lzhuf::encode(in_file,out),
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => lzss_huff::compress(in_file,out,&codec.lzss_huff),
+        #[cfg(feature="td0")]
+        "td0" => td0::compress_with_options(in_file,out,&codec.td0),
+        _ => Err(unsupported(codec.method))
+    }
+}
+
+#[allow(unused_variables,unused_mut)]
+fn expand<W: Write + Seek>(codec: &Codec,in_file: &mut Box<dyn ReadSeek>,out: &mut W) -> Result<(u64,u64),DYNERR> {
+    if codec.is_chain() {
+        return run_chain(false,codec,in_file,out);
+    }
+    match codec.method {
+        #[cfg(feature="lzw")]
+        "lzw" => lzw::expand(in_file,out,&codec.lzw),
+        #[cfg(feature="direct_ports")]
+        "lzhuf-port" => direct_ports::lzhuf::decode(in_file,out),
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => lzss_huff::expand(in_file,out,&codec.lzss_huff),
+        #[cfg(feature="td0")]
+        "td0" => td0::expand_with_options(in_file,out,&codec.td0),
+        _ => Err(unsupported(codec.method))
+    }
+}
+
+/// Expand straight to stdout as the data is produced, or return `None` if the codec cannot do this.
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn expand_to_stdout(codec: &Codec,in_file: &mut Box<dyn ReadSeek>,max_size: u64) -> Option<Result<(u64,u64),DYNERR>> {
+    let mut stdout = std::io::stdout().lock();
+    // the codecs skip the offset when streaming, so fill it in as they would in a file
+    let mut written = codec.out_offset();
+    let mut status = match written > max_size {
+        true => Err(too_large(max_size)),
+        false => std::io::copy(&mut std::io::repeat(0).take(written),&mut stdout).map(|_| ())
+    };
+    let sink = |chunk: &[u8]| {
+        written += chunk.len() as u64;
+        if status.is_ok() && written > max_size {
+            status = Err(too_large(max_size));
+        }
+        if status.is_ok() {
+            status = stdout.write_all(chunk);
+        }
+    };
+    let ans = match codec.method {
+        #[cfg(feature="lzw")]
+        "lzw" => lzw::expand_chunked(in_file,&codec.lzw,STDOUT_CHUNK,sink),
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => lzss_huff::expand_chunked(in_file,&codec.lzss_huff,STDOUT_CHUNK,sink),
+        #[cfg(feature="td0")]
+        "td0" => td0::expand_chunked_with_options(in_file,STDOUT_CHUNK,&codec.td0,sink),
+        _ => return None
+    };
+    match (ans,status.and_then(|_| stdout.flush())) {
+        (Ok(sizes),Ok(())) => Some(Ok(sizes)),
+        (Err(e),_) => Some(Err(e)),
+        (_,Err(e)) => Some(Err(Box::new(e)))
+    }
+}
+
+/// Compress a file straight to stdout as the codes are produced, so the output is never held in memory.
+#[cfg(feature="lzss_huff")]
+fn compress_to_stdout(codec: &Codec,path_in: &Path) -> Result<(u64,u64),DYNERR> {
+    let mut in_file = open_input(path_in)?;
+    let mut stdout = std::io::stdout().lock();
+    // the header goes out first, so the offset is filled in as it would be in a file
+    std::io::copy(&mut std::io::repeat(0).take(codec.out_offset()),&mut stdout)?;
+    let ans = lzss_huff::compress_stream(&mut in_file,&mut stdout,&codec.lzss_huff)?;
+    stdout.flush()?;
+    Ok(ans)
+}
+
+/// What would hold a whole input or output in memory during the conversion, if anything.
+/// Converting a file to a file with a single method never does.
+fn unbounded_memory(compress: bool,codec: &Codec,path_in: &Path,path_out: &Path) -> Option<&'static str> {
+    if codec.is_chain() {
+        return Some("a chained method");
+    }
+    // the normal image is put together in memory to find where the padding starts
+    #[cfg(feature="td0")]
+    if !compress && codec.method == "td0" && codec.td0.trim_padding {
+        return Some("--trim-padding");
+    }
+    #[cfg(feature="td0")]
+    if !compress && codec.method == "td0" && codec.td0.padding != td0::Padding::None {
+        return Some("padding the image");
+    }
+    match (compress,is_stdio(path_in),is_stdio(path_out)) {
+        // lzw and lzss_huff compress stdin as it arrives
+        (true,true,_) if !matches!(codec.method,"lzw" | "lzss_huff") => Some("compressing stdin with this method"),
+        // only lzss_huff streams its codes, and only when it can find the length of the input
+        (true,_,true) if codec.method != "lzss_huff" || is_stdio(path_in) => Some("compressing to stdout with this method"),
+        (false,true,_) => Some("expanding stdin"),
+        (false,_,true) if codec.method == "lzhuf-port" => Some("expanding to stdout with this method"),
+        _ => None
+    }
+}
+
+fn low_memory_refusal(what: &str) -> DYNERR {
+    fail(Cause::Usage,format!("{} holds all of the data in memory, which --low-memory does not allow",what))
+}
+
+fn past_tense(compress: bool) -> &'static str {
+    match compress {
+        true => "compressed",
+        false => "expanded"
+    }
+}
+
+/// Convert one file, either path can be `-` for stdin or stdout, returns (in_size,out_size).
+/// Expansion fails rather than write more than `max_size` bytes.
+fn convert(compress: bool,codec: &Codec,path_in: &Path,path_out: &Path,max_size: u64) -> Result<(u64,u64),DYNERR> {
+    if compress {
+        #[cfg(feature="lzss_huff")]
+        if codec.method == "lzss_huff" && is_stdio(path_out) && !is_stdio(path_in) {
+            return compress_to_stdout(codec,path_in);
+        }
+        let mut out = Output::create(path_out,codec.out_offset())?;
+        let (in_size,out_size) = self::compress(codec,path_in,&mut out)?;
+        out.finish(codec.out_offset() + out_size)?;
+        return Ok((in_size,out_size));
+    }
+    let mut in_file = open_input(path_in)?;
+    let streamed: Option<Result<(u64,u64),DYNERR>> = match is_stdio(path_out) {
+        #[cfg(any(feature="lzw",feature="lzss_huff"))]
+        true => expand_to_stdout(codec,&mut in_file,max_size),
+        _ => None
+    };
+    match streamed {
+        Some(res) => res,
+        None => {
+            let mut out = Output::create(path_out,codec.out_offset())?;
+            let (in_size,out_size) = expand(codec,&mut in_file,&mut Limited::new(&mut out,max_size))?;
+            out.finish(codec.out_offset() + out_size)?;
+            Ok((in_size,out_size))
+        }
+    }
+}
+
+/// One of the files that were concatenated before compressing, the offset is in the expanded data
+#[derive(serde::Serialize,serde::Deserialize)]
+struct Member {
+    name: String,
+    offset: u64,
+    size: u64
+}
+
+/// Boundaries of the members of a concatenation, kept in a TOML file next to the compressed stream
+#[derive(serde::Serialize,serde::Deserialize)]
+struct Manifest {
+    member: Vec<Member>
+}
+
+/// Compress the concatenation of `paths` as one stream, returns (in_size,out_size).
+/// If `members` is given, the boundaries of the inputs are written there.
+fn compress_concat(codec: &Codec,paths: &[PathBuf],path_out: &Path,members: Option<&Path>) -> Result<(u64,u64),DYNERR> {
+    let mut data = Vec::new();
+    let mut manifest = Manifest { member: Vec::new() };
+    for path in paths {
+        let offset = data.len() as u64;
+        open_input(path).and_then(|mut f| Ok(f.read_to_end(&mut data)?))
+            .map_err(|e| fail(Cause::of(&*e),format!("{}: {}",path.display(),e)))?;
+        let name = path.file_name().expect(RCH).to_string_lossy().to_string();
+        if members.is_some() && manifest.member.iter().any(|m| m.name == name) {
+            return Err(fail(Cause::Usage,format!("{} appears twice, members need distinct names",name)));
+        }
+        manifest.member.push(Member { name, offset, size: data.len() as u64 - offset });
+    }
+    let mut in_file: Box<dyn ReadSeek> = Box::new(Cursor::new(data));
+    let mut out = Output::create(path_out,codec.out_offset())?;
+    let (in_size,out_size) = match codec.is_chain() {
+        true => run_chain(true,codec,&mut in_file,&mut out)?,
+        false => compress_from(codec,&mut in_file,&mut out)?
+    };
+    out.finish(codec.out_offset() + out_size)?;
+    if let Some(path) = members {
+        std::fs::write(path,toml::to_string(&manifest)?)?;
+    }
+    Ok((in_size,out_size))
+}
+
+/// Expand `path_in` and write each member listed in the `members` file into `dir`,
+/// the members have to cover the expanded data exactly.  Returns (in_size,out_size).
+fn expand_members(codec: &Codec,path_in: &Path,members: &Path,dir: &Path,clobber: Clobber,max_size: u64) -> Result<(u64,u64),DYNERR> {
+    let manifest: Manifest = toml::from_str(&std::fs::read_to_string(members)?)
+        .map_err(|e| fail(Cause::Format,format!("{}: {}",members.display(),e)))?;
+    let mut in_file = open_input(path_in)?;
+    let mut buf = Cursor::new(Vec::new());
+    let (in_size,out_size) = expand(codec,&mut in_file,&mut Limited::new(&mut buf,max_size))
+        .map_err(|e| fail(Cause::of(&*e),format!("{}: {}",path_in.display(),e)))?;
+    let buf = buf.into_inner();
+    let data = usize::try_from(codec.out_offset()).ok().and_then(|beg| buf.get(beg..)).unwrap_or_default();
+    let mut end = 0;
+    for m in &manifest.member {
+        if m.offset != end || Path::new(&m.name).file_name() != Some(m.name.as_ref()) {
+            return Err(fail(Cause::Format,format!("{}: bad entry for {}",members.display(),m.name)));
+        }
+        end = end.saturating_add(m.size);
+    }
+    if end != out_size {
+        return Err(fail(Cause::Format,format!("{}: members add up to {} but the data is {}",members.display(),end,out_size)));
+    }
+    std::fs::create_dir_all(dir)?;
+    for m in &manifest.member {
+        let path_out = dir.join(&m.name);
+        if !ok_to_overwrite(path_in,&path_out,clobber)? {
+            continue;
+        }
+        let mut out = Output::create(&path_out,0)?;
+        out.write_all(&data[m.offset as usize..(m.offset + m.size) as usize])?;
+        out.finish(m.size)?;
+        eprintln!("{}: {} bytes",path_out.display(),m.size);
+    }
+    Ok((in_size,out_size))
+}
+
+/// How output files are named after the input when no output path is given
+struct Naming {
+    method: &'static str,
+    /// extension of compressed files
+    ext: &'static str,
+    /// for formats where both forms share the extension, the (compressed,expanded) tags that go before it
+    tags: Option<(&'static str,&'static str)>
+}
+
+/// naming for each method, a codec that is added should add its entry here
+const NAMING: &[Naming] = &[
+    #[cfg(feature="lzw")]
+    Naming { method: "lzw", ext: "lzw", tags: None },
+    #[cfg(feature="direct_ports")]
+    Naming { method: "lzhuf-port", ext: "lzh", tags: None },
+    #[cfg(feature="lzss_huff")]
+    Naming { method: "lzss_huff", ext: "lzh", tags: None },
+    #[cfg(feature="td0")]
+    Naming { method: "td0", ext: "td0", tags: Some(("adv","norm")) }
+];
+
+/// Extension of files the method produces when compressing, `None` if it varies
+fn method_extension(method: &str) -> Option<&'static str> {
+    NAMING.iter().find(|n| n.method == method).map(|n| n.ext)
+}
+
+/// Name of the output file, which is the input name with the method's extension added or removed.
+/// Expanded files that lack the extension get `.out`.  If the method tags its files, as with
+/// `.adv.td0` and `.norm.td0`, the tag is replaced or inserted instead, keeping the case of the extension.
+/// A chained method applies the naming of each stage in turn.  Returns `None` if the method has no naming.
+fn output_name(compress: bool,method: &str,path_in: &Path) -> Option<OsString> {
+    if method.contains(CHAIN) {
+        let mut stages: Vec<&str> = method.split(CHAIN).filter(|stage| method_extension(stage).is_some()).collect();
+        if !compress {
+            stages.reverse();
+        }
+        let mut path = path_in.to_path_buf();
+        for stage in &stages {
+            path.set_file_name(output_name(compress,stage,&path)?);
+        }
+        return match stages.is_empty() {
+            true => None,
+            false => Some(path.file_name().expect(RCH).to_os_string())
+        };
+    }
+    let naming = NAMING.iter().find(|n| n.method == method)?;
+    let mut name = path_in.file_name().expect(RCH).to_os_string();
+    let has_ext = matches!(path_in.extension(),Some(x) if x.eq_ignore_ascii_case(naming.ext));
+    if let Some((compressed,expanded)) = naming.tags {
+        let tag = if compress { compressed } else { expanded };
+        let (base,ext) = match has_ext {
+            true => (Path::new(path_in.file_stem().expect(RCH)),path_in.extension().expect(RCH).to_os_string()),
+            false => (Path::new(path_in.file_name().expect(RCH)),OsString::from(naming.ext))
+        };
+        name = match base.extension() {
+            Some(x) if x.eq_ignore_ascii_case(compressed) || x.eq_ignore_ascii_case(expanded) => base.file_stem().expect(RCH).to_os_string(),
+            _ => base.as_os_str().to_os_string()
+        };
+        name.push(".");
+        name.push(tag);
+        name.push(".");
+        name.push(ext);
+        return Some(name);
+    }
+    match (compress,has_ext) {
+        (true,_) => {
+            name.push(".");
+            name.push(naming.ext);
+        },
+        (false,true) => name = path_in.file_stem().expect(RCH).to_os_string(),
+        (false,false) => name.push(".out")
+    }
+    Some(name)
+}
+
+/// Gather the files in the tree below `dir` in sorted order, skipping the tree below `skip`
+fn walk(dir: &Path,skip: &Path,files: &mut Vec<PathBuf>) -> Result<(),std::io::Error> {
+    let mut entries = std::fs::read_dir(dir)?.map(|e| e.map(|e| e.path())).collect::<Result<Vec<PathBuf>,std::io::Error>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if path.canonicalize()? != skip {
+                walk(&path,skip,files)?;
+            }
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Settings for converting many files
+struct Batch<'a> {
+    compress: bool,
+    codec: &'a Codec<'a>,
+    /// put the outputs here, the structure of any input directory is mirrored
+    dir: &'a Path,
+    /// descend into input directories
+    recursive: bool,
+    /// only take files with this extension from input directories
+    ext: Option<&'a str>,
+    /// number of files to process at once
+    jobs: usize,
+    clobber: Clobber,
+    /// largest expanded file allowed
+    max_size: u64,
+    /// refuse conversions whose memory use grows with the data
+    low_memory: bool
+}
+
+/// Paths matching a glob pattern, a bad pattern or one with no matches is added to `failures`
+fn glob_paths(pattern: &str,failures: &mut Vec<(String,Cause)>) -> Vec<PathBuf> {
+    let matches: Vec<PathBuf> = match glob::glob(pattern) {
+        Ok(paths) => paths.filter_map(Result::ok).collect(),
+        Err(e) => {
+            eprintln!("{}: {}",pattern,e);
+            failures.push((pattern.to_string(),Cause::Usage));
+            return Vec::new();
+        }
+    };
+    if matches.is_empty() {
+        eprintln!("{}: no matching files",pattern);
+        failures.push((pattern.to_string(),Cause::Io));
+    }
+    matches
+}
+
+/// Error summing up the failures of a batch, the cause is the one they share, if any
+fn batch_failure(failures: &[(String,Cause)],total: usize) -> DYNERR {
+    fail(Cause::common(failures.iter().map(|(_,c)| *c)),format!("{} of {} failed",failures.len(),total))
+}
+
+/// Convert every file matching the glob `patterns`, putting the outputs in `batch.dir`.
+/// Failures are reported and skipped, the result is an error if there were any.
+fn convert_many(batch: &Batch,patterns: &[&String]) -> STDRESULT {
+    let mut jobs = Vec::new();
+    let mut failures = Vec::new();
+    std::fs::create_dir_all(batch.dir)?;
+    let out_dir = batch.dir.canonicalize()?;
+    for pattern in patterns {
+        for path_in in glob_paths(pattern,&mut failures) {
+            if !path_in.is_dir() {
+                let name = output_name(batch.compress,batch.codec.method,&path_in).unwrap_or_else(|| path_in.file_name().expect(RCH).to_os_string());
+                let path_out = batch.dir.join(name);
+                jobs.push((path_in,path_out));
+                continue;
+            }
+            if !batch.recursive {
+                eprintln!("{}: is a directory, use --recursive",path_in.display());
+                failures.push((path_in.display().to_string(),Cause::Usage));
+                continue;
+            }
+            let mut files = Vec::new();
+            walk(&path_in,&out_dir,&mut files)?;
+            for file in files {
+                let matching = match (batch.ext,file.extension()) {
+                    (None,_) => true,
+                    (Some(ext),Some(x)) => x.eq_ignore_ascii_case(ext),
+                    (Some(_),None) => false
+                };
+                if matching {
+                    let rel = file.strip_prefix(&path_in).expect(RCH);
+                    let name = output_name(batch.compress,batch.codec.method,&file).unwrap_or_else(|| file.file_name().expect(RCH).to_os_string());
+                    let path_out = batch.dir.join(rel).with_file_name(name);
+                    jobs.push((file,path_out));
+                }
+            }
+        }
+    }
+    run_jobs(batch,&jobs,failures)
+}
+
+/// Convert each (input,output) pair with up to `batch.jobs` threads, reporting as we go and summarizing at the end.
+/// A failed file does not stop the others.
+/// `failures` lists problems that came up while gathering the jobs.
+fn run_jobs(batch: &Batch,jobs: &[(PathBuf,PathBuf)],mut failures: Vec<(String,Cause)>) -> STDRESULT {
+    // checks that can prompt the user are done before any work starts
+    let mut failed = Vec::new();
+    let mut pending = Vec::new();
+    let mut skipped = 0;
+    for (idx,(path_in,path_out)) in jobs.iter().enumerate() {
+        if let (Ok(a),Ok(b)) = (path_in.canonicalize(),path_out.canonicalize()) {
+            if a == b {
+                eprintln!("{}: output would overwrite input",path_in.display());
+                failed.push((idx,Cause::Usage));
+                continue;
+            }
+        }
+        match ok_to_overwrite(path_in,path_out,batch.clobber) {
+            Ok(true) => pending.push(idx),
+            Ok(false) => {
+                eprintln!("{}: skipped",path_in.display());
+                skipped += 1;
+            },
+            Err(e) => {
+                eprintln!("{}: {}",path_in.display(),e);
+                failed.push((idx,Cause::of(&*e)));
+            }
+        }
+    }
+    let done: Vec<(usize,Result<(),Cause>)> = pending.iter().copied().zip(on_workers(&pending,batch.jobs,|&idx| {
+        let (path_in,path_out) = &jobs[idx];
+        run_job(batch,path_in,path_out)
+    })).collect();
+    let successes = done.iter().filter(|(_,res)| res.is_ok()).count();
+    failed.extend(done.iter().filter_map(|(idx,res)| res.err().map(|cause| (*idx,cause))));
+    failed.sort_by_key(|(idx,_)| *idx);
+    failures.extend(failed.iter().map(|(idx,cause)| (jobs[*idx].0.display().to_string(),*cause)));
+    match skipped {
+        0 => eprintln!("{} succeeded, {} failed",successes,failures.len()),
+        _ => eprintln!("{} succeeded, {} failed, {} skipped",successes,failures.len(),skipped)
+    }
+    for (failure,_) in &failures {
+        eprintln!("  failed: {}",failure);
+    }
+    match failures.len() {
+        0 => Ok(()),
+        _ => Err(batch_failure(&failures,successes + failures.len()))
+    }
+}
+
+/// Convert one file of a batch and report the outcome, returns the cause if it failed
+fn run_job(batch: &Batch,path_in: &Path,path_out: &Path) -> Result<(),Cause> {
+    let res = match path_out.parent() {
+        Some(parent) => std::fs::create_dir_all(parent).map_err(|e| e.into()),
+        None => Ok(())
+    }.and_then(|_| match unbounded_memory(batch.compress,batch.codec,path_in,path_out) {
+        Some(what) if batch.low_memory => Err(low_memory_refusal(what)),
+        _ => convert(batch.compress,batch.codec,path_in,path_out,batch.max_size)
+    });
+    match res {
+        Ok((in_size,out_size)) => {
+            eprintln!("{}: {} {} into {}",path_in.display(),past_tense(batch.compress),in_size,out_size);
+            Ok(())
+        },
+        Err(e) => {
+            eprintln!("{}: {}",path_in.display(),e);
+            Err(Cause::of(&*e))
+        }
+    }
+}
+
+/// Fully decode the input and discard the output, returns (in_size,out_size,complete),
+/// where `complete` is false if the data appears to be truncated.
+fn verify(codec: &Codec,path_in: &Path) -> Result<(u64,u64,bool),DYNERR> {
+    let mut in_file = open_input(path_in)?;
+    if codec.is_chain() {
+        let (in_size,out_size) = run_chain(false,codec,&mut in_file,&mut std::io::sink())?;
+        return Ok((in_size,out_size,true));
+    }
+    match codec.method {
+        #[cfg(feature="lzw")]
+        "lzw" => lzw::verify(&mut in_file,&codec.lzw),
+        #[cfg(feature="direct_ports")]
+        "lzhuf-port" => {
+            // the port decodes past the end of the data, so truncation goes unnoticed
+            let (in_size,out_size) = direct_ports::lzhuf::decode(&mut in_file,&mut Cursor::new(Vec::new()))?;
+            Ok((in_size,out_size,true))
+        },
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => lzss_huff::verify(&mut in_file,&codec.lzss_huff),
+        #[cfg(feature="td0")]
+        "td0" => {
+            let (in_size,out_size,complete) = td0::verify(&mut in_file)?;
+            if complete {
+                verify_td0_sectors(&mut in_file)?;
+            }
+            Ok((in_size,out_size,complete))
+        },
+        _ => Err(unsupported(codec.method))
+    }
+}
+
+/// Check the CRC of every track and sector of a TD0 image, a mismatch is a checksum failure
+#[cfg(feature="td0")]
+fn verify_td0_sectors(in_file: &mut Box<dyn ReadSeek>) -> STDRESULT {
+    let mut image = Vec::new();
+    in_file.rewind()?;
+    in_file.read_to_end(&mut image)?;
+    let mismatches = td0::verify_sectors(&image)?.mismatches();
+    match mismatches.first() {
+        Some(first) if mismatches.len() > 1 => Err(fail(Cause::Checksum,format!("{}, and {} more CRCs do not match",first,mismatches.len() - 1))),
+        Some(first) => Err(fail(Cause::Checksum,first.to_string())),
+        None => Ok(())
+    }
+}
+
+/// What a successful test established, beyond the data decoding without error
+fn integrity_checks(method: &str) -> &'static str {
+    match method {
+        "lzw" => "stop code found",
+        "td0" => "header, track, and sector CRCs ok",
+        "lzhuf-port" => "end of data not checked",
+        m if m.contains(CHAIN) || FRAMINGS.contains(&m) => "every stage decoded",
+        _ => "length matches header"
+    }
+}
+
+/// Test each file matching the glob `patterns` (or stdin), printing a line per file and a summary,
+/// the result is an error if any file failed.
+fn test_many(codec: &Codec,patterns: &[&String]) -> STDRESULT {
+    let mut paths = Vec::new();
+    let mut failures = Vec::new();
+    for pattern in patterns {
+        match is_stdio(Path::new(pattern)) {
+            true => paths.push(PathBuf::from(STDIO)),
+            false => paths.append(&mut glob_paths(pattern,&mut failures))
+        }
+    }
+    let mut successes = 0;
+    for path_in in paths {
+        match verify(codec,&path_in) {
+            Ok((in_size,out_size,true)) => {
+                println!("{}: OK, {} expands to {}, {}",path_in.display(),in_size,out_size,integrity_checks(codec.method));
+                successes += 1;
+            },
+            Ok((_,out_size,false)) => {
+                println!("{}: TRUNCATED, data ended after expanding {}",path_in.display(),out_size);
+                failures.push((path_in.display().to_string(),Cause::Truncated));
+            },
+            Err(e) => {
+                println!("{}: FAILED, {}",path_in.display(),e);
+                failures.push((path_in.display().to_string(),Cause::of(&*e)));
+            }
+        }
+    }
+    println!("{} passed, {} failed",successes,failures.len());
+    match failures.len() {
+        0 => Ok(()),
+        _ => Err(batch_failure(&failures,successes + failures.len()))
+    }
+}
+
+/// Write one line per token to stdout, as text or JSON
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn print_trace<E: serde::Serialize>(trace: &[E],json: bool,describe: fn(&E) -> String) -> STDRESULT {
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    if !json {
+        writeln!(out,"{:>10} {:>10}  token","bit","pos")?;
+    }
+    for event in trace {
+        let res = match json {
+            true => writeln!(out,"{}",serde_json::to_string(event)?),
+            false => writeln!(out,"{}",describe(event))
+        };
+        match res {
+            // the reader went away, e.g. `head`
+            Err(e) if e.kind()==std::io::ErrorKind::BrokenPipe => return Ok(()),
+            res => res?
+        }
+    }
+    match out.flush() {
+        Err(e) if e.kind()==std::io::ErrorKind::BrokenPipe => Ok(()),
+        res => Ok(res?)
+    }
+}
+
+#[cfg(feature="lzw")]
+fn describe_lzw(event: &lzw::TraceEvent) -> String {
+    let token = match &event.token {
+        lzw::Token::Header { value } => format!("header {}",value),
+        lzw::Token::Code { code, width, length } => format!("code {} ({} bits) expands to {}",code,width,length),
+        lzw::Token::Clear => "clear".to_string(),
+        lzw::Token::Stop => "stop".to_string()
+    };
+    format!("{:>10} {:>10}  {}",event.bit,event.pos,token)
+}
+
+#[cfg(feature="lzss_huff")]
+fn describe_lzss_huff(event: &lzss_huff::TraceEvent) -> String {
+    let token = match &event.token {
+        lzss_huff::Token::Literal { byte } if byte.is_ascii_graphic() => format!("literal {:02x} '{}'",byte,*byte as char),
+        lzss_huff::Token::Literal { byte } => format!("literal {:02x}",byte),
+        lzss_huff::Token::Match { offset, length } => format!("match offset {} length {}",offset,length),
+        lzss_huff::Token::Rebuild => "rebuild Huffman tree".to_string()
+    };
+    format!("{:>10} {:>10}  {}",event.bit,event.pos,token)
+}
+
+/// Decode and print the token trace, stopping at the first error, which is reported along with how far we got
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn inspect(codec: &Codec,path_in: &Path,json: bool) -> STDRESULT {
+    let mut in_file = open_input(path_in)?;
+    #[cfg(feature="td0")]
+    if codec.method == "td0" {
+        // the stream follows the image header, the version tells which codec
+        let mut header = [0;12];
+        in_file.read_exact(&mut header)?;
+        if &header[0..2] != b"td" {
+            return Err(fail(Cause::Format,format!("{}: not an advanced TD0 image",path_in.display())));
+        }
+        let version = td0::Version::new(header[4]).map_err(|e| fail(Cause::Format,format!("{}: {}",path_in.display(),e)))?;
+        eprintln!("{}: Teledisk {} image",path_in.display(),version);
+        let td_codec = match version.is_lzw() {
+            true => Codec { method: "lzw", lzw: td0::TD_V1_OPTIONS, lzss_huff: lzss_huff::STD_OPTIONS, td0: td0::STD_OPTIONS },
+            false => Codec { method: "lzss_huff", lzw: lzw::STD_OPTIONS, lzss_huff: td0::TD_V2_OPTIONS, td0: td0::STD_OPTIONS }
+        };
+        return inspect_stream(&td_codec,&mut in_file,path_in,json);
+    }
+    inspect_stream(codec,&mut in_file,path_in,json)
+}
+
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn inspect_stream(codec: &Codec,in_file: &mut Box<dyn ReadSeek>,path_in: &Path,json: bool) -> STDRESULT {
+    let (count,res) = match codec.method {
+        #[cfg(feature="lzw")]
+        "lzw" => {
+            let (trace,res) = lzw::expand_traced(in_file,&codec.lzw);
+            print_trace(&trace,json,describe_lzw)?;
+            (trace.len(),res)
+        },
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => {
+            let (trace,res) = lzss_huff::expand_traced(in_file,&codec.lzss_huff);
+            print_trace(&trace,json,describe_lzss_huff)?;
+            (trace.len(),res)
+        },
+        _ => return Err(unsupported(codec.method))
+    };
+    match res {
+        Ok((in_size,out_size)) => {
+            eprintln!("{} tokens, {} expands to {}",count,in_size,out_size);
+            Ok(())
+        },
+        Err(e) => {
+            Err(fail(Cause::of(&*e),format!("{}: stopped after {} tokens, {}",path_in.display(),count,e)))
+        }
+    }
+}
+
+/// Count of values falling in `min..=max`
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+#[derive(serde::Serialize)]
+struct Bucket {
+    min: u64,
+    max: u64,
+    count: u64
+}
+
+/// Size the input compresses to with a method, `None` if the method cannot take it
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+#[derive(serde::Serialize)]
+struct Projection {
+    method: &'static str,
+    size: Option<u64>
+}
+
+/// Statistics that help decide which method suits the data
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+#[derive(serde::Serialize)]
+struct Analysis {
+    size: u64,
+    /// number of byte values that occur
+    distinct: usize,
+    /// order-0 entropy in bits per byte
+    entropy: f64,
+    /// count of each byte value
+    histogram: Vec<u64>,
+    /// tokens from `lzss_huff` with the standard options
+    literals: u64,
+    matches: u64,
+    match_lengths: Vec<Bucket>,
+    match_offsets: Vec<Bucket>,
+    sizes: Vec<Projection>
+}
+
+/// Tally values into buckets that double in size, the first bucket starts at `start`
+#[cfg(feature="lzss_huff")]
+fn tally(buckets: &mut Vec<Bucket>,start: u64,val: u64) {
+    if buckets.is_empty() {
+        buckets.push(Bucket { min: start, max: start, count: 0 });
+    }
+    while buckets.last().expect(RCH).max < val {
+        let min = buckets.last().expect(RCH).max + 1;
+        buckets.push(Bucket { min, max: 2*min - 1, count: 0 });
+    }
+    buckets.iter_mut().find(|b| val <= b.max).expect(RCH).count += 1;
+}
+
+/// Gather the statistics, this compresses the data with every method
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn analysis(data: &[u8]) -> Analysis {
+    let mut histogram = vec![0;256];
+    for byte in data {
+        histogram[*byte as usize] += 1;
+    }
+    let entropy = histogram.iter().filter(|n| **n > 0).fold(0.0,|sum,n| {
+        let p = *n as f64 / data.len() as f64;
+        sum - p * p.log2()
+    });
+    let mut ans = Analysis {
+        size: data.len() as u64,
+        distinct: histogram.iter().filter(|n| **n > 0).count(),
+        entropy,
+        histogram,
+        literals: 0,
+        matches: 0,
+        match_lengths: Vec::new(),
+        match_offsets: Vec::new(),
+        sizes: Vec::new()
+    };
+    for method in STREAM_METHODS {
+        let size = standard_method(method).compress_slice(data).ok().map(|c| c.len() as u64);
+        ans.sizes.push(Projection { method, size });
+    }
+    #[cfg(feature="lzss_huff")]
+    if let Ok(compressed) = lzss_huff::compress_slice(data,&lzss_huff::STD_OPTIONS) {
+        let (trace,_) = lzss_huff::expand_traced(&mut Cursor::new(&compressed),&lzss_huff::STD_OPTIONS);
+        for event in trace {
+            match event.token {
+                lzss_huff::Token::Literal { .. } => ans.literals += 1,
+                lzss_huff::Token::Match { offset, length } => {
+                    ans.matches += 1;
+                    tally(&mut ans.match_lengths,lzss_huff::STD_OPTIONS.threshold as u64 + 1,length as u64);
+                    tally(&mut ans.match_offsets,0,offset as u64);
+                },
+                lzss_huff::Token::Rebuild => {}
+            }
+        }
+    }
+    ans
+}
+
+/// Print the statistics for the input, as text or a JSON object
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn analyze(path_in: &Path,json: bool) -> STDRESULT {
+    let mut data = Vec::new();
+    open_input(path_in)?.read_to_end(&mut data)?;
+    let ans = analysis(&data);
+    if json {
+        println!("{}",serde_json::to_string(&ans)?);
+        return Ok(());
+    }
+    let percent = |n: u64| match ans.size {
+        0 => 0.0,
+        size => 100.0 * n as f64 / size as f64
+    };
+    println!("size {}, {} distinct byte values",ans.size,ans.distinct);
+    println!("entropy {:.3} bits per byte, order-0 limit {} bytes",ans.entropy,(ans.entropy * ans.size as f64 / 8.0).ceil());
+    let mut common: Vec<(usize,u64)> = ans.histogram.iter().copied().enumerate().filter(|(_,n)| *n > 0).collect();
+    common.sort_by(|a,b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    println!("most frequent bytes:");
+    for (byte,n) in common.iter().take(8) {
+        println!("  {:02x}  {:>10}  {:5.1}%",byte,n,percent(*n));
+    }
+    if ans.literals + ans.matches > 0 {
+        println!("lzss_huff tokens: {} literals, {} matches",ans.literals,ans.matches);
+        for (title,buckets) in [("match lengths:",&ans.match_lengths),("match offsets:",&ans.match_offsets)] {
+            println!("{}",title);
+            for b in buckets {
+                println!("  {:>11}  {:>10}",format!("{}-{}",b.min,b.max),b.count);
+            }
+        }
+    }
+    println!("compressed sizes:");
+    for p in &ans.sizes {
+        match p.size {
+            Some(size) => println!("  {:<10} {:>10}  {:5.1}%",p.method,size,percent(size)),
+            None => println!("  {:<10} {:>10}",p.method,"n/a")
+        }
+    }
+    Ok(())
+}
+
+/// Library method with the standard options
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn standard_method(method: &str) -> retrocompressor::Method {
+    match method {
+        #[cfg(feature="lzw")]
+        "lzw" => retrocompressor::Method::Lzw(lzw::STD_OPTIONS),
+        #[cfg(feature="lzss_huff")]
+        "lzss_huff" => retrocompressor::Method::LzssHuff(lzss_huff::STD_OPTIONS),
+        #[cfg(feature="td0")]
+        "td0" => retrocompressor::Method::Td0,
+        _ => panic!("{}",RCH)
+    }
+}
+
+/// Expand with one method and compress with another, without an intermediate file
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+fn transcode(cmd: &ArgMatches) -> STDRESULT {
+    let from = cmd.get_one::<String>("from").expect(RCH);
+    let to = cmd.get_one::<String>("to").expect(RCH);
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let path_out = match cmd.get_one::<String>("output") {
+        Some(path) => PathBuf::from(path),
+        None if is_stdio(path_in) => PathBuf::from("-"),
+        None => {
+            // name it as if it were expanded and then compressed
+            let expanded = output_name(false,from,path_in).expect(RCH);
+            let path = path_in.with_file_name(output_name(true,to,Path::new(&expanded)).expect(RCH));
+            eprintln!("writing {}",path.display());
+            path
+        }
+    };
+    if !ok_to_overwrite(path_in,&path_out,Clobber::from(cmd))? {
+        eprintln!("abort operation");
+        return Ok(());
+    }
+    let mut in_file = open_input(path_in)?;
+    let mut out = Output::create(&path_out,0)?;
+    let (in_size,out_size) = standard_method(from).transcode(&standard_method(to),&mut in_file,&mut out)?;
+    out.finish(out_size)?;
+    eprintln!("converted {} into {}",in_size,out_size);
+    Ok(())
+}
+
+/// Print, replace, or strip the comment of a TD0 image, changes are written in place unless there is an output path
+#[cfg(feature="td0")]
+fn td0_comment(cmd: &ArgMatches) -> STDRESULT {
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let mut image = Vec::new();
+    open_input(path_in)?.read_to_end(&mut image)?;
+    let comment = match (cmd.get_one::<String>("set"),cmd.get_flag("strip")) {
+        (Some(text),_) => {
+            // after 2155 use the last date a comment can hold
+            let timestamp = td0::Timestamp::now().to_bytes().unwrap_or([255,11,31,23,59,59]);
+            Some(td0::Comment::from_text(text,timestamp))
+        },
+        (None,true) => None,
+        (None,false) => {
+            match td0::get_comment(&image)? {
+                Some(comment) => {
+                    println!("{}",comment.date());
+                    println!("{}",comment.text());
+                },
+                None => eprintln!("{}: no comment",path_in.display())
+            }
+            return Ok(());
+        }
+    };
+    let ans = td0::set_comment(&image,comment.as_ref())?;
+    let path_out = cmd.get_one::<String>("output").map(Path::new).unwrap_or(path_in);
+    let mut out = Output::create(path_out,0)?;
+    out.write_all(&ans)?;
+    out.finish(ans.len() as u64)?;
+    match comment {
+        Some(_) => eprintln!("{}: comment set",path_out.display()),
+        None => eprintln!("{}: comment stripped",path_out.display())
+    }
+    Ok(())
+}
+
+/// Report CRC mismatches in a TD0 image and fix them, changes are written in place unless there is an output path
+#[cfg(feature="td0")]
+fn td0_fix_crc(cmd: &ArgMatches) -> STDRESULT {
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let mut image = Vec::new();
+    open_input(path_in)?.read_to_end(&mut image)?;
+    let (fixed,found) = td0::fix_crc(&image,cmd.get_flag("sectors"))?;
+    for mismatch in &found {
+        println!("{}: {}",path_in.display(),mismatch);
+    }
+    println!("{}: {} CRC mismatches",path_in.display(),found.len());
+    if found.is_empty() || cmd.get_flag("dry-run") {
+        return Ok(());
+    }
+    let path_out = cmd.get_one::<String>("output").map(Path::new).unwrap_or(path_in);
+    let mut out = Output::create(path_out,0)?;
+    out.write_all(&fixed)?;
+    out.finish(fixed.len() as u64)?;
+    eprintln!("{}: CRC fixed",path_out.display());
+    Ok(())
+}
+
+/// Report the damage to a TD0 image and write what could be recovered, the input is never changed
+#[cfg(feature="td0")]
+fn td0_salvage(cmd: &ArgMatches) -> STDRESULT {
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let mut image = Vec::new();
+    open_input(path_in)?.read_to_end(&mut image)?;
+    let (salvaged,found) = td0::salvage(&image,*cmd.get_one::<u8>("fill").expect(RCH))?;
+    for damage in &found {
+        println!("{}: {}",path_in.display(),damage);
+    }
+    println!("{}: {} damaged regions",path_in.display(),found.len());
+    if let Some(path_out) = cmd.get_one::<String>("output").map(Path::new) {
+        let mut out = Output::create(path_out,0)?;
+        out.write_all(&salvaged)?;
+        out.finish(salvaged.len() as u64)?;
+        eprintln!("{}: salvaged",path_out.display());
+    }
+    Ok(())
+}
+
+/// Report how much of a truncated TD0 image is there and write the whole tracks, padded if asked
+#[cfg(feature="td0")]
+fn td0_recover(cmd: &ArgMatches) -> STDRESULT {
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let mut image = Vec::new();
+    open_input(path_in)?.read_to_end(&mut image)?;
+    let cylinders = cmd.get_one::<u8>("cylinders").copied();
+    let (recovered,report) = td0::recover(&image,cylinders,*cmd.get_one::<u8>("fill").expect(RCH))?;
+    println!("{}: {}",path_in.display(),report);
+    if let Some(path_out) = cmd.get_one::<String>("output").map(Path::new) {
+        let mut out = Output::create(path_out,0)?;
+        out.write_all(&recovered)?;
+        out.finish(recovered.len() as u64)?;
+        eprintln!("{}: recovered",path_out.display());
+    }
+    Ok(())
+}
+
+/// Split a TD0 image into a multi-volume set, written next to the output path
+#[cfg(feature="td0")]
+fn td0_split(cmd: &ArgMatches) -> STDRESULT {
+    let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+    let path_out = Path::new(cmd.get_one::<String>("output").expect(RCH));
+    let size = *cmd.get_one::<u64>("size").expect(RCH);
+    if size <= 12 || is_stdio(path_out) {
+        return Err(fail(Cause::Usage,"a set needs volumes of more than 12 bytes written to files"));
+    }
+    let mut image = Vec::new();
+    open_input(path_in)?.read_to_end(&mut image)?;
+    let volumes = td0::split_volumes(&image,usize::try_from(size).unwrap_or(usize::MAX))?;
+    for (sequence,volume) in volumes.iter().enumerate() {
+        let path = td0::volume_path(path_out,sequence);
+        let mut out = Output::create(&path,0)?;
+        out.write_all(volume)?;
+        out.finish(volume.len() as u64)?;
+        eprintln!("{}: volume {} of {}",path.display(),sequence + 1,volumes.len());
+    }
+    Ok(())
+}
+
+/// Run the program, any error is reported on a single line that starts with `error[<cause>]:`,
+/// and the exit code is the `Cause`
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            let cause = Cause::of(&*e);
+            let msg = e.to_string().lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<&str>>().join(" ");
+            eprintln!("error[{}]: {}",cause.name(),msg);
+            std::process::ExitCode::from(cause as u8)
+        }
+    }
+}
+
+fn run() -> STDRESULT
 {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
     let long_help =
 "Examples:
 ---------
-Compress:      `retrocompressor compress -m lzss_huff -i my_compressed -o my_expanded`
-Expand:        `retrocompressor expand -m lzss_huff -i my_expanded -o my_compressed`";
-
-    let methods = ["lzw","lzhuf-port","lzss_huff","td0"];
+Compress:      `retrocompressor compress -m lzss_huff -i my_expanded -o my_compressed`
+Expand:        `retrocompressor expand -m lzss_huff -i my_compressed -o my_expanded`
+Pipeline:      `cat my_compressed | retrocompressor expand -m lzss_huff -i - -o - > my_expanded`
+Default name:  `retrocompressor expand -m td0 -i my_image.adv.td0`
+Many files:    `retrocompressor expand -m td0 -i '*.td0' -d normal`
+Directories:   `retrocompressor expand -m td0 -i images -d normal --recursive`
+Parallel:      `retrocompressor compress -m lzss_huff -i '*.txt' -d packed --jobs 4`
+Concatenate:   `retrocompressor compress -m lzss_huff -i part1 part2 -o parts.lzh --members parts.toml`
+Level:         `retrocompressor compress -m lzss_huff --level 9 -i my_expanded -o my_compressed`
+Chained:       `retrocompressor compress -m lzw+sub_block -i my_expanded -o my_compressed`
+Test:          `retrocompressor test -m td0 -i '*.td0'`
+Inspect:       `retrocompressor inspect -m lzss_huff -i my_compressed --json`
+Analyze:       `retrocompressor analyze -i my_expanded`
+Convert:       `retrocompressor convert --from td0 --to lzss_huff -i my_image.td0 -o my_image.lzh`
+TD0 comment:   `retrocompressor td0 comment -i my_image.td0 --set 'imaged from the original disk'`
+TD0 CRC:       `retrocompressor td0 fix-crc -i my_image.td0 --sectors --dry-run`
+TD0 salvage:   `retrocompressor td0 salvage -i damaged.td0 -o rescued.td0 --fill 229`
+TD0 recover:   `retrocompressor td0 recover -i cut.td0 -o whole.td0 --cylinders 40`
+Variant:       `retrocompressor expand -m lzw --code-width 13 --bit-order msb0 -i my_compressed -o my_expanded`";
 
     let mut main_cmd = Command::new("retrocompressor")
         .about("Compress and expand with retro formats")
         .after_long_help(long_help)
         .version(crate_version!());
-    main_cmd = main_cmd.subcommand(Command::new("compress")
-        .arg(arg!(-m --method <METHOD> "compression algorithm").value_parser(methods)
+    for (name,about) in [("compress","compress files"),("expand","expand files")] {
+        main_cmd = main_cmd.subcommand(codec_args(Command::new(name))
+            .arg(arg!(-m --method <METHOD> "compression algorithm, or several chained with +, such as lzw+sub_block").value_parser(parse_method)
+                .required(true))
+            .arg(arg!(-i --input <PATH> "input path, or - for stdin, or several paths or glob patterns with --output-dir")
+                .required(true).num_args(1..))
+            .arg(arg!(-o --output <PATH> "output path, or - for stdout, derived from the input if omitted")
+                .required(false))
+            .arg(arg!(-d --"output-dir" <DIR> "directory for outputs, named after the inputs")
+                .conflicts_with("output")
+                .required(false))
+            .arg(arg!(-r --recursive "process input directories recursively, mirroring them in the output directory")
+                .requires("output-dir"))
+            .arg(arg!(--ext <EXT> "with --recursive, process files with this extension, default depends on the method")
+                .requires("recursive")
+                .required(false))
+            .arg(arg!(-j --jobs <N> "with --output-dir, number of files to process at once")
+                .value_parser(value_parser!(u16).range(1..)).default_value("1")
+                .requires("output-dir"))
+            .arg(arg!(-f --force "overwrite existing outputs without asking, and convert TD0 images with a bad header CRC"))
+            .arg(arg!(-n --"no-clobber" "skip existing outputs without asking").conflicts_with("force"))
+            .arg(arg!(--"low-memory" "refuse anything that would hold a whole input or output in memory, such as stdin or chained methods"))
+            .about(about));
+    }
+    main_cmd = main_cmd.mut_subcommand("compress",|cmd| cmd
+        .mut_arg("input",|arg| arg.help("input path, or - for stdin, or several paths or glob patterns, which are concatenated with --output"))
+        .arg(arg!(--members <FILE> "with --output, write where each input starts in the concatenation to this TOML file")
+            .conflicts_with("output-dir")
+            .required(false))
+        .arg(check_signature_arg()));
+    main_cmd = main_cmd.mut_subcommand("expand",|cmd| cmd
+        .arg(arg!(--members <FILE> "split the expanded data into the files listed in this TOML file, written to --output-dir")
+            .requires("output-dir")
+            .required(false))
+        .arg(arg!(--"max-size" <BYTES> "refuse to expand a file past this size, suffixes k, M, G are allowed, 0 means no limit")
+            .value_parser(parse_size).default_value("64M"))
+        .arg(arg!(--"trim-padding" "td0: drop the bytes Teledisk compressed after the end of the image"))
+        .arg(arg!(--"pad-to" <BYTES> "td0: add zeros until the image is a multiple of this size, after --trim-padding")
+            .value_parser(value_parser!(u32)).required(false))
+        .arg(arg!(--"pad-min" <BYTES> "td0: add zeros until at least this many bytes follow the end of the image, after --trim-padding")
+            .value_parser(value_parser!(u32)).conflicts_with("pad-to").required(false))
+        .arg(check_signature_arg()));
+
+    main_cmd = main_cmd.subcommand(codec_args(Command::new("test"))
+        .arg(arg!(-m --method <METHOD> "compression algorithm, or several chained with +, such as lzw+sub_block").value_parser(parse_method)
+            .required(true))
+        .arg(arg!(-i --input <PATH> "input paths or glob patterns, or - for stdin")
+            .required(true).num_args(1..))
+        .about("decode files and check their integrity without writing anything"));
+
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    let main_cmd = main_cmd.subcommand(codec_args(Command::new("inspect"))
+        .arg(arg!(-m --method <METHOD> "compression algorithm").value_parser(STREAM_METHODS.to_vec())
             .required(true))
-        .arg(arg!(-i --input <PATH> "input path").required(true))
-        .arg(arg!(-o --output <PATH> "output path").required(true))
-        .about("compress a file"));
+        .arg(arg!(-i --input <PATH> "input path, or - for stdin").required(true))
+        .arg(arg!(--json "print each token as a line of JSON"))
+        .about("print the decoded tokens, for debugging streams that fail to expand"));
 
-        main_cmd = main_cmd.subcommand(Command::new("expand")
-        .arg(arg!(-m --method <METHOD> "compression algorithm").required(true))
-        .arg(arg!(-i --input <PATH> "input path").required(true))
-        .arg(arg!(-o --output <PATH> "output path").required(true))
-        .about("expand a file"));
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    let main_cmd = main_cmd.subcommand(Command::new("analyze")
+        .arg(arg!(-i --input <PATH> "expanded input path, or - for stdin").required(true))
+        .arg(arg!(--json "print the statistics as a JSON object"))
+        .about("report byte statistics, match distributions, and the size with each method, to help choose one"));
+
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    let main_cmd = main_cmd.subcommand(Command::new("convert")
+        .arg(arg!(--from <METHOD> "method the input is compressed with").value_parser(STREAM_METHODS.to_vec())
+            .required(true))
+        .arg(arg!(--to <METHOD> "method to compress the output with").value_parser(STREAM_METHODS.to_vec())
+            .required(true))
+        .arg(arg!(-i --input <PATH> "input path, or - for stdin").required(true))
+        .arg(arg!(-o --output <PATH> "output path, or - for stdout, derived from the input if omitted").required(false))
+        .arg(arg!(-f --force "overwrite an existing output without asking"))
+        .arg(arg!(-n --"no-clobber" "skip an existing output without asking").conflicts_with("force"))
+        .about("expand with one method and compress with another in a single pass, using the standard options"));
+
+    #[cfg(feature="td0")]
+    let main_cmd = main_cmd.subcommand(Command::new("td0")
+        .subcommand_required(true)
+        .subcommand(Command::new("comment")
+            .arg(arg!(-i --input <PATH> "TD0 image, normal or advanced, or - for stdin").required(true))
+            .arg(arg!(-o --output <PATH> "write the changed image here rather than changing the input").required(false))
+            .arg(arg!(--set <TEXT> "add or replace the comment, lines are separated by newlines").required(false))
+            .arg(arg!(--strip "remove the comment").conflicts_with("set"))
+            .about("print, add, replace, or strip the comment"))
+        .subcommand(Command::new("fix-crc")
+            .arg(arg!(-i --input <PATH> "TD0 image, normal or advanced, or - for stdin").required(true))
+            .arg(arg!(-o --output <PATH> "write the fixed image here rather than changing the input").required(false))
+            .arg(arg!(--sectors "also check the track and sector CRC"))
+            .arg(arg!(--"dry-run" "only report the mismatches").conflicts_with("output"))
+            .about("recompute the header and comment CRC, and optionally the track and sector CRC"))
+        .subcommand(Command::new("salvage")
+            .arg(arg!(-i --input <PATH> "damaged TD0 image, normal or advanced, or - for stdin").required(true))
+            .arg(arg!(-o --output <PATH> "where to write the salvaged image").required_unless_present("dry-run"))
+            .arg(arg!(--fill <BYTE> "value for the bytes of unreadable sectors")
+                .value_parser(value_parser!(u8)).default_value("0"))
+            .arg(arg!(--"dry-run" "only report the damage").conflicts_with("output"))
+            .about("recover what can be read from a damaged image, filling bad sectors and dropping incomplete tracks"))
+        .subcommand(Command::new("recover")
+            .arg(arg!(-i --input <PATH> "truncated TD0 image, normal or advanced, or - for stdin").required(true))
+            .arg(arg!(-o --output <PATH> "where to write the recovered image").required_unless_present("dry-run"))
+            .arg(arg!(--cylinders <COUNT> "add filled tracks until the image has this many cylinders")
+                .value_parser(value_parser!(u8)))
+            .arg(arg!(--fill <BYTE> "value for the bytes of added tracks")
+                .value_parser(value_parser!(u8)).default_value("0"))
+            .arg(arg!(--"dry-run" "only report what can be recovered").conflicts_with("output"))
+            .about("keep the whole tracks of an image that was cut short, optionally padding it to a full disk"))
+        .subcommand(Command::new("split")
+            .arg(arg!(-i --input <PATH> "TD0 image, normal or advanced, or - for stdin").required(true))
+            .arg(arg!(-o --output <PATH> "path of the first volume, the last character of the extension is replaced by the volume number").required(true))
+            .arg(arg!(--size <BYTES> "largest a volume can be, suffixes k, M, G are allowed")
+                .value_parser(parse_size).required(true))
+            .about("split an image into a multi-volume set"))
+        .about("work with the contents of TD0 images"));
 
     let matches = main_cmd.get_matches();
-    
-    if let Some(cmd) = matches.subcommand_matches("compress") {
-        let path_in = cmd.get_one::<String>("input").expect(RCH);
-        let path_out = cmd.get_one::<String>("output").expect(RCH);
-        let method = cmd.get_one::<String>("method").expect(RCH);
-        if !ok_to_overwrite(path_out) {
-            eprintln!("abort operation");
-            return Ok(());
+
+    #[cfg(feature="td0")]
+    if let Some(cmd) = matches.subcommand_matches("td0") {
+        if let Some(cmd) = cmd.subcommand_matches("comment") {
+            return td0_comment(cmd);
+        }
+        if let Some(cmd) = cmd.subcommand_matches("fix-crc") {
+            return td0_fix_crc(cmd);
+        }
+        if let Some(cmd) = cmd.subcommand_matches("salvage") {
+            return td0_salvage(cmd);
+        }
+        if let Some(cmd) = cmd.subcommand_matches("recover") {
+            return td0_recover(cmd);
+        }
+        if let Some(cmd) = cmd.subcommand_matches("split") {
+            return td0_split(cmd);
         }
-        let mut in_file = std::fs::File::open(path_in)?;
-        let mut out_file = std::fs::OpenOptions::new().write(true).truncate(false).create(true).open(path_out)?;
-        let (in_size,out_size) = match method.as_str() {
-            "lzw" => lzw::compress(&mut in_file,&mut out_file,&lzw::STD_OPTIONS)?,
-            "lzhuf-port" => direct_ports::lzhuf::encode(&mut in_file,&mut out_file)?,
-            "lzss_huff" => lzss_huff::compress(&mut in_file,&mut out_file,&lzss_huff::STD_OPTIONS)?,
-            "td0" => td0::compress(&mut in_file,&mut out_file)?,
-            _ => {
-                eprintln!("{} not supported",method);
-                return Err(Box::new(std::fmt::Error));
-            }
-        };
-        out_file.set_len(out_size)?;
-        eprintln!("compressed {} into {}",in_size,out_size);
     }
 
-    if let Some(cmd) = matches.subcommand_matches("expand") {
-        let path_in = cmd.get_one::<String>("input").expect(RCH);
-        let path_out = cmd.get_one::<String>("output").expect(RCH);
-        let method = cmd.get_one::<String>("method").expect(RCH);
-        if !ok_to_overwrite(path_out) {
-            eprintln!("abort operation");
-            return Ok(());
-        }
-        let mut in_file = std::fs::File::open(path_in)?;
-        let mut out_file = std::fs::OpenOptions::new().write(true).truncate(false).create(true).open(path_out)?;
-        let (in_size,out_size) = match method.as_str() {
-            "lzw" => lzw::expand(&mut in_file,&mut out_file,&lzw::STD_OPTIONS)?,
-            "lzhuf-port" => direct_ports::lzhuf::decode(&mut in_file,&mut out_file)?,
-            "lzss_huff" => lzss_huff::expand(&mut in_file,&mut out_file,&lzss_huff::STD_OPTIONS)?,
-            "td0" => td0::expand(&mut in_file,&mut out_file)?,
-            _ => {
-                eprintln!("{} not supported",method);
-                return Err(Box::new(std::fmt::Error));
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    if let Some(cmd) = matches.subcommand_matches("inspect") {
+        let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+        return inspect(&codec_from(cmd)?,path_in,cmd.get_flag("json"));
+    }
+
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    if let Some(cmd) = matches.subcommand_matches("analyze") {
+        let path_in = Path::new(cmd.get_one::<String>("input").expect(RCH));
+        return analyze(path_in,cmd.get_flag("json"));
+    }
+
+    #[cfg(any(feature="lzw",feature="lzss_huff"))]
+    if let Some(cmd) = matches.subcommand_matches("convert") {
+        return transcode(cmd);
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("test") {
+        let inputs: Vec<&String> = cmd.get_many::<String>("input").expect(RCH).collect();
+        return test_many(&codec_from(cmd)?,&inputs);
+    }
+
+    for (name,compress) in [("compress",true),("expand",false)] {
+        if let Some(cmd) = matches.subcommand_matches(name) {
+            let inputs: Vec<&String> = cmd.get_many::<String>("input").expect(RCH).collect();
+            let codec = codec_from(cmd)?;
+            let method = codec.method;
+            let max_size = match cmd.try_get_one::<u64>("max-size") {
+                Ok(Some(0)) | Ok(None) | Err(_) => u64::MAX,
+                Ok(Some(n)) => *n
+            };
+            let members = cmd.get_one::<String>("members").map(Path::new);
+            let low_memory = cmd.get_flag("low-memory");
+            if low_memory && members.is_some() {
+                return Err(low_memory_refusal("--members"));
             }
-        };
-        out_file.set_len(out_size)?;
-        eprintln!("expanded {} into {}",in_size,out_size);
+            if let (false,Some(members)) = (compress,members) {
+                if inputs.len() > 1 {
+                    return Err(fail(Cause::Usage,"--members splits a single input"));
+                }
+                let path_in = Path::new(inputs[0]);
+                let dir = Path::new(cmd.get_one::<String>("output-dir").expect(RCH));
+                let (in_size,out_size) = expand_members(&codec,path_in,members,dir,Clobber::from(cmd),max_size)?;
+                eprintln!("{} {} into {}",past_tense(compress),in_size,out_size);
+                return Ok(());
+            }
+            if let Some(dir) = cmd.get_one::<String>("output-dir") {
+                let recursive = cmd.get_flag("recursive");
+                let ext = match cmd.get_one::<String>("ext") {
+                    Some(ext) => Some(ext.trim_start_matches('.')),
+                    None if compress && method != "td0" => None,
+                    None => method_extension(method)
+                };
+                let jobs = *cmd.get_one::<u16>("jobs").expect(RCH) as usize;
+                return convert_many(&Batch { compress, codec: &codec, dir: Path::new(dir), recursive, ext, jobs, clobber: Clobber::from(cmd), max_size, low_memory },&inputs);
+            }
+            if compress && (inputs.len() > 1 || members.is_some()) {
+                let path_out = match cmd.get_one::<String>("output") {
+                    Some(path) => Path::new(path),
+                    None => return Err(fail(Cause::Usage,"concatenating inputs requires --output or --output-dir"))
+                };
+                if low_memory {
+                    return Err(low_memory_refusal("concatenating inputs"));
+                }
+                let mut failures = Vec::new();
+                let mut paths = Vec::new();
+                for pattern in &inputs {
+                    match is_stdio(Path::new(pattern)) {
+                        true => paths.push(PathBuf::from(STDIO)),
+                        false => paths.append(&mut glob_paths(pattern,&mut failures))
+                    }
+                }
+                if !failures.is_empty() {
+                    return Err(batch_failure(&failures,inputs.len()));
+                }
+                // only matters whether stdin is among the inputs
+                let path_in = paths.iter().find(|path| is_stdio(path)).unwrap_or(&paths[0]);
+                if !ok_to_overwrite(path_in,path_out,Clobber::from(cmd))? {
+                    eprintln!("abort operation");
+                    return Ok(());
+                }
+                let (in_size,out_size) = compress_concat(&codec,&paths,path_out,members)?;
+                eprintln!("{} {} files, {} into {}",past_tense(compress),paths.len(),in_size,out_size);
+                return Ok(());
+            }
+            if inputs.len() > 1 {
+                return Err(fail(Cause::Usage,"multiple inputs require --output-dir"));
+            }
+            let path_in = Path::new(inputs[0]);
+            let path_out = match cmd.get_one::<String>("output") {
+                Some(path) => PathBuf::from(path),
+                None if is_stdio(path_in) => PathBuf::from("-"),
+                None => match output_name(compress,method,path_in) {
+                    Some(name) => {
+                        let path = path_in.with_file_name(name);
+                        eprintln!("writing {}",path.display());
+                        path
+                    },
+                    None => return Err(fail(Cause::Usage,format!("no default output name for {}, use --output",method)))
+                }
+            };
+            let path_out = path_out.as_path();
+            if let (true,Some(what)) = (low_memory,unbounded_memory(compress,&codec,path_in,path_out)) {
+                return Err(low_memory_refusal(what));
+            }
+            if !ok_to_overwrite(path_in,path_out,Clobber::from(cmd))? {
+                eprintln!("abort operation");
+                return Ok(());
+            }
+            let (in_size,out_size) = convert(compress,&codec,path_in,path_out,max_size)
+                .map_err(|e| fail(Cause::of(&*e),format!("{}: {}",path_in.display(),e)))?;
+            eprintln!("{} {} into {}",past_tense(compress),in_size,out_size);
+        }
     }
 
     Ok(())   
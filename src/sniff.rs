@@ -0,0 +1,309 @@
+//! Disk Image Identification
+//!
+//! Tells the common disk image formats apart from the first few hundred bytes of a file, so a
+//! front end can route each file to the right module without trusting its extension.  Along with
+//! the format, whatever geometry the prefix gives away is returned as hints.
+//!
+//! ```rs
+//! use retrocompressor::*;
+//! let mut prefix = vec![0;sniff::PREFIX_LEN];
+//! let n = file.read(&mut prefix)?;
+//! let found = sniff::identify(&prefix[0..n],Some(file.metadata()?.len()));
+//! if let Some(method) = found.format.method() {
+//!     method.expand(&mut file,&mut out)?;
+//! }
+//! ```
+//!
+//! Only the headers are looked at, nothing is checked past them, so the result is a good guess
+//! rather than a promise that the file is sound.  Anything that is not recognized is `Format::Raw`.
+
+#[cfg(feature="td0")]
+use crate::Method;
+
+/// Bytes to read from the start of a file to give `identify` all it needs, unless there is a long comment
+pub const PREFIX_LEN: usize = 512;
+
+/// Version bytes that Teledisk wrote, from 1.0 to 2.1, also what `td0::Version` accepts.
+/// This lives here rather than in `td0` because sniffing works without the `td0` feature.
+pub(crate) const TD0_VERSIONS: std::ops::RangeInclusive<u8> = 10..=21;
+
+/// Size of a CopyQM header, whose bytes add up to 0
+const CQM_HEADER_LEN: usize = 133;
+
+/// Sizes of sector by sector images, with their (cylinders,heads,sectors,sector size)
+const RAW_SIZES: [(u64,(u16,u8,u16,u16));11] = [
+    (143360,(35,1,16,256)),
+    (163840,(40,1,8,512)),
+    (184320,(40,1,9,512)),
+    (327680,(40,2,8,512)),
+    (368640,(40,2,9,512)),
+    (737280,(80,2,9,512)),
+    (819200,(80,2,10,512)),
+    (901120,(80,2,11,512)),
+    (1228800,(80,2,15,512)),
+    (1474560,(80,2,18,512)),
+    (2949120,(80,2,36,512))
+];
+
+/// Disk image formats that can be identified
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Format {
+    /// Teledisk image without compression
+    Td0Normal,
+    /// Teledisk image with advanced compression, see `td0`
+    Td0Advanced,
+    /// ImageDisk
+    Imd,
+    /// Amiga DiskMasher
+    Dms,
+    /// Atari ST Magic Shadow Archiver
+    Msa,
+    /// Sydex CopyQM
+    CopyQm,
+    /// sector by sector image with no header, or anything not recognized
+    Raw
+}
+
+impl Format {
+    /// The method that expands this format, if this crate has one, requires the `td0` feature
+    #[cfg(feature="td0")]
+    pub fn method(&self) -> Option<Method> {
+        match self {
+            #[cfg(feature="td0")]
+            Self::Td0Advanced => Some(Method::Td0),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"{}",match self {
+            Self::Td0Normal => "TD0 normal",
+            Self::Td0Advanced => "TD0 advanced",
+            Self::Imd => "IMD",
+            Self::Dms => "DMS",
+            Self::Msa => "MSA",
+            Self::CopyQm => "CopyQM",
+            Self::Raw => "raw"
+        })
+    }
+}
+
+/// What the header says about the disk, each is `None` if it does not say
+#[derive(Clone,Copy,Debug,Default,PartialEq)]
+pub struct Geometry {
+    pub cylinders: Option<u16>,
+    pub heads: Option<u8>,
+    /// sectors per track, for formats that can vary it is the first track
+    pub sectors: Option<u16>,
+    /// bytes per sector, for formats that can vary it is the first sector
+    pub sector_size: Option<u16>,
+    /// in kbps
+    pub data_rate: Option<u16>
+}
+
+/// Format of a disk image and the geometry hints from its header
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Identified {
+    pub format: Format,
+    pub geometry: Geometry
+}
+
+fn u16_le(prefix: &[u8],i: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*prefix.get(i)?,*prefix.get(i+1)?]))
+}
+
+fn u16_be(prefix: &[u8],i: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*prefix.get(i)?,*prefix.get(i+1)?]))
+}
+
+/// Sector size from a size code, as IMD and TD0 store it
+fn size_code(code: u8) -> Option<u16> {
+    (code <= 6).then(|| 128 << code)
+}
+
+fn td0(prefix: &[u8]) -> Option<Identified> {
+    let format = match prefix.get(0..2)? {
+        b"TD" => Format::Td0Normal,
+        b"td" => Format::Td0Advanced,
+        _ => return None
+    };
+    if prefix.len() < 12 || !TD0_VERSIONS.contains(&prefix[4]) {
+        return None;
+    }
+    let mut geometry = Geometry {
+        heads: Some(prefix[9]),
+        data_rate: [250,300,500].get(prefix[5] as usize & 0x7f).copied(),
+        ..Geometry::default()
+    };
+    // with advanced compression the tracks cannot be seen without expanding
+    if format == Format::Td0Normal {
+        let track = match prefix[7] & 0x80 {
+            0 => 12,
+            _ => 22 + u16_le(prefix,14)? as usize
+        };
+        if let Some(&count) = prefix.get(track).filter(|count| **count != 0xff) {
+            geometry.sectors = Some(count as u16);
+            geometry.sector_size = prefix.get(track + 7).and_then(|code| size_code(*code));
+        }
+    }
+    Some(Identified { format, geometry })
+}
+
+fn imd(prefix: &[u8]) -> Option<Identified> {
+    if !prefix.starts_with(b"IMD ") {
+        return None;
+    }
+    let mut geometry = Geometry::default();
+    // the comment ends with 0x1a, then the first track starts with its mode
+    if let Some(end) = prefix.iter().position(|b| *b == 0x1a) {
+        if let Some(track) = prefix.get(end+1..end+6) {
+            geometry.data_rate = [500,300,250,500,300,250].get(track[0] as usize).copied();
+            geometry.sectors = Some(track[3] as u16);
+            geometry.sector_size = size_code(track[4]);
+        }
+    }
+    Some(Identified { format: Format::Imd, geometry })
+}
+
+fn dms(prefix: &[u8]) -> Option<Identified> {
+    if !prefix.starts_with(b"DMS!") {
+        return None;
+    }
+    // the header gives the range of tracks it holds, each with both sides of a cylinder
+    let geometry = Geometry {
+        cylinders: u16_be(prefix,18).map(|last| last + 1),
+        heads: Some(2),
+        sector_size: Some(512),
+        ..Geometry::default()
+    };
+    Some(Identified { format: Format::Dms, geometry })
+}
+
+fn msa(prefix: &[u8]) -> Option<Identified> {
+    if u16_be(prefix,0)? != 0x0e0f {
+        return None;
+    }
+    let (sectors,sides,first,last) = (u16_be(prefix,2)?,u16_be(prefix,4)?,u16_be(prefix,6)?,u16_be(prefix,8)?);
+    if sectors == 0 || sides > 1 || first > last {
+        return None;
+    }
+    let geometry = Geometry {
+        cylinders: Some(last + 1),
+        heads: Some(sides as u8 + 1),
+        sectors: Some(sectors),
+        sector_size: Some(512),
+        data_rate: None
+    };
+    Some(Identified { format: Format::Msa, geometry })
+}
+
+fn copyqm(prefix: &[u8]) -> Option<Identified> {
+    if !prefix.starts_with(b"CQ\x14") {
+        return None;
+    }
+    // the checksum can only be tried if the whole header is there
+    if prefix.len() >= CQM_HEADER_LEN && prefix[0..CQM_HEADER_LEN].iter().fold(0u8,|sum,b| sum.wrapping_add(*b)) != 0 {
+        return None;
+    }
+    let geometry = Geometry {
+        cylinders: prefix.get(0x5b).map(|cyls| *cyls as u16),
+        heads: u16_le(prefix,0x12).map(|heads| heads as u8),
+        sectors: u16_le(prefix,0x10),
+        sector_size: u16_le(prefix,3),
+        data_rate: prefix.get(0x59).and_then(|density| [250,500,1000].get(*density as usize).copied())
+    };
+    Some(Identified { format: Format::CopyQm, geometry })
+}
+
+/// Geometry from a DOS boot sector, or failing that from the size of the image
+fn raw(prefix: &[u8],file_size: Option<u64>) -> Identified {
+    let bpb = || -> Option<Geometry> {
+        if prefix.first() != Some(&0xeb) && prefix.first() != Some(&0xe9) {
+            return None;
+        }
+        let (sector_size,total,sectors,heads) = (u16_le(prefix,11)?,u16_le(prefix,19)?,u16_le(prefix,24)?,u16_le(prefix,26)?);
+        if !sector_size.is_power_of_two() || !(128..=4096).contains(&sector_size) || sectors == 0 || !(1..=2).contains(&heads) {
+            return None;
+        }
+        let per_cylinder = sectors * heads;
+        Some(Geometry {
+            cylinders: (total > 0 && total % per_cylinder == 0).then(|| total / per_cylinder),
+            heads: Some(heads as u8),
+            sectors: Some(sectors),
+            sector_size: Some(sector_size),
+            data_rate: None
+        })
+    };
+    let geometry = bpb().or_else(|| {
+        let (_,(cylinders,heads,sectors,sector_size)) = RAW_SIZES.iter().find(|(size,_)| Some(*size) == file_size)?;
+        Some(Geometry {
+            cylinders: Some(*cylinders),
+            heads: Some(*heads),
+            sectors: Some(*sectors),
+            sector_size: Some(*sector_size),
+            data_rate: None
+        })
+    }).unwrap_or_default();
+    Identified { format: Format::Raw, geometry }
+}
+
+/// Identify a disk image from the start of the file, `PREFIX_LEN` bytes is enough, or the whole file if it is shorter.
+/// Giving the size of the file helps with raw images that have no boot sector to go by.
+pub fn identify(prefix: &[u8],file_size: Option<u64>) -> Identified {
+    td0(prefix)
+        .or_else(|| imd(prefix))
+        .or_else(|| dms(prefix))
+        .or_else(|| msa(prefix))
+        .or_else(|| copyqm(prefix))
+        .unwrap_or_else(|| raw(prefix,file_size))
+}
+
+#[test]
+fn identification() {
+    for (path,format,sectors) in [("tests/td105.adv.td0",Format::Td0Advanced,None),("tests/td215.norm.td0",Format::Td0Normal,Some(9))] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let found = identify(&image[0..PREFIX_LEN],None);
+        assert_eq!(found.format,format);
+        assert_eq!((found.geometry.heads,found.geometry.sectors),(Some(2),sectors));
+    }
+    let found = identify(b"IMD 1.18: 01/02/2024 12:00:00\r\nsome disk\x1a\x05\x00\x00\x0a\x02",None);
+    assert_eq!(found.format,Format::Imd);
+    assert_eq!(found.geometry,Geometry { sectors: Some(10), sector_size: Some(512), data_rate: Some(250), ..Geometry::default() });
+    let found = identify(&[0x0e,0x0f,0,9,0,1,0,0,0,79],None);
+    assert_eq!(found.geometry,Geometry { cylinders: Some(80), heads: Some(2), sectors: Some(9), sector_size: Some(512), data_rate: None });
+    let mut dms = b"DMS!".to_vec();
+    dms.resize(56,0);
+    dms[19] = 79;
+    assert_eq!(identify(&dms,None).geometry.cylinders,Some(80));
+    let mut cqm = vec![0;CQM_HEADER_LEN];
+    cqm[0..3].copy_from_slice(b"CQ\x14");
+    cqm[3..5].copy_from_slice(&[0,2]);
+    cqm[0x10] = 18;
+    cqm[0x12] = 2;
+    cqm[0x59] = 1;
+    cqm[0x5b] = 80;
+    cqm[CQM_HEADER_LEN-1] = cqm.iter().fold(0u8,|sum,b| sum.wrapping_sub(*b));
+    assert_eq!(identify(&cqm,None),Identified { format: Format::CopyQm, geometry: Geometry { cylinders: Some(80), heads: Some(2), sectors: Some(18), sector_size: Some(512), data_rate: Some(500) } });
+    // a bad checksum is not CopyQM
+    cqm[0x5b] = 40;
+    assert_eq!(identify(&cqm,None).format,Format::Raw);
+    // an Apple II image has no boot sector to go by, only its size
+    let dsk = std::fs::read("tests/shkspr.dsk").expect("could not read test file");
+    assert_eq!(identify(&dsk[0..PREFIX_LEN],None),Identified { format: Format::Raw, geometry: Geometry::default() });
+    assert_eq!(identify(&dsk[0..PREFIX_LEN],Some(dsk.len() as u64)).geometry.sectors,Some(16));
+    // a 360K DOS disk
+    let mut boot = vec![0;PREFIX_LEN];
+    boot[0..3].copy_from_slice(&[0xeb,0x3c,0x90]);
+    boot[11..13].copy_from_slice(&u16::to_le_bytes(512));
+    boot[19..21].copy_from_slice(&u16::to_le_bytes(720));
+    boot[24] = 9;
+    boot[26] = 2;
+    assert_eq!(identify(&boot,None).geometry,Geometry { cylinders: Some(40), heads: Some(2), sectors: Some(9), sector_size: Some(512), data_rate: None });
+    #[cfg(feature="td0")]
+    {
+        assert!(matches!(Format::Td0Advanced.method(),Some(Method::Td0)));
+        assert!(Format::Imd.method().is_none());
+    }
+}
@@ -0,0 +1,170 @@
+//! GIF-style Sub-block Framing
+//!
+//! Some formats, notably GIF, break the compressed stream into sub-blocks.  Each sub-block
+//! is a length byte followed by up to 255 data bytes, and the sequence is terminated by a
+//! zero length byte.  This module provides `Read` and `Write` adapters that add or remove
+//! the framing, so that it can be combined with any codec.
+//!
+//! The codecs need `Seek` on both ends, so the usual pattern is to work with buffers:
+//!
+//! ```rs
+//! use retrocompressor::*;
+//! let compressed = lzw::compress_slice(raster,&lzw::STD_OPTIONS)?;
+//! let framed = sub_block::frame(&compressed);
+//! let (unframed,_) = sub_block::unframe(&framed)?;
+//! ```
+
+use std::io::{Read,Write,Cursor,ErrorKind};
+
+/// Maximum number of data bytes in a sub-block
+pub const MAX_BLOCK_LEN: usize = 255;
+
+/// Adapter that frames everything written to it into sub-blocks.
+/// Call `finish` to write the final partial block and the terminator.
+pub struct SubBlockWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    count: u64
+}
+
+/// Adapter that reads the data out of a sequence of sub-blocks.
+/// Reading stops at the terminator, whatever follows is left in the inner reader.
+pub struct SubBlockReader<R: Read> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+    count: u64
+}
+
+impl<W: Write> SubBlockWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(MAX_BLOCK_LEN),
+            count: 0
+        }
+    }
+    fn write_block(&mut self) -> Result<(),std::io::Error> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_all(&[self.buf.len() as u8])?;
+        self.inner.write_all(&self.buf)?;
+        self.count += 1 + self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+    /// Write the last block and the terminator, returns the inner writer and
+    /// the number of framed bytes that were written to it.
+    pub fn finish(mut self) -> Result<(W,u64),std::io::Error> {
+        self.write_block()?;
+        self.inner.write_all(&[0])?;
+        self.inner.flush()?;
+        Ok((self.inner,self.count + 1))
+    }
+}
+
+impl<W: Write> Write for SubBlockWriter<W> {
+    fn write(&mut self,data: &[u8]) -> Result<usize,std::io::Error> {
+        let n = usize::min(data.len(),MAX_BLOCK_LEN - self.buf.len());
+        self.buf.extend_from_slice(&data[0..n]);
+        if self.buf.len() == MAX_BLOCK_LEN {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+    /// Flushes the inner writer, but does not close the current block, since
+    /// a short block in the middle of the stream would be legal, but wasteful.
+    fn flush(&mut self) -> Result<(),std::io::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> SubBlockReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+            count: 0
+        }
+    }
+    /// Number of framed bytes consumed so far, including length bytes
+    pub fn framed_count(&self) -> u64 {
+        self.count
+    }
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for SubBlockReader<R> {
+    fn read(&mut self,buf: &mut [u8]) -> Result<usize,std::io::Error> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut len: [u8;1] = [0];
+            self.inner.read_exact(&mut len)?;
+            self.count += 1;
+            if len[0] == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+            self.remaining = len[0] as usize;
+        }
+        let n = usize::min(buf.len(),self.remaining);
+        self.inner.read_exact(&mut buf[0..n])?;
+        self.remaining -= n;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Convenience function, frames `data` into sub-blocks including the terminator
+pub fn frame(data: &[u8]) -> Vec<u8> {
+    let mut ans = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK_LEN + 2);
+    for block in data.chunks(MAX_BLOCK_LEN) {
+        ans.push(block.len() as u8);
+        ans.extend_from_slice(block);
+    }
+    ans.push(0);
+    ans
+}
+
+/// Convenience function, removes the sub-block framing from the start of `framed`.
+/// Returns the data and the number of framed bytes consumed, or an error if there is no terminator.
+pub fn unframe(framed: &[u8]) -> Result<(Vec<u8>,usize),std::io::Error> {
+    let mut reader = SubBlockReader::new(Cursor::new(framed));
+    let mut ans = Vec::new();
+    match reader.read_to_end(&mut ans) {
+        Ok(_) => Ok((ans,reader.framed_count() as usize)),
+        Err(e) if e.kind()==ErrorKind::UnexpectedEof => {
+            log::error!("sub-blocks are not terminated");
+            Err(e)
+        },
+        Err(e) => Err(e)
+    }
+}
+
+#[test]
+fn framing() {
+    let data: Vec<u8> = (0..600).map(|x| (x % 256) as u8).collect();
+    let framed = frame(&data);
+    assert_eq!(framed.len(),600 + 4);
+    assert_eq!(framed[0],255);
+    assert_eq!(framed[256],255);
+    assert_eq!(framed[512],90);
+    assert_eq!(framed[603],0);
+    let mut writer = SubBlockWriter::new(Vec::new());
+    writer.write_all(&data).expect("write failed");
+    let (streamed,count) = writer.finish().expect("finish failed");
+    assert_eq!(streamed,framed);
+    assert_eq!(count,framed.len() as u64);
+    let trailing = [framed.clone(),vec![0x3b]].concat();
+    let (unframed,count) = unframe(&trailing).expect("unframe failed");
+    assert_eq!(unframed,data);
+    assert_eq!(count,framed.len());
+    assert!(unframe(&framed[0..600]).is_err());
+    assert_eq!(frame(&[]),vec![0]);
+}
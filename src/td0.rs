@@ -3,117 +3,641 @@
 //! This module allows enabling or disabling of advanced compression in
 //! TD0 image files.  This does not do any analysis of the TD0 image, it
 //! only verifies and updates the 2-byte signature, CRC, and compresses or
-//! expands everything following the 12-byte header.
+//! expands everything following the 12-byte header.  The exceptions are the
+//! optional comment block, which can be read or changed with `get_comment`
+//! and `set_comment`, and `fix_crc`, which walks the tracks and sectors.
+//! To inspect or change an image at the sector level, `parse_image` breaks it down into an `Image`,
+//! whose tracks hold the sector headers, flags, and data blocks, and `Image::to_normal` puts it back together.
+//! A wrong image header CRC is an error unless `Options::tolerate_bad_crc` is set.
 //! 
 //! Because TD0 does not store the size of the expanded image, there can be
 //! an extra byte at the end of the file after expanding.  It appears Teledisk
 //! would usually (maybe always) pad the expanded data by several bytes.
 //! Some decoders count on this padding to correctly decode the last symbol.
 //! The aforementioned issue does not apply to v1.x (LZW) compression.
+//!
+//! The padding is part of the compressed data, after the track record that ends the image.
+//! Its content cannot be worked out from the image, so it is kept when expanding, which means
+//! compressing the result reproduces the original bit for bit.  To get the image as Teledisk
+//! would save it without compression, set `Options::trim_padding`.  For a program that expects
+//! padding of its own, `Options::padding` adds zeros, either to a boundary or to a minimum count.
+//! 
+//! Expanding a truncated image is an error (`Error::TruncatedInput`), since part of the disk is lost.
+//! For v2.x a cut can look like the padded end of the stream, see `lzss_huff::Options::truncation`.
+//! Use `verify` to check an image without the error, or `recover` to keep the whole tracks and
+//! optionally pad the image out to a full disk.
 
-use std::io::{Cursor,Read,Write,Seek};
-use crate::DYNERR;
+use std::io::{Cursor,Read,Write,Seek,SeekFrom};
+use std::path::{Path,PathBuf};
+use crate::tools::workers::on_workers;
+use crate::{DYNERR,ReadSeek,WriteSeek};
+use crate::diagnostics::{self,Warning};
+use crate::sniff::TD0_VERSIONS;
 use crate::lzss_huff;
 use crate::lzw;
+use crate::tools::range_writer::RangeWriter;
+use crate::tools::chunk_writer::ChunkWriter;
+use crate::tools::iter_reader::IterReader;
+
+/// size of the blocks that are expanded and cached by `ExpandedImage`
+const CACHE_BLOCK_SIZE: u64 = 0x4000;
+/// number of blocks retained by `ExpandedImage`
+const CACHE_BLOCKS: usize = 8;
 
 /// Calculate the checksum for the TD0 data in `buf`.
-/// This is only used for the image header, the several other CRC in the TD0 require
-/// no explicit handling at the level of this module.
+/// The image header and comment use the full 16 bits, tracks and sectors keep only the low byte.
+/// This is `checksum::TELEDISK` continuing from `crc_seed`.
 pub fn crc16(crc_seed: u16, buf: &[u8]) -> u16
 {
-    let mut crc: u16 = crc_seed;
-    for i in 0..buf.len() {
-        crc ^= (buf[i] as u16) << 8;
-        for _bit in 0..8 {
-            crc = (crc << 1) ^ match crc & 0x8000 { 0 => 0, _ => 0xa097 };
-        }
-    }
-    crc
-}
-
-pub const TD_V1_OPTIONS: lzw::Options = lzw::Options {
-    header_bits: 16,
-    header_divisor: 4,
-    in_offset: 12,
-    out_offset: 12,
-    chunk_size: 4096,
-    min_symbol: 0,
-    max_symbol: 255,
-    clear_code: None,
-    stop_code: None,
-    min_code_width: 12,
-    max_code_width: 12,
-    ord: crate::BitOrder::Lsb0,
-    max_file_size: 3_000_000
-};
+    crate::checksum::TELEDISK.update(crc_seed,buf)
+}
+
+pub const TD_V1_OPTIONS: lzw::Options = lzw::Options::teledisk_v1();
+
+pub const TD_V2_OPTIONS: lzss_huff::Options = lzss_huff::Options::teledisk_v2();
+
+/// Options controlling conversion of the image, the codec options are fixed by the version
+#[derive(Clone)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+#[cfg_attr(feature="serde",serde(deny_unknown_fields))]
+pub struct Options {
+    /// log a wrong image header CRC and carry on rather than returning `Error::BadChecksum`,
+    /// the converted image gets the correct CRC either way
+    pub tolerate_bad_crc: bool,
+    /// when expanding, drop the padding after the end of the image, the image is held in memory to find the end
+    pub trim_padding: bool,
+    /// put this in the check signature byte of the converted image, `None` keeps the one in the image, see `Volume`
+    pub check_signature: Option<u8>,
+    /// when expanding, add zeros to the end of the image, after `trim_padding` if both are set
+    pub padding: Padding
+}
 
-pub const TD_V2_OPTIONS: lzss_huff::Options = lzss_huff::Options {
-    header: false,
-    in_offset: 12,
-    out_offset: 12,
-    window_size: 4096,
-    threshold: 2,
-    lookahead: 60,
-    precursor: b' ',
-    max_file_size: 3_000_000
+pub const STD_OPTIONS: Options = Options {
+    tolerate_bad_crc: false,
+    trim_padding: false,
+    check_signature: None,
+    padding: Padding::None
 };
 
-/// Convert a TD0 image from advanced compression to normal.
-/// For Teledisk 2.x, the heavy lifting is done by the `lzss_huff` module.
-/// For Teledisk 1.x, the heavy lifting is done by the `lzw` module.
-pub fn expand<R,W>(compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
-where R: Read + Seek, W: Write + Seek {
+/// Zeros added after an expanded image, for programs that count on Teledisk's padding being there.
+/// Along with `Options::trim_padding`, the length of the image no longer depends on the compressed data.
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum Padding {
+    /// add nothing
+    None,
+    /// add zeros until the length of the image is a multiple of this many bytes, 0 adds nothing
+    Boundary(u32),
+    /// add zeros until at least this many bytes follow the track record that ends the image,
+    /// as with the few bytes Teledisk 2.x leaves there
+    AtLeast(u32)
+}
+
+/// Teledisk version that made an image, which decides how advanced compression works.
+/// Dunfield's notes suggest looking at nibble values, but we find it is the decimal digits that count,
+/// e.g., the byte 21 is version 2.1.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Version(u8);
+
+impl Version {
+    /// Check the version byte from the image header, returns `Error::UnsupportedVersion` if Teledisk never wrote it
+    pub fn new(byte: u8) -> Result<Self,crate::Error> {
+        match TD0_VERSIONS.contains(&byte) {
+            true => Ok(Self(byte)),
+            false => Err(crate::Error::UnsupportedVersion(byte))
+        }
+    }
+    /// The version byte as it appears in the image header
+    pub fn byte(&self) -> u8 {
+        self.0
+    }
+    pub fn major(&self) -> u8 {
+        self.0 / 10
+    }
+    pub fn minor(&self) -> u8 {
+        self.0 % 10
+    }
+    /// Version 1.x compresses with LZW, version 2.x with LZSS and adaptive Huffman coding
+    pub fn is_lzw(&self) -> bool {
+        self.major() < 2
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"{}.{}",self.major(),self.minor())
+    }
+}
+
+/// Where an image belongs in a set of volumes, as recorded in header bytes 2 and 3.
+/// An image that is not part of a set is volume 0, with whatever check signature Teledisk picked.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Volume {
+    /// the number of this volume, starting from 0
+    pub sequence: u8,
+    /// the same for every volume of a set, so they can be checked to belong together
+    pub check_signature: u8
+}
+
+/// Image header with its fields broken out, see `read_header` and `Image::header`
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Td0Header {
+    /// signature is `td` for advanced compression, `TD` for normal
+    pub advanced: bool,
+    /// number of this volume, see `Volume`
+    pub sequence: u8,
+    /// same for every volume of a set, see `Volume`
+    pub check_signature: u8,
+    pub version: Version,
+    /// low bits are 0 for 250 kbps, 1 for 300, 2 for 500, the high bit means FM
+    pub data_rate: u8,
+    /// drive type code, e.g., 1 for a 360K drive
+    pub drive_type: u8,
+    /// stepping code, without the comment flag
+    pub stepping: u8,
+    /// the high bit of the stepping byte, a comment block follows the header
+    pub has_comment: bool,
+    /// nonzero if only sectors that DOS had allocated were kept
+    pub dos_allocation: u8,
+    pub sides: u8,
+    /// the CRC as stored, see `crc_ok`, it is computed afresh by `to_bytes`
+    pub crc: u16
+}
+
+impl Td0Header {
+    /// Parse the first 12 bytes of `bytes`.  A missing or wrong signature is `Error::FileFormatMismatch`,
+    /// and a version Teledisk never wrote is `Error::UnsupportedVersion`.  The CRC is kept as it is.
+    pub fn parse(bytes: &[u8]) -> Result<Self,crate::Error> {
+        let bytes = bytes.get(0..12).ok_or(crate::Error::FileFormatMismatch)?;
+        let advanced = match &bytes[0..2] {
+            b"td" => true,
+            b"TD" => false,
+            _ => return Err(crate::Error::FileFormatMismatch)
+        };
+        Ok(Self {
+            advanced,
+            sequence: bytes[2],
+            check_signature: bytes[3],
+            version: Version::new(bytes[4])?,
+            data_rate: bytes[5],
+            drive_type: bytes[6],
+            stepping: bytes[7] & 0x7f,
+            has_comment: bytes[7] & 0x80 > 0,
+            dos_allocation: bytes[8],
+            sides: bytes[9],
+            crc: u16::from_le_bytes([bytes[10],bytes[11]])
+        })
+    }
+    /// The 12 header bytes, with the CRC computed from the other fields rather than taken from `crc`
+    pub fn to_bytes(&self) -> [u8;12] {
+        let mut ans = [0;12];
+        ans[0..2].copy_from_slice(match self.advanced {
+            true => b"td",
+            false => b"TD"
+        });
+        ans[2] = self.sequence;
+        ans[3] = self.check_signature;
+        ans[4] = self.version.byte();
+        ans[5] = self.data_rate;
+        ans[6] = self.drive_type;
+        ans[7] = (self.stepping & 0x7f) | if self.has_comment { 0x80 } else { 0 };
+        ans[8] = self.dos_allocation;
+        ans[9] = self.sides;
+        let crc = u16::to_le_bytes(crc16(0,&ans[0..10]));
+        ans[10..12].copy_from_slice(&crc);
+        ans
+    }
+    /// The CRC the header should have
+    pub fn computed_crc(&self) -> u16 {
+        let bytes = self.to_bytes();
+        u16::from_le_bytes([bytes[10],bytes[11]])
+    }
+    /// Whether the stored CRC is the one the header should have
+    pub fn crc_ok(&self) -> bool {
+        self.crc == self.computed_crc()
+    }
+    pub fn volume(&self) -> Volume {
+        Volume { sequence: self.sequence, check_signature: self.check_signature }
+    }
+}
+
+/// Read the 12 header bytes of a normal or advanced image, the position of `image` is restored afterward
+fn read_header_bytes<R: Read + Seek>(image: &mut R) -> Result<[u8;12],DYNERR> {
+    let save = image.stream_position()?;
+    image.seek(SeekFrom::Start(0))?;
+    let mut td_header: [u8;12] = [0;12];
+    let res = image.read_exact(&mut td_header);
+    image.seek(SeekFrom::Start(save))?;
+    match res {
+        Err(e) if e.kind()==std::io::ErrorKind::UnexpectedEof => return Err(Box::new(crate::Error::FileFormatMismatch)),
+        res => res?
+    }
+    if &td_header[0..2] != b"td" && &td_header[0..2] != b"TD" {
+        return Err(Box::new(crate::Error::FileFormatMismatch))
+    }
+    Ok(td_header)
+}
+
+/// Read the header of a normal or advanced image, the position of `image` is restored afterward.
+/// The CRC is not checked, so this works on a damaged header, see `Td0Header::crc_ok`.
+pub fn read_header<R: Read + Seek>(image: &mut R) -> Result<Td0Header,DYNERR> {
+    Ok(Td0Header::parse(&read_header_bytes(image)?)?)
+}
+
+/// Read the volume bytes from the header of a normal or advanced image, the position of `image` is restored afterward.
+/// The CRC is not checked, so this works on a damaged header.
+pub fn read_volume<R: Read + Seek>(image: &mut R) -> Result<Volume,DYNERR> {
+    let td_header = read_header_bytes(image)?;
+    Ok(Volume { sequence: td_header[2], check_signature: td_header[3] })
+}
+
+/// Read the version from the header of a normal or advanced image, the position of `image` is restored afterward.
+/// The CRC is not checked, so this works on a damaged header.
+pub fn read_version<R: Read + Seek>(image: &mut R) -> Result<Version,DYNERR> {
+    let td_header = read_header_bytes(image)?;
+    Ok(Version::new(td_header[4])?)
+}
+
+/// Read and verify the 12 byte image header with signature `from`, return it with signature `to` and updated CRC,
+/// along with the version.
+fn convert_header<R: Read + ?Sized>(img_in: &mut R,from: &str,to: &str,opt: &Options) -> Result<([u8;12],Version),DYNERR> {
     let mut td_header: [u8;12] = [0;12];
-    compressed_in.read_exact(&mut td_header)?;
-    if &td_header[0..2] != "td".as_bytes() {
+    crate::read_header(img_in,&mut td_header)?;
+    if &td_header[0..2] != from.as_bytes() {
         return Err(Box::new(crate::Error::FileFormatMismatch))
     }
     let crc = u16::to_le_bytes(crc16(0,&td_header[0..10]));
-    if crc!=td_header[10..12] {
+    if crc!=td_header[10..12] && opt.tolerate_bad_crc {
+        diagnostics::warn(Warning::HeaderCrc { stored: u16::from_le_bytes([td_header[10],td_header[11]]), computed: u16::from_le_bytes(crc) });
+    } else if crc!=td_header[10..12] {
         return Err(Box::new(crate::Error::BadChecksum))
     }
-    td_header[0..2].copy_from_slice("TD".as_bytes());
+    let version = Version::new(td_header[4]).inspect_err(|_| log::error!("version byte is {}",td_header[4]))?;
+    // the low bits of the data rate are 250, 300, or 500 kbps, the high bit means FM
+    if td_header[5] & 0x7f > 2 {
+        diagnostics::warn(Warning::HeaderField { field: "data rate", value: td_header[5] });
+    }
+    if !(1..=2).contains(&td_header[9]) {
+        diagnostics::warn(Warning::HeaderField { field: "sides", value: td_header[9] });
+    }
+    td_header[0..2].copy_from_slice(to.as_bytes());
+    if let Some(check_signature) = opt.check_signature {
+        td_header[3] = check_signature;
+    }
     let crc = u16::to_le_bytes(crc16(0,&td_header[0..10]));
     td_header[10..12].copy_from_slice(&crc);
+    Ok((td_header,version))
+}
+
+/// Convert a TD0 image from advanced compression to normal.
+/// For Teledisk 2.x, the heavy lifting is done by the `lzss_huff` module.
+/// For Teledisk 1.x, the heavy lifting is done by the `lzw` module.
+pub fn expand<R,W>(compressed_in: &mut R, expanded_out: &mut W) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    expand_dyn(compressed_in,expanded_out)
+}
+
+/// Same as `expand` with the streams as trait objects
+pub fn expand_dyn(compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+    expand_core(compressed_in,expanded_out,&STD_OPTIONS)
+}
+
+/// Same as `expand` with options other than `STD_OPTIONS`
+pub fn expand_with_options<R,W>(compressed_in: &mut R, expanded_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    expand_core(compressed_in,expanded_out,opt)
+}
+
+fn expand_core(compressed_in: &mut dyn ReadSeek, expanded_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    if opt.trim_padding || opt.padding != Padding::None {
+        let mut normal = Cursor::new(Vec::new());
+        let (in_size,_) = expand_core(compressed_in,&mut normal,&Options { trim_padding: false, padding: Padding::None, ..opt.clone() })?;
+        let mut normal = normal.into_inner();
+        let end = image_end(&normal)?;
+        if opt.trim_padding {
+            log::debug!("dropping {} bytes of padding",normal.len() - end);
+            // Teledisk 1.x has no need to pad
+            if normal.len() > end && Version::new(normal[4])?.is_lzw() {
+                diagnostics::warn(Warning::Padding { bytes: (normal.len() - end) as u64 });
+            }
+            normal.truncate(end);
+        }
+        let len = match opt.padding {
+            Padding::None | Padding::Boundary(0) => normal.len(),
+            Padding::Boundary(n) => normal.len().next_multiple_of(n as usize),
+            Padding::AtLeast(n) => usize::max(normal.len(),end + n as usize)
+        };
+        log::debug!("adding {} bytes of padding",len - normal.len());
+        normal.resize(len,0);
+        expanded_out.write_all(&normal)?;
+        return Ok((in_size,len as u64));
+    }
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("td0_expand",version=tracing::field::Empty).entered();
+    let (td_header,version) = convert_header(compressed_in,"td","TD",opt)?;
+    #[cfg(feature="tracing")]
+    _span.record("version",td_header[4]);
     expanded_out.write_all(&td_header)?;
-    // Dunfield's notes suggest looking at nibble values, but we find it is the decimal digits that count
-    if td_header[4] < 20 {
-        let (in_size,out_size) = lzw::expand(compressed_in,expanded_out,&TD_V1_OPTIONS)?;
+    if version.is_lzw() {
+        let (in_size,out_size) = lzw::expand_dyn(compressed_in,expanded_out,&TD_V1_OPTIONS)?;
         Ok((in_size+td_header.len() as u64,out_size+td_header.len() as u64))
     } else {
-        let (in_size,out_size) = lzss_huff::expand(compressed_in,expanded_out,&TD_V2_OPTIONS)?;
+        let (in_size,out_size) = lzss_huff::expand_dyn(compressed_in,expanded_out,&TD_V2_OPTIONS)?;
         Ok((in_size+td_header.len() as u64,out_size+td_header.len() as u64))
     }
 }
 
+/// Convert a TD0 image from advanced compression to normal, handing the normal image to `callback`
+/// in pieces of at most `chunk_size` bytes.  Returns (in_size,out_size).  Panics if `chunk_size` is 0.
+pub fn expand_chunked<R,F>(compressed_in: &mut R, chunk_size: usize, callback: F) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, F: FnMut(&[u8]) {
+    expand_chunked_with_options(compressed_in,chunk_size,&STD_OPTIONS,callback)
+}
+
+/// Same as `expand_chunked` with options other than `STD_OPTIONS`
+pub fn expand_chunked_with_options<R,F>(compressed_in: &mut R, chunk_size: usize, opt: &Options, mut callback: F) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, F: FnMut(&[u8]) {
+    let mut writer = ChunkWriter::new(0,chunk_size,&mut callback);
+    let ans = expand_core(compressed_in,&mut writer,opt)?;
+    writer.flush()?;
+    Ok(ans)
+}
+
+/// Expand an advanced TD0 image and discard the output, to check its integrity.
+/// The image header CRC is verified, `Error::BadChecksum` is returned if it fails.
+/// Returns (in_size,out_size,complete), where `complete` is false if the data ran out in the middle
+/// of an LZW chunk.  Teledisk 2.x does not record the expanded size, so then `complete` is always true.
+pub fn verify<R: Read + Seek>(compressed_in: &mut R) -> Result<(u64,u64,bool),DYNERR> {
+    let (td_header,version) = convert_header(compressed_in,"td","TD",&STD_OPTIONS)?;
+    let (in_size,out_size,complete) = match version.is_lzw() {
+        true => lzw::verify(compressed_in,&TD_V1_OPTIONS)?,
+        false => lzss_huff::verify(compressed_in,&TD_V2_OPTIONS)?
+    };
+    Ok((in_size+td_header.len() as u64,out_size+td_header.len() as u64,complete))
+}
+
 /// Convert a TD0 image from normal to advanced compression.
 /// For Teledisk 2.x, the heavy lifting is done by the `lzss_huff` module.
 /// For Teledisk 1.x, the heavy lifting is done by the `lzw` module.
 pub fn compress<R,W>(expanded_in: &mut R, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
 where R: Read + Seek, W: Write + Seek {
-    let mut td_header: [u8;12] = [0;12];
-    expanded_in.read_exact(&mut td_header)?;
-    if &td_header[0..2] != "TD".as_bytes() {
-        return Err(Box::new(crate::Error::FileFormatMismatch))
-    }
-    let crc = u16::to_le_bytes(crc16(0,&td_header[0..10]));
-    if crc!=td_header[10..12] {
-        return Err(Box::new(crate::Error::BadChecksum))
-    }
-    td_header[0..2].copy_from_slice("td".as_bytes());
-    let crc = u16::to_le_bytes(crc16(0,&td_header[0..10]));
-    td_header[10..12].copy_from_slice(&crc);
+    compress_dyn(expanded_in,compressed_out)
+}
+
+/// Same as `compress` with the streams as trait objects
+pub fn compress_dyn(expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek) -> Result<(u64,u64),DYNERR> {
+    compress_core(expanded_in,compressed_out,&STD_OPTIONS)
+}
+
+/// Same as `compress` with options other than `STD_OPTIONS`
+pub fn compress_with_options<R,W>(expanded_in: &mut R, compressed_out: &mut W, opt: &Options) -> Result<(u64,u64),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    compress_core(expanded_in,compressed_out,opt)
+}
+
+fn compress_core(expanded_in: &mut dyn ReadSeek, compressed_out: &mut dyn WriteSeek, opt: &Options) -> Result<(u64,u64),DYNERR> {
+    #[cfg(feature="tracing")]
+    let _span = tracing::debug_span!("td0_compress",version=tracing::field::Empty).entered();
+    let (td_header,version) = convert_header(expanded_in,"TD","td",opt)?;
+    #[cfg(feature="tracing")]
+    _span.record("version",td_header[4]);
     compressed_out.write_all(&td_header)?;
-    // Dunfield's notes suggest looking at nibble values, but we find it is the decimal digits that count
-    if td_header[4] < 20 {
-        let (in_size,out_size) = lzw::compress(expanded_in,compressed_out,&TD_V1_OPTIONS)?;
+    if version.is_lzw() {
+        let (in_size,out_size) = lzw::compress_dyn(expanded_in,compressed_out,&TD_V1_OPTIONS)?;
         Ok((in_size+td_header.len() as u64,out_size+td_header.len() as u64))
     } else {
-        let (in_size,out_size) = lzss_huff::compress(expanded_in,compressed_out,&TD_V2_OPTIONS)?;
+        let (in_size,out_size) = lzss_huff::compress_dyn(expanded_in,compressed_out,&TD_V2_OPTIONS)?;
         Ok((in_size+td_header.len() as u64,out_size+td_header.len() as u64))
     }
 }
 
+/// Checkpoints for the version that made the image, see `compress_indexed`
+#[derive(Clone,PartialEq,Debug)]
+pub enum Index {
+    V1(Vec<lzw::Checkpoint>),
+    V2(Vec<lzss_huff::Checkpoint>)
+}
+
+/// Same as `compress`, with the same output, except that a checkpoint is recorded whenever `interval` bytes
+/// of the image after the header have been compressed since the last one.  Passing the index to `expand_lazy_indexed`
+/// lets reads start near any position.  Returns (in_size,out_size,index).
+pub fn compress_indexed<R,W>(expanded_in: &mut R, compressed_out: &mut W, interval: u64) -> Result<(u64,u64,Index),DYNERR>
+where R: Read + Seek, W: Write + Seek {
+    let (td_header,version) = convert_header(expanded_in,"TD","td",&STD_OPTIONS)?;
+    compressed_out.write_all(&td_header)?;
+    let hdr_len = td_header.len() as u64;
+    if version.is_lzw() {
+        let (in_size,out_size,index) = lzw::compress_indexed(expanded_in,compressed_out,&TD_V1_OPTIONS,interval)?;
+        Ok((in_size+hdr_len,out_size+hdr_len,Index::V1(index)))
+    } else {
+        let (in_size,out_size,index) = lzss_huff::compress_indexed(expanded_in,compressed_out,&TD_V2_OPTIONS,interval)?;
+        Ok((in_size+hdr_len,out_size+hdr_len,Index::V2(index)))
+    }
+}
+
+/// Convert a normal TD0 image from an iterator of bytes, items can be `u8` or `std::io::Result<u8>`.
+/// The data is consumed as it is compressed, there is no need to collect it first.  Returns (in_size,out_size).
+pub fn compress_iter<I,W>(expanded_in: I, compressed_out: &mut W) -> Result<(u64,u64),DYNERR>
+where I: IntoIterator, I::Item: crate::ByteItem, W: Write + Seek {
+    compress_dyn(&mut IterReader::new(expanded_in),compressed_out)
+}
+
+/// Convert a TD0 image from normal to advanced compression, mapping the input file into memory.
+/// The file must not be modified or truncated, by this process or any other, while this is running,
+/// the result of that is undefined behavior rather than an error, e.g. truncation can end the process with `SIGBUS`.
+#[cfg(feature="mmap")]
+pub fn compress_mapped<W: Write + Seek>(expanded_in: &std::fs::File, compressed_out: &mut W) -> Result<(u64,u64),DYNERR> {
+    // SAFETY: the map is only read, and dropped before returning.  It stays valid only if nothing modifies
+    // or truncates the file while it is mapped, which the caller is required to ensure, see above.
+    let map = unsafe { memmap2::Mmap::map(expanded_in)? };
+    compress(&mut Cursor::new(&map[..]),compressed_out)
+}
+
+/// Which way `convert_batch` converts the images
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Direction {
+    /// normal to advanced compression, as `compress`
+    Compress,
+    /// advanced compression to normal, as `expand`
+    Expand
+}
+
+type BatchResult = Result<(u64,u64),std::io::Error>;
+
+fn convert_file(path_in: &Path,path_out: &Path,direction: Direction) -> Result<(u64,u64),DYNERR> {
+    let mut in_file = std::fs::File::open(path_in)?;
+    let mut out_file = std::fs::File::create(path_out)?;
+    match direction {
+        Direction::Compress => compress(&mut in_file,&mut out_file),
+        Direction::Expand => expand(&mut in_file,&mut out_file)
+    }
+}
+
+/// Convert each (input path,output path) pair using up to `workers` threads, output files are created or truncated.
+/// Each image is handled independently, so one failure does not stop the others.
+/// Returns (in_size,out_size) or error for each pair, in the same order as `jobs`.
+pub fn convert_batch<P: AsRef<Path> + Sync>(jobs: &[(P,P)], direction: Direction, workers: usize) -> Vec<BatchResult> {
+    on_workers(jobs,workers,|(path_in,path_out)| {
+        log::debug!("convert {}",path_in.as_ref().display());
+        convert_file(path_in.as_ref(),path_out.as_ref(),direction).map_err(crate::to_io_error)
+    })
+}
+
+/// View of a TD0 image with advanced compression as the normal image, implementing `Read` and `Seek`.
+/// The image is expanded lazily in blocks, and the most recently used blocks are cached.
+/// Reading in order continues the expansion from where the previous block ended, going back to a
+/// block that is no longer cached starts over from the beginning, or with Teledisk 1.x from the
+/// nearest chunk, or from the nearest checkpoint if opened with `expand_lazy_indexed`.
+/// Random access is slower than `expand`, the benefit is that there is no temporary file.
+pub struct ExpandedImage<R: Read + Seek> {
+    compressed: R,
+    header: [u8;12],
+    pos: u64,
+    len: Option<u64>,
+    /// (block index, expanded data), most recently used at the end
+    cache: Vec<(u64,Vec<u8>)>,
+    /// kept between blocks so the next block can continue from the last one
+    expander: BlockExpander
+}
+
+/// Decoder for the version that made the image
+enum BlockExpander {
+    V1(lzw::Expander),
+    V2(lzss_huff::Expander)
+}
+
+impl <R: Read + Seek> ExpandedImage<R> {
+    fn is_v1(&self) -> bool {
+        self.version().is_lzw()
+    }
+    /// Teledisk version that made the image
+    pub fn version(&self) -> Version {
+        // checked when the image was opened
+        Version(self.header[4])
+    }
+    /// get the expanded block, which is shorter than the block size only at the end of the image
+    fn block(&mut self,idx: u64) -> Result<&[u8],DYNERR> {
+        match self.cache.iter().position(|(i,_)| *i==idx) {
+            Some(i) => {
+                let entry = self.cache.remove(i);
+                self.cache.push(entry);
+            },
+            None => {
+                log::debug!("expand block {}",idx);
+                let mut data = Cursor::new(Vec::new());
+                match &mut self.expander {
+                    BlockExpander::V1(expander) => expander.expand_range(&mut self.compressed,&mut data,idx*CACHE_BLOCK_SIZE,CACHE_BLOCK_SIZE)?,
+                    BlockExpander::V2(expander) => expander.expand_range(&mut self.compressed,&mut data,idx*CACHE_BLOCK_SIZE,CACHE_BLOCK_SIZE)?
+                };
+                if self.cache.len() >= CACHE_BLOCKS {
+                    self.cache.remove(0);
+                }
+                self.cache.push((idx,data.into_inner()));
+            }
+        }
+        Ok(&self.cache[self.cache.len()-1].1)
+    }
+    /// length of the normal image, requires expanding everything the first time
+    pub fn len(&mut self) -> Result<u64,DYNERR> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+        let mut sink = RangeWriter::new(0,0);
+        let (_,out_size) = match self.is_v1() {
+            true => {
+                let mut opt = TD_V1_OPTIONS;
+                opt.out_offset = 0;
+                lzw::expand(&mut self.compressed,&mut sink,&opt)?
+            },
+            false => {
+                let mut opt = TD_V2_OPTIONS;
+                opt.out_offset = 0;
+                lzss_huff::expand(&mut self.compressed,&mut sink,&opt)?
+            }
+        };
+        self.len = Some(out_size + self.header.len() as u64);
+        Ok(out_size + self.header.len() as u64)
+    }
+    pub fn is_empty(&mut self) -> Result<bool,DYNERR> {
+        Ok(self.len()? == 0)
+    }
+    pub fn into_inner(self) -> R {
+        self.compressed
+    }
+}
+
+impl <R: Read + Seek> Read for ExpandedImage<R> {
+    fn read(&mut self,buf: &mut [u8]) -> Result<usize,std::io::Error> {
+        let hdr_len = self.header.len() as u64;
+        if self.pos < hdr_len {
+            let n = usize::min(buf.len(),(hdr_len - self.pos) as usize);
+            buf[0..n].copy_from_slice(&self.header[self.pos as usize..self.pos as usize + n]);
+            self.pos += n as u64;
+            return Ok(n);
+        }
+        let offset = self.pos - hdr_len;
+        let beg = (offset % CACHE_BLOCK_SIZE) as usize;
+        let block = self.block(offset / CACHE_BLOCK_SIZE).map_err(crate::to_io_error)?;
+        if beg >= block.len() {
+            return Ok(0);
+        }
+        let n = usize::min(buf.len(),block.len() - beg);
+        buf[0..n].copy_from_slice(&block[beg..beg+n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl <R: Read + Seek> Seek for ExpandedImage<R> {
+    fn seek(&mut self,pos: SeekFrom) -> Result<u64,std::io::Error> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::Current(delta) => self.pos as i64 + delta,
+            SeekFrom::End(delta) => self.len().map_err(crate::to_io_error)? as i64 + delta
+        };
+        if new_pos < 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,"seek before start"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+}
+
+/// Same as `expand_lazy`, with the index that `compress_indexed` returned for the image, so that
+/// a block that is not cached is expanded starting from the nearest checkpoint.
+/// Returns `Error::FileFormatMismatch` if the index is for the other version.
+pub fn expand_lazy_indexed<R: Read + Seek>(compressed_in: R,index: Index) -> Result<ExpandedImage<R>,DYNERR> {
+    let mut img = expand_lazy(compressed_in)?;
+    match (&mut img.expander,index) {
+        (BlockExpander::V1(expander),Index::V1(index)) => expander.set_index(index),
+        (BlockExpander::V2(expander),Index::V2(index)) => expander.set_index(index),
+        _ => {
+            log::error!("index does not match image version {}",img.version());
+            return Err(Box::new(crate::Error::FileFormatMismatch));
+        }
+    }
+    Ok(img)
+}
+
+/// Open a TD0 image with advanced compression as an `ExpandedImage`, which can be read and
+/// seeked as if it were the normal image.  Only the header is read immediately.
+pub fn expand_lazy<R: Read + Seek>(mut compressed_in: R) -> Result<ExpandedImage<R>,DYNERR> {
+    compressed_in.seek(SeekFrom::Start(0))?;
+    let (header,_) = convert_header(&mut compressed_in,"td","TD",&STD_OPTIONS)?;
+    let expander = match Version(header[4]).is_lzw() {
+        true => BlockExpander::V1(lzw::Expander::new(&lzw::Options { out_offset: 0, ..TD_V1_OPTIONS })),
+        false => BlockExpander::V2(lzss_huff::Expander::new(&lzss_huff::Options { out_offset: 0, ..TD_V2_OPTIONS }))
+    };
+    Ok(ExpandedImage {
+        compressed: compressed_in,
+        header,
+        pos: 0,
+        len: None,
+        cache: Vec::new(),
+        expander
+    })
+}
+
 /// Convenience function, calls `compress` with a slice returning a Vec
 pub fn compress_slice(slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
     let mut src = Cursor::new(slice);
@@ -130,14 +654,1503 @@ pub fn expand_slice(slice: &[u8]) -> Result<Vec<u8>,DYNERR> {
     Ok(ans.into_inner())
 }
 
+/// Expand a TD0 image into a buffer the caller provides, so nothing is allocated for the output.
+/// Returns the size of the normal image, or `Error::FileTooLarge` if it does not fit.
+pub fn expand_into(slice: &[u8],buf: &mut [u8]) -> Result<usize,DYNERR> {
+    expand_reader_into(&mut Cursor::new(slice),buf)
+}
+
+/// Same as `expand_into`, reading the advanced image from a stream
+pub fn expand_reader_into<R: Read + Seek>(compressed_in: &mut R,buf: &mut [u8]) -> Result<usize,DYNERR> {
+    let mut ans = Cursor::new(buf);
+    let (_,out_size) = expand(compressed_in,&mut ans).map_err(crate::buffer_full)?;
+    Ok(out_size as usize)
+}
+
+/// Upper bound on the size of the advanced image produced by compressing a normal image of `input_len` bytes.
+pub fn compress_bound(input_len: usize) -> usize {
+    let data_len = input_len.saturating_sub(12);
+    usize::max(lzw::compress_bound(data_len,&TD_V1_OPTIONS),lzss_huff::compress_bound(data_len,&TD_V2_OPTIONS)).saturating_add(12)
+}
+
+/// Date and time in a comment block, which is stored as 6 bytes:
+/// years since 1900, month starting from 0, day, hour, minute, second
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct Timestamp {
+    pub year: u16,
+    /// month starting from 1
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl Timestamp {
+    /// Decode the packed timestamp from a comment block
+    pub fn from_bytes(bytes: [u8;6]) -> Self {
+        Self {
+            year: 1900 + bytes[0] as u16,
+            month: bytes[1].wrapping_add(1),
+            day: bytes[2],
+            hour: bytes[3],
+            minute: bytes[4],
+            second: bytes[5]
+        }
+    }
+    /// Pack the timestamp for a comment block, `None` if the year is not from 1900 to 2155
+    pub fn to_bytes(&self) -> Option<[u8;6]> {
+        let year = u8::try_from(self.year.checked_sub(1900)?).ok()?;
+        Some([year,self.month.wrapping_sub(1),self.day,self.hour,self.minute,self.second])
+    }
+    /// UTC date and time of `time`, times before 1970 are taken as the start of 1970
+    pub fn from_system_time(time: std::time::SystemTime) -> Self {
+        let secs = time.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (days,rem) = (secs / 86400,secs % 86400);
+        // civil date from days since 1970, following Howard Hinnant's algorithm
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z - era * 146097;
+        let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+        let doy = doe - (365*yoe + yoe/4 - yoe/100);
+        let mp = (5*doy + 2) / 153;
+        let day = doy - (153*mp + 2)/5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as u64;
+        Self {
+            year: u16::try_from(year).unwrap_or(u16::MAX),
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8
+        }
+    }
+    /// Current UTC date and time
+    pub fn now() -> Self {
+        Self::from_system_time(std::time::SystemTime::now())
+    }
+}
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f,"{}-{:02}-{:02} {:02}:{:02}:{:02}",self.year,self.month,self.day,self.hour,self.minute,self.second)
+    }
+}
+
+/// Comment block that can follow the image header, its presence is flagged by the high bit of header byte 7
+#[derive(Clone,Debug,PartialEq)]
+pub struct Comment {
+    /// packed date and time, see `Timestamp`
+    pub timestamp: [u8;6],
+    /// comment text, each line is terminated by a null
+    pub data: Vec<u8>
+}
+
+impl Comment {
+    /// Create a comment from text, with newlines separating the lines
+    pub fn from_text(text: &str,timestamp: [u8;6]) -> Self {
+        let mut data = Vec::new();
+        for line in text.lines() {
+            data.extend_from_slice(line.as_bytes());
+            data.push(0);
+        }
+        Self { timestamp, data }
+    }
+    /// The comment text, with newlines separating the lines
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).trim_end_matches('\0').replace('\0',"\n")
+    }
+    /// The decoded date and time
+    pub fn date(&self) -> Timestamp {
+        Timestamp::from_bytes(self.timestamp)
+    }
+    /// The CRC that the comment block stores, which covers the length, timestamp, and text
+    pub fn crc(&self) -> u16 {
+        let len = u16::to_le_bytes(self.data.len() as u16);
+        crc16(crc16(crc16(0,&len),&self.timestamp),&self.data)
+    }
+}
+
+/// Get a normal image, expanding if necessary, the header CRC is checked either way.
+/// Returns the image and whether it was advanced.
+fn normal_image(image: &[u8]) -> Result<(Vec<u8>,bool),DYNERR> {
+    match image.get(0..2) {
+        Some(b"td") => Ok((expand_slice(image)?,true)),
+        Some(b"TD") => {
+            convert_header(&mut Cursor::new(image),"TD","TD",&STD_OPTIONS)?;
+            Ok((image.to_vec(),false))
+        },
+        _ => Err(Box::new(crate::Error::FileFormatMismatch))
+    }
+}
+
+/// Find the comment block in a normal image, returns (comment,end of comment block)
+fn find_comment(normal: &[u8]) -> Result<(Option<Comment>,usize),DYNERR> {
+    if normal[7] & 0x80 == 0 {
+        return Ok((None,12));
+    }
+    let block = normal.get(12..22).ok_or(crate::Error::FileFormatMismatch)?;
+    let end = 22 + u16::from_le_bytes([block[2],block[3]]) as usize;
+    let data = normal.get(22..end).ok_or(crate::Error::FileFormatMismatch)?;
+    if crc16(crc16(0,&block[2..10]),data) != u16::from_le_bytes([block[0],block[1]]) {
+        return Err(Box::new(crate::Error::BadChecksum));
+    }
+    let mut timestamp = [0;6];
+    timestamp.copy_from_slice(&block[4..10]);
+    Ok((Some(Comment { timestamp, data: data.to_vec() }),end))
+}
+
+/// The comment block with its CRC, returns `Error::FileTooLarge` if the comment is longer than 65535 bytes
+fn comment_block(comment: &Comment) -> Result<Vec<u8>,DYNERR> {
+    let len = u16::try_from(comment.data.len()).map_err(|_| crate::Error::FileTooLarge)?;
+    let mut block = u16::to_le_bytes(comment.crc()).to_vec();
+    block.extend_from_slice(&u16::to_le_bytes(len));
+    block.extend_from_slice(&comment.timestamp);
+    block.extend_from_slice(&comment.data);
+    Ok(block)
+}
+
+/// Get the comment from a TD0 image, which can be normal or advanced.
+/// The header and comment CRC are checked, `Error::BadChecksum` is returned if either fails.
+pub fn get_comment(image: &[u8]) -> Result<Option<Comment>,DYNERR> {
+    let (normal,_) = normal_image(image)?;
+    Ok(find_comment(&normal)?.0)
+}
+
+/// Add or replace the comment, or remove it if `comment` is `None`.  The image can be normal or advanced,
+/// and is returned the same way, with the header and comment CRC recomputed.
+/// Returns `Error::FileTooLarge` if the comment is longer than 65535 bytes.
+pub fn set_comment(image: &[u8],comment: Option<&Comment>) -> Result<Vec<u8>,DYNERR> {
+    let (normal,advanced) = normal_image(image)?;
+    let (_,end) = find_comment(&normal)?;
+    let mut header = [0;12];
+    header.copy_from_slice(&normal[0..12]);
+    header[7] = match comment {
+        Some(_) => header[7] | 0x80,
+        None => header[7] & 0x7f
+    };
+    let crc = u16::to_le_bytes(crc16(0,&header[0..10]));
+    header[10..12].copy_from_slice(&crc);
+    let mut ans = header.to_vec();
+    if let Some(comment) = comment {
+        ans.extend_from_slice(&comment_block(comment)?);
+    }
+    ans.extend_from_slice(&normal[end..]);
+    match advanced {
+        true => compress_slice(&ans),
+        false => Ok(ans)
+    }
+}
+
+/// Most volumes in a set, since each one is named by the last digit of its extension, see `volume_path`
+pub const MAX_VOLUMES: usize = 10;
+
+/// Split a normal or advanced image into a multi-volume set, for media that cannot hold it whole.
+/// Each volume starts with a copy of the header, numbered in sequence, and holds the next piece of what
+/// follows the header, so that no volume is longer than `volume_size`.  The check signature is the one in
+/// the image, set it first with `Options::check_signature` or `Builder::set_volume` to choose another.
+/// A bad header CRC is `Error::BadChecksum`, an image that is already part of a set is `Error::FileFormatMismatch`,
+/// and needing more than `MAX_VOLUMES` is `Error::FileTooLarge`.  Panics if `volume_size` is 12 or less.
+pub fn split_volumes(image: &[u8],volume_size: usize) -> Result<Vec<Vec<u8>>,DYNERR> {
+    assert!(volume_size > 12,"a volume must hold more than the header");
+    let header = Td0Header::parse(image)?;
+    if !header.crc_ok() {
+        return Err(Box::new(crate::Error::BadChecksum));
+    }
+    if header.sequence != 0 {
+        log::error!("image is already volume {} of a set",header.sequence);
+        return Err(Box::new(crate::Error::FileFormatMismatch));
+    }
+    let mut pieces: Vec<&[u8]> = image[12..].chunks(volume_size - 12).collect();
+    // an image that is only the header is still one volume
+    if pieces.is_empty() {
+        pieces.push(&[]);
+    }
+    if pieces.len() > MAX_VOLUMES {
+        log::error!("image needs {} volumes",pieces.len());
+        return Err(Box::new(crate::Error::FileTooLarge));
+    }
+    Ok(pieces.iter().enumerate().map(|(sequence,piece)| {
+        let mut volume = Td0Header { sequence: sequence as u8, ..header }.to_bytes().to_vec();
+        volume.extend_from_slice(piece);
+        volume
+    }).collect())
+}
+
+/// Put a multi-volume set back together as one image, with the volumes given in order.
+/// Every header must have a good CRC, `Error::BadChecksum` otherwise, and all must be the same but for
+/// the sequence, which must count up from 0, or it is `Error::FileFormatMismatch`.
+pub fn join_volumes<V: AsRef<[u8]>>(volumes: &[V]) -> Result<Vec<u8>,DYNERR> {
+    let first = Td0Header::parse(volumes.first().ok_or(crate::Error::FileFormatMismatch)?.as_ref())?;
+    let mut ans = Td0Header { sequence: 0, ..first }.to_bytes().to_vec();
+    for (sequence,volume) in volumes.iter().enumerate() {
+        let header = Td0Header::parse(volume.as_ref())?;
+        if !header.crc_ok() {
+            log::error!("volume {} has a bad header CRC",sequence);
+            return Err(Box::new(crate::Error::BadChecksum));
+        }
+        let same = Td0Header { sequence: 0, crc: 0, ..header } == Td0Header { sequence: 0, crc: 0, ..first };
+        if !same || header.sequence as usize != sequence {
+            log::error!("volume {} does not belong to the set",sequence);
+            return Err(Box::new(crate::Error::FileFormatMismatch));
+        }
+        ans.extend_from_slice(&volume.as_ref()[12..]);
+    }
+    Ok(ans)
+}
+
+/// Path of a volume in a set named after `path`, the last character of the extension is replaced by
+/// the sequence, so `disk.td0` is followed by `disk.td1`, and so on, keeping the case.
+/// If there is no extension `.td` and the sequence is added.  Panics if `sequence` is not a single digit.
+pub fn volume_path<P: AsRef<Path>>(path: P,sequence: usize) -> PathBuf {
+    assert!(sequence < MAX_VOLUMES,"volumes are numbered with a single digit");
+    let path = path.as_ref();
+    let ext = match path.extension().map(|ext| ext.to_string_lossy()) {
+        Some(ext) if !ext.is_empty() => {
+            let mut ext = ext.to_string();
+            ext.pop();
+            ext
+        },
+        _ => "td".to_string()
+    };
+    path.with_extension(format!("{}{}",ext,sequence))
+}
+
+/// A CRC in a TD0 image that did not match the data it covers, see `fix_crc`.
+/// The track and sector CRCs are the low byte of the full CRC.
+#[derive(Clone,Debug,PartialEq)]
+pub enum CrcMismatch {
+    Header { stored: u16, computed: u16 },
+    Comment { stored: u16, computed: u16 },
+    Track { cylinder: u8, head: u8, stored: u8, computed: u8 },
+    Sector { cylinder: u8, head: u8, sector: u8, stored: u8, computed: u8 }
+}
+
+impl std::fmt::Display for CrcMismatch {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Header { stored, computed } => write!(f,"header CRC is {:04x}, should be {:04x}",stored,computed),
+            Self::Comment { stored, computed } => write!(f,"comment CRC is {:04x}, should be {:04x}",stored,computed),
+            Self::Track { cylinder, head, stored, computed } =>
+                write!(f,"track {}/{} CRC is {:02x}, should be {:02x}",cylinder,head,stored,computed),
+            Self::Sector { cylinder, head, sector, stored, computed } =>
+                write!(f,"sector {}/{}/{} CRC is {:02x}, should be {:02x}",cylinder,head,sector,stored,computed)
+        }
+    }
+}
+
+/// Flags byte of a TD0 sector header
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct SectorFlags(pub u8);
+
+impl SectorFlags {
+    /// the sector number appears more than once in the track
+    pub fn duplicate(&self) -> bool {
+        self.0 & 0x01 > 0
+    }
+    /// the data was read with a CRC error
+    pub fn crc_error(&self) -> bool {
+        self.0 & 0x02 > 0
+    }
+    /// the sector has a deleted data address mark
+    pub fn deleted(&self) -> bool {
+        self.0 & 0x04 > 0
+    }
+    /// the sector was skipped because DOS had not allocated it, there is no data block
+    pub fn unallocated(&self) -> bool {
+        self.0 & 0x10 > 0
+    }
+    /// the sector has an ID field but its data could not be read, there is no data block
+    pub fn no_data(&self) -> bool {
+        self.0 & 0x20 > 0
+    }
+    /// the sector has data but no ID field
+    pub fn no_id(&self) -> bool {
+        self.0 & 0x40 > 0
+    }
+    /// a data block follows the sector header
+    pub fn has_data(&self) -> bool {
+        self.0 & 0x30 == 0
+    }
+}
+
+/// Header of a track record, the sector count is the length of `Track::sectors`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct TrackHeader {
+    pub cylinder: u8,
+    /// the high bit is set if the track was recorded in FM
+    pub head: u8,
+    /// low byte of the CRC of the sector count, cylinder, and head
+    pub crc: u8
+}
+
+/// Header of a sector, as found in the sector's ID field
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct SectorHeader {
+    pub cylinder: u8,
+    pub head: u8,
+    pub sector: u8,
+    /// the sector holds 128 << `size_code` bytes
+    pub size_code: u8,
+    pub flags: SectorFlags,
+    /// low byte of the CRC of the sector data
+    pub crc: u8
+}
+
+impl SectorHeader {
+    /// Size of the sector in bytes
+    pub fn size(&self) -> usize {
+        128 << (self.size_code & 7)
+    }
+}
+
+/// Piece of a sector stored with `SectorData::Rle`
+#[derive(Clone,Debug,PartialEq)]
+pub enum Fragment {
+    /// up to 255 bytes stored as they are
+    Literal(Vec<u8>),
+    /// a pattern of 2, 4, 8, and so on up to 16384 bytes, repeated `count` times
+    Repeat { pattern: Vec<u8>, count: u8 }
+}
+
+/// How the data block of a sector is stored, see `SectorData`
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Encoding {
+    Raw,
+    Repeat,
+    Rle
+}
+
+/// Data block of a sector, in each of the ways Teledisk stores it
+#[derive(Clone,Debug,PartialEq)]
+pub enum SectorData {
+    /// encoding 0, the bytes as they are
+    Raw(Vec<u8>),
+    /// encoding 1, a 2 byte pattern repeated `count` times
+    Repeat { pattern: [u8;2], count: u16 },
+    /// encoding 2, a run length encoding made of fragments
+    Rle(Vec<Fragment>)
+}
+
+impl SectorData {
+    /// Parse a data block that starts with the encoding byte, for a sector of `size` bytes.
+    /// Run length fragments are read until there are `size` bytes or the block ends.
+    pub fn parse(block: &[u8],size: usize) -> Result<Self,DYNERR> {
+        let bad = || crate::Error::FileFormatMismatch;
+        let (encoding,block) = block.split_first().ok_or(bad())?;
+        match encoding {
+            0 => Ok(Self::Raw(block.to_vec())),
+            1 => {
+                let count = u16::from_le_bytes([*block.first().ok_or(bad())?,*block.get(1).ok_or(bad())?]);
+                let pattern = block.get(2..4).ok_or(bad())?;
+                Ok(Self::Repeat { pattern: [pattern[0],pattern[1]], count })
+            },
+            2 => {
+                let mut fragments = Vec::new();
+                let mut len = 0;
+                let mut ptr = 0;
+                while len < size && ptr < block.len() {
+                    let kind = block[ptr];
+                    let count = *block.get(ptr+1).ok_or(bad())?;
+                    ptr += 2;
+                    let bytes = match kind {
+                        0 => count as usize,
+                        1..=14 => 1 << kind,
+                        _ => return Err(Box::new(bad()))
+                    };
+                    let bytes = block.get(ptr..ptr+bytes).ok_or(bad())?.to_vec();
+                    ptr += bytes.len();
+                    fragments.push(match kind {
+                        0 => {
+                            len += bytes.len();
+                            Fragment::Literal(bytes)
+                        },
+                        _ => {
+                            len += bytes.len() * count as usize;
+                            Fragment::Repeat { pattern: bytes, count }
+                        }
+                    });
+                }
+                Ok(Self::Rle(fragments))
+            },
+            _ => Err(Box::new(bad()))
+        }
+    }
+    /// Store `data` in whichever encoding gives the smallest block, preferring a repeated
+    /// 2 byte pattern, then the bytes as they are, then the run length encoding
+    pub fn encode(data: &[u8]) -> Self {
+        if let [a,b,..] = data {
+            if data.len().is_multiple_of(2) && data.len() < 0x20000 && data.chunks(2).all(|pair| pair==[*a,*b]) {
+                return Self::Repeat { pattern: [*a,*b], count: (data.len() / 2) as u16 };
+            }
+        }
+        let fragments = rle_fragments(data);
+        let rle_len: usize = fragments.iter().map(|fragment| match fragment {
+            Fragment::Literal(bytes) => 2 + bytes.len(),
+            Fragment::Repeat { pattern, .. } => 2 + pattern.len()
+        }).sum();
+        match rle_len < data.len() {
+            true => Self::Rle(fragments),
+            false => Self::Raw(data.to_vec())
+        }
+    }
+    /// Which of the encodings this is
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            Self::Raw(_) => Encoding::Raw,
+            Self::Repeat { .. } => Encoding::Repeat,
+            Self::Rle(_) => Encoding::Rle
+        }
+    }
+    /// The sector data
+    pub fn decode(&self) -> Vec<u8> {
+        match self {
+            Self::Raw(data) => data.clone(),
+            Self::Repeat { pattern, count } => pattern.repeat(*count as usize),
+            Self::Rle(fragments) => fragments.iter().flat_map(|fragment| match fragment {
+                Fragment::Literal(bytes) => bytes.clone(),
+                Fragment::Repeat { pattern, count } => pattern.repeat(*count as usize)
+            }).collect()
+        }
+    }
+    /// The data block, starting with the encoding byte.
+    /// A fragment that breaks the limits on its length is `Error::FileFormatMismatch`.
+    pub fn to_block(&self) -> Result<Vec<u8>,DYNERR> {
+        let mut ans = Vec::new();
+        match self {
+            Self::Raw(data) => {
+                ans.push(0);
+                ans.extend_from_slice(data);
+            },
+            Self::Repeat { pattern, count } => {
+                ans.push(1);
+                ans.extend_from_slice(&u16::to_le_bytes(*count));
+                ans.extend_from_slice(pattern);
+            },
+            Self::Rle(fragments) => {
+                ans.push(2);
+                for fragment in fragments {
+                    match fragment {
+                        Fragment::Literal(bytes) => {
+                            let count = u8::try_from(bytes.len()).map_err(|_| crate::Error::FileFormatMismatch)?;
+                            ans.extend_from_slice(&[0,count]);
+                            ans.extend_from_slice(bytes);
+                        },
+                        Fragment::Repeat { pattern, count } => {
+                            let kind = pattern.len().trailing_zeros();
+                            if !pattern.len().is_power_of_two() || !(1..=14).contains(&kind) {
+                                return Err(Box::new(crate::Error::FileFormatMismatch));
+                            }
+                            ans.extend_from_slice(&[kind as u8,*count]);
+                            ans.extend_from_slice(pattern);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(ans)
+    }
+}
+
+/// Run length encode `data`, at each position taking the repeated pattern that saves the most,
+/// if any saves anything, otherwise adding to a literal
+fn rle_fragments(data: &[u8]) -> Vec<Fragment> {
+    let mut ans = Vec::new();
+    let mut literal: Vec<u8> = Vec::new();
+    let mut ptr = 0;
+    while ptr < data.len() {
+        // (bytes saved,pattern length,count)
+        let mut best = (0,0,0);
+        for kind in 1..=14 {
+            let len = 1 << kind;
+            if ptr + 2 * len > data.len() {
+                break;
+            }
+            let pattern = &data[ptr..ptr+len];
+            let mut count = 1;
+            while count < 255 && data.get(ptr+count*len..ptr+(count+1)*len)==Some(pattern) {
+                count += 1;
+            }
+            let saved = (len * count).saturating_sub(len + 2);
+            if saved > best.0 {
+                best = (saved,len,count);
+            }
+        }
+        // a repeat in the middle of a literal also costs the header of the literal that follows
+        if best.0 > 2 || (best.0 > 0 && literal.is_empty()) {
+            if !literal.is_empty() {
+                ans.push(Fragment::Literal(std::mem::take(&mut literal)));
+            }
+            let (_,len,count) = best;
+            ans.push(Fragment::Repeat { pattern: data[ptr..ptr+len].to_vec(), count: count as u8 });
+            ptr += len * count;
+        } else {
+            literal.push(data[ptr]);
+            ptr += 1;
+            if literal.len()==255 {
+                ans.push(Fragment::Literal(std::mem::take(&mut literal)));
+            }
+        }
+    }
+    if !literal.is_empty() {
+        ans.push(Fragment::Literal(literal));
+    }
+    ans
+}
+
+/// Sector of a TD0 image, `data` is present if the flags say there is a data block
+#[derive(Clone,Debug,PartialEq)]
+pub struct Sector {
+    pub header: SectorHeader,
+    pub data: Option<SectorData>
+}
+
+impl Sector {
+    /// The sector data, if there is any
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        self.data.as_ref().map(|data| data.decode())
+    }
+    /// Replace the sector data, encoded with `SectorData::encode`.  The CRC is updated and the sector
+    /// is flagged as having data, the size code and the other flags are left alone.
+    pub fn set_bytes(&mut self,data: &[u8]) {
+        self.header.crc = crc16(0,data) as u8;
+        self.header.flags.0 &= !0x30;
+        self.data = Some(SectorData::encode(data));
+    }
+}
+
+/// Track of a TD0 image
+#[derive(Clone,Debug,PartialEq)]
+pub struct Track {
+    pub header: TrackHeader,
+    pub sectors: Vec<Sector>
+}
+
+/// TD0 image broken down into its parts, see `parse_image`
+#[derive(Clone,Debug,PartialEq)]
+pub struct Image {
+    /// the image header as it was found, the signature, comment flag, and CRC are set to match
+    /// when the image is put back together
+    pub header: Td0Header,
+    pub comment: Option<Comment>,
+    pub tracks: Vec<Track>,
+    /// the record that ends the image and anything after it, kept as it is
+    pub end: Vec<u8>
+}
+
+impl Image {
+    /// Put the normal image back together.  The signature, comment flag, and the header and comment CRCs
+    /// are set to match, while the track and sector CRCs are written as they are, use `fix_crc` to update them.
+    /// Returns `Error::FileTooLarge` if a track has 255 or more sectors, or the comment or a data block is too long,
+    /// and `Error::FileFormatMismatch` if a sector's flags and data disagree.
+    pub fn to_normal(&self) -> Result<Vec<u8>,DYNERR> {
+        let header = Td0Header { advanced: false, has_comment: self.comment.is_some(), ..self.header };
+        let mut ans = header.to_bytes().to_vec();
+        if let Some(comment) = &self.comment {
+            ans.extend_from_slice(&comment_block(comment)?);
+        }
+        for track in &self.tracks {
+            let count = match u8::try_from(track.sectors.len()) {
+                Ok(count) if count < 0xff => count,
+                _ => return Err(Box::new(crate::Error::FileTooLarge))
+            };
+            ans.extend_from_slice(&[count,track.header.cylinder,track.header.head,track.header.crc]);
+            for sector in &track.sectors {
+                let h = &sector.header;
+                ans.extend_from_slice(&[h.cylinder,h.head,h.sector,h.size_code,h.flags.0,h.crc]);
+                match (h.flags.has_data(),&sector.data) {
+                    (false,None) => {},
+                    (true,Some(data)) => {
+                        let block = data.to_block()?;
+                        let len = u16::try_from(block.len()).map_err(|_| crate::Error::FileTooLarge)?;
+                        ans.extend_from_slice(&u16::to_le_bytes(len));
+                        ans.extend_from_slice(&block);
+                    },
+                    _ => return Err(Box::new(crate::Error::FileFormatMismatch))
+                }
+            }
+        }
+        ans.extend_from_slice(&self.end);
+        Ok(ans)
+    }
+    /// Where the image belongs in a set of volumes, to change it set `header.sequence` and `header.check_signature`
+    pub fn volume(&self) -> Volume {
+        self.header.volume()
+    }
+    /// Put the image back together with advanced compression, as `to_normal` followed by `compress_slice`
+    pub fn to_advanced(&self) -> Result<Vec<u8>,DYNERR> {
+        compress_slice(&self.to_normal()?)
+    }
+}
+
+/// Break down a TD0 image, which can be normal or advanced, into its tracks and sectors.
+/// The header and comment CRCs are checked, `Error::BadChecksum` is returned if either fails,
+/// while the track and sector CRCs are kept as they are, check them with `fix_crc`.
+/// An image that ends in the middle of a track is `Error::FileFormatMismatch`, see `recover`.
+pub fn parse_image(image: &[u8]) -> Result<Image,DYNERR> {
+    let bad = || crate::Error::FileFormatMismatch;
+    let (mut normal,_) = normal_image(image)?;
+    let (comment,mut ptr) = find_comment(&normal)?;
+    let mut tracks = Vec::new();
+    loop {
+        // the rest of the end record may be missing
+        if *normal.get(ptr).ok_or(bad())? == 0xff {
+            break;
+        }
+        let track = normal.get(ptr..ptr+4).ok_or(bad())?;
+        let count = track[0];
+        let header = TrackHeader { cylinder: track[1], head: track[2], crc: track[3] };
+        #[cfg(feature="tracing")]
+        tracing::debug!(cylinder=header.cylinder,head=header.head,sectors=count,offset=ptr,"td0 track");
+        let mut sectors = Vec::with_capacity(count as usize);
+        ptr += 4;
+        for _i in 0..count {
+            let h = normal.get(ptr..ptr+6).ok_or(bad())?;
+            let header = SectorHeader { cylinder: h[0], head: h[1], sector: h[2], size_code: h[3], flags: SectorFlags(h[4]), crc: h[5] };
+            ptr += 6;
+            let data = match header.flags.has_data() {
+                true => {
+                    let len = u16::from_le_bytes([*normal.get(ptr).ok_or(bad())?,*normal.get(ptr+1).ok_or(bad())?]) as usize;
+                    let block = normal.get(ptr+2..ptr+2+len).ok_or(bad())?;
+                    ptr += 2 + len;
+                    Some(SectorData::parse(block,header.size())?)
+                },
+                false => None
+            };
+            sectors.push(Sector { header, data });
+        }
+        tracks.push(Track { header, sectors });
+    }
+    Ok(Image { header: Td0Header::parse(image)?, comment, tracks, end: normal.split_off(ptr) })
+}
+
+/// What `verify_sectors` found in a sector
+#[derive(Clone,Debug,PartialEq)]
+pub struct SectorCheck {
+    pub header: SectorHeader,
+    /// how the data is stored, `None` if the sector has no data
+    pub encoding: Option<Encoding>,
+    /// low byte of the CRC of the data, `None` if the sector has no data
+    pub computed_crc: Option<u8>
+}
+
+impl SectorCheck {
+    /// True if the CRC matches, or there is no data to check
+    pub fn crc_ok(&self) -> bool {
+        self.computed_crc.is_none_or(|crc| crc==self.header.crc)
+    }
+}
+
+/// What `verify_sectors` found in a track
+#[derive(Clone,Debug,PartialEq)]
+pub struct TrackCheck {
+    pub header: TrackHeader,
+    /// low byte of the CRC of the sector count, cylinder, and head
+    pub computed_crc: u8,
+    pub sectors: Vec<SectorCheck>
+}
+
+impl TrackCheck {
+    /// True if the CRC of the track header matches
+    pub fn crc_ok(&self) -> bool {
+        self.computed_crc==self.header.crc
+    }
+}
+
+/// CRC results and encodings for every track and sector of an image, see `verify_sectors`
+#[derive(Clone,Debug,PartialEq)]
+pub struct SectorReport {
+    pub tracks: Vec<TrackCheck>
+}
+
+impl SectorReport {
+    /// The track and sector CRCs that do not match, in the order they appear
+    pub fn mismatches(&self) -> Vec<CrcMismatch> {
+        let mut ans = Vec::new();
+        for track in &self.tracks {
+            let (cylinder,head) = (track.header.cylinder,track.header.head);
+            if !track.crc_ok() {
+                ans.push(CrcMismatch::Track { cylinder, head, stored: track.header.crc, computed: track.computed_crc });
+            }
+            for sector in track.sectors.iter().filter(|sector| !sector.crc_ok()) {
+                let (sector,stored,computed) = (sector.header.sector,sector.header.crc,sector.computed_crc.unwrap_or_default());
+                ans.push(CrcMismatch::Sector { cylinder, head, sector, stored, computed });
+            }
+        }
+        ans
+    }
+    /// Number of sectors
+    pub fn sectors(&self) -> usize {
+        self.tracks.iter().map(|track| track.sectors.len()).sum()
+    }
+    /// Number of sectors whose data is stored with `encoding`
+    pub fn count(&self,encoding: Encoding) -> usize {
+        self.tracks.iter().flat_map(|track| &track.sectors).filter(|sector| sector.encoding==Some(encoding)).count()
+    }
+}
+
+/// Walk every track and sector of a TD0 image, which can be normal or advanced, and check their CRCs.
+/// The header and comment CRCs are checked first, `Error::BadChecksum` is returned if either fails,
+/// after that the results go in the report, so all of the bad sectors can be found at once.
+/// Sector data that cannot be decoded, or an image that ends in the middle of a track, is
+/// `Error::FileFormatMismatch`, see `salvage` and `recover` for those.
+pub fn verify_sectors(image: &[u8]) -> Result<SectorReport,DYNERR> {
+    let parsed = parse_image(image)?;
+    let tracks = parsed.tracks.iter().map(|track| {
+        let h = &track.header;
+        TrackCheck {
+            header: *h,
+            computed_crc: crc16(0,&[track.sectors.len() as u8,h.cylinder,h.head]) as u8,
+            sectors: track.sectors.iter().map(|sector| SectorCheck {
+                header: sector.header,
+                encoding: sector.data.as_ref().map(|data| data.encoding()),
+                computed_crc: sector.bytes().map(|data| crc16(0,&data) as u8)
+            }).collect()
+        }
+    }).collect();
+    Ok(SectorReport { tracks })
+}
+
+/// What `validate_tree` found in one file
+#[derive(Debug)]
+pub struct FileValidation {
+    pub path: PathBuf,
+    /// the sectors, or why the image could not be checked, e.g., a bad header CRC, data that does not expand,
+    /// or a directory that could not be read
+    pub result: Result<SectorReport,std::io::Error>
+}
+
+impl FileValidation {
+    /// True if the image was read and every CRC matches
+    pub fn is_ok(&self) -> bool {
+        matches!(&self.result,Ok(report) if report.mismatches().is_empty())
+    }
+}
+
+/// What `validate_tree` found in all of the files
+#[derive(Debug)]
+pub struct TreeValidation {
+    /// one for each file, sorted by path
+    pub files: Vec<FileValidation>
+}
+
+impl TreeValidation {
+    /// Number of images that were read and whose CRCs all match
+    pub fn passed(&self) -> usize {
+        self.files.iter().filter(|file| file.is_ok()).count()
+    }
+    /// Number of images that could not be read or have a CRC that does not match
+    pub fn failed(&self) -> usize {
+        self.files.len() - self.passed()
+    }
+    /// Number of sectors in the images that were read
+    pub fn sectors(&self) -> usize {
+        self.files.iter().filter_map(|file| file.result.as_ref().ok()).map(|report| report.sectors()).sum()
+    }
+    /// Number of track and sector CRCs that do not match, in the images that were read
+    pub fn mismatches(&self) -> usize {
+        self.files.iter().filter_map(|file| file.result.as_ref().ok()).map(|report| report.mismatches().len()).sum()
+    }
+}
+
+/// Paths of the files in `entries` of `dir` and below with the extension `td0`, in any case,
+/// symbolic links to directories are not followed.  A directory that cannot be read is put in `failed`.
+fn find_images(dir: &Path,entries: std::fs::ReadDir,found: &mut Vec<PathBuf>,failed: &mut Vec<FileValidation>) {
+    for entry in entries {
+        let res = entry.and_then(|entry| Ok((entry.path(),entry.file_type()?)));
+        match res {
+            Ok((path,kind)) if kind.is_dir() => match std::fs::read_dir(&path) {
+                Ok(entries) => find_images(&path,entries,found,failed),
+                Err(e) => failed.push(FileValidation { path, result: Err(e) })
+            },
+            Ok((path,_)) if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("td0")) => found.push(path),
+            Ok(_) => {},
+            Err(e) => failed.push(FileValidation { path: dir.to_path_buf(), result: Err(e) })
+        }
+    }
+}
+
+/// Find every TD0 file under `dir`, by the extension `td0` in any case, and check it with `verify_sectors`
+/// using up to `workers` threads.  This checks the header CRC, that advanced compression expands, and the
+/// comment, track, and sector CRCs.  Files that cannot be checked, and directories that cannot be read, are
+/// recorded in the result rather than stopping the scan.  Returns an error only if `dir` itself cannot be read.
+pub fn validate_tree<P: AsRef<Path>>(dir: P,workers: usize) -> Result<TreeValidation,std::io::Error> {
+    let mut paths = Vec::new();
+    let mut files = Vec::new();
+    find_images(dir.as_ref(),std::fs::read_dir(dir.as_ref())?,&mut paths,&mut files);
+    files.append(&mut on_workers(&paths,workers,|path| {
+        log::debug!("validate {}",path.display());
+        let result = std::fs::read(path).map_err(DYNERR::from).and_then(|image| verify_sectors(&image));
+        FileValidation { path: path.clone(), result: result.map_err(crate::to_io_error) }
+    }));
+    files.sort_by(|a,b| a.path.cmp(&b.path));
+    Ok(TreeValidation { files })
+}
+
+/// Assembles a TD0 image from the contents of its sectors, as an emulator saving a disk would.
+/// Each sector is stored in whichever encoding is smallest, and the CRCs are computed.
+///
+/// ```rs
+/// let mut builder = td0::Builder::new(td0::Version::new(21)?,2,1,2);
+/// for cylinder in 0..40 {
+///     for head in 0..2 {
+///         builder.add_track(cylinder,head);
+///         for sector in 1..=9 {
+///             builder.add_sector(sector,&disk.read(cylinder,head,sector))?;
+///         }
+///     }
+/// }
+/// std::fs::write("disk.td0",builder.build().to_advanced()?)?;
+/// ```
+#[derive(Clone,Debug)]
+pub struct Builder {
+    image: Image
+}
+
+impl Builder {
+    /// Start an image with a header for Teledisk `version`, giving the `data_rate` (0 is 250 kbps, 1 is 300,
+    /// 2 is 500, plus 0x80 for FM), the `drive_type` code, and the number of `sides`.
+    /// Fields that are not given are 0, see `set_volume`, or change `Image::header` of the result.
+    pub fn new(version: Version,data_rate: u8,drive_type: u8,sides: u8) -> Self {
+        let header = Td0Header {
+            advanced: false,
+            sequence: 0,
+            check_signature: 0,
+            version,
+            data_rate,
+            drive_type,
+            stepping: 0,
+            has_comment: false,
+            dos_allocation: 0,
+            sides,
+            crc: 0
+        };
+        Self { image: Image { header, comment: None, tracks: Vec::new(), end: vec![0xff,0,0,crc16(0,&[0xff,0,0]) as u8] } }
+    }
+    /// Set where the image belongs in a set of volumes, by default it is volume 0 with check signature 0
+    pub fn set_volume(&mut self,volume: Volume) {
+        self.image.header.sequence = volume.sequence;
+        self.image.header.check_signature = volume.check_signature;
+    }
+    /// Add or remove the comment
+    pub fn set_comment(&mut self,comment: Option<Comment>) {
+        self.image.comment = comment;
+    }
+    /// Start a new track, the sectors that are added go into it
+    pub fn add_track(&mut self,cylinder: u8,head: u8) {
+        self.image.tracks.push(Track { header: TrackHeader { cylinder, head, crc: 0 }, sectors: Vec::new() });
+    }
+    /// Add a sector whose ID field matches the track, with no flags set.
+    /// The size of `data` must be 128 << n, for n from 0 to 6, or it is `Error::FileFormatMismatch`.
+    /// Panics if no track was started.
+    pub fn add_sector(&mut self,sector: u8,data: &[u8]) -> Result<(),DYNERR> {
+        let track = &self.image.tracks.last().expect("add a track before its sectors").header;
+        let header = SectorHeader { cylinder: track.cylinder, head: track.head & 0x7f, sector, size_code: 0, flags: SectorFlags(0), crc: 0 };
+        self.add_sector_with(header,Some(data))
+    }
+    /// Add a sector with the ID field and flags in `header`, the size code and CRC are worked out from `data`.
+    /// If there is no data, the sector is flagged as having none, unless it is already flagged as unallocated,
+    /// and `header.size_code` and `header.crc` are kept.  A sector can only be added 254 times to a track,
+    /// after which it is `Error::FileTooLarge`, and a wrong size is as for `add_sector`.
+    /// Panics if no track was started.
+    pub fn add_sector_with(&mut self,mut header: SectorHeader,data: Option<&[u8]>) -> Result<(),DYNERR> {
+        let track = self.image.tracks.last_mut().expect("add a track before its sectors");
+        if track.sectors.len() >= 254 {
+            return Err(Box::new(crate::Error::FileTooLarge));
+        }
+        match data {
+            Some(data) => {
+                let size_code = data.len().checked_ilog2().unwrap_or(0).wrapping_sub(7);
+                if size_code > 6 || 128 << size_code != data.len() {
+                    log::error!("sector of {} bytes is not a size TD0 can hold",data.len());
+                    return Err(Box::new(crate::Error::FileFormatMismatch));
+                }
+                header.size_code = size_code as u8;
+                header.flags.0 &= !0x30;
+                header.crc = crc16(0,data) as u8;
+                track.sectors.push(Sector { header, data: Some(SectorData::encode(data)) });
+            },
+            None => {
+                if header.flags.has_data() {
+                    header.flags.0 |= 0x20;
+                }
+                track.sectors.push(Sector { header, data: None });
+            }
+        }
+        Ok(())
+    }
+    /// The finished image, with the header and track CRCs computed, see `Image::to_normal` and `Image::to_advanced`
+    pub fn build(&self) -> Image {
+        let mut ans = self.image.clone();
+        for track in ans.tracks.iter_mut() {
+            let h = &mut track.header;
+            h.crc = crc16(0,&[track.sectors.len() as u8,h.cylinder,h.head]) as u8;
+        }
+        ans.header.has_comment = ans.comment.is_some();
+        ans.header.crc = ans.header.computed_crc();
+        ans
+    }
+}
+
+/// Decode the data block of a sector, `block` starts with the encoding byte
+fn decode_sector(block: &[u8],size: usize) -> Result<Vec<u8>,DYNERR> {
+    Ok(SectorData::parse(block,size)?.decode())
+}
+
+/// Find where a normal image ends, just after the track record with the end marker
+fn image_end(normal: &[u8]) -> Result<usize,DYNERR> {
+    let bad = || crate::Error::FileFormatMismatch;
+    let mut ptr = 12;
+    if *normal.get(7).ok_or(bad())? & 0x80 > 0 {
+        ptr = 22 + u16::from_le_bytes([*normal.get(14).ok_or(bad())?,*normal.get(15).ok_or(bad())?]) as usize;
+    }
+    loop {
+        let count = *normal.get(ptr).ok_or(bad())?;
+        if count == 0xff {
+            // the rest of the record may be missing
+            return Ok(usize::min(ptr + 4,normal.len()));
+        }
+        ptr += 4;
+        for _i in 0..count {
+            let flags = *normal.get(ptr+4).ok_or(bad())?;
+            ptr += 6;
+            // no data for sectors that are unallocated or were not read
+            if flags & 0x30 > 0 {
+                continue;
+            }
+            ptr += 2 + u16::from_le_bytes([*normal.get(ptr).ok_or(bad())?,*normal.get(ptr+1).ok_or(bad())?]) as usize;
+        }
+    }
+}
+
+/// Check the comment, track, and sector CRCs of a normal image, fixing any that are wrong.
+/// Only the comment is checked unless `sectors` is true.
+fn fix_normal_crc(normal: &mut [u8],sectors: bool,found: &mut Vec<CrcMismatch>) -> Result<(),DYNERR> {
+    let bad = || crate::Error::FileFormatMismatch;
+    let mut ptr = 12;
+    if normal[7] & 0x80 > 0 {
+        let len = u16::from_le_bytes([*normal.get(14).ok_or(bad())?,*normal.get(15).ok_or(bad())?]) as usize;
+        let covered = normal.get(14..22+len).ok_or(bad())?;
+        let computed = crc16(0,covered);
+        let stored = u16::from_le_bytes([normal[12],normal[13]]);
+        if computed != stored {
+            found.push(CrcMismatch::Comment { stored, computed });
+            normal[12..14].copy_from_slice(&u16::to_le_bytes(computed));
+        }
+        ptr = 22 + len;
+    }
+    if !sectors {
+        return Ok(());
+    }
+    loop {
+        let track = normal.get(ptr..ptr+4).ok_or(bad())?;
+        let (count,cylinder,head) = (track[0],track[1],track[2]);
+        if count == 0xff {
+            return Ok(());
+        }
+        #[cfg(feature="tracing")]
+        tracing::debug!(cylinder,head,sectors=count,offset=ptr,"td0 track");
+        let computed = crc16(0,&track[0..3]) as u8;
+        if computed != track[3] {
+            found.push(CrcMismatch::Track { cylinder, head, stored: track[3], computed });
+            normal[ptr+3] = computed;
+        }
+        ptr += 4;
+        for _i in 0..count {
+            let header = normal.get(ptr..ptr+6).ok_or(bad())?;
+            let (sector,size_code,flags,stored) = (header[2],header[3],header[4],header[5]);
+            let crc_ptr = ptr + 5;
+            ptr += 6;
+            // no data for sectors that are unallocated or were not read
+            if flags & 0x30 > 0 {
+                continue;
+            }
+            let len = u16::from_le_bytes([*normal.get(ptr).ok_or(bad())?,*normal.get(ptr+1).ok_or(bad())?]) as usize;
+            let data = decode_sector(normal.get(ptr+2..ptr+2+len).ok_or(bad())?,128 << (size_code & 7))?;
+            ptr += 2 + len;
+            let computed = crc16(0,&data) as u8;
+            if computed != stored {
+                found.push(CrcMismatch::Sector { cylinder, head, sector, stored, computed });
+                normal[crc_ptr] = computed;
+            }
+        }
+    }
+}
+
+/// Recompute the CRCs of a TD0 image, which can be normal or advanced, and fix any that are wrong.
+/// The header and comment CRCs are always checked, track and sector CRCs only if `sectors` is true.
+/// Returns the repaired image, in the same form as the input, and the mismatches that were found.
+pub fn fix_crc(image: &[u8],sectors: bool) -> Result<(Vec<u8>,Vec<CrcMismatch>),DYNERR> {
+    let mut found = Vec::new();
+    let mut fixed = image.to_vec();
+    if !matches!(image.get(0..2),Some(b"td") | Some(b"TD")) || image.len() < 12 {
+        return Err(Box::new(crate::Error::FileFormatMismatch));
+    }
+    let computed = crc16(0,&image[0..10]);
+    let stored = u16::from_le_bytes([image[10],image[11]]);
+    if computed != stored {
+        found.push(CrcMismatch::Header { stored, computed });
+        fixed[10..12].copy_from_slice(&u16::to_le_bytes(computed));
+    }
+    let (mut normal,advanced) = normal_image(&fixed)?;
+    let header_only = found.len();
+    fix_normal_crc(&mut normal,sectors,&mut found)?;
+    // only recompress if something inside the compressed data changed
+    match (advanced,found.len() > header_only) {
+        (true,false) => Ok((fixed,found)),
+        (true,true) => Ok((compress_slice(&normal)?,found)),
+        (false,_) => Ok((normal,found))
+    }
+}
+
+/// Part of a TD0 image that `salvage` found damaged, and what was done about it
+#[derive(Clone,Debug,PartialEq)]
+pub enum Damage {
+    /// a header, comment, or track CRC did not match, the CRC was corrected
+    Crc(CrcMismatch),
+    /// the sector data could not be decoded or failed its CRC, it was replaced by fill bytes and flagged as a CRC error
+    Sector { cylinder: u8, head: u8, sector: u8 },
+    /// the compressed data could not be decoded past this offset in the normal image
+    Stream { offset: u64 },
+    /// the image ended in the middle of a track, only the `tracks` before it were kept
+    Truncated { tracks: usize }
+}
+
+impl std::fmt::Display for Damage {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Crc(mismatch) => write!(f,"{}, corrected",mismatch),
+            Self::Sector { cylinder, head, sector } => write!(f,"sector {}/{}/{} is unreadable, filled",cylinder,head,sector),
+            Self::Stream { offset } => write!(f,"compressed data is unreadable after offset {}",offset),
+            Self::Truncated { tracks } => write!(f,"image ends in the middle of a track, kept {} tracks",tracks)
+        }
+    }
+}
+
+/// Length and data block of a sector of `size` bytes that repeats `fill`
+fn fill_block(size: usize,fill: u8) -> Vec<u8> {
+    let rep = u16::to_le_bytes((size / 2) as u16);
+    vec![5,0,1,rep[0],rep[1],fill,fill]
+}
+
+/// Expand the compressed data as far as it goes, a cut is not an error.
+/// Returns the data following the image header, and the error if the rest could not be decoded.
+fn expand_available(image: &[u8],version: Version) -> (Vec<u8>,Option<DYNERR>) {
+    let mut normal = Vec::new();
+    let mut collect = |chunk: &[u8]| normal.extend_from_slice(chunk);
+    let res = match version.is_lzw() {
+        true => lzw::expand_chunked(&mut Cursor::new(image),&lzw::Options { truncation: crate::Truncation::Stop, ..TD_V1_OPTIONS },CACHE_BLOCK_SIZE as usize,&mut collect),
+        false => lzss_huff::expand_chunked(&mut Cursor::new(image),&lzss_huff::Options { truncation: crate::Truncation::Stop, ..TD_V2_OPTIONS },CACHE_BLOCK_SIZE as usize,&mut collect)
+    };
+    (normal,res.err())
+}
+
+/// Expand the compressed data as far as it goes, recording where it stops if it cannot be decoded to the end
+fn salvage_stream(image: &[u8],version: Version,found: &mut Vec<Damage>) -> Vec<u8> {
+    let (normal,err) = expand_available(image,version);
+    if let Some(e) = err {
+        log::warn!("expansion stopped: {}",e);
+        found.push(Damage::Stream { offset: 12 + normal.len() as u64 });
+    }
+    normal
+}
+
+/// Recover what can be recovered from a damaged TD0 image, which can be normal or advanced.
+/// Wrong CRCs on the header, comment, and tracks are corrected.  A sector whose data cannot be decoded
+/// or fails its CRC is replaced by `fill` bytes, with the CRC error flag set.  If the compressed data
+/// cannot be decoded to the end, or the image ends in the middle of a track, the complete tracks are kept.
+/// Anything after the end of the image is dropped.  Returns the image, in the same form as the input,
+/// and the damage that was found, which is empty if the image was sound.
+pub fn salvage(image: &[u8],fill: u8) -> Result<(Vec<u8>,Vec<Damage>),DYNERR> {
+    let bad = || crate::Error::FileFormatMismatch;
+    let mut found = Vec::new();
+    let advanced = match image.get(0..2) {
+        Some(b"td") => true,
+        Some(b"TD") => false,
+        _ => return Err(Box::new(bad()))
+    };
+    let mut header = image.get(0..12).ok_or(bad())?.to_vec();
+    let version = Version::new(header[4])?;
+    let computed = crc16(0,&header[0..10]);
+    let stored = u16::from_le_bytes([header[10],header[11]]);
+    if computed != stored {
+        found.push(Damage::Crc(CrcMismatch::Header { stored, computed }));
+    }
+    let normal = match advanced {
+        true => salvage_stream(image,version,&mut found),
+        false => image[12..].to_vec()
+    };
+    // `normal` starts after the image header here
+    let mut out = Vec::new();
+    let mut ptr = 0;
+    if header[7] & 0x80 > 0 {
+        match normal.get(2..4).map(|len| 10 + u16::from_le_bytes([len[0],len[1]]) as usize) {
+            Some(end) if end <= normal.len() => {
+                let computed = crc16(0,&normal[2..end]);
+                let stored = u16::from_le_bytes([normal[0],normal[1]]);
+                out.extend_from_slice(&u16::to_le_bytes(computed));
+                out.extend_from_slice(&normal[2..end]);
+                if computed != stored {
+                    found.push(Damage::Crc(CrcMismatch::Comment { stored, computed }));
+                }
+                ptr = end;
+            },
+            _ => header[7] &= 0x7f
+        }
+    }
+    let mut tracks = 0;
+    loop {
+        let track = match normal.get(ptr..ptr+4) {
+            Some(track) if track[0] != 0xff => track,
+            Some(_) | None => break
+        };
+        let (count,cylinder,head) = (track[0],track[1],track[2]);
+        let computed = crc16(0,&track[0..3]) as u8;
+        if computed != track[3] {
+            found.push(Damage::Crc(CrcMismatch::Track { cylinder, head, stored: track[3], computed }));
+        }
+        let mut buf = vec![count,cylinder,head,computed];
+        let mut sectors = 0;
+        let mut p = ptr + 4;
+        for _i in 0..count {
+            let Some(sector_header) = normal.get(p..p+6) else { break };
+            let (sector,size_code,flags,stored) = (sector_header[2],sector_header[3],sector_header[4],sector_header[5]);
+            p += 6;
+            if flags & 0x30 > 0 {
+                buf.extend_from_slice(sector_header);
+                sectors += 1;
+                continue;
+            }
+            let Some(len) = normal.get(p..p+2).map(|len| u16::from_le_bytes([len[0],len[1]]) as usize) else { break };
+            let Some(block) = normal.get(p+2..p+2+len) else { break };
+            p += 2 + len;
+            let size = 128 << (size_code & 7);
+            match decode_sector(block,size) {
+                Ok(data) if crc16(0,&data) as u8 == stored => {
+                    buf.extend_from_slice(sector_header);
+                    buf.extend_from_slice(&normal[p-2-len..p]);
+                },
+                _ => {
+                    found.push(Damage::Sector { cylinder, head, sector });
+                    buf.extend_from_slice(&sector_header[0..4]);
+                    buf.push(flags | 0x02);
+                    buf.push(crc16(0,&vec![fill;size]) as u8);
+                    buf.extend_from_slice(&fill_block(size,fill));
+                }
+            }
+            sectors += 1;
+        }
+        // a track is only kept if all of it is there
+        if sectors < count {
+            break;
+        }
+        out.extend_from_slice(&buf);
+        ptr = p;
+        tracks += 1;
+    }
+    match normal.get(ptr..ptr+4) {
+        Some(end) if end[0] == 0xff => out.extend_from_slice(end),
+        _ => {
+            found.push(Damage::Truncated { tracks });
+            out.extend_from_slice(&[0xff,0,0,crc16(0,&[0xff,0,0]) as u8]);
+        }
+    }
+    header[0..2].copy_from_slice(b"TD");
+    let crc = u16::to_le_bytes(crc16(0,&header[0..10]));
+    header[10..12].copy_from_slice(&crc);
+    let normal = [header,out].concat();
+    match advanced {
+        true => Ok((compress_slice(&normal)?,found)),
+        false => Ok((normal,found))
+    }
+}
+
+/// How much of an image `recover` got back
+#[derive(Clone,Debug,PartialEq)]
+pub struct Recovery {
+    /// false if the image ended before the track record that marks the end
+    pub complete: bool,
+    /// tracks that were recovered whole
+    pub tracks: usize,
+    /// sectors in the recovered tracks
+    pub sectors: usize,
+    /// bytes of the normal image after the last whole track, which were dropped
+    pub dropped_bytes: u64,
+    /// tracks added to reach the requested number of cylinders
+    pub padded_tracks: usize,
+    /// bytes of sector data in the added tracks, all of them fill bytes
+    pub missing_bytes: u64
+}
+
+impl std::fmt::Display for Recovery {
+    fn fmt(&self,f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.complete {
+            true => write!(f,"image is complete, {} tracks with {} sectors",self.tracks,self.sectors),
+            false => write!(f,"image is truncated, recovered {} tracks with {} sectors, dropped {} bytes of a partial track, padded {} tracks with {} missing bytes",
+                self.tracks,self.sectors,self.dropped_bytes,self.padded_tracks,self.missing_bytes)
+        }
+    }
+}
+
+/// Copy of the track at the start of `template`, moved to `cylinder` and `head`, with every sector
+/// holding `fill` and flagged as a CRC error.  Sectors without data stay that way.
+/// Returns the track and the number of bytes of sector data.
+fn fill_track(template: &[u8],cylinder: u8,head: u8,fill: u8) -> (Vec<u8>,u64) {
+    let count = template[0];
+    // the track is recorded in FM if the template was, the sector IDs only have the head
+    let track_head = head | (template[2] & 0x80);
+    let mut track = vec![count,cylinder,track_head,crc16(0,&[count,cylinder,track_head]) as u8];
+    let mut data_bytes = 0;
+    let mut ptr = 4;
+    for _i in 0..count {
+        let (sector,size_code,flags) = (template[ptr+2],template[ptr+3],template[ptr+4]);
+        ptr += 6;
+        if flags & 0x30 > 0 {
+            track.extend_from_slice(&[cylinder,head,sector,size_code,flags,template[ptr-1]]);
+            continue;
+        }
+        ptr += 2 + u16::from_le_bytes([template[ptr],template[ptr+1]]) as usize;
+        let size = 128 << (size_code & 7);
+        track.extend_from_slice(&[cylinder,head,sector,size_code,flags | 0x02,crc16(0,&vec![fill;size]) as u8]);
+        track.extend_from_slice(&fill_block(size,fill));
+        data_bytes += size as u64;
+    }
+    (track,data_bytes)
+}
+
+/// Get back what is there from a TD0 image that was cut short, which can be normal or advanced.
+/// The whole tracks are kept and the image is closed with the end record.  If `cylinders` is given,
+/// tracks are added until there are that many cylinders, each laid out like the last recovered track
+/// on the same head, with the sectors holding `fill` bytes and flagged as CRC errors.  This way tools
+/// that expect a full disk can still open the image.  The header CRC is checked, and compressed data
+/// that is damaged rather than cut is still an error, see `salvage` for those.
+/// Returns the image, in the same form as the input, and what was recovered.
+pub fn recover(image: &[u8],cylinders: Option<u8>,fill: u8) -> Result<(Vec<u8>,Recovery),DYNERR> {
+    let advanced = match image.get(0..2) {
+        Some(b"td") => true,
+        Some(b"TD") => false,
+        _ => return Err(Box::new(crate::Error::FileFormatMismatch))
+    };
+    let from = std::str::from_utf8(&image[0..2])?;
+    let (mut header,version) = convert_header(&mut Cursor::new(image),from,"TD",&STD_OPTIONS)?;
+    let normal = match advanced {
+        true => match expand_available(image,version) {
+            (_,Some(e)) => return Err(e),
+            (normal,None) => normal
+        },
+        false => image[12..].to_vec()
+    };
+    // `normal` starts after the image header here
+    let mut ptr = 0;
+    if header[7] & 0x80 > 0 {
+        match normal.get(2..4).map(|len| 10 + u16::from_le_bytes([len[0],len[1]]) as usize) {
+            Some(end) if end <= normal.len() => ptr = end,
+            _ => {
+                log::warn!("comment is cut off, dropping it");
+                header[7] &= 0x7f;
+            }
+        }
+    }
+    let mut out = normal[0..ptr].to_vec();
+    let mut ans = Recovery { complete: false, tracks: 0, sectors: 0, dropped_bytes: 0, padded_tracks: 0, missing_bytes: 0 };
+    // where the last whole track on each head starts in `out`, to lay out the padding
+    let mut last_track: Vec<(u8,u8,usize)> = Vec::new();
+    while let Some(&count) = normal.get(ptr) {
+        if count == 0xff {
+            ans.complete = true;
+            break;
+        }
+        let Some(track) = normal.get(ptr..ptr+4) else { break };
+        // the high bit of the head marks an FM track
+        let (cylinder,head) = (track[1],track[2] & 0x7f);
+        let mut p = ptr + 4;
+        let mut sectors = 0;
+        for _i in 0..count {
+            let Some(flags) = normal.get(p+4) else { break };
+            p += 6;
+            if flags & 0x30 == 0 {
+                let Some(len) = normal.get(p..p+2).map(|len| u16::from_le_bytes([len[0],len[1]]) as usize) else { break };
+                p += 2 + len;
+            }
+            if p > normal.len() {
+                break;
+            }
+            sectors += 1;
+        }
+        if sectors < count {
+            break;
+        }
+        last_track.retain(|(_,h,_)| *h != head);
+        last_track.push((cylinder,head,out.len()));
+        out.extend_from_slice(&normal[ptr..p]);
+        ans.tracks += 1;
+        ans.sectors += count as usize;
+        ptr = p;
+    }
+    if !ans.complete {
+        ans.dropped_bytes = (normal.len() - ptr) as u64;
+        log::warn!("image is cut off after {} tracks",ans.tracks);
+        match (cylinders,last_track.last().copied()) {
+            (Some(cylinders),Some((mut cylinder,mut head,_))) => {
+                // a bogus head byte cannot make up more sides than a floppy has
+                let heads = u8::max(header[9],head + 1).clamp(1,2);
+                loop {
+                    (cylinder,head) = match (head + 1 < heads,cylinder.checked_add(1)) {
+                        (true,_) => (cylinder,head + 1),
+                        (false,Some(next)) => (next,0),
+                        (false,None) => break
+                    };
+                    if cylinder >= cylinders {
+                        break;
+                    }
+                    let template = last_track.iter().find(|(_,h,_)| *h == head).unwrap_or(last_track.last().unwrap()).2;
+                    let (track,data_bytes) = fill_track(&out[template..],cylinder,head,fill);
+                    out.extend_from_slice(&track);
+                    ans.padded_tracks += 1;
+                    ans.missing_bytes += data_bytes;
+                }
+            },
+            (Some(_),None) => log::warn!("no whole track to lay out the padding"),
+            _ => {}
+        }
+    }
+    match normal.get(ptr..ptr+4) {
+        Some(end) if ans.complete => out.extend_from_slice(end),
+        _ => out.extend_from_slice(&[0xff,0,0,crc16(0,&[0xff,0,0]) as u8])
+    }
+    let crc = u16::to_le_bytes(crc16(0,&header[0..10]));
+    header[10..12].copy_from_slice(&crc);
+    let normal = [header.to_vec(),out].concat();
+    match advanced {
+        true => Ok((compress_slice(&normal)?,ans)),
+        false => Ok((normal,ans))
+    }
+}
+
+#[test]
+fn salvage_images() {
+    for path in ["tests/td105","tests/td215"] {
+        let compressed = std::fs::read(path.to_string() + ".adv.td0").expect("could not read test file");
+        let normal = std::fs::read(path.to_string() + ".norm.td0").expect("could not read test file");
+        assert_eq!(salvage(&normal,0).expect("salvage failed"),(normal.clone(),vec![]));
+        let (salvaged,found) = salvage(&compressed,0).expect("salvage failed");
+        assert_eq!(found,vec![]);
+        let trim = Options { trim_padding: true, ..STD_OPTIONS };
+        let mut trimmed = Cursor::new(Vec::new());
+        expand_with_options(&mut Cursor::new(&compressed),&mut trimmed,&trim).expect("expansion failed");
+        assert_eq!(expand_slice(&salvaged).expect("expansion failed"),trimmed.into_inner());
+        // bad data in the first sector, which starts after the track and sector headers
+        assert_eq!(normal[7] & 0x80,0);
+        assert_eq!(normal[20] & 0x30,0);
+        let mut damaged = normal.clone();
+        damaged[26] ^= 0x55;
+        let (salvaged,found) = salvage(&damaged,0xe5).expect("salvage failed");
+        assert_eq!(found,vec![Damage::Sector { cylinder: normal[13], head: normal[14], sector: normal[18] }]);
+        assert_eq!(salvaged[20],normal[20] | 0x02);
+        assert_eq!(decode_sector(&salvaged[24..29],128 << normal[19]).expect("bad fill"),vec![0xe5;128 << normal[19]]);
+        assert_eq!(fix_crc(&salvaged,true).expect("not an image").1,vec![]);
+        // cut in the middle of the image
+        let (salvaged,found) = salvage(&normal[0..normal.len()/2],0).expect("salvage failed");
+        assert!(matches!(found[..],[Damage::Truncated { tracks }] if tracks > 0));
+        assert!(normal.starts_with(&salvaged[0..salvaged.len()-4]));
+        assert_eq!(fix_crc(&salvaged,true).expect("not an image").1,vec![]);
+        // damaged compressed data still gives a usable image
+        let mut damaged = compressed.clone();
+        damaged[compressed.len()/2] ^= 0x55;
+        let (salvaged,found) = salvage(&damaged,0).expect("salvage failed");
+        assert!(!found.is_empty());
+        assert_eq!(fix_crc(&salvaged,true).expect("not an image").1,vec![]);
+    }
+}
+
+#[test]
+fn truncated_images() {
+    for path in ["tests/td105","tests/td215"] {
+        let compressed = std::fs::read(path.to_string() + ".adv.td0").expect("could not read test file");
+        let normal = std::fs::read(path.to_string() + ".norm.td0").expect("could not read test file");
+        let (recovered,whole) = recover(&normal,None,0).expect("recovery failed");
+        assert_eq!(recovered,normal);
+        assert!(whole.complete && whole.tracks > 0 && whole.sectors >= whole.tracks);
+        let (recovered,report) = recover(&compressed,Some(80),0).expect("recovery failed");
+        assert_eq!(report,whole);
+        let trim = Options { trim_padding: true, ..STD_OPTIONS };
+        let mut trimmed = Cursor::new(Vec::new());
+        expand_with_options(&mut Cursor::new(&compressed),&mut trimmed,&trim).expect("expansion failed");
+        assert_eq!(expand_slice(&recovered).expect("expansion failed"),trimmed.into_inner());
+        // cut in the middle, first without and then with padding
+        let (recovered,report) = recover(&compressed[0..compressed.len()/2],None,0).expect("recovery failed");
+        assert!(!report.complete && report.tracks > 0 && report.tracks < whole.tracks);
+        assert_eq!(report.padded_tracks,0);
+        let recovered = expand_slice(&recovered).expect("expansion failed");
+        assert!(normal[12..].starts_with(&recovered[12..recovered.len()-4]));
+        let heads = normal[9] as usize;
+        assert_eq!(whole.tracks % heads,0);
+        let (recovered,report) = recover(&normal[0..normal.len()/2],Some((whole.tracks / heads) as u8),0xe5).expect("recovery failed");
+        assert_eq!(report.tracks + report.padded_tracks,whole.tracks);
+        assert!(report.dropped_bytes > 0 && report.missing_bytes > 0);
+        assert_eq!(fix_crc(&recovered,true).expect("not an image").1,vec![]);
+    }
+    assert!(recover(b"TD0",None,0).is_err());
+}
+
+#[test]
+fn recover_odd_heads() {
+    // image cut off after one track of a single 128 byte sector
+    let cut_image = |cylinder: u8,head: u8| {
+        let mut image = b"TD\x00\x00\x15\x00\x00\x00\x00\x01".to_vec();
+        image.extend_from_slice(&u16::to_le_bytes(crc16(0,&image)));
+        image.extend_from_slice(&[1,cylinder,head,crc16(0,&[1,cylinder,head]) as u8]);
+        image.extend_from_slice(&[cylinder,head & 0x7f,1,0,0,crc16(0,&[0;128]) as u8,129,0,0]);
+        image.extend_from_slice(&[0;128]);
+        image.extend_from_slice(&[1,cylinder + 1]);
+        image
+    };
+    // an FM track is padded on the same head, and the padding is FM too
+    let (recovered,report) = recover(&cut_image(0,0x80),Some(3),0xe5).expect("recovery failed");
+    assert_eq!((report.tracks,report.padded_tracks),(1,2));
+    let image = parse_image(&recovered).expect("not an image");
+    assert_eq!(image.tracks.iter().map(|t| (t.header.cylinder,t.header.head)).collect::<Vec<_>>(),vec![(0,0x80),(1,0x80),(2,0x80)]);
+    assert!(image.tracks.iter().flat_map(|t| &t.sectors).all(|s| s.header.head == 0));
+    assert_eq!(fix_crc(&recovered,true).expect("not an image").1,vec![]);
+    // a bogus head byte makes no more than 2 sides
+    let (_,report) = recover(&cut_image(0,0xff),Some(3),0xe5).expect("recovery failed");
+    assert_eq!(report.padded_tracks,4);
+    // there is no cylinder after 255
+    let (_,report) = recover(&cut_image(254,0),Some(255),0xe5).expect("recovery failed");
+    assert_eq!(report.padded_tracks,0);
+    let mut image = cut_image(254,0);
+    image[14] = 255;
+    image[15] = crc16(0,&[1,255,0]) as u8;
+    let (_,report) = recover(&image,Some(255),0xe5).expect("recovery failed");
+    assert_eq!(report.padded_tracks,0);
+}
+
+#[test]
+fn tiny_inputs() {
+    let mut header = b"TD\x00\x00\x15\x00\x00\x00\x00\x01".to_vec();
+    header.extend_from_slice(&u16::to_le_bytes(crc16(0,&header)));
+    for len in 0..5 {
+        let normal = [header.clone(),b"abcd"[0..len].to_vec()].concat();
+        let compressed = compress_slice(&normal).expect("compression failed");
+        assert_eq!(expand_slice(&compressed).expect("expansion failed"),normal);
+    }
+    for len in 0..12 {
+        match expand_slice(&compress_slice(&header).expect("compression failed")[0..len]) {
+            Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { missing_bits, .. }) if *missing_bits == 96 - 8*len as u64)),
+            Ok(_) => panic!("truncation was not detected")
+        }
+    }
+}
+
 #[test]
 fn compression_works() {
-    let mut normal_header = "TD0123456789".as_bytes().to_vec();
+    let mut normal_header = "TD01\x153456789".as_bytes().to_vec();
     let normal_data = "I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes().to_vec();
     let crc = u16::to_le_bytes(crc16(0,&normal_header[0..10]));
     normal_header[10..12].copy_from_slice(&crc);
 
-    let mut advanced_header = "td0123456789".as_bytes().to_vec();
+    let mut advanced_header = "td01\x153456789".as_bytes().to_vec();
     let advanced_data = "EA EB 3D BF 9C 4E FE 1E 16 EA 34 09 1C 0D C0 8C 02 FC 3F 77 3F 57 20 17 7F 1F 5F BF C6 AB 7F A5 AF FE 4C 39 96";
     let crc = u16::to_le_bytes(crc16(0,&advanced_header[0..10]));
     advanced_header[10..12].copy_from_slice(&crc);
@@ -150,10 +2163,553 @@ fn compression_works() {
 
 #[test]
 fn invertibility() {
-    let mut test_data = "TD0123456789I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes().to_vec();
+    let mut test_data = "TD01\x153456789I am Sam. Sam I am. I do not like this Sam I am.\n".as_bytes().to_vec();
     let crc = u16::to_le_bytes(crc16(0,&test_data[0..10]));
     test_data[10..12].copy_from_slice(&crc);
     let compressed = compress_slice(&test_data).expect("compression failed");
     let expanded = expand_slice(&compressed).expect("expansion failed");
     assert_eq!(test_data.to_vec(),expanded);
-}
\ No newline at end of file
+}
+#[test]
+fn lazy_expansion() {
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let expected = expand_slice(&compressed).expect("expansion failed");
+        let mut img = expand_lazy(Cursor::new(&compressed)).expect("could not open image");
+        let mut buf = vec![0;100];
+        img.seek(SeekFrom::Start(16350)).expect("seek failed");
+        img.read_exact(&mut buf).expect("read failed");
+        assert_eq!(buf,expected[16350..16450]);
+        img.seek(SeekFrom::Start(5)).expect("seek failed");
+        img.read_exact(&mut buf).expect("read failed");
+        assert_eq!(buf,expected[5..105]);
+        assert_eq!(img.seek(SeekFrom::End(-10)).expect("seek failed"),expected.len() as u64 - 10);
+        let mut all = Vec::new();
+        img.rewind().expect("seek failed");
+        img.read_to_end(&mut all).expect("read failed");
+        assert_eq!(all,expected);
+    }
+    // errors keep their kind
+    let compressed = std::fs::read("tests/td105.adv.td0").expect("could not read test file");
+    let mut img = expand_lazy(Cursor::new(&compressed[0..compressed.len()/2])).expect("could not open image");
+    assert_eq!(img.read_to_end(&mut Vec::new()).expect_err("image is truncated").kind(),std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn lazy_expansion_in_order() {
+    /// counts the reads that begin at the start of the compressed data
+    struct Restarts {
+        inner: Cursor<Vec<u8>>,
+        count: usize
+    }
+    impl Read for Restarts {
+        fn read(&mut self,buf: &mut [u8]) -> Result<usize,std::io::Error> {
+            if self.inner.position() == 12 {
+                self.count += 1;
+            }
+            self.inner.read(buf)
+        }
+    }
+    impl Seek for Restarts {
+        fn seek(&mut self,pos: SeekFrom) -> Result<u64,std::io::Error> {
+            self.inner.seek(pos)
+        }
+    }
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let expected = expand_slice(&compressed).expect("expansion failed");
+        assert!(expected.len() as u64 > 3*CACHE_BLOCK_SIZE);
+        let mut img = expand_lazy(Restarts { inner: Cursor::new(compressed), count: 0 }).expect("could not open image");
+        let mut all = Vec::new();
+        img.read_to_end(&mut all).expect("read failed");
+        assert_eq!(all,expected);
+        // each block picks up where the last one stopped, only the first starts at the beginning
+        assert_eq!(img.into_inner().count,1);
+    }
+}
+
+#[test]
+fn lazy_expansion_indexed() {
+    let mut indexes = Vec::new();
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let expected = expand_slice(&std::fs::read(path).expect("could not read test file")).expect("expansion failed");
+        let mut compressed = Cursor::new(Vec::new());
+        let (_,_,index) = compress_indexed(&mut Cursor::new(&expected),&mut compressed,1000).expect("compression failed");
+        let compressed = compressed.into_inner();
+        assert_eq!(compressed,compress_slice(&expected).expect("compression failed"));
+        // read the blocks backwards, so none continues from the one before
+        let mut img = expand_lazy_indexed(Cursor::new(&compressed),index.clone()).expect("could not open image");
+        let mut buf = vec![0;50];
+        for pos in (0..expected.len() - 50).step_by(3001).rev() {
+            img.seek(SeekFrom::Start(pos as u64)).expect("seek failed");
+            img.read_exact(&mut buf).expect("read failed");
+            assert_eq!(buf,expected[pos..pos + 50]);
+        }
+        indexes.push((compressed,index));
+    }
+    // the index has to be for the version of the image
+    assert!(expand_lazy_indexed(Cursor::new(&indexes[0].0),indexes[1].1.clone()).is_err());
+}
+
+#[test]
+fn volumes() {
+    let compressed = std::fs::read("tests/td105.adv.td0").expect("could not read test file");
+    let original = read_volume(&mut Cursor::new(&compressed)).expect("no volume");
+    assert_eq!(original,Volume { sequence: 0, check_signature: 0x1a });
+    assert_eq!(parse_image(&compressed).unwrap().volume(),original);
+    // the check signature can be set while converting, the rest of the image is the same
+    let opt = Options { check_signature: Some(0x5a), ..STD_OPTIONS };
+    let mut normal = Cursor::new(Vec::new());
+    expand_with_options(&mut Cursor::new(&compressed),&mut normal,&opt).expect("expansion failed");
+    let normal = normal.into_inner();
+    assert_eq!(read_volume(&mut Cursor::new(&normal)).unwrap().check_signature,0x5a);
+    assert_eq!(normal[12..],expand_slice(&compressed).unwrap()[12..]);
+    let mut again = Cursor::new(Vec::new());
+    compress_with_options(&mut Cursor::new(&normal),&mut again,&Options { check_signature: Some(0x1a), ..STD_OPTIONS }).expect("compression failed");
+    assert_eq!(again.into_inner(),compressed);
+    let mut builder = Builder::new(Version::new(21).unwrap(),0,1,1);
+    builder.set_volume(Volume { sequence: 1, check_signature: 0x77 });
+    let image = builder.build().to_normal().unwrap();
+    assert_eq!(read_volume(&mut Cursor::new(&image)).unwrap(),Volume { sequence: 1, check_signature: 0x77 });
+    assert_eq!(fix_crc(&image,true).unwrap().1,vec![]);
+}
+
+#[test]
+fn volume_sets() {
+    for path in ["tests/td105.adv.td0","tests/td215.norm.td0"] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let set = split_volumes(&image,20000).expect("split failed");
+        assert_eq!(set.len(),(image.len() - 12).div_ceil(20000 - 12));
+        for (sequence,volume) in set.iter().enumerate() {
+            assert!(volume.len() <= 20000);
+            let header = read_header(&mut Cursor::new(volume)).expect("no header");
+            assert!(header.crc_ok());
+            assert_eq!(header.volume(),Volume { sequence: sequence as u8, check_signature: image[3] });
+        }
+        assert_eq!(join_volumes(&set).expect("join failed"),image);
+        // out of order, or from another set
+        assert!(join_volumes(&[&set[1],&set[0]]).is_err());
+        let other = split_volumes(&Td0Header { check_signature: 0x55, ..Td0Header::parse(&image).unwrap() }.to_bytes(),20000).unwrap();
+        assert!(matches!(join_volumes(&[&set[0],&other[0]]).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::FileFormatMismatch)));
+        let mut damaged = set[1].clone();
+        damaged[10] ^= 1;
+        assert!(matches!(join_volumes(&[&set[0],&damaged]).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum)));
+        assert!(matches!(split_volumes(&set[1],20000).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::FileFormatMismatch)));
+        assert!(matches!(split_volumes(&image,1000).unwrap_err().downcast_ref::<crate::Error>(),Some(crate::Error::FileTooLarge)));
+    }
+    assert_eq!(volume_path("disk.TD0",1),PathBuf::from("disk.TD1"));
+    assert_eq!(volume_path("dir/disk.td0",9),PathBuf::from("dir/disk.td9"));
+    assert_eq!(volume_path("disk",0),PathBuf::from("disk.td0"));
+}
+
+#[test]
+fn headers() {
+    for (path,advanced,check_signature,drive_type) in [("tests/td105.adv.td0",true,0x1a,0x0f),("tests/td215.norm.td0",false,0x07,1)] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let header = read_header(&mut Cursor::new(&image)).expect("no header");
+        assert_eq!((header.advanced,header.check_signature,header.drive_type,header.sides),(advanced,check_signature,drive_type,2));
+        assert!(header.crc_ok());
+        assert_eq!(header.to_bytes(),image[0..12]);
+        assert_eq!(Td0Header::parse(&image).unwrap(),header);
+        assert_eq!(header.volume(),read_volume(&mut Cursor::new(&image)).unwrap());
+        assert_eq!(parse_image(&image).unwrap().header,header);
+        let mangled = Td0Header { crc: header.crc ^ 1, ..header };
+        assert!(!mangled.crc_ok());
+        assert_eq!(mangled.to_bytes(),image[0..12]);
+    }
+    assert!(matches!(Td0Header::parse(b"TD"),Err(crate::Error::FileFormatMismatch)));
+    assert!(matches!(Td0Header::parse(b"XD\x00\x00\x15\x00\x01\x00\x00\x02\x00\x00"),Err(crate::Error::FileFormatMismatch)));
+    assert!(matches!(Td0Header::parse(b"TD\x00\x00\x1e\x00\x01\x00\x00\x02\x00\x00"),Err(crate::Error::UnsupportedVersion(30))));
+}
+
+#[test]
+fn versions() {
+    for (path,major,minor) in [("tests/td105.adv.td0",1,1),("tests/td215.adv.td0",2,1)] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let version = read_version(&mut Cursor::new(&compressed)).expect("no version");
+        assert_eq!((version.major(),version.minor()),(major,minor));
+        assert_eq!(version.to_string(),format!("{}.{}",major,minor));
+        assert_eq!(expand_lazy(Cursor::new(&compressed)).expect("open failed").version(),version);
+        let mut unknown = compressed.clone();
+        unknown[4] = 30;
+        let crc = crc16(0,&unknown[0..10]).to_le_bytes();
+        unknown[10..12].copy_from_slice(&crc);
+        match expand_slice(&unknown) {
+            Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::UnsupportedVersion(30)))),
+            Ok(_) => panic!("version was not checked")
+        }
+        assert!(read_version(&mut Cursor::new(&unknown)).is_err());
+    }
+}
+
+#[test]
+fn padding() {
+    let trim = Options { trim_padding: true, ..STD_OPTIONS };
+    for path in ["tests/td105","tests/td215"] {
+        let compressed = std::fs::read(path.to_string() + ".adv.td0").expect("could not read test file");
+        let normal = std::fs::read(path.to_string() + ".norm.td0").expect("could not read test file");
+        let mut trimmed = Cursor::new(Vec::new());
+        let (_,out_size) = expand_with_options(&mut Cursor::new(&compressed),&mut trimmed,&trim).expect("expansion failed");
+        assert_eq!(out_size,normal.len() as u64);
+        // the test images were made separately, so only the data is the same
+        assert_eq!(trimmed.get_ref()[12..],normal[12..]);
+        // keeping the padding gives back the original
+        let padded = expand_slice(&compressed).expect("expansion failed");
+        assert!(padded.len() >= normal.len());
+        assert_eq!(compress_slice(&padded).expect("compression failed"),compressed);
+        // zeros added after trimming do not depend on the compressed data
+        for (padding,len) in [(Padding::AtLeast(5),normal.len() + 5),(Padding::Boundary(0x1000),0xd000),(Padding::Boundary(0),normal.len())] {
+            let opt = Options { padding, ..trim };
+            let mut out = Cursor::new(Vec::new());
+            let (_,out_size) = expand_with_options(&mut Cursor::new(&compressed),&mut out,&opt).expect("expansion failed");
+            assert_eq!((out_size,out.get_ref().len()),(len as u64,len));
+            assert_eq!(out.get_ref()[12..normal.len()],normal[12..]);
+            assert!(out.get_ref()[normal.len()..].iter().all(|b| *b==0));
+            let mut chunked = Vec::new();
+            expand_chunked_with_options(&mut Cursor::new(&compressed),0x1000,&opt,|chunk| chunked.extend_from_slice(chunk)).expect("expansion failed");
+            assert_eq!(&chunked,out.get_ref());
+        }
+        // padding that is kept counts toward the minimum
+        let opt = Options { padding: Padding::AtLeast(2), ..STD_OPTIONS };
+        let mut out = Cursor::new(Vec::new());
+        expand_with_options(&mut Cursor::new(&compressed),&mut out,&opt).expect("expansion failed");
+        assert_eq!(out.get_ref()[0..padded.len()],padded);
+        assert_eq!(out.get_ref().len(),usize::max(padded.len(),normal.len() + 2));
+    }
+}
+
+#[test]
+fn bad_header_crc() {
+    let force = Options { tolerate_bad_crc: true, ..STD_OPTIONS };
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let normal = expand_slice(&compressed).expect("expansion failed");
+        let mut damaged = compressed.clone();
+        damaged[10] ^= 0xff;
+        match expand_slice(&damaged) {
+            Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum))),
+            Ok(_) => panic!("bad CRC was not detected")
+        }
+        let mut ans = Cursor::new(Vec::new());
+        let (res,warnings) = diagnostics::collect(|| expand_with_options(&mut Cursor::new(&damaged),&mut ans,&force));
+        res.expect("expansion failed");
+        assert!(matches!(warnings[..],[Warning::HeaderCrc { stored, computed }] if stored != computed));
+        assert_eq!(ans.into_inner(),normal);
+        let mut damaged = normal.clone();
+        damaged[11] ^= 0xff;
+        assert!(compress_slice(&damaged).is_err());
+        let mut ans = Cursor::new(Vec::new());
+        compress_with_options(&mut Cursor::new(&damaged),&mut ans,&force).expect("compression failed");
+        assert_eq!(ans.into_inner(),compressed);
+    }
+}
+
+#[test]
+fn chunked_expansion() {
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let expected = expand_slice(&compressed).expect("expansion failed");
+        let mut all = Vec::new();
+        let mut largest = 0;
+        let (_,out_size) = expand_chunked(&mut Cursor::new(&compressed),1000,|chunk| {
+            largest = usize::max(largest,chunk.len());
+            all.extend_from_slice(chunk);
+        }).expect("expansion failed");
+        assert_eq!(largest,1000);
+        assert_eq!(out_size,expected.len() as u64);
+        assert_eq!(all,expected);
+    }
+}
+
+#[test]
+fn transcode_images() {
+    use crate::Method;
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let compressed = std::fs::read(path).expect("could not read test file");
+        let normal = expand_slice(&compressed).expect("expansion failed");
+        // advanced image to a plain LZHUF stream of the normal image, and back again
+        let mut lzh = Cursor::new(Vec::new());
+        let (in_size,out_size) = Method::Td0.transcode(&Method::LzssHuff(lzss_huff::STD_OPTIONS),&mut Cursor::new(&compressed),&mut lzh)
+            .expect("transcode failed");
+        assert_eq!(in_size,compressed.len() as u64);
+        assert_eq!(out_size,lzh.get_ref().len() as u64);
+        assert_eq!(lzss_huff::expand_slice(lzh.get_ref(),&lzss_huff::STD_OPTIONS).expect("expansion failed"),normal);
+        let mut td = Cursor::new(Vec::new());
+        Method::LzssHuff(lzss_huff::STD_OPTIONS).transcode(&Method::Td0,&mut Cursor::new(lzh.get_ref()),&mut td)
+            .expect("transcode failed");
+        assert_eq!(td.into_inner(),compress_slice(&normal).expect("compression failed"));
+    }
+    // a bad source is an error even though the compressor finishes
+    let mut out = Cursor::new(Vec::new());
+    assert!(Method::Td0.transcode(&Method::Lzw(lzw::STD_OPTIONS),&mut Cursor::new(b"not an image"),&mut out).is_err());
+}
+
+#[test]
+fn verify_images() {
+    for path in ["tests/td105.adv.td0","tests/td215.adv.td0"] {
+        let mut compressed = std::fs::read(path).expect("could not read test file");
+        let expected = expand_slice(&compressed).expect("expansion failed");
+        let (in_size,out_size,complete) = verify(&mut Cursor::new(&compressed)).expect("verify failed");
+        assert_eq!(in_size,compressed.len() as u64);
+        assert_eq!(out_size,expected.len() as u64);
+        assert!(complete);
+        compressed[10] ^= 1;
+        match verify(&mut Cursor::new(&compressed)) {
+            Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum))),
+            Ok(_) => panic!("bad CRC was not detected")
+        }
+    }
+    // v1 images are in chunks of known length
+    let compressed = std::fs::read("tests/td105.adv.td0").expect("could not read test file");
+    let (_,_,complete) = verify(&mut Cursor::new(&compressed[0..compressed.len()-100])).expect("verify failed");
+    assert!(!complete);
+    // truncation means part of the disk is lost, so expanding it is an error
+    match expand_slice(&compressed[0..compressed.len()-100]) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::TruncatedInput { .. }))),
+        Ok(_) => panic!("truncation was not detected")
+    }
+}
+
+#[test]
+fn sector_reports() {
+    for path in ["tests/td105.norm.td0","tests/td215.adv.td0"] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let report = verify_sectors(&image).expect("could not verify sectors");
+        assert_eq!(report.mismatches(),vec![]);
+        assert_eq!(report.tracks.len(),82);
+        assert_eq!(report.sectors(),738);
+        assert_eq!(report.count(Encoding::Raw),62);
+        assert_eq!(report.count(Encoding::Repeat),646);
+        assert_eq!(report.count(Encoding::Rle),30);
+    }
+    let normal = std::fs::read("tests/td105.norm.td0").expect("could not read test file");
+    let mut mangled = normal.clone();
+    mangled[0x0f] ^= 0xff; // first track CRC
+    mangled[0x15] ^= 0xff; // first sector CRC
+    mangled[0x1d] ^= 0xff; // data of the first sector
+    let report = verify_sectors(&mangled).expect("could not verify sectors");
+    let mismatches = report.mismatches();
+    assert_eq!(mismatches.len(),2);
+    assert_eq!(mismatches[0],CrcMismatch::Track { cylinder: 0, head: 0, stored: normal[0x0f] ^ 0xff, computed: normal[0x0f] });
+    assert!(matches!(mismatches[1],CrcMismatch::Sector { cylinder: 0, head: 0, sector: 1, .. }));
+    assert!(!report.tracks[0].sectors[0].crc_ok());
+    assert!(report.tracks[0].sectors[1].crc_ok());
+    mangled[10] ^= 1;
+    match verify_sectors(&mangled) {
+        Err(e) => assert!(matches!(e.downcast_ref::<crate::Error>(),Some(crate::Error::BadChecksum))),
+        Ok(_) => panic!("bad header CRC was not detected")
+    }
+}
+
+#[test]
+fn comments() {
+    let compressed = std::fs::read("tests/td105.adv.td0").expect("could not read test file");
+    let normal = std::fs::read("tests/td105.norm.td0").expect("could not read test file");
+    assert_eq!(get_comment(&compressed).expect("could not get comment"),None);
+    let comment = Comment::from_text("imaged from the original\nby a careful person\n",[124,2,29,13,5,0]);
+    assert_eq!(comment.data,b"imaged from the original\0by a careful person\0");
+    assert_eq!(comment.date().to_string(),"2024-03-29 13:05:00");
+    assert_eq!(comment.date().to_bytes(),Some(comment.timestamp));
+    assert_eq!(Timestamp { year: 1899, ..comment.date() }.to_bytes(),None);
+    let epoch = std::time::UNIX_EPOCH + std::time::Duration::from_secs(951827696);
+    assert_eq!(Timestamp::from_system_time(epoch).to_string(),"2000-02-29 12:34:56");
+    for image in [&compressed,&normal] {
+        let with = set_comment(image,Some(&comment)).expect("could not set comment");
+        assert_eq!(&with[0..2],&image[0..2]);
+        let got = get_comment(&with).expect("could not get comment").expect("comment is missing");
+        assert_eq!(got,comment);
+        assert_eq!(got.text(),"imaged from the original\nby a careful person");
+        let without = set_comment(&with,None).expect("could not strip comment");
+        assert_eq!(&without,image);
+    }
+    // comment is covered by its CRC
+    let mut with = set_comment(&normal,Some(&comment)).expect("could not set comment");
+    with[25] ^= 1;
+    assert!(get_comment(&with).is_err());
+}
+
+#[test]
+fn crc_repair() {
+    for path in ["tests/td105.norm.td0","tests/td215.adv.td0"] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let (fixed,found) = fix_crc(&image,true).expect("could not check CRC");
+        assert!(found.is_empty());
+        assert_eq!(fixed,image);
+    }
+    let normal = std::fs::read("tests/td105.norm.td0").expect("could not read test file");
+    let mut mangled = normal.clone();
+    mangled[10] ^= 0xff;
+    mangled[0x0f] ^= 0xff; // first track CRC
+    mangled[0x15] ^= 0xff; // first sector CRC
+    let (fixed,found) = fix_crc(&mangled,false).expect("could not check CRC");
+    assert_eq!(found.len(),1);
+    assert!(matches!(found[0],CrcMismatch::Header { .. }));
+    assert_eq!(fixed[10..12],normal[10..12]);
+    let (fixed,found) = fix_crc(&mangled,true).expect("could not check CRC");
+    assert_eq!(found.len(),3);
+    assert_eq!(found[1],CrcMismatch::Track { cylinder: 0, head: 0, stored: normal[0x0f] ^ 0xff, computed: normal[0x0f] });
+    assert!(matches!(found[2],CrcMismatch::Sector { cylinder: 0, head: 0, .. }));
+    assert_eq!(fixed,normal);
+    // advanced image with a bad header CRC is fixed without recompressing
+    let compressed = std::fs::read("tests/td215.adv.td0").expect("could not read test file");
+    let mut mangled = compressed.clone();
+    mangled[11] ^= 1;
+    assert!(expand_slice(&mangled).is_err());
+    let (fixed,found) = fix_crc(&mangled,true).expect("could not check CRC");
+    assert_eq!(found.len(),1);
+    assert_eq!(fixed,compressed);
+}
+
+#[test]
+fn sector_parsing() {
+    for path in ["tests/td105.norm.td0","tests/td215.adv.td0"] {
+        let image = std::fs::read(path).expect("could not read test file");
+        let parsed = parse_image(&image).expect("could not parse image");
+        assert_eq!(parsed.tracks.len(),82);
+        let sectors: Vec<&Sector> = parsed.tracks.iter().flat_map(|track| &track.sectors).collect();
+        assert_eq!(sectors.len(),738);
+        assert_eq!(sectors.iter().filter(|s| matches!(s.data,Some(SectorData::Repeat { .. }))).count(),646);
+        assert_eq!(sectors.iter().filter(|s| matches!(s.data,Some(SectorData::Rle(_)))).count(),30);
+        for sector in &sectors {
+            let data = sector.bytes().expect("sector has no data");
+            assert_eq!(data.len(),sector.header.size());
+            assert_eq!(crc16(0,&data) as u8,sector.header.crc);
+        }
+        // putting it back together gives the normal image
+        let normal = parsed.to_normal().expect("could not rebuild image");
+        assert_eq!(normal,normal_image(&image).unwrap().0);
+        assert_eq!(fix_crc(&normal,true).expect("could not check CRC").1,vec![]);
+    }
+    let normal = std::fs::read("tests/td105.norm.td0").expect("could not read test file");
+    let mut parsed = parse_image(&normal).expect("could not parse image");
+    assert_eq!(parsed.to_normal().unwrap(),normal);
+    // change a sector
+    let sector = &mut parsed.tracks[3].sectors[2];
+    let mut data = sector.bytes().unwrap();
+    data[0..5].copy_from_slice(b"hello");
+    sector.set_bytes(&data);
+    assert_eq!(sector.data,Some(SectorData::Raw(data.clone())));
+    let changed = parsed.to_normal().expect("could not rebuild image");
+    assert_eq!(fix_crc(&changed,true).expect("could not check CRC").1,vec![]);
+    assert_eq!(parse_image(&changed).unwrap().tracks[3].sectors[2].bytes(),Some(data));
+    // encodings
+    let rle = SectorData::Rle(vec![Fragment::Literal(b"abc".to_vec()),Fragment::Repeat { pattern: b"xy".to_vec(), count: 3 }]);
+    let block = rle.to_block().unwrap();
+    assert_eq!(block,b"\x02\x00\x03abc\x01\x03xy");
+    assert_eq!(SectorData::parse(&block,9).unwrap(),rle);
+    assert_eq!(rle.decode(),b"abcxyxyxy");
+    assert_eq!(SectorData::encode(&[0xe5;512]),SectorData::Repeat { pattern: [0xe5,0xe5], count: 256 });
+    assert!(SectorData::Rle(vec![Fragment::Repeat { pattern: b"xyz".to_vec(), count: 3 }]).to_block().is_err());
+    let flags = SectorFlags(0x22);
+    assert!(flags.crc_error() && flags.no_data() && !flags.has_data());
+}
+
+#[test]
+fn building_images() {
+    let text = b"I am Sam. Sam I am. I do not like this Sam I am.\n".repeat(11)[0..512].to_vec();
+    let runs = [vec![0xe5;200],b"boot".to_vec(),vec![0;308]].concat();
+    let mut builder = Builder::new(Version::new(21).unwrap(),2,1,2);
+    builder.set_comment(Some(Comment::from_text("built",[124,0,1,0,0,0])));
+    for cylinder in 0..2 {
+        for head in 0..2 {
+            builder.add_track(cylinder,head);
+            builder.add_sector(1,&[0xe5;512]).expect("could not add sector");
+            builder.add_sector(2,&text).expect("could not add sector");
+            builder.add_sector(3,&runs).expect("could not add sector");
+            let header = SectorHeader { cylinder, head, sector: 4, size_code: 2, flags: SectorFlags(0), crc: 0 };
+            builder.add_sector_with(header,None).expect("could not add sector");
+        }
+    }
+    assert!(builder.add_sector(5,&[0;100]).is_err());
+    let normal = builder.build().to_normal().expect("could not build image");
+    assert_eq!(fix_crc(&normal,true).expect("could not check CRC").1,vec![]);
+    let parsed = parse_image(&normal).expect("could not parse image");
+    assert_eq!(parsed.comment.unwrap().text(),"built");
+    assert_eq!(parsed.tracks.len(),4);
+    let sectors = &parsed.tracks[3].sectors;
+    assert_eq!(sectors[0].data,Some(SectorData::Repeat { pattern: [0xe5,0xe5], count: 256 }));
+    assert_eq!(sectors[1].data,Some(SectorData::Raw(text.clone())));
+    assert!(matches!(sectors[2].data,Some(SectorData::Rle(_))));
+    assert_eq!(sectors[2].bytes(),Some(runs));
+    assert!(sectors[3].header.flags.no_data());
+    assert_eq!(expand_slice(&builder.build().to_advanced().unwrap()).unwrap(),normal);
+    // rebuilding a real image gives back the same sectors
+    let original = parse_image(&std::fs::read("tests/td105.norm.td0").unwrap()).unwrap();
+    let mut builder = Builder::new(Version::new(11).unwrap(),0,0x0f,2);
+    for track in &original.tracks {
+        builder.add_track(track.header.cylinder,track.header.head);
+        for sector in &track.sectors {
+            builder.add_sector_with(sector.header,sector.bytes().as_deref()).expect("could not add sector");
+        }
+    }
+    let rebuilt = builder.build();
+    for (a,b) in rebuilt.tracks.iter().zip(&original.tracks) {
+        assert_eq!(a.header,b.header);
+        assert!(a.sectors.iter().zip(&b.sectors).all(|(a,b)| a.header==b.header && a.bytes()==b.bytes()));
+    }
+    assert_eq!(fix_crc(&rebuilt.to_normal().unwrap(),true).unwrap().1,vec![]);
+}
+
+#[test]
+fn tree_validation() {
+    let dir = tempfile::tempdir().expect("no temp dir");
+    let sub = dir.path().join("sub");
+    std::fs::create_dir(&sub).unwrap();
+    std::fs::copy("tests/td105.adv.td0",dir.path().join("td105.adv.td0")).unwrap();
+    std::fs::copy("tests/td215.norm.td0",sub.join("TD215.TD0")).unwrap();
+    let mut bad_sector = std::fs::read("tests/td105.norm.td0").unwrap();
+    bad_sector[0x15] ^= 0xff;
+    std::fs::write(sub.join("bad_sector.td0"),&bad_sector).unwrap();
+    let compressed = std::fs::read("tests/td215.adv.td0").unwrap();
+    std::fs::write(dir.path().join("cut.td0"),&compressed[0..1000]).unwrap();
+    std::fs::write(dir.path().join("notes.txt"),b"not an image").unwrap();
+    let tree = validate_tree(dir.path(),4).expect("could not scan");
+    let names: Vec<_> = tree.files.iter().map(|file| file.path.strip_prefix(dir.path()).unwrap().to_path_buf()).collect();
+    assert_eq!(names,[Path::new("cut.td0"),Path::new("sub/TD215.TD0"),Path::new("sub/bad_sector.td0"),Path::new("td105.adv.td0")]);
+    assert_eq!(tree.files[0].result.as_ref().unwrap_err().kind(),std::io::ErrorKind::UnexpectedEof);
+    assert!(tree.files[1].is_ok() && tree.files[3].is_ok());
+    assert!(!tree.files[2].is_ok());
+    assert_eq!((tree.passed(),tree.failed()),(2,2));
+    assert_eq!(tree.sectors(),738 * 3);
+    assert_eq!(tree.mismatches(),1);
+    assert!(validate_tree(dir.path().join("missing"),4).is_err());
+}
+
+#[test]
+fn batch_conversion() {
+    let dir = tempfile::tempdir().expect("no temp dir");
+    let mut jobs = Vec::new();
+    for name in ["td105","td215"] {
+        jobs.push((Path::new("tests").join(format!("{}.adv.td0",name)),dir.path().join(format!("{}.norm.td0",name))));
+    }
+    jobs.push((Path::new("tests").join("missing.td0"),dir.path().join("missing.norm.td0")));
+    let results = convert_batch(&jobs,Direction::Expand,4);
+    assert_eq!(results[2].as_ref().unwrap_err().kind(),std::io::ErrorKind::NotFound);
+    let jobs: Vec<_> = jobs[0..2].iter().map(|(_,norm)| (norm.clone(),norm.with_extension("adv"))).collect();
+    for (i,res) in convert_batch(&jobs,Direction::Compress,4).iter().enumerate() {
+        assert_eq!(res.as_ref().expect("compression failed").1,std::fs::metadata(&jobs[i].1).unwrap().len());
+        let normal = std::fs::read(&jobs[i].0).unwrap();
+        assert_eq!(expand_slice(&std::fs::read(&jobs[i].1).unwrap()).expect("expansion failed"),normal);
+    }
+}
+
+#[test]
+fn malformed_input() {
+    // anything may come out of damaged images, but it must come back as a result
+    for name in ["td105","td215"] {
+        let compressed = std::fs::read(format!("tests/{}.adv.td0",name)).expect("could not read test file");
+        for corrupted in crate::tools::mutate::Mutations::new(&compressed,0x5eed).take(100) {
+            let _ = expand_slice(&corrupted);
+            let _ = verify(&mut Cursor::new(&corrupted));
+            if let Ok(mut img) = expand_lazy(Cursor::new(&corrupted)) {
+                let _ = img.seek(SeekFrom::Start(1000)).and_then(|_| img.read(&mut [0;100]));
+            }
+        }
+        let normal = std::fs::read(format!("tests/{}.norm.td0",name)).expect("could not read test file");
+        for corrupted in crate::tools::mutate::Mutations::new(&normal,0x5eed).take(100) {
+            let _ = fix_crc(&corrupted,true);
+            let _ = get_comment(&corrupted);
+            let _ = set_comment(&corrupted,None);
+        }
+    }
+}
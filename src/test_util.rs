@@ -0,0 +1,230 @@
+//! Round trip harness for checking a codec and its options, requires the `test-util` feature.
+//! Anyone adding a preset or a new set of options can use it to check that the data survives the round trip.
+//!
+//! ```rs
+//! use retrocompressor::*;
+//! let opt = lzw::Options { max_code_width: 10, ..lzw::Options::preset("unix_compress").unwrap() };
+//! test_util::assert_invertible(&Method::Lzw(opt.clone()));
+//! test_util::assert_reference(&Method::Lzw(opt),&expanded,&compressed);
+//! ```
+//!
+//! The corpora are generated from a seed, so a failure can be reproduced from the message alone.
+//! The assertions panic with a description of what went wrong, as `assert_eq!` does.
+
+use crate::Method;
+
+/// Kinds of data to generate
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Corpus {
+    /// words and punctuation, which compress well
+    Text,
+    /// uniformly random bytes, which do not compress
+    Binary,
+    /// runs of a repeated byte, long enough to need more than one match
+    Runs,
+    /// every pair of bytes exactly once (a de Bruijn sequence), so there are no matches of three
+    /// bytes, every symbol is equally likely, and an LZW dictionary fills with strings that are never used
+    WorstCase
+}
+
+/// Every kind of corpus
+pub const CORPORA: [Corpus;4] = [Corpus::Text,Corpus::Binary,Corpus::Runs,Corpus::WorstCase];
+
+/// Lengths that `assert_invertible` tries, from empty, through the edges of the LZSS lookahead
+/// and window and the usual LZW chunk, to enough to fill a dictionary
+pub const LENGTHS: [usize;11] = [0,1,2,3,4,60,61,4095,4096,4097,20000];
+
+/// seed `assert_invertible` uses
+const SEED: u64 = 0x5eed;
+
+const WORDS: [&str;16] = ["the","chaunt","of","priests","Mung","I","am","Sam","do","not","like","this","green","eggs","and","ham"];
+
+/// xorshift, the corpora need to be repeatable rather than random
+struct Rand(u64);
+
+impl Rand {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+impl Corpus {
+    /// Generate `len` bytes of this kind, the same bytes for the same seed
+    pub fn generate(&self,len: usize,seed: u64) -> Vec<u8> {
+        if *self == Self::WorstCase {
+            // the Lyndon words of length 1 and 2 in order, which wraps around to the start,
+            // the seed decides where in the cycle to begin
+            let mut cycle = Vec::with_capacity(0x10000);
+            for a in 0..=255u8 {
+                cycle.push(a);
+                for b in a as u16 + 1..=255 {
+                    cycle.extend_from_slice(&[a,b as u8]);
+                }
+            }
+            let start = (seed % 0x10000) as usize;
+            return cycle.iter().cycle().skip(start).take(len).copied().collect();
+        }
+        let mut rand = Rand(seed | 1);
+        let mut ans = Vec::with_capacity(len + 300);
+        while ans.len() < len {
+            match self {
+                Self::Text => {
+                    ans.extend_from_slice(WORDS[rand.next() as usize % WORDS.len()].as_bytes());
+                    ans.extend_from_slice(match rand.next() % 8 {
+                        0 => b". ",
+                        1 => b"\r\n",
+                        _ => b" "
+                    });
+                },
+                Self::Binary => ans.push(rand.next() as u8),
+                Self::Runs => {
+                    let count = 1 + rand.next() as usize % 300;
+                    ans.extend(std::iter::repeat_n(rand.next() as u8,count));
+                },
+                Self::WorstCase => unreachable!()
+            }
+        }
+        ans.truncate(len);
+        ans
+    }
+}
+
+/// The method with the offsets cleared, since they only move the data
+fn without_offsets(method: &Method) -> Method {
+    match method {
+        #[cfg(feature="lzw")]
+        Method::Lzw(opt) => Method::Lzw(crate::lzw::Options { in_offset: 0, out_offset: 0, ..opt.clone() }),
+        #[cfg(feature="lzss_huff")]
+        Method::LzssHuff(opt) => Method::LzssHuff(crate::lzss_huff::Options { in_offset: 0, out_offset: 0, ..opt.clone() }),
+        #[cfg(feature="td0")]
+        Method::Td0 => Method::Td0
+    }
+}
+
+/// Whether the zeros that pad the last byte can be taken for data.  Without a size header, `lzss_huff`
+/// cannot tell them from a short Huffman code, so a literal or few can turn up on the end.
+fn padding_decodes(method: &Method) -> bool {
+    match method {
+        #[cfg(feature="lzss_huff")]
+        Method::LzssHuff(opt) => !opt.header,
+        #[allow(unreachable_patterns)]
+        _ => false
+    }
+}
+
+/// Describe the first difference, which is more use than two dumps of the data
+fn same(what: &str,actual: &[u8],expected: &[u8]) -> Result<(),String> {
+    if actual != expected {
+        let i = actual.iter().zip(expected).take_while(|(a,b)| a==b).count();
+        return Err(format!("{}: {} bytes where {} were expected, first difference at byte {}",what,actual.len(),expected.len(),i));
+    }
+    Ok(())
+}
+
+fn check_round_trip(method: &Method,data: &[u8]) -> Result<(),String> {
+    let method = without_offsets(method);
+    let compressed = method.compress_slice(data)
+        .map_err(|e| format!("compressing {} bytes failed: {}",data.len(),e))?;
+    let mut streamed = std::io::Cursor::new(Vec::new());
+    method.compress(&mut std::io::Cursor::new(data),&mut streamed)
+        .map_err(|e| format!("compressing {} bytes as a stream failed: {}",data.len(),e))?;
+    same("stream compression",&streamed.into_inner(),&compressed)?;
+    let expanded = method.expand_slice(&compressed)
+        .map_err(|e| format!("expanding {} bytes back to {} failed: {}",compressed.len(),data.len(),e))?;
+    match padding_decodes(&method) && expanded.len() > data.len() && expanded.len() - data.len() < 8 {
+        true => same("expansion",&expanded[0..data.len()],data)?,
+        false => same("expansion",&expanded,data)?
+    }
+    let mut chunked = Vec::new();
+    method.expand_chunked(&mut std::io::Cursor::new(&compressed),0x1000,|chunk| chunked.extend_from_slice(chunk))
+        .map_err(|e| format!("expanding {} bytes in chunks failed: {}",compressed.len(),e))?;
+    same("chunked expansion",&chunked,&expanded)
+}
+
+fn check_reference(method: &Method,expanded: &[u8],compressed: &[u8]) -> Result<(),String> {
+    let method = without_offsets(method);
+    let actual = method.compress_slice(expanded)
+        .map_err(|e| format!("compressing {} bytes failed: {}",expanded.len(),e))?;
+    same("compression",&actual,compressed)?;
+    let actual = method.expand_slice(compressed)
+        .map_err(|e| format!("expanding {} bytes failed: {}",compressed.len(),e))?;
+    same("expansion",&actual,expanded)
+}
+
+/// Check that `method` expands what it compresses from `data` back to `data`, and that the
+/// slice, stream, and chunked functions all agree.  The offsets in the options are not used.
+/// For `lzss_huff` without a header, which has nothing to mark the end of the data, as many as 7
+/// bytes decoded from the padding in the last byte are allowed on the end, as with Teledisk.
+pub fn assert_round_trip(method: &Method,data: &[u8]) {
+    if let Err(msg) = check_round_trip(method,data) {
+        panic!("{}",msg);
+    }
+}
+
+/// Run `assert_round_trip` on every corpus at every one of `LENGTHS`.
+/// For LZW with fewer than 256 symbols, the bytes are reduced to the alphabet first.
+/// Panics for `Method::Td0`, which needs disk images, use `assert_round_trip` with a normal image.
+pub fn assert_invertible(method: &Method) {
+    let alphabet = match method {
+        #[cfg(feature="lzw")]
+        Method::Lzw(opt) => opt.max_symbol + 1,
+        #[cfg(feature="td0")]
+        Method::Td0 => panic!("TD0 needs disk images, use `assert_round_trip` with a normal image"),
+        #[allow(unreachable_patterns)]
+        _ => 256
+    };
+    for corpus in CORPORA {
+        for len in LENGTHS {
+            let data: Vec<u8> = corpus.generate(len,SEED).iter().map(|b| (*b as usize % alphabet) as u8).collect();
+            if let Err(msg) = check_round_trip(method,&data) {
+                panic!("{:?} corpus of {} bytes with seed {:#x}: {}",corpus,len,SEED,msg);
+            }
+        }
+    }
+}
+
+/// Check that `method` compresses `expanded` to exactly `compressed`, and expands it back,
+/// as with a reference vector made by a legacy program.  The offsets in the options are not used.
+pub fn assert_reference(method: &Method,expanded: &[u8],compressed: &[u8]) {
+    if let Err(msg) = check_reference(method,expanded,compressed) {
+        panic!("{}",msg);
+    }
+}
+
+#[test]
+fn presets_are_invertible() {
+    #[cfg(feature="lzw")]
+    for name in crate::lzw::PRESETS {
+        assert_invertible(&Method::Lzw(crate::lzw::Options::preset(name).unwrap()));
+    }
+    #[cfg(feature="lzss_huff")]
+    for name in crate::lzss_huff::PRESETS {
+        assert_invertible(&Method::LzssHuff(crate::lzss_huff::Options::preset(name).unwrap()));
+    }
+}
+
+#[test]
+fn corpora() {
+    for corpus in CORPORA {
+        assert_eq!(corpus.generate(5000,1),corpus.generate(5000,1));
+        assert_eq!(corpus.generate(5000,1).len(),5000);
+    }
+    // every pair once, so the sequence does not repeat a pair until it wraps around
+    let worst = Corpus::WorstCase.generate(0x10000,0x1234);
+    let pairs: std::collections::HashSet<&[u8]> = worst.windows(2).collect();
+    assert_eq!(pairs.len(),0xffff);
+}
+
+#[cfg(feature="lzss_huff")]
+#[test]
+fn reference_vectors() {
+    // made by LZHUF.C
+    let compressed = [0x18,0,0,0,0xde,0xef,0xb7,0xfc,0x0e,0x0c,0x70,0x13,0x85,0xc3,0xe2,0x71,0x64,0x81,0x19,0x60];
+    assert_reference(&Method::LzssHuff(crate::lzss_huff::STD_OPTIONS),b"12345123456789123456789\n",&compressed);
+    let res = check_reference(&Method::LzssHuff(crate::lzss_huff::STD_OPTIONS),b"12345123456789123456780\n",&compressed);
+    assert_eq!(res,Err("compression: 21 bytes where 20 were expected, first difference at byte 17".to_string()));
+}
+
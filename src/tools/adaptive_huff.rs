@@ -1,15 +1,23 @@
 //! Module to perform the adaptive Huffman coding.
 //! This is used by the `lzss_huff` module.
-//! This is supposed to perform the coding the same way as `LZHUF.C`,
+//! By default this is supposed to perform the coding the same way as `LZHUF.C`,
 //! see the `direct_ports` module for more on the legacy.
+//!
+//! Alternatively the tree can be updated using the FGK or Vitter algorithms.
+//! These start from a single "not yet transmitted" (NYT) node, and new symbols
+//! are sent as the NYT code followed by the symbol value in a fixed number of bits.
 
-use bit_vec::BitVec;
-use std::io::{Read,Write,Seek,SeekFrom,BufWriter,BufReader};
+use std::io::{BufRead,Write};
+use crate::BitOrder;
+use super::bit_writer::BitWriter;
+use super::bit_reader::BitReader;
 
 /// Tree used for both encoding and decoding.
 /// The tree is constantly updated during either operation.
+#[derive(Clone,PartialEq,Debug)]
 pub struct AdaptiveHuffmanTree {
     max_freq: usize,
+    rescale_divisor: usize,
     num_symb: usize,
     node_count: usize,
     root: usize,
@@ -20,19 +28,70 @@ pub struct AdaptiveHuffmanTree {
     /// index of the left son of the node in this slot, right son is found by incrementing by 1
     son: Vec<usize>,
     /// map from symbols (index) to leaves (value)
-    symb_map: Vec<usize>
+    symb_map: Vec<usize>,
+    /// starting frequencies, empty means all are 1
+    initial_freq: Vec<usize>,
+    /// number of times the tree was rebuilt since the last reset
+    rebuilds: u64
 }
 
+/// Algorithm used to update the tree after each symbol
+#[derive(Clone,Copy,PartialEq,Debug)]
+#[cfg_attr(feature="serde",derive(serde::Serialize,serde::Deserialize))]
+pub enum UpdateStrategy {
+    /// frequency-sorted node array with all symbols present from the start, as in `LZHUF.C`
+    Lzhuf,
+    /// Faller-Gallager-Knuth algorithm with NYT node
+    Fgk,
+    /// Vitter's algorithm Λ with NYT node
+    Vitter
+}
+
+/// Tree used for the FGK and Vitter algorithms.
+/// Nodes are identified by index, and separately carry an implicit number,
+/// which is the position in the ordering that the sibling property is based on.
+/// Exchanging numbers also exchanges positions in the tree.
+#[derive(Clone,PartialEq,Debug)]
+pub struct NytTree {
+    vitter: bool,
+    /// bits used to send a new symbol
+    symb_bits: u16,
+    root: usize,
+    nyt: usize,
+    weight: Vec<u64>,
+    parent: Vec<Option<usize>>,
+    /// [left,right] children of internal nodes
+    child: Vec<[usize;2]>,
+    /// symbol attached to a leaf, NYT and internal nodes have None
+    symbol: Vec<Option<usize>>,
+    /// map from symbols (index) to leaves (value)
+    leaf: Vec<Option<usize>>,
+    /// implicit number of each node
+    number: Vec<usize>,
+    /// map from implicit numbers (index) to nodes (value)
+    node_at: Vec<usize>
+}
+
+#[derive(Clone,PartialEq,Debug)]
+enum Tree {
+    Lzhuf(AdaptiveHuffmanTree),
+    Nyt(NytTree)
+}
+
+/// Copy of the tree at some point in the stream, the coder and decoder trees are the same after the same symbols
+#[derive(Clone,PartialEq,Debug)]
+pub struct TreeState(Tree);
+
 pub struct AdaptiveHuffmanCoder {
-    tree: AdaptiveHuffmanTree,
-    bits: BitVec,
-    ptr: usize,
+    tree: Tree,
+    bits: BitWriter
 }
 
 pub struct AdaptiveHuffmanDecoder {
-    tree: AdaptiveHuffmanTree,
-    bits: BitVec,
-    ptr: usize,
+    tree: Tree,
+    bits: BitReader,
+    /// bits consumed since the last reset
+    bits_read: u64
 }
 
 /// encoding table giving number of bits used to encode the
@@ -138,63 +197,133 @@ const D_CODE: [u8;256] = [
 	0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E, 0x3F,
 ];
 
+/// Largest depth a leaf can have in a tree with the sibling property and total weight `total`.
+/// Going up from a leaf, each ancestor weighs at least as much as the two below it, so the total
+/// is at least the Fibonacci number F(depth+1), even if the leaf's sibling is a zero weight NYT node.
+fn fibonacci_depth(total: u64) -> usize {
+    let (mut f1,mut f2) = (1u64,1u64);
+    let mut depth = 0;
+    while f2 <= total {
+        (f1,f2) = (f2,f1.saturating_add(f2));
+        depth += 1;
+    }
+    depth
+}
+
+/// Upper bound on the number of bits used to encode any one symbol, given that no more than
+/// `count` symbols have been encoded since the last reset.
+pub fn max_code_len(num_symbols: usize,strategy: UpdateStrategy,max_freq: usize,count: u64) -> usize {
+    match strategy {
+        // frequencies are rescaled before the total reaches `max_freq`
+        UpdateStrategy::Lzhuf => usize::min(fibonacci_depth(max_freq as u64),num_symbols - 1),
+        // a new symbol is sent as the NYT code followed by the symbol verbatim
+        _ => {
+            let symb_bits = usize::max(1,(usize::BITS - (num_symbols - 1).leading_zeros()) as usize);
+            usize::min(fibonacci_depth(count),num_symbols) + symb_bits
+        }
+    }
+}
+
 impl AdaptiveHuffmanTree {
-    pub fn create(num_symbols: usize) -> Self {
+    /// Create the tree, `initial_freq` gives the starting frequency of each symbol,
+    /// if it is empty every symbol starts with frequency 1.
+    /// The tree is rebuilt when the total frequency reaches `max_freq`, at which point each
+    /// leaf frequency is divided by `rescale_divisor`, rounding up.
+    /// Panics if the parameters are inconsistent, `lzss_huff::Options::validate` checks them first.
+    pub fn create(num_symbols: usize,rescale_divisor: usize,max_freq: usize,initial_freq: &[usize]) -> Self {
         let mut ans = Self {
-            max_freq: 0x8000,
+            max_freq,
+            rescale_divisor,
             num_symb: num_symbols,
             node_count: 2*num_symbols - 1,
             root: 2*num_symbols - 2,
             freq: vec![0;2*num_symbols],
             parent: vec![0;2*num_symbols-1],
             son: vec![0;2*num_symbols-1],
-            symb_map: vec![0;num_symbols]
+            symb_map: vec![0;num_symbols],
+            initial_freq: initial_freq.to_vec(),
+            rebuilds: 0
         };
-        // Leaves are stored first, one for each symbol (character)
-        // leaves are signaled by son[i] >= node_count
-        for i in 0..ans.num_symb {
-            ans.freq[i] = 1;
-            ans.son[i] = i + ans.node_count;
-            ans.symb_map[i] = i;
-        }
-        // Next construct the branches and root, there are num_symb-1 non-leaf nodes.
-        // The sons will be 0,2,4,...,node_count-3, these are left sons, the right sons
-        // are not explicitly stored, because we always have rson[i] = lson[i] + 1
-        // parent will be n,n,n+1,n+1,n+2,n+2,...,n+node_count-1,n+node_count-1
-        // Frequency (freq) of a parent node is the sum of the frequencies attached to it.
-        // Note the frequencies will be in ascending order.
-        let mut i = 0;
-        let mut j = ans.num_symb;
-        while j <= ans.root {
-            ans.freq[j] = ans.freq[i] + ans.freq[i+1];
-            ans.son[j] = i;
-            ans.parent[i] = j;
-            ans.parent[i+1] = j;
-            i += 2;
-            j += 1;
+        if rescale_divisor < 2 {
+            panic!("rescale divisor must be at least 2");
+        }
+        if max_freq <= num_symbols {
+            panic!("maximum frequency must exceed the number of symbols");
+        }
+        if !initial_freq.is_empty() {
+            if initial_freq.len() != num_symbols || initial_freq.contains(&0) {
+                panic!("initial frequencies must be nonzero and given for every symbol");
+            }
+            if initial_freq.iter().sum::<usize>() > ans.max_freq {
+                panic!("initial frequencies cannot total more than {}",ans.max_freq);
+            }
+        }
+        ans.reset();
+        ans
+    }
+    /// Put the tree back in its initial state without allocating
+    pub fn reset(&mut self) {
+        self.rebuilds = 0;
+        if self.initial_freq.is_empty() {
+            // Leaves are stored first, one for each symbol (character)
+            // leaves are signaled by son[i] >= node_count
+            for i in 0..self.num_symb {
+                self.freq[i] = 1;
+                self.son[i] = i + self.node_count;
+                self.symb_map[i] = i;
+            }
+            // Next construct the branches and root, there are num_symb-1 non-leaf nodes.
+            // The sons will be 0,2,4,...,node_count-3, these are left sons, the right sons
+            // are not explicitly stored, because we always have rson[i] = lson[i] + 1
+            // parent will be n,n,n+1,n+1,n+2,n+2,...,n+node_count-1,n+node_count-1
+            // Frequency (freq) of a parent node is the sum of the frequencies attached to it.
+            // Note the frequencies will be in ascending order.
+            let mut i = 0;
+            let mut j = self.num_symb;
+            while j <= self.root {
+                self.freq[j] = self.freq[i] + self.freq[i+1];
+                self.son[j] = i;
+                self.parent[i] = j;
+                self.parent[i+1] = j;
+                i += 2;
+                j += 1;
+            }
+            self.parent[self.root] = 0;
+        } else {
+            // leaves must start out sorted, the sort is stable so ties are in symbol order
+            let mut symbols: Vec<usize> = (0..self.num_symb).collect();
+            symbols.sort_by_key(|c| self.initial_freq[*c]);
+            for (i,c) in symbols.iter().enumerate() {
+                self.freq[i] = self.initial_freq[*c];
+                self.son[i] = c + self.node_count;
+            }
+            self.connect();
         }
         // last frequency entry is a backstop that prevents any frequency from moving
         // beyond the end of the array (must be larger than any possible frequency)
-        ans.freq[ans.node_count] = 0xffff;
-        ans.parent[ans.root] = 0;
-        ans
+        self.freq[self.node_count] = 0xffff;
     }
     /// Rebuild the adaptive Huffman tree, triggered by frequency hitting the maximum.
     fn rebuild_huff(&mut self) {
+        self.rebuilds += 1;
         // Collect leaf nodes from anywhere and pack them on the left.
-        // Replace the freq of every leaf by (freq+1)/2.
+        // Replace the freq of every leaf by (freq+1)/2, or in general divide rounding up.
         let mut j = 0;
         for i in 0..self.node_count {
             if self.son[i] >= self.node_count {
-                self.freq[j] = (self.freq[i] + 1)/2;
+                self.freq[j] = self.freq[i].div_ceil(self.rescale_divisor);
                 self.son[j] = self.son[i];
                 j += 1;
             }
         }
+        self.connect();
+    }
+    /// Build the branches given sorted leaves packed on the left.
+    fn connect(&mut self) {
         // Connect sons, old connections are not used in any way.
         // LZHUF has i,j,k as signed, seems to be no reason.
         let mut i: usize = 0; // left son
-        j = self.num_symb; // parent node - should already be num_symb
+        let mut j = self.num_symb; // parent node - should already be num_symb
         let mut k: usize; // right son or sorting reference
         let mut f: usize; // sum of lson and rson frequencies
         let mut l: usize; // offset from sorting reference to parent node
@@ -242,7 +371,7 @@ impl AdaptiveHuffmanTree {
         let mut j: usize;
         let mut k: usize;
         let mut l: usize;
-        if self.freq[self.root] == self.max_freq {
+        if self.freq[self.root] >= self.max_freq {
             self.rebuild_huff()
         }
         // the leaf node corresponding to this character
@@ -289,48 +418,15 @@ impl AdaptiveHuffmanTree {
             }
         }
     }
-}
-
-impl AdaptiveHuffmanCoder {
-    pub fn create(num_symbols: usize) -> Self {
-        Self {
-            tree: AdaptiveHuffmanTree::create(num_symbols),
-            bits: BitVec::new(),
-            ptr: 0
-        }
-    }
-    /// keep the bit vector small, we don't need the bits behind us
-    fn drop_leading_bits(&mut self) {
-        let cpy = self.bits.clone();
-        self.bits = BitVec::new();
-        for i in self.ptr..cpy.len() {
-            self.bits.push(cpy.get(i).unwrap());
-        }
-        self.ptr = 0;
-    }
-    /// output `num_bits` of `code` starting from the MSB, unlike LZHUF.C the bits are always
-    /// written to the output stream (sometimes backing up and rewriting)
-    fn put_code<W: Write + Seek>(&mut self,num_bits: u16,mut code: u16,writer: &mut BufWriter<W>) {
-        for _i in 0..num_bits {
-            self.bits.push(code & 0x8000 > 0);
-            code <<= 1;
-            self.ptr += 1;
-        }
-        let bytes = self.bits.to_bytes();
-        writer.write(&bytes.as_slice()).expect("write err");
-        if self.bits.len() % 8 > 0 {
-            writer.seek(SeekFrom::Current(-1)).expect("seek err");
-            self.ptr = 8 * (self.bits.len() / 8);
-            self.drop_leading_bits();
-        } else {
-            self.bits = BitVec::new();
-            self.ptr = 0;
-        }
-    }
-    pub fn encode_char<W: Write + Seek>(&mut self,c: u16,writer: &mut BufWriter<W>) {
+    /// Get (code,length) of symbol `c`, the code is aligned to the MSB.
+    /// The code is worked out from the tree every time.  Caching it per leaf does not pay: an update
+    /// usually moves a branch, which invalidates the codes of every leaf below it, and checking or
+    /// invalidating the cache costs more than the short walk to the root.  `benches/huffman_encode.rs`
+    /// measures this, a cache invalidated on every swap was about 8% slower on both text and base64.
+    fn code(&self,c: usize) -> (u16,u16) {
         let mut code: u16 = 0;
         let mut num_bits: u16 = 0;
-        let mut curr_node: usize = self.tree.symb_map[c as usize];
+        let mut curr_node: usize = self.symb_map[c];
         // This is the Huffman scheme: going from leaf to root, add a 0 bit if we
         // are coming from the left, or a 1 bit if we are coming from the right.
         loop {
@@ -338,87 +434,407 @@ impl AdaptiveHuffmanCoder {
             // if node's address is odd-numbered, we are coming from the right
             code += (curr_node as u16 & 1) << 15;
             num_bits += 1;
-            curr_node = self.tree.parent[curr_node];
-            if curr_node==self.tree.root {
+            curr_node = self.parent[curr_node];
+            if curr_node==self.root {
                 break;
             }
         }
-        self.put_code(num_bits,code,writer);
-        self.tree.update(c as i16); // TODO: why is input to update signed
+        (code,num_bits)
+    }
+}
+
+impl NytTree {
+    pub fn create(num_symbols: usize,vitter: bool) -> Self {
+        // with every symbol transmitted there are num_symbols+1 leaves, including NYT
+        let max_nodes = 2*num_symbols + 1;
+        let mut symb_bits = 1;
+        while (1 << symb_bits) < num_symbols {
+            symb_bits += 1;
+        }
+        let mut ans = Self {
+            vitter,
+            symb_bits,
+            root: 0,
+            nyt: 0,
+            weight: Vec::with_capacity(max_nodes),
+            parent: Vec::with_capacity(max_nodes),
+            child: Vec::with_capacity(max_nodes),
+            symbol: Vec::with_capacity(max_nodes),
+            leaf: vec![None;num_symbols],
+            number: Vec::with_capacity(max_nodes),
+            node_at: vec![0;max_nodes]
+        };
+        ans.reset();
+        ans
+    }
+    /// Put the tree back in its initial state without allocating
+    pub fn reset(&mut self) {
+        self.weight.clear();
+        self.parent.clear();
+        self.child.clear();
+        self.symbol.clear();
+        self.number.clear();
+        self.leaf.fill(None);
+        self.root = self.new_node(None,self.node_at.len()-1);
+        self.nyt = self.root;
+    }
+    fn new_node(&mut self,symbol: Option<usize>,number: usize) -> usize {
+        let node = self.weight.len();
+        self.weight.push(0);
+        self.parent.push(None);
+        self.child.push([0,0]);
+        self.symbol.push(symbol);
+        self.number.push(number);
+        self.node_at[number] = node;
+        if let Some(c) = symbol {
+            self.leaf[c] = Some(node);
+        }
+        node
+    }
+    fn is_leaf(&self,node: usize) -> bool {
+        node == self.nyt || self.symbol[node].is_some()
+    }
+    /// Exchange the positions of two nodes in the tree, including their numbers.
+    /// Subtrees go along with the nodes.  Neither node can be an ancestor of the other.
+    fn swap(&mut self,a: usize,b: usize) {
+        let (pa,pb) = (self.parent[a].unwrap(),self.parent[b].unwrap());
+        let sa = match self.child[pa][0]==a { true => 0, false => 1 };
+        let sb = match self.child[pb][0]==b { true => 0, false => 1 };
+        self.child[pa][sa] = b;
+        self.child[pb][sb] = a;
+        self.parent[a] = Some(pb);
+        self.parent[b] = Some(pa);
+        let (na,nb) = (self.number[a],self.number[b]);
+        self.number[a] = nb;
+        self.number[b] = na;
+        self.node_at[nb] = a;
+        self.node_at[na] = b;
+    }
+    /// Replace the NYT node with an internal node whose children are a new NYT and a new leaf.
+    /// Returns the new leaf.
+    fn split_nyt(&mut self,c: usize) -> usize {
+        let old = self.nyt;
+        let num = self.number[old];
+        let nyt = self.new_node(None,num-2);
+        let leaf = self.new_node(Some(c),num-1);
+        self.child[old] = [nyt,leaf];
+        self.parent[nyt] = Some(old);
+        self.parent[leaf] = Some(old);
+        self.nyt = nyt;
+        leaf
+    }
+    /// Append the code for symbol `c` to `code`, first bit to write comes first.
+    fn encode(&self,c: usize,code: &mut Vec<bool>) {
+        let (mut node,escape) = match self.leaf[c] {
+            Some(leaf) => (leaf,false),
+            None => (self.nyt,true)
+        };
+        let start = code.len();
+        while let Some(parent) = self.parent[node] {
+            code.push(self.child[parent][1]==node);
+            node = parent;
+        }
+        code[start..].reverse();
+        if escape {
+            for i in (0..self.symb_bits).rev() {
+                code.push((c >> i) & 1 > 0);
+            }
+        }
+    }
+    fn update(&mut self,c: usize) {
+        match self.vitter {
+            true => self.update_vitter(c),
+            false => self.update_fgk(c)
+        }
+    }
+    fn update_fgk(&mut self,c: usize) {
+        let mut q = match self.leaf[c] {
+            Some(leaf) => leaf,
+            None => self.split_nyt(c)
+        };
+        loop {
+            // find highest numbered node of the same weight, excluding the parent
+            let w = self.weight[q];
+            let mut n = self.number[q];
+            while n + 1 < self.node_at.len() && self.number[self.root] > n && self.weight[self.node_at[n+1]] == w {
+                n += 1;
+            }
+            while self.parent[q].is_some() && self.parent[q] == Some(self.node_at[n]) {
+                n -= 1;
+            }
+            let leader = self.node_at[n];
+            if leader != q {
+                self.swap(q,leader);
+            }
+            self.weight[q] += 1;
+            match self.parent[q] {
+                Some(p) => q = p,
+                None => break
+            }
+        }
     }
-    pub fn encode_position<W: Write + Seek>(&mut self,c: u16,writer: &mut BufWriter<W>) {
+    fn update_vitter(&mut self,c: usize) {
+        let mut leaf_to_increment = None;
+        let mut q = match self.leaf[c] {
+            Some(leaf) => {
+                // move q to the front of its block
+                let leader = self.block_leader(leaf);
+                if leader != leaf {
+                    self.swap(leaf,leader);
+                }
+                match self.parent[leaf] {
+                    Some(p) if self.child[p][0]==self.nyt => {
+                        leaf_to_increment = Some(leaf);
+                        Some(p)
+                    },
+                    _ => Some(leaf)
+                }
+            },
+            None => {
+                let leaf = self.split_nyt(c);
+                leaf_to_increment = Some(leaf);
+                self.parent[leaf]
+            }
+        };
+        while let Some(p) = q {
+            q = self.slide_and_increment(p);
+        }
+        if let Some(leaf) = leaf_to_increment {
+            self.slide_and_increment(leaf);
+        }
+    }
+    /// highest numbered node with the same weight and the same type (leaf or internal)
+    fn block_leader(&self,node: usize) -> usize {
+        let (w,leaf) = (self.weight[node],self.is_leaf(node));
+        let mut n = self.number[node];
+        while n < self.number[self.root] {
+            let next = self.node_at[n+1];
+            if self.weight[next] != w || self.is_leaf(next) != leaf {
+                break;
+            }
+            n += 1;
+        }
+        self.node_at[n]
+    }
+    /// Slide `p` past the block that follows it (internal nodes of the same weight if `p` is a leaf,
+    /// leaves of weight one more if `p` is internal), then increment.  Returns the next node to process.
+    fn slide_and_increment(&mut self,p: usize) -> Option<usize> {
+        let former_parent = self.parent[p];
+        let w = self.weight[p];
+        let leaf = self.is_leaf(p);
+        while self.number[p] < self.number[self.root] {
+            let next = self.node_at[self.number[p]+1];
+            let in_block = match leaf {
+                true => !self.is_leaf(next) && self.weight[next] == w,
+                false => self.is_leaf(next) && self.weight[next] == w + 1
+            };
+            if !in_block {
+                break;
+            }
+            self.swap(p,next);
+        }
+        self.weight[p] += 1;
+        match leaf {
+            true => self.parent[p],
+            false => former_parent
+        }
+    }
+}
+
+impl Tree {
+    fn reset(&mut self) {
+        match self {
+            Self::Lzhuf(tree) => tree.reset(),
+            Self::Nyt(tree) => tree.reset()
+        }
+    }
+    fn create(num_symbols: usize,strategy: UpdateStrategy,rescale_divisor: usize,max_freq: usize,initial_freq: &[usize]) -> Self {
+        match strategy {
+            UpdateStrategy::Lzhuf => Self::Lzhuf(AdaptiveHuffmanTree::create(num_symbols,rescale_divisor,max_freq,initial_freq)),
+            UpdateStrategy::Fgk => Self::Nyt(NytTree::create(num_symbols,false)),
+            UpdateStrategy::Vitter => Self::Nyt(NytTree::create(num_symbols,true))
+        }
+    }
+}
+
+impl AdaptiveHuffmanCoder {
+    /// The rescale divisor, maximum frequency, and initial frequencies only apply to the `Lzhuf` strategy,
+    /// see `AdaptiveHuffmanTree::create`.
+    pub fn create(num_symbols: usize,strategy: UpdateStrategy,rescale_divisor: usize,max_freq: usize,initial_freq: &[usize]) -> Self {
+        Self {
+            tree: Tree::create(num_symbols,strategy,rescale_divisor,max_freq,initial_freq),
+            bits: BitWriter::new(BitOrder::Msb0)
+        }
+    }
+    /// Put the coder back in its initial state, keeping allocations
+    pub fn reset(&mut self) {
+        self.tree.reset();
+        self.bits.reset();
+    }
+    /// output `num_bits` of `code` starting from the MSB, as in LZHUF.C only completed bytes are written
+    fn put_code<W: Write>(&mut self,num_bits: u16,code: u16,writer: &mut W) -> Result<(),std::io::Error> {
+        let code = (code as u64) >> (16 - u16::min(num_bits,16));
+        self.bits.put_bits(num_bits as usize,code,writer)
+    }
+    /// number of times the tree was rebuilt since the last reset, always 0 unless the strategy is `Lzhuf`
+    #[cfg(feature="tracing")]
+    pub fn rebuilds(&self) -> u64 {
+        match &self.tree {
+            Tree::Lzhuf(tree) => tree.rebuilds,
+            Tree::Nyt(_) => 0
+        }
+    }
+    /// write the partial byte, if any, this ends the bit stream
+    pub fn finish<W: Write>(&mut self,writer: &mut W) -> Result<(),std::io::Error> {
+        self.bits.finish(writer)
+    }
+    /// bits in the partial byte that has not been written yet
+    pub fn pending(&self) -> usize {
+        self.bits.pending()
+    }
+    /// copy of the tree, for a decoder to continue from
+    pub fn tree_state(&self) -> TreeState {
+        TreeState(self.tree.clone())
+    }
+    pub fn encode_char<W: Write>(&mut self,c: u16,writer: &mut W) -> Result<(),std::io::Error> {
+        let tree = match &mut self.tree {
+            Tree::Lzhuf(tree) => tree,
+            Tree::Nyt(tree) => {
+                let mut code = Vec::new();
+                tree.encode(c as usize,&mut code);
+                tree.update(c as usize);
+                for bit in code {
+                    self.put_code(1,(bit as u16) << 15,writer)?;
+                }
+                return Ok(());
+            }
+        };
+        let (code,num_bits) = tree.code(c as usize);
+        tree.update(c as i16); // TODO: why is input to update signed
+        self.put_code(num_bits,code,writer)
+    }
+    pub fn encode_position<W: Write>(&mut self,c: u16,writer: &mut W) -> Result<(),std::io::Error> {
         // upper 6 bits come from table
         let i = (c >> 6) as usize;
-        self.put_code(P_LEN[i] as u16,(P_CODE[i] as u16) << 8,writer);
+        self.put_code(P_LEN[i] as u16,(P_CODE[i] as u16) << 8,writer)?;
         // lower 6 bits verbatim
-        self.put_code(6,(c & 0x3f) << 10,writer);
+        self.put_code(6,(c & 0x3f) << 10,writer)
     }
 }
 
+/// Same as `AdaptiveHuffmanDecoder::get_bit`, taking the fields it needs so the tree can be borrowed at the same time
+fn next_bit<R: BufRead>(bits: &mut BitReader,bits_read: &mut u64,reader: &mut R) -> Result<u8,std::io::Error> {
+    let bit = bits.get_bit(reader)?;
+    *bits_read += 1;
+    Ok(bit)
+}
+
 impl AdaptiveHuffmanDecoder {
-    pub fn create(num_symbols: usize) -> Self {
+    /// The rescale divisor, maximum frequency, and initial frequencies only apply to the `Lzhuf` strategy,
+    /// see `AdaptiveHuffmanTree::create`.
+    pub fn create(num_symbols: usize,strategy: UpdateStrategy,rescale_divisor: usize,max_freq: usize,initial_freq: &[usize]) -> Self {
+        let mut bits = BitReader::new(BitOrder::Msb0);
+        bits.set_zero_fill(true);
         Self {
-            tree: AdaptiveHuffmanTree::create(num_symbols),
-            bits: BitVec::new(),
-            ptr: 0
+            tree: Tree::create(num_symbols,strategy,rescale_divisor,max_freq,initial_freq),
+            bits,
+            bits_read: 0
+        }
+    }
+    /// Put the coder back in its initial state, keeping allocations
+    pub fn reset(&mut self) {
+        self.tree.reset();
+        self.bits.reset();
+        self.bits_read = 0;
+    }
+    /// Consume the bytes that bits have been taken from, call this before looking at the position of `reader`
+    pub fn sync<R: BufRead>(&mut self,reader: &mut R) {
+        self.bits.sync(reader);
+    }
+    /// Consume all the bytes the decoder is holding, so that decoding can continue with a reader
+    /// that starts at the position `reader` is left at
+    pub fn release<R: BufRead>(&mut self,reader: &mut R) {
+        self.bits.release(reader);
+    }
+    /// Continue with a copy of a tree from `AdaptiveHuffmanCoder::tree_state`, starting `skip` bits into
+    /// the byte `reader` is at.  The skipped bits are counted by `bits_read`.
+    pub fn restore<R: BufRead>(&mut self,tree: &TreeState,skip: usize,reader: &mut R) -> Result<(),std::io::Error> {
+        self.tree.clone_from(&tree.0);
+        self.bits.reset();
+        self.bits_read = 0;
+        for _ in 0..skip {
+            self.get_bit(reader)?;
         }
+        Ok(())
     }
-    /// keep the bit vector small, we don't need the bits behind us
-    fn drop_leading_bits(&mut self) {
-        let cpy = self.bits.clone();
-        self.bits = BitVec::new();
-        for i in self.ptr..cpy.len() {
-            self.bits.push(cpy.get(i).unwrap());
+    /// bits consumed since the last reset
+    pub fn bits_read(&self) -> u64 {
+        self.bits_read
+    }
+    /// Bits consumed from beyond the end of the stream.  As in `LZHUF.C` these are zeros, so decoding
+    /// never fails at the end, it is up to the caller to stop.
+    pub fn bits_past_end(&self) -> u64 {
+        self.bits.bits_past_end()
+    }
+    /// number of times the tree was rebuilt since the last reset, always 0 unless the strategy is `Lzhuf`
+    pub fn rebuilds(&self) -> u64 {
+        match &self.tree {
+            Tree::Lzhuf(tree) => tree.rebuilds,
+            Tree::Nyt(_) => 0
         }
-        self.ptr = 0;
     }
-    /// Get the next bit reading from the stream as needed.
-    /// When EOF is reached 0 is returned, consistent with original C code.
+    /// Get the next bit reading from the stream as needed, zeros are shifted in at EOF.
     /// `reader` should not be advanced outside this function until decoding is done.
-    fn get_bit<R: Read>(&mut self,reader: &mut BufReader<R>) -> Result<u8,std::io::Error> {
-        match self.bits.get(self.ptr) {
-            Some(bit) => {
-                self.ptr += 1;
-                Ok(bit as u8)
+    fn get_bit<R: BufRead>(&mut self,reader: &mut R) -> Result<u8,std::io::Error> {
+        next_bit(&mut self.bits,&mut self.bits_read,reader)
+    }
+    /// get the next 8 bits into a u8, used exlusively to decode the position
+    fn get_byte<R: BufRead>(&mut self,reader: &mut R) -> Result<u8,std::io::Error> {
+        let byte = self.bits.get_bits(8,reader)?;
+        self.bits_read += 8;
+        Ok(byte as u8)
+    }
+    pub fn decode_char<R: BufRead>(&mut self,reader: &mut R) -> Result<i16,std::io::Error> {
+        let bits = &mut self.bits;
+        let bits_read = &mut self.bits_read;
+        match &mut self.tree {
+            Tree::Lzhuf(tree) => {
+                // This is the Huffman scheme: go from root to leaf, branching left or right depending on the
+                // successive bits.  The nodes are arranged so that branching left or right means adding 0 or
+                // 1 to the index.  Remember leaves are signaled by son >= node_count.
+                let mut c = tree.son[tree.root];
+                while c < tree.node_count {
+                    c += next_bit(bits,bits_read,reader)? as usize;
+                    c = tree.son[c];
+                }
+                c -= tree.node_count;
+                tree.update(c as i16); // TODO: why is input to update signed
+                Ok(c as i16)
             },
-            None => {
-                let mut by: [u8;1] = [0];
-                match reader.read_exact(&mut by) {
-                    Ok(()) => {
-                        if self.bits.len()>512 {
-                            self.drop_leading_bits();
+            Tree::Nyt(tree) => {
+                let mut node = tree.root;
+                while !tree.is_leaf(node) {
+                    node = tree.child[node][next_bit(bits,bits_read,reader)? as usize];
+                }
+                let c = match tree.symbol[node] {
+                    Some(c) => c,
+                    None => {
+                        let mut c = 0;
+                        for _i in 0..tree.symb_bits {
+                            c = (c << 1) | next_bit(bits,bits_read,reader)? as usize;
                         }
-                        self.bits.append(&mut BitVec::from_bytes(&by));
-                        self.get_bit(reader)
+                        c
                     }
-                    Err(e) => Err(e)
+                };
+                if c >= tree.leaf.len() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,"symbol out of range"));
                 }
+                tree.update(c);
+                Ok(c as i16)
             }
         }
     }
-    /// get the next 8 bits into a u8, used exlusively to decode the position
-    fn get_byte<R: Read>(&mut self,bytes: &mut BufReader<R>) -> Result<u8,std::io::Error> {
-        let mut ans: u8 = 0;
-        for _i in 0..8 {
-            ans <<= 1;
-            ans |= self.get_bit(bytes)?;
-        }
-        Ok(ans)
-    }
-    pub fn decode_char<R: Read>(&mut self,reader: &mut BufReader<R>) -> Result<i16,std::io::Error> {
-        let mut c: usize = self.tree.son[self.tree.root];
-        // This is the Huffman scheme: go from root to leaf, branching left or right depending on the
-        // successive bits.  The nodes are arranged so that branching left or right means adding 0 or
-        // 1 to the index.  Remember leaves are signaled by son >= node_count.
-        while c < self.tree.node_count {
-            c += self.get_bit(reader)? as usize;
-            c = self.tree.son[c];
-        }
-        c -= self.tree.node_count;
-        self.tree.update(c as i16); // TODO: why is input to update signed
-        Ok(c as i16)
-    }
-    pub fn decode_position<R: Read>(&mut self,reader: &mut BufReader<R>) -> Result<u16,std::io::Error> {
+    pub fn decode_position<R: BufRead>(&mut self,reader: &mut R) -> Result<u16,std::io::Error> {
         // get upper 6 bits from table
         let mut first8 = self.get_byte(reader)? as u16;
         let upper6 = (D_CODE[first8 as usize] as u16) << 6;
@@ -432,3 +848,28 @@ impl AdaptiveHuffmanDecoder {
         Ok(upper6 | (first8 & 0x3f))
     }
 }
+
+#[test]
+fn nyt_tree_invariants() {
+    // check the sibling property after every update, for both algorithms
+    let data = "abracadabra, the quick brown fox jumps over the lazy dog, aaaaaaaaaabbbbbbbbc".as_bytes();
+    for vitter in [false,true] {
+        let mut tree = NytTree::create(256,vitter);
+        for c in data {
+            tree.update(*c as usize);
+            for n in tree.number[tree.nyt]+1..=tree.number[tree.root] {
+                let (a,b) = (tree.node_at[n-1],tree.node_at[n]);
+                assert!(tree.weight[a] <= tree.weight[b]);
+            }
+            for node in 0..tree.weight.len() {
+                if !tree.is_leaf(node) {
+                    let [l,r] = tree.child[node];
+                    assert_eq!(tree.weight[node],tree.weight[l]+tree.weight[r]);
+                    assert_eq!(tree.number[r],tree.number[l]+1);
+                    assert!(tree.number[node] > tree.number[r]);
+                }
+            }
+        }
+        assert_eq!(tree.weight[tree.root],data.len() as u64);
+    }
+}
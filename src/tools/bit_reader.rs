@@ -0,0 +1,191 @@
+//! Bit accumulator for the decoders, the counterpart of `BitWriter`.
+//! Up to 8 bytes at a time are copied into an integer straight out of the reader's buffer, and bits
+//! are taken off without copying.  Bytes are consumed from the reader lazily, so the reader is never
+//! advanced past the byte holding the last bit taken, but it may lag behind until `sync` is called.
+//! At the end of the stream an error is returned, unless zero filling is turned on, in which case
+//! zeros are shifted in as `LZHUF.C` does, and counted so the decoder can tell the data ran out.
+
+use std::io::BufRead;
+use crate::BitOrder;
+
+#[derive(Clone)]
+pub struct BitReader {
+    acc: u64,
+    /// number of bits waiting in `acc`
+    len: usize,
+    /// number of whole bytes waiting in `acc` that have not been consumed from the reader
+    ahead: usize,
+    /// shift in zeros at the end of the stream rather than returning an error
+    zero_fill: bool,
+    /// number of zero bits shifted in
+    past_end: u64,
+    ord: BitOrder
+}
+
+impl BitReader {
+    pub fn new(ord: BitOrder) -> Self {
+        Self {
+            acc: 0,
+            len: 0,
+            ahead: 0,
+            zero_fill: false,
+            past_end: 0,
+            ord
+        }
+    }
+    /// Shift in zeros at the end of the stream, see `bits_past_end`
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn set_zero_fill(&mut self,zero_fill: bool) {
+        self.zero_fill = zero_fill;
+    }
+    /// Number of bits that have been taken from beyond the end of the stream, which are always zero
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn bits_past_end(&self) -> u64 {
+        self.past_end.saturating_sub(self.len as u64)
+    }
+    /// Number of bits that are waiting, after an error these are the bits that were there
+    #[allow(dead_code)] // unused if lzw is not enabled
+    pub fn waiting(&self) -> usize {
+        self.len
+    }
+    /// Forget any bits that are waiting
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn reset(&mut self) {
+        self.acc = 0;
+        self.len = 0;
+        self.ahead = 0;
+        self.past_end = 0;
+    }
+    /// Load bytes until at least `num_bits` are waiting.  The bytes that were waiting are
+    /// consumed first, since all of them are about to be taken.
+    fn refill<R: BufRead>(&mut self,num_bits: usize,reader: &mut R) -> Result<(),std::io::Error> {
+        while self.len < num_bits {
+            reader.consume(self.ahead);
+            self.ahead = 0;
+            let avail = reader.fill_buf()?;
+            if avail.is_empty() && self.zero_fill {
+                if let BitOrder::Msb0 = self.ord {
+                    self.acc <<= 8;
+                }
+                self.len += 8;
+                self.past_end += 8;
+                continue;
+            }
+            if avail.is_empty() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+            }
+            let count = usize::min(avail.len(),(64 - self.len) / 8);
+            for by in &avail[0..count] {
+                match self.ord {
+                    BitOrder::Msb0 => self.acc = (self.acc << 8) | *by as u64,
+                    BitOrder::Lsb0 => self.acc |= (*by as u64) << self.len
+                }
+                self.len += 8;
+            }
+            self.ahead = count;
+        }
+        Ok(())
+    }
+    /// Consume the bytes that bits have been taken from, whole bytes that are still waiting stay in the reader.
+    /// Call this before looking at the position of the reader.
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn sync<R: BufRead>(&mut self,reader: &mut R) {
+        // zeros from beyond the end are not in the reader
+        let keep = (self.len - u64::min(self.len as u64,self.past_end) as usize) / 8;
+        if keep < self.ahead {
+            reader.consume(self.ahead - keep);
+            self.ahead = keep;
+        }
+    }
+    /// Consume every byte that is waiting, so the reader is positioned after all of them.
+    /// Decoding can then continue with a different reader that starts there.
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn release<R: BufRead>(&mut self,reader: &mut R) {
+        reader.consume(self.ahead);
+        self.ahead = 0;
+    }
+    /// Get the next `num_bits` in the given bit-order, reading from the stream as needed.
+    /// If the stream ends first the error is returned, and the bits that were read stay waiting.
+    /// Panics if `num_bits` exceeds 56.
+    pub fn get_bits<R: BufRead>(&mut self,num_bits: usize,reader: &mut R) -> Result<u64,std::io::Error> {
+        if num_bits > 56 {
+            panic!("too many bits at once");
+        }
+        if self.len < num_bits {
+            self.refill(num_bits,reader)?;
+        }
+        let mask = (1u64 << num_bits) - 1;
+        let ans = match self.ord {
+            BitOrder::Msb0 => {
+                self.len -= num_bits;
+                let ans = (self.acc >> self.len) & mask;
+                self.acc &= (1 << self.len) - 1;
+                ans
+            },
+            BitOrder::Lsb0 => {
+                let ans = self.acc & mask;
+                self.acc >>= num_bits;
+                self.len -= num_bits;
+                ans
+            }
+        };
+        Ok(ans)
+    }
+    /// Get the next bit, reading from the stream as needed
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn get_bit<R: BufRead>(&mut self,reader: &mut R) -> Result<u8,std::io::Error> {
+        if self.len == 0 {
+            self.refill(1,reader)?;
+        }
+        self.len -= 1;
+        let bit = match self.ord {
+            BitOrder::Msb0 => (self.acc >> self.len) & 1,
+            BitOrder::Lsb0 => {
+                let bit = self.acc & 1;
+                self.acc >>= 1;
+                bit
+            }
+        };
+        Ok(bit as u8)
+    }
+}
+
+#[test]
+fn bit_orders() {
+    let data: [u8;2] = [0b1011_1000,0b0001_0000];
+    let mut src = std::io::Cursor::new(&data);
+    let mut bits = BitReader::new(BitOrder::Msb0);
+    assert_eq!(bits.get_bits(3,&mut src).unwrap(),0b101);
+    assert_eq!(bits.get_bit(&mut src).unwrap(),1);
+    bits.sync(&mut src);
+    assert_eq!(src.position(),1);
+    assert_eq!(bits.get_bits(8,&mut src).unwrap(),0b1000_0001);
+    assert!(bits.get_bits(5,&mut src).is_err());
+    let mut src = std::io::Cursor::new(&data);
+    let mut bits = BitReader::new(BitOrder::Msb0);
+    assert_eq!(bits.get_bits(3,&mut src).unwrap(),0b101);
+    bits.release(&mut src);
+    assert_eq!(src.position(),2);
+    assert_eq!(bits.get_bits(13,&mut std::io::Cursor::new(&[])).unwrap(),0b1_1000_0001_0000);
+    let data: [u8;2] = [0b0000_1101,0b0000_1100];
+    let mut src = std::io::Cursor::new(&data);
+    let mut bits = BitReader::new(BitOrder::Lsb0);
+    assert_eq!(bits.get_bits(3,&mut src).unwrap(),0b101);
+    assert_eq!(bits.get_bit(&mut src).unwrap(),1);
+    assert_eq!(bits.get_bits(8,&mut src).unwrap(),0b1100_0000);
+    assert_eq!(bits.get_bits(4,&mut src).unwrap(),0);
+}
+
+#[test]
+fn zero_fill() {
+    let data: [u8;1] = [0b1011_1000];
+    let mut src = std::io::Cursor::new(&data);
+    let mut bits = BitReader::new(BitOrder::Msb0);
+    bits.set_zero_fill(true);
+    assert_eq!(bits.get_bits(6,&mut src).unwrap(),0b10_1110);
+    assert_eq!(bits.bits_past_end(),0);
+    assert_eq!(bits.get_bits(4,&mut src).unwrap(),0);
+    assert_eq!(bits.bits_past_end(),2);
+    bits.sync(&mut src);
+    assert_eq!(src.position(),1);
+}
@@ -0,0 +1,97 @@
+//! Bit accumulator for the coders.
+//! Codes are packed into an integer and only completed bytes are written, so the output
+//! never has to be rewound.  The partial byte at the end goes out when the coder finishes.
+
+use std::io::Write;
+use crate::BitOrder;
+
+#[derive(Clone)]
+pub struct BitWriter {
+    acc: u64,
+    /// number of bits waiting in `acc`, less than 8 between calls
+    len: usize,
+    ord: BitOrder
+}
+
+impl BitWriter {
+    pub fn new(ord: BitOrder) -> Self {
+        Self {
+            acc: 0,
+            len: 0,
+            ord
+        }
+    }
+    /// Forget any bits that are waiting
+    #[allow(dead_code)] // unused if lzss_huff is not enabled
+    pub fn reset(&mut self) {
+        self.acc = 0;
+        self.len = 0;
+    }
+    /// Number of bits that are waiting for their byte to be completed
+    pub fn pending(&self) -> usize {
+        self.len
+    }
+    /// Put the low `num_bits` of `code` in the given bit-order, writing any bytes that are completed.
+    /// Panics if `num_bits` exceeds 56.
+    pub fn put_bits<W: Write>(&mut self,num_bits: usize,code: u64,writer: &mut W) -> Result<(),std::io::Error> {
+        if num_bits == 0 {
+            return Ok(());
+        }
+        if num_bits > 56 {
+            panic!("too many bits at once");
+        }
+        let code = code & (u64::MAX >> (64 - num_bits));
+        let mut bytes = [0;8];
+        let mut count = 0;
+        match self.ord {
+            BitOrder::Msb0 => {
+                self.acc = (self.acc << num_bits) | code;
+                self.len += num_bits;
+                while self.len >= 8 {
+                    self.len -= 8;
+                    bytes[count] = (self.acc >> self.len) as u8;
+                    count += 1;
+                }
+                self.acc &= (1 << self.len) - 1;
+            },
+            BitOrder::Lsb0 => {
+                self.acc |= code << self.len;
+                self.len += num_bits;
+                while self.len >= 8 {
+                    bytes[count] = self.acc as u8;
+                    self.acc >>= 8;
+                    self.len -= 8;
+                    count += 1;
+                }
+            }
+        }
+        writer.write_all(&bytes[0..count])
+    }
+    /// Write the partial byte, if any, with zeros in the unused bits
+    pub fn finish<W: Write>(&mut self,writer: &mut W) -> Result<(),std::io::Error> {
+        if self.len > 0 {
+            let pad = 8 - self.len;
+            self.put_bits(pad,0,writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn bit_orders() {
+    let mut out = Vec::new();
+    let mut bits = BitWriter::new(BitOrder::Msb0);
+    bits.put_bits(3,0b101,&mut out).unwrap();
+    bits.put_bits(9,0b1_1000_0001,&mut out).unwrap();
+    assert_eq!(out,vec![0b1011_1000]);
+    assert_eq!(bits.pending(),4);
+    bits.finish(&mut out).unwrap();
+    assert_eq!(out,vec![0b1011_1000,0b0001_0000]);
+    let mut out = Vec::new();
+    let mut bits = BitWriter::new(BitOrder::Lsb0);
+    bits.put_bits(3,0b101,&mut out).unwrap();
+    bits.put_bits(9,0b1_1000_0001,&mut out).unwrap();
+    assert_eq!(out,vec![0b0000_1101]);
+    bits.finish(&mut out).unwrap();
+    assert_eq!(out,vec![0b0000_1101,0b0000_1100]);
+}
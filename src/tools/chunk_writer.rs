@@ -0,0 +1,62 @@
+//! Writer that hands what is written to a callback in bounded chunks.
+//! Like `RangeWriter`, seeking is limited to what the decoders need.
+
+use std::io::{Write,Seek,SeekFrom,ErrorKind};
+
+/// Chunk size to use when the callback discards the data, e.g. to verify a stream
+pub const DISCARD_CHUNK: usize = 0x1000;
+
+pub struct ChunkWriter<'a> {
+    pos: u64,
+    chunk_size: usize,
+    buf: Vec<u8>,
+    callback: &'a mut dyn FnMut(&[u8])
+}
+
+impl<'a> ChunkWriter<'a> {
+    /// Stream positions start at `start`, the callback never sees more than `chunk_size` bytes at once.
+    /// Panics if `chunk_size` is 0.
+    pub fn new(start: u64,chunk_size: usize,callback: &'a mut dyn FnMut(&[u8])) -> Self {
+        if chunk_size == 0 {
+            panic!("chunk size must be positive");
+        }
+        Self {
+            pos: start,
+            chunk_size,
+            buf: Vec::with_capacity(chunk_size),
+            callback
+        }
+    }
+}
+
+impl Write for ChunkWriter<'_> {
+    fn write(&mut self,data: &[u8]) -> Result<usize,std::io::Error> {
+        let n = usize::min(data.len(),self.chunk_size - self.buf.len());
+        self.buf.extend_from_slice(&data[0..n]);
+        if self.buf.len() == self.chunk_size {
+            self.flush()?;
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+    /// Sends whatever is buffered, even if it is less than a full chunk
+    fn flush(&mut self) -> Result<(),std::io::Error> {
+        if !self.buf.is_empty() {
+            (self.callback)(&self.buf);
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+impl Seek for ChunkWriter<'_> {
+    /// Only seeking to the current position is supported, since
+    /// the decoders only do this to initialize or report the position.
+    fn seek(&mut self,pos: SeekFrom) -> Result<u64,std::io::Error> {
+        match pos {
+            SeekFrom::Start(p) if p == self.pos => Ok(self.pos),
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(std::io::Error::new(ErrorKind::Unsupported,"chunk writer cannot seek"))
+        }
+    }
+}
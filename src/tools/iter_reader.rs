@@ -0,0 +1,118 @@
+//! Reader that pulls its data from an iterator of bytes.
+//! The codecs expect `Seek`, so this supports seeking forward (by discarding),
+//! and backward as far as the start of the current buffer, which covers the
+//! one byte backup the LZW coder does.  The end of the stream is unknown,
+//! so seeking from the end is reported as unsupported.
+
+use std::io::{Read,BufRead,Seek,SeekFrom,ErrorKind};
+use crate::ByteItem;
+
+/// bytes pulled from the iterator at a time
+const FILL_SIZE: usize = 0x1000;
+
+pub struct IterReader<'a> {
+    iter: Box<dyn Iterator<Item=Result<u8,std::io::Error>> + 'a>,
+    buf: Vec<u8>,
+    /// stream position of `buf[0]`
+    start: u64,
+    /// index into `buf` of the current position
+    cursor: usize,
+    /// error from the iterator that is waiting for the buffer to drain
+    err: Option<std::io::Error>
+}
+
+impl<'a> IterReader<'a> {
+    pub fn new<I>(iter: I) -> Self where I: IntoIterator + 'a, I::Item: ByteItem {
+        Self {
+            iter: Box::new(iter.into_iter().map(ByteItem::into_byte)),
+            buf: Vec::new(),
+            start: 0,
+            cursor: 0,
+            err: None
+        }
+    }
+    fn pos(&self) -> u64 {
+        self.start + self.cursor as u64
+    }
+}
+
+impl Read for IterReader<'_> {
+    fn read(&mut self,data: &mut [u8]) -> Result<usize,std::io::Error> {
+        let avail = self.fill_buf()?;
+        let n = usize::min(avail.len(),data.len());
+        data[0..n].copy_from_slice(&avail[0..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for IterReader<'_> {
+    fn fill_buf(&mut self) -> Result<&[u8],std::io::Error> {
+        if self.cursor == self.buf.len() {
+            if let Some(e) = self.err.take() {
+                return Err(e);
+            }
+            // keep the last byte so the position can be backed up by one
+            if self.cursor > 1 {
+                self.start += self.cursor as u64 - 1;
+                self.buf.drain(0..self.cursor-1);
+                self.cursor = 1;
+            }
+            while self.buf.len() < self.cursor + FILL_SIZE {
+                match self.iter.next() {
+                    Some(Ok(b)) => self.buf.push(b),
+                    Some(Err(e)) => {
+                        self.err = Some(e);
+                        break;
+                    },
+                    None => break
+                }
+            }
+            if self.cursor == self.buf.len() {
+                if let Some(e) = self.err.take() {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(&self.buf[self.cursor..])
+    }
+    fn consume(&mut self,amt: usize) {
+        self.cursor = usize::min(self.cursor + amt,self.buf.len());
+    }
+}
+
+impl Seek for IterReader<'_> {
+    /// Forward seeks discard data, backward seeks are limited to the buffer.
+    fn seek(&mut self,pos: SeekFrom) -> Result<u64,std::io::Error> {
+        let target = match pos {
+            SeekFrom::Start(p) => p,
+            SeekFrom::Current(d) => match self.pos().checked_add_signed(d) {
+                Some(p) => p,
+                None => return Err(std::io::Error::new(ErrorKind::InvalidInput,"seek before start"))
+            },
+            SeekFrom::End(_) => return Err(std::io::Error::new(ErrorKind::Unsupported,"iterator has no known end"))
+        };
+        if target < self.start {
+            return Err(std::io::Error::new(ErrorKind::Unsupported,"iterator cannot seek that far back"));
+        }
+        while target > self.start + self.buf.len() as u64 {
+            self.cursor = self.buf.len();
+            if self.fill_buf()?.is_empty() {
+                // seeking past the end is allowed, reading will give EOF
+                return Ok(self.pos());
+            }
+        }
+        self.cursor = (target - self.start) as usize;
+        Ok(target)
+    }
+}
+
+/// Find the end of a stream, or `None` if it does not support seeking from the end.
+/// The position is left at the end.
+pub fn known_end<S: Seek + ?Sized>(stream: &mut S) -> Result<Option<u64>,std::io::Error> {
+    match stream.seek(SeekFrom::End(0)) {
+        Ok(end) => Ok(Some(end)),
+        Err(e) if e.kind()==ErrorKind::Unsupported => Ok(None),
+        Err(e) => Err(e)
+    }
+}
@@ -1,5 +1,24 @@
 //! Tools for Compression
+//!
+//! Building blocks of the codecs, the checksums they use are in `checksum`.
 
-pub mod adaptive_huff;
-pub mod node_pool;
-pub mod ring_buffer;
\ No newline at end of file
+#[cfg(feature="lzss_huff")]
+pub(crate) mod adaptive_huff;
+#[cfg(feature="lzss_huff")]
+pub(crate) mod node_pool;
+#[cfg(feature="lzss_huff")]
+pub(crate) mod ring_buffer;
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+pub(crate) mod range_writer;
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+pub(crate) mod iter_reader;
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+pub(crate) mod chunk_writer;
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+pub(crate) mod bit_writer;
+#[cfg(any(feature="lzw",feature="lzss_huff"))]
+pub(crate) mod bit_reader;
+#[cfg(feature="td0")]
+pub(crate) mod workers;
+#[cfg(test)]
+pub(crate) mod mutate;
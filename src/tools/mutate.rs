@@ -0,0 +1,48 @@
+//! Corrupted copies of good data, for checking that the decoders fail gracefully.
+//! A fixed seed makes every run produce the same corpus.
+
+/// Iterator over copies of `data`, each with a few bits flipped, bytes overwritten or inserted,
+/// or the end cut off.
+pub struct Mutations<'a> {
+    data: &'a [u8],
+    state: u64
+}
+
+impl<'a> Mutations<'a> {
+    pub fn new(data: &'a [u8],seed: u64) -> Self {
+        Self {
+            data,
+            state: seed | 1
+        }
+    }
+    /// xorshift, good enough to scatter the damage
+    fn next_rand(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Iterator for Mutations<'_> {
+    type Item = Vec<u8>;
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut ans = self.data.to_vec();
+        let kind = self.next_rand() % 4;
+        let count = 1 + self.next_rand() % 8;
+        for _ in 0..count {
+            if ans.is_empty() {
+                break;
+            }
+            let i = self.next_rand() as usize % ans.len();
+            let r = self.next_rand();
+            match kind {
+                0 => ans[i] ^= 1 << (r % 8),
+                1 => ans[i] = r as u8,
+                2 => ans.insert(i,r as u8),
+                _ => ans.truncate(i)
+            }
+        }
+        Some(ans)
+    }
+}
@@ -39,49 +39,66 @@ pub enum Side {
     Right = 1
 }
 
-/// Node used to build a tree.
-/// With this type of tree the values may also serve as node pointers.
-#[derive(Clone)]
-struct Node {
-    /// value is also index into node pool
-    val: usize,
-    /// if this is a root, gives the value of the symbol
-    symbol: Option<usize>,
-    /// index to the parent
-    up: Option<usize>,
-    /// index to the children [left,right]
-    down: [Option<usize>;2]
+/// marks a missing link in the pool arrays
+const NIL: u32 = u32::MAX;
+
+fn unlink(v: u32) -> Option<usize> {
+    match v {
+        NIL => None,
+        v => Some(v as usize)
+    }
 }
 
+/// The node pool is kept as parallel arrays of `u32` links, where `NIL` is a missing link.
+/// The value of a node is its index, so it is not stored.
 pub struct Tree {
     /// one element for each symbol, value is index into the node pool
-    roots: Vec<Option<usize>>,
+    roots: Vec<u32>,
     /// cursor as index into the node pool, can be None
     curs: Option<usize>,
-    /// the node pool, one node for each unique value that is allowed
-    pool: Vec<Node>
+    /// if the node is a root, gives the value of the symbol
+    symbol: Vec<u32>,
+    /// index to the parent
+    up: Vec<u32>,
+    /// index to the children [left,right]
+    down: Vec<[u32;2]>
 }
 
 impl Tree {
     /// The node values are from 0..len-1, the symbol values from 0..symbols-1.
     /// Each symbol may create its own root and tree within the node buffer.
+    /// Panics if either count does not fit in 32 bits.
     pub fn create(len: usize,symbols: usize) -> Self {
-        let roots: Vec<Option<usize>> = vec![None;symbols];
-        let mut pool: Vec<Node> = Vec::new();
-        for i in 0..len {
-            pool.push(Node {val: i, symbol: None, up: None, down: [None,None]});
+        if len >= NIL as usize || symbols >= NIL as usize {
+            panic!("node pool is too large");
         }
         Self {
-            roots,
+            roots: vec![NIL;symbols],
             curs: None,
-            pool
+            symbol: vec![NIL;len],
+            up: vec![NIL;len],
+            down: vec![[NIL,NIL];len]
         }
     }
+    /// Remove all nodes without deallocating
+    pub fn reset(&mut self) {
+        self.roots.fill(NIL);
+        self.curs = None;
+        self.symbol.fill(NIL);
+        self.up.fill(NIL);
+        self.down.fill([NIL,NIL]);
+    }
+    /// clear the links of one node
+    fn free(&mut self,node: usize) {
+        self.symbol[node] = NIL;
+        self.up[node] = NIL;
+        self.down[node] = [NIL,NIL];
+    }
     fn chk_cursor(&self) -> Result<usize,Error> {
         match self.curs {
             None => Err(Error::NoCursor),
             Some(curs) => {
-                match curs < self.pool.len() {
+                match curs < self.up.len() {
                     true => Ok(curs),
                     false => Err(Error::OutOfRange)
                 }
@@ -92,7 +109,7 @@ impl Tree {
         self.curs
     }
     pub fn set_cursor(&mut self,curs: usize) -> Result<(),Error> {
-        match curs < self.pool.len() {
+        match curs < self.up.len() {
             true => {
                 self.curs = Some(curs);
                 Ok(())
@@ -101,7 +118,7 @@ impl Tree {
         }
     }
     pub fn set_cursor_to_root(&mut self,symbol: usize) -> Result<(),Error> {
-        match self.roots[symbol] {
+        match unlink(self.roots[symbol]) {
             None => Err(Error::NodeMissing),
             Some(v) => {
                 self.curs = Some(v);
@@ -110,7 +127,7 @@ impl Tree {
         }
     }
     pub fn up(&mut self) -> Result<usize,Error> {
-        match self.pool[self.chk_cursor()?].up {
+        match unlink(self.up[self.chk_cursor()?]) {
             None => Err(Error::NodeMissing),
             Some(v) => {
                 self.curs = Some(v);
@@ -119,7 +136,7 @@ impl Tree {
         }
     }
     pub fn down(&mut self,side: Side) -> Result<usize,Error> {
-        match self.pool[self.chk_cursor()?].down[side as usize] {
+        match unlink(self.down[self.chk_cursor()?][side as usize]) {
             None => Err(Error::NodeMissing),
             Some(v) => {
                 self.curs = Some(v);
@@ -138,16 +155,16 @@ impl Tree {
     /// Get the array of children, cursor does not move.
     pub fn get_down(&self) -> Result<[Option<usize>;2],Error> {
         let curs = self.chk_cursor()?;
-        Ok(self.pool[curs].down)
+        Ok(self.down[curs].map(unlink))
     }
     /// Get the parent and side (left or right) of the current cursor location.
     /// Cursor does not move.
     pub fn get_parent_and_side(&mut self) -> Result<(usize,Side),Error> {
         let curs = self.chk_cursor()?;
-        if let Some(parent) = self.pool[curs].up {
-            return match self.pool[parent].down {
-                [Some(v),_] if v==curs => Ok((parent,Side::Left)),
-                [_,Some(v)] if v==curs => Ok((parent,Side::Right)),
+        if let Some(parent) = unlink(self.up[curs]) {
+            return match self.down[parent] {
+                [v,_] if v==curs as u32 => Ok((parent,Side::Left)),
+                [_,v] if v==curs as u32 => Ok((parent,Side::Right)),
                 _ => Err(Error::BrokenConnection)
             };
         }
@@ -158,9 +175,9 @@ impl Tree {
     /// Cursor does not move.
     pub fn get_symbol(&mut self) -> Result<usize,Error> {
         let curs = self.chk_cursor()?;
-        if let Some(symbol) = self.pool[curs].symbol {
+        if let Some(symbol) = unlink(self.symbol[curs]) {
             return match self.roots[symbol] {
-                Some(v) if v==curs => Ok(symbol),
+                v if v==curs as u32 => Ok(symbol),
                 _ => Err(Error::BrokenConnection)
             };
         }
@@ -168,48 +185,45 @@ impl Tree {
     }
     pub fn is_root(&self) -> Result<bool,Error> {
         let curs = self.chk_cursor()?;
-        Ok(self.pool[curs].up==None)
+        Ok(self.up[curs]==NIL)
     }
     pub fn is_leaf(&self) -> Result<bool,Error> {
         let curs = self.chk_cursor()?;
-        Ok(self.pool[curs].down==[None,None])
+        Ok(self.down[curs]==[NIL,NIL])
     }
     pub fn is_free(&self,curs: usize) -> Result<bool,Error> {
-        match (self.pool[curs].symbol,self.pool[curs].up,self.pool[curs].down) {
-            // TODO: how should we define a free slot
-            (None,None,_) => Ok(true),
-            _ => Ok(false),
-        }
+        // TODO: how should we define a free slot
+        Ok(self.symbol[curs]==NIL && self.up[curs]==NIL)
     }
     /// Spawn a new node attaching to the cursor, cursor does not move.
     /// If the cursor is already linked downward an error is returned.
     /// If the target slot is already linked, the old links are overwritten.
     pub fn spawn(&mut self, val: usize, side: Side) -> Result<(),Error> {
-        if val >= self.pool.len() {
+        if val >= self.up.len() {
             log::trace!("spawn out of range {}",val);
             return Err(Error::OutOfRange);
         }
         let curs = self.chk_cursor()?;
-        if self.pool[curs].down[side as usize].is_some() {
-            log::trace!("spawn: cannot overwrite {}",self.pool[curs].down[side as usize].unwrap());
+        if self.down[curs][side as usize] != NIL {
+            log::trace!("spawn: cannot overwrite {}",self.down[curs][side as usize]);
             return Err(Error::NodeExists);
         }
-        self.pool[curs].down[side as usize] = Some(val);
-        self.pool[val].up = Some(curs);
-        self.pool[val].down = [None,None];
+        self.down[curs][side as usize] = val as u32;
+        self.up[val] = curs as u32;
+        self.down[val] = [NIL,NIL];
         Ok(())
     }
     /// This type of tree can have multiple roots or no roots.
     /// The root occupies a slot in the node pool, the slot must be free.
     pub fn spawn_root(&mut self, symbol: usize, curs: usize) -> Result<(),Error> {
-        if symbol >= self.roots.len() || curs >= self.pool.len() {
+        if symbol >= self.roots.len() || curs >= self.up.len() {
             return Err(Error::OutOfRange);
         }
         if self.is_free(curs)? {
-            self.roots[symbol] = Some(curs);
-            self.pool[curs].symbol = Some(symbol);
-            self.pool[curs].up = None;
-            self.pool[curs].down = [None,None];
+            self.roots[symbol] = curs as u32;
+            self.symbol[curs] = symbol as u32;
+            self.up[curs] = NIL;
+            self.down[curs] = [NIL,NIL];
             return Ok(())
         }
         log::trace!("spawn_root: cannot overwrite {}",curs);
@@ -219,35 +233,33 @@ impl Tree {
     /// If node is root, cursor becomes None.  This may be called recursively.
     pub fn drop(&mut self) -> Result<(),Error> {
         let curs = self.chk_cursor()?;
-        let maybe_parent = self.pool[curs].up;
-        let maybe_symbol = self.pool[curs].symbol;
+        let maybe_parent = unlink(self.up[curs]);
+        let maybe_symbol = unlink(self.symbol[curs]);
         // recursively delete everything below
-        if let Ok(_) = self.down(Side::Left) {
+        if self.down(Side::Left).is_ok() {
             self.drop()?;
             self.set_cursor(curs)?;
         }
-        if let Ok(_) = self.down(Side::Right) {
+        if self.down(Side::Right).is_ok() {
             self.drop()?;
             self.set_cursor(curs)?;
         }
         // cut all links
         if let Some(parent) = maybe_parent {
             let (_,side) = self.get_parent_and_side()?;
-            self.pool[parent].down[side as usize] = None;
+            self.down[parent][side as usize] = NIL;
             self.curs = Some(parent);
         }
         if let Some(symbol) = maybe_symbol {
-            self.roots[symbol] = None;
+            self.roots[symbol] = NIL;
             self.curs = None;
         }
-        self.pool[curs].symbol = None;
-        self.pool[curs].up = None;
-        self.pool[curs].down = [None,None];
+        self.free(curs);
         Ok(())
     }
     /// Drop everything below the cursor on one side, OK if no branch to drop.
     pub fn drop_branch(&mut self,side: Side) -> Result<(),Error> {
-        if let Ok(_) = self.down(side) {
+        if self.down(side).is_ok() {
             self.drop()?;
         }
         Ok(())
@@ -256,17 +268,17 @@ impl Tree {
     /// another operation (tree is left broken).
     pub fn cut_upward(&mut self) -> Result<(),Error> {
         let (parent,side) = self.get_parent_and_side()?;
-        self.pool[parent].down[side as usize] = None;
-        self.pool[self.curs.unwrap()].up = None;
+        self.down[parent][side as usize] = NIL;
+        self.up[self.curs.unwrap()] = NIL;
         Ok(())
     }
     /// Cut the links between this node and one below.  Normally part of
     /// another operation (tree could be left broken).
     pub fn cut_downward(&mut self,side: Side) -> Result<(),Error> {
         let curs: usize = self.chk_cursor()?;
-        if let Some(son) = self.pool[curs].down[side as usize] {
-            self.pool[curs].down[side as usize] = None;
-            self.pool[son].up = None;
+        if let Some(son) = unlink(self.down[curs][side as usize]) {
+            self.down[curs][side as usize] = NIL;
+            self.up[son] = NIL;
         }
         Ok(())
     }
@@ -278,40 +290,40 @@ impl Tree {
     /// This may free up slots in the node pool if `force==true`.
     pub fn move_node(&mut self,new_parent: usize,side: Side,force: bool) -> Result<(),Error> {
         let curs: usize = self.chk_cursor()?;
-        match (self.pool[new_parent].down[side as usize],force) {
+        match (unlink(self.down[new_parent][side as usize]),force) {
             (None,_) => {
-                if self.pool[curs].up.is_some() {
+                if self.up[curs] != NIL {
                     self.cut_upward()?; // do first
                 }
             },
             (Some(_),true) => {
-                if self.pool[curs].up.is_some() {
+                if self.up[curs] != NIL {
                     self.cut_upward()?; // do first
                 }
                 self.set_cursor(new_parent)?;
                 self.drop_branch(side)?;
                 self.set_cursor(curs)?;
             },
-            _ => {
-                log::trace!("move: cannot overwrite {}",self.pool[new_parent].down[side as usize].unwrap());
+            (Some(old),false) => {
+                log::trace!("move: cannot overwrite {}",old);
                 return Err(Error::NodeExists);
             }
         }
-        self.pool[new_parent].down[side as usize] = Some(curs);
-        self.pool[curs].up = Some(new_parent);
-        return Ok(());
+        self.down[new_parent][side as usize] = curs as u32;
+        self.up[curs] = new_parent as u32;
+        Ok(())
     }
     /// Same as `move_node` except target node is a root
     pub fn move_node_to_root(&mut self,symbol: usize,force: bool) -> Result<(),Error> {
         let curs: usize = self.chk_cursor()?;
-        match (self.roots[symbol],force) {
+        match (unlink(self.roots[symbol]),force) {
             (None,_) => {
-                if self.pool[curs].up.is_some() {
+                if self.up[curs] != NIL {
                     self.cut_upward()?; // do first
                 }
             },
             (Some(old_root),true) => {
-                if self.pool[curs].up.is_some() {
+                if self.up[curs] != NIL {
                     self.cut_upward()?; // do first
                 }
                 self.set_cursor(old_root)?;
@@ -323,10 +335,10 @@ impl Tree {
                 return Err(Error::NodeExists);
             }
         }
-        self.roots[symbol] = Some(curs);
-        self.pool[curs].up = None;
-        self.pool[curs].symbol = Some(symbol);
-        return Ok(());
+        self.roots[symbol] = curs as u32;
+        self.up[curs] = NIL;
+        self.symbol[curs] = symbol as u32;
+        Ok(())
     }
     /// Change the value of a node.  This frees one slot in the node pool and uses another.
     /// The cursor stays on the node, but its value has changed.
@@ -350,28 +362,23 @@ impl Tree {
             }
         }
         // update links pointing into old_val
-        if let Some(symbol) = self.pool[old_val].symbol {
-            self.roots[symbol] = Some(new_val);
+        if let Some(symbol) = unlink(self.symbol[old_val]) {
+            self.roots[symbol] = new_val as u32;
         }
-        if let Some(parent) = self.pool[old_val].up {
+        if let Some(parent) = unlink(self.up[old_val]) {
             let (_,side) = self.get_parent_and_side()?;
-            self.pool[parent].down[side as usize] = Some(new_val);
-        }
-        if let Some(child) = self.pool[old_val].down[0] {
-            self.pool[child].up = Some(new_val);
+            self.down[parent][side as usize] = new_val as u32;
         }
-        if let Some(child) = self.pool[old_val].down[1] {
-            self.pool[child].up = Some(new_val);
+        for child in self.down[old_val] {
+            if let Some(child) = unlink(child) {
+                self.up[child] = new_val as u32;
+            }
         }
-        // update links pointing out of old_val and new_val 
-        self.pool[new_val] = self.pool[old_val].clone();
-        self.pool[new_val].val = new_val;
-        self.pool[old_val] = Node {
-            val: old_val,
-            symbol: None,
-            up: None,
-            down: [None,None]
-        };
+        // update links pointing out of old_val and new_val
+        self.symbol[new_val] = self.symbol[old_val];
+        self.up[new_val] = self.up[old_val];
+        self.down[new_val] = self.down[old_val];
+        self.free(old_val);
         self.curs = Some(new_val);
         Ok(())
     }
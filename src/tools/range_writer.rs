@@ -0,0 +1,60 @@
+//! Writer that keeps only a window of what is written to it.
+//! This lets a decoder run from the start of a stream without materializing
+//! everything that precedes the range the caller actually wants.
+
+use std::io::{Write,Seek,SeekFrom,ErrorKind};
+
+pub struct RangeWriter {
+    pos: u64,
+    start: u64,
+    end: u64,
+    buf: Vec<u8>
+}
+
+impl RangeWriter {
+    /// Keep bytes written at positions `start..start+len`
+    pub fn new(start: u64,len: u64) -> Self {
+        Self {
+            pos: 0,
+            start,
+            end: start.saturating_add(len),
+            buf: Vec::new()
+        }
+    }
+    /// The position just past the window, decoding can stop here
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Write for RangeWriter {
+    fn write(&mut self,data: &[u8]) -> Result<usize,std::io::Error> {
+        let beg = self.pos;
+        let end = self.pos + data.len() as u64;
+        if end > self.start && beg < self.end {
+            let i0 = self.start.saturating_sub(beg) as usize;
+            let i1 = (u64::min(end,self.end) - beg) as usize;
+            self.buf.extend_from_slice(&data[i0..i1]);
+        }
+        self.pos = end;
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> Result<(),std::io::Error> {
+        Ok(())
+    }
+}
+
+impl Seek for RangeWriter {
+    /// Only seeking to the current position is supported, since
+    /// the decoders only do this to initialize or report the position.
+    fn seek(&mut self,pos: SeekFrom) -> Result<u64,std::io::Error> {
+        match pos {
+            SeekFrom::Start(p) if p == self.pos => Ok(self.pos),
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(std::io::Error::new(ErrorKind::Unsupported,"range writer cannot seek"))
+        }
+    }
+}
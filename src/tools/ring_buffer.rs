@@ -1,20 +1,40 @@
 //! Ring buffer for LZ type compression windows
 use num_traits::PrimInt;
 
+#[derive(Clone,PartialEq,Debug)]
 pub struct RingBuffer<T: PrimInt> {
+    /// `n` values followed by a copy of the first `mirror` values
     buf: Vec<T>,
     pos: usize,
-    n: usize
+    n: usize,
+    mirror: usize
 }
 
 impl <T: PrimInt> RingBuffer<T> {
-    pub fn create(fill: T,n: usize) -> Self {
+    /// The first `mirror` values are repeated past the end, so that any run of up to `mirror`
+    /// values can be borrowed as a slice, see `slice_abs`.  Panics if `mirror` exceeds `n`.
+    pub fn create(fill: T,n: usize,mirror: usize) -> Self {
+        if mirror > n {
+            panic!("mirror is larger than the buffer");
+        }
         Self {
-            buf: vec![fill;n],
+            buf: vec![fill;n+mirror],
             pos: 0,
-            n
+            n,
+            mirror
+        }
+    }
+    fn put(&mut self,idx: usize,val: T) {
+        self.buf[idx] = val;
+        if idx < self.mirror {
+            self.buf[self.n + idx] = val;
         }
     }
+    /// fill with `fill` and put the cursor at 0, without allocating
+    pub fn reset(&mut self,fill: T) {
+        self.buf.fill(fill);
+        self.pos = 0;
+    }
     /// get absolute position of cursor + offset
     pub fn get_pos(&self,offset: i64) -> usize {
         (self.pos as i64 + offset).rem_euclid(self.n as i64) as usize
@@ -23,13 +43,17 @@ impl <T: PrimInt> RingBuffer<T> {
     pub fn set_pos(&mut self,pos: usize) {
         self.pos = pos % self.n;
     }
-    /// get value at absolute position, cursor does not move
-    pub fn get_abs(&self,abs: usize) -> T {
-        self.buf[abs % self.n]
-    }
     /// set value at absolute position, cursor does not move
     pub fn set_abs(&mut self,abs: usize,val: T) {
-        self.buf[abs % self.n] = val;
+        self.put(abs % self.n,val);
+    }
+    /// borrow `len` values starting at an absolute position, panics if `len` exceeds the mirror
+    pub fn slice_abs(&self,abs: usize,len: usize) -> &[T] {
+        if len > self.mirror {
+            panic!("slice is longer than the mirror");
+        }
+        let start = abs % self.n;
+        &self.buf[start..start+len]
     }
     /// get value at cursor + offset
     pub fn get(&self,offset: i64) -> T {
@@ -37,7 +61,7 @@ impl <T: PrimInt> RingBuffer<T> {
     }
     /// set value at cursor + offset
     pub fn set(&mut self,offset: i64,val: T) {
-        self.buf[(self.pos as i64 + offset).rem_euclid(self.n as i64) as usize] = val;
+        self.put((self.pos as i64 + offset).rem_euclid(self.n as i64) as usize,val);
     }
     /// advance cursor by 1
     pub fn advance(&mut self) {
@@ -56,7 +80,7 @@ impl <T: PrimInt> RingBuffer<T> {
 
 #[test]
 fn offset() {
-    let mut ring: RingBuffer<u8> = RingBuffer::create(0,4);
+    let mut ring: RingBuffer<u8> = RingBuffer::create(0,4,0);
     ring.set_pos(5);
     assert_eq!(ring.get_pos(0),1);
     assert_eq!(ring.get_pos(4),1);
@@ -68,10 +92,26 @@ fn offset() {
 fn distance() {
     // four positions 0 1 2 3
     // set position     ^       (wraps once)
-    let mut ring: RingBuffer<u8> = RingBuffer::create(0,4);
+    let mut ring: RingBuffer<u8> = RingBuffer::create(0,4,0);
     ring.set_pos(5);
     assert_eq!(ring.get_pos(0),1);
     assert_eq!(ring.distance_behind(0),1);
     assert_eq!(ring.distance_behind(1),0);
     assert_eq!(ring.distance_behind(3),2);
 }
+
+#[test]
+fn mirror() {
+    let mut ring: RingBuffer<u8> = RingBuffer::create(0,4,2);
+    for i in 0..4 {
+        ring.set_abs(i,i as u8 + 1);
+    }
+    assert_eq!(ring.slice_abs(3,2),[4,1]);
+    ring.set_pos(4);
+    ring.set(1,9);
+    assert_eq!(ring.slice_abs(2,2),[3,4]);
+    assert_eq!(ring.slice_abs(7,2),[4,1]);
+    assert_eq!(ring.slice_abs(5,2),[9,3]);
+    ring.reset(7);
+    assert_eq!(ring.slice_abs(3,2),[7,7]);
+}
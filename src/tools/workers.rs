@@ -0,0 +1,29 @@
+//! Scoped worker threads for batches of independent jobs.
+//! The binary includes this file as its own module, so the library and the command line share it
+//! without making it part of the public API.
+
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+/// Run `f` on each job using up to `workers` threads, returns the results in the same order as `jobs`
+pub fn on_workers<J,T,F>(jobs: &[J],workers: usize,f: F) -> Vec<T>
+where J: Sync, T: Send, F: Fn(&J) -> T + Sync {
+    // workers take the next job until there are none left
+    let next = AtomicUsize::new(0);
+    let workers = workers.min(jobs.len()).max(1);
+    let mut done: Vec<(usize,T)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+            let mut done = Vec::new();
+            loop {
+                let idx = next.fetch_add(1,Ordering::Relaxed);
+                let Some(job) = jobs.get(idx) else {
+                    break;
+                };
+                done.push((idx,f(job)));
+            }
+            done
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().expect("worker panicked")).collect()
+    });
+    done.sort_by_key(|(idx,_)| *idx);
+    done.into_iter().map(|(_,res)| res).collect()
+}
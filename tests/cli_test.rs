@@ -191,4 +191,1055 @@ fn teledisk_compression() -> STDRESULT {
 fn teledisk_expansion() -> STDRESULT {
     expand_test("td105","norm.td0","adv.td0","td0")?;
     expand_test("td215","norm.td0","adv.td0","td0")
-}
\ No newline at end of file
+}
+#[test]
+fn teledisk_trim_padding() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let out = temp_dir.path().join("td215.norm.td0");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--trim-padding")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .success();
+    // the header differs because the test images were made separately
+    let normal = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    assert_eq!(std::fs::read(&out)?[12..],normal[12..]);
+    // the test images were made separately, so they only match with the check signature set
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--trim-padding").arg("--check-signature").arg("7").arg("-f")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&out)?,normal);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--trim-padding")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(2)
+        .stderr(predicates::str::ends_with("error[usage]: --trim-padding does not apply to lzw\n"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw").arg("--check-signature").arg("7")
+        .arg("-i").arg(Path::new("tests").join("td215.norm.td0"))
+        .arg("-o").arg(temp_dir.path().join("td215.lzw"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::ends_with("error[usage]: --check-signature does not apply to lzw\n"));
+    Ok(())
+}
+#[test]
+fn teledisk_pad() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let out = temp_dir.path().join("td215.norm.td0");
+    let normal = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--trim-padding").arg("--pad-to").arg("1024")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .success();
+    let padded = std::fs::read(&out)?;
+    assert_eq!(padded.len(),53248);
+    assert_eq!(padded[12..normal.len()],normal[12..]);
+    assert!(padded[normal.len()..].iter().all(|b| *b==0));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--trim-padding").arg("--pad-min").arg("4").arg("-f")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&out)?.len(),normal.len() + 4);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff").arg("--pad-min").arg("4")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(temp_dir.path().join("td215.out"))
+        .assert()
+        .code(2)
+        .stderr(predicates::str::ends_with("error[usage]: --pad-min does not apply to lzss_huff\n"));
+    Ok(())
+}
+
+#[test]
+fn stdio_pipeline() -> STDRESULT {
+    let expanded = std::fs::read(Path::new("tests").join("hamlet_act_1.txt"))?;
+    for method in ["lzw","lzss_huff","lzhuf-port"] {
+        let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+        let compressed = cmd.arg("compress")
+            .arg("-m").arg(method)
+            .arg("-i").arg("-")
+            .arg("-o").arg("-")
+            .write_stdin(expanded.clone())
+            .output()?;
+        assert!(compressed.status.success());
+        let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+        cmd.arg("expand")
+            .arg("-m").arg(method)
+            .arg("-i").arg("-")
+            .arg("-o").arg("-")
+            .write_stdin(compressed.stdout)
+            .assert()
+            .success()
+            .stdout(expanded.clone());
+    }
+    // lzss_huff compression streams from a file to stdout, matching the output written to a file
+    let temp_dir = tempfile::tempdir()?;
+    let out_path = temp_dir.path().join("hamlet.lzh");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(&out_path)
+        .assert()
+        .success();
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg("-")
+        .assert()
+        .success()
+        .stdout(std::fs::read(&out_path)?);
+    // teledisk expansion streams from a file to stdout
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    let normal = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    let output = cmd.arg("expand")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg("-")
+        .output()?;
+    assert!(output.status.success());
+    // the header differs in the signature and CRC, and there can be a few bytes of padding at the end
+    assert_eq!(output.stdout[12..normal.len()],normal[12..]);
+    Ok(())
+}
+
+#[test]
+fn output_dir_with_glob() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let pattern = Path::new("tests").join("td*.adv.td0");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(&pattern)
+        .arg("-d").arg(temp_dir.path())
+        .assert()
+        .success();
+    for base_name in ["td105","td215"] {
+        let v1 = std::fs::read(Path::new("tests").join([base_name,".norm.td0"].concat()))?;
+        let v2 = std::fs::read(temp_dir.path().join([base_name,".norm.td0"].concat()))?;
+        compare_slices("td0",&v1,&v2);
+    }
+    // names are derived from the inputs, missing inputs are reported but do not stop the others
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt")).arg(Path::new("tests").join("missing*.txt"))
+        .arg("-d").arg(temp_dir.path())
+        .assert()
+        .failure();
+    let compressed = temp_dir.path().join("hamlet_act_1.txt.lzh");
+    let expanded_dir = temp_dir.path().join("expanded");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(&compressed)
+        .arg("-d").arg(&expanded_dir)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(Path::new("tests").join("hamlet_act_1.txt"))?,std::fs::read(expanded_dir.join("hamlet_act_1.txt"))?);
+    Ok(())
+}
+
+#[test]
+fn default_output_name() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = temp_dir.path().join("hamlet.txt");
+    std::fs::copy(Path::new("tests").join("hamlet_act_1.txt"),&original)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(&original)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("hamlet.txt.lzh"));
+    std::fs::remove_file(&original)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(temp_dir.path().join("hamlet.txt.lzh"))
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(Path::new("tests").join("hamlet_act_1.txt"))?,std::fs::read(&original)?);
+    // TD0 images keep the extension, but the tag is switched
+    let image = temp_dir.path().join("td105.adv.TD0");
+    std::fs::copy(Path::new("tests").join("td105.adv.td0"),&image)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(&image)
+        .assert()
+        .success();
+    let v1 = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    let v2 = std::fs::read(temp_dir.path().join("td105.norm.TD0"))?;
+    compare_slices("td0",&v1,&v2);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("td0")
+        .arg("-i").arg(temp_dir.path().join("td105.norm.TD0"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("use --force"));
+    Ok(())
+}
+
+#[test]
+fn convert_methods() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = temp_dir.path().join("td215.adv.td0");
+    std::fs::copy(Path::new("tests").join("td215.adv.td0"),&image)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("convert").arg("--from").arg("td0").arg("--to").arg("lzw")
+        .arg("-i").arg(&image)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("td215.norm.td0.lzw"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("convert").arg("--from").arg("lzw").arg("--to").arg("lzss_huff")
+        .arg("-i").arg(temp_dir.path().join("td215.norm.td0.lzw"))
+        .arg("-o").arg(temp_dir.path().join("td215.lzh"))
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(temp_dir.path().join("td215.lzh"))
+        .arg("-o").arg(temp_dir.path().join("td215.norm.td0"))
+        .assert()
+        .success();
+    let v1 = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    let v2 = std::fs::read(temp_dir.path().join("td215.norm.td0"))?;
+    compare_slices("td0",&v1,&v2);
+    // a failed expansion leaves no output behind
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("convert").arg("--from").arg("td0").arg("--to").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(temp_dir.path().join("hamlet.lzh"))
+        .assert()
+        .failure();
+    assert!(!temp_dir.path().join("hamlet.lzh").exists());
+    Ok(())
+}
+
+#[test]
+fn analyze_input() -> STDRESULT {
+    let original = Path::new("tests").join("hamlet_act_1.txt");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("analyze")
+        .arg("-i").arg(&original)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("size 38418, 62 distinct byte values"))
+        .stdout(predicates::str::contains("match lengths:"))
+        .stdout(predicates::str::is_match("lzss_huff +18528 ")?)
+        .stdout(predicates::str::is_match("td0 +n/a")?);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("analyze")
+        .arg("-i").arg(&original)
+        .arg("--json")
+        .assert()
+        .success()
+        .stdout(predicates::str::starts_with("{\"size\":38418,\"distinct\":62,"))
+        .stdout(predicates::str::contains("{\"method\":\"lzss_huff\",\"size\":18528}"));
+    Ok(())
+}
+
+#[test]
+fn recursive_directory() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let tree = temp_dir.path().join("images");
+    std::fs::create_dir_all(tree.join("sub"))?;
+    std::fs::copy(Path::new("tests").join("td105.adv.td0"),tree.join("td105.adv.td0"))?;
+    std::fs::copy(Path::new("tests").join("td215.adv.td0"),tree.join("sub").join("td215.adv.TD0"))?;
+    std::fs::copy(Path::new("tests").join("hamlet_act_1.txt"),tree.join("sub").join("hamlet_act_1.txt"))?;
+    let out_dir = temp_dir.path().join("normal");
+    // without --recursive the directory is refused
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(&tree)
+        .arg("-d").arg(&out_dir)
+        .assert()
+        .failure();
+    // only the td0 files are taken, and the tree is mirrored
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(&tree)
+        .arg("-d").arg(&out_dir)
+        .arg("--recursive")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("2 succeeded, 0 failed"));
+    let v1 = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    let v2 = std::fs::read(out_dir.join("td105.norm.td0"))?;
+    compare_slices("td0",&v1,&v2);
+    let v1 = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    let v2 = std::fs::read(out_dir.join("sub").join("td215.norm.TD0"))?;
+    compare_slices("td0",&v1,&v2);
+    assert!(!out_dir.join("sub").join("hamlet_act_1.txt").exists());
+    Ok(())
+}
+
+#[test]
+fn test_subcommand() -> STDRESULT {
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td*.adv.td0"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("td105.adv.td0: OK, 30196 expands to 53218, header, track, and sector CRCs ok"))
+        .stdout(predicates::str::contains("2 passed, 0 failed"));
+    // truncated data and the wrong format are both failures
+    let temp_dir = tempfile::tempdir()?;
+    let truncated = temp_dir.path().join("truncated.lzh");
+    let compressed = std::fs::read(Path::new("tests").join("hamlet_act_1.lzh"))?;
+    std::fs::write(&truncated,&compressed[0..3000])?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.lzh")).arg(&truncated)
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("hamlet_act_1.lzh: OK"))
+        .stdout(predicates::str::contains("truncated.lzh: TRUNCATED"))
+        .stdout(predicates::str::contains("1 passed, 1 failed"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td105.norm.td0"))
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("FAILED, file format mismatch"));
+    // a sector CRC that does not match is found inside the compressed data
+    let mut normal = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    normal[0x15] ^= 0xff;
+    let bad_sector = temp_dir.path().join("bad_sector.norm.td0");
+    let bad_sector_adv = temp_dir.path().join("bad_sector.adv.td0");
+    std::fs::write(&bad_sector,&normal)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("td0")
+        .arg("-i").arg(&bad_sector)
+        .arg("-o").arg(&bad_sector_adv)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(&bad_sector_adv)
+        .assert()
+        .failure()
+        .code(4)
+        .stdout(predicates::str::contains("FAILED, sector 0/0/1 CRC is 38, should be c7\n"));
+    Ok(())
+}
+
+#[test]
+fn codec_overrides() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = Path::new("tests").join("hamlet_act_1.txt");
+    let compressed = temp_dir.path().join("hamlet.lzh");
+    let expanded = temp_dir.path().join("hamlet.txt");
+    let lzss_flags = ["--window-size","256","--lookahead","18","--max-freq","4096","--out-offset","5"];
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzss_huff").args(lzss_flags)
+        .arg("-i").arg(&original)
+        .arg("-o").arg(&compressed)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&compressed)?[0..5],[0;5]);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff").args(&lzss_flags[0..6]).arg("--in-offset").arg("5")
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg(&expanded)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&original)?,std::fs::read(&expanded)?);
+    // lzw variant through a pipeline
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    let lzw = cmd.arg("compress").arg("-m").arg("lzw").arg("--code-width").arg("14").arg("--bit-order").arg("msb0")
+        .arg("-i").arg(&original)
+        .arg("-o").arg("-")
+        .assert()
+        .success()
+        .get_output().stdout.clone();
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--code-width").arg("14").arg("--bit-order").arg("msb0")
+        .arg("-i").arg("-")
+        .arg("-o").arg("-")
+        .write_stdin(lzw)
+        .assert()
+        .success()
+        .stdout(std::fs::read(&original)?);
+    // flags must fit the method
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--lookahead").arg("18")
+        .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+        .arg("-o").arg(&expanded)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--lookahead does not apply to td0"));
+    Ok(())
+}
+
+#[test]
+fn options_profile() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = Path::new("tests").join("hamlet_act_1.txt");
+    let profile = temp_dir.path().join("crunch.toml");
+    let compressed = temp_dir.path().join("hamlet.Z");
+    std::fs::write(&profile,"# 12 bit compress\npreset = \"unix_compress\"\nmax_code_width = 12\n")?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw").arg("--options").arg(&profile)
+        .arg("-i").arg(&original)
+        .arg("-o").arg(&compressed)
+        .assert()
+        .success();
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--options").arg(&profile)
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg("-")
+        .assert()
+        .success()
+        .stdout(std::fs::read(&original)?);
+    // without a preset every field is needed, and unknown fields are rejected
+    std::fs::write(&profile,"max_code_width = 12\n")?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--options").arg(&profile)
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg("-")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("missing field"));
+    std::fs::write(&profile,"preset = \"standard\"\nwindow = 256\n")?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff").arg("--options").arg(&profile)
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg("-")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("unknown field `window`"));
+    Ok(())
+}
+
+#[test]
+fn inconsistent_profile() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = Path::new("tests").join("hamlet_act_1.txt");
+    let profile = temp_dir.path().join("bad.toml");
+    let cases = [
+        ("lzss_huff","preset = \"standard\"\nhuffman_rescale_divisor = 1\n","rescale divisor must be at least 2"),
+        ("lzss_huff","preset = \"standard\"\nhuffman_initial_freq = [0]\n","initial frequencies must be nonzero"),
+        ("lzw","preset = \"standard\"\nmin_code_width = 14\n","min code width exceeds max code width")
+    ];
+    for (method,toml,msg) in cases {
+        std::fs::write(&profile,toml)?;
+        for action in ["compress","expand"] {
+            let mut cmd = Command::cargo_bin("retrocompressor")?;
+            cmd.arg(action).arg("-m").arg(method).arg("--options").arg(&profile)
+                .arg("-i").arg(&original)
+                .arg("-o").arg(temp_dir.path().join("out"))
+                .assert()
+                .code(2)
+                .stderr(predicates::str::contains(msg));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn compression_level() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = Path::new("tests").join("hamlet_act_1.txt");
+    let mut sizes = Vec::new();
+    for level in ["1","9"] {
+        let compressed = temp_dir.path().join(format!("hamlet{}.lzh",level));
+        let mut cmd = Command::cargo_bin("retrocompressor")?;
+        cmd.arg("compress").arg("-m").arg("lzss_huff").arg("--level").arg(level)
+            .arg("-i").arg(&original)
+            .arg("-o").arg(&compressed)
+            .assert()
+            .success();
+        let mut cmd = Command::cargo_bin("retrocompressor")?;
+        cmd.arg("expand").arg("-m").arg("lzss_huff")
+            .arg("-i").arg(&compressed)
+            .arg("-o").arg("-")
+            .assert()
+            .success()
+            .stdout(std::fs::read(&original)?);
+        sizes.push(std::fs::metadata(&compressed)?.len());
+    }
+    assert!(sizes[1] < sizes[0]);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw").arg("--level").arg("9")
+        .arg("-i").arg(&original)
+        .arg("-o").arg(temp_dir.path().join("hamlet.lzw"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--level does not apply to lzw"));
+    Ok(())
+}
+
+#[test]
+fn max_size_guard() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let compressed = Path::new("tests").join("td105.adv.td0");
+    let expanded = temp_dir.path().join("td105.norm.td0");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--max-size").arg("16k")
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg(&expanded)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("expanded size would exceed 16384 bytes"));
+    assert!(!expanded.exists());
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--max-size").arg("16k")
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg("-")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("expanded size would exceed 16384 bytes"));
+    // the exact size is allowed
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--max-size").arg("53218")
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg(&expanded)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--max-size").arg("lots")
+        .arg("-i").arg(&compressed)
+        .arg("-o").arg(&expanded)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("lots is not a size"));
+    Ok(())
+}
+
+#[test]
+fn chained_methods() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let txt = temp_dir.path().join("hamlet_act_1.txt");
+    std::fs::copy(Path::new("tests").join("hamlet_act_1.txt"),&txt)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw+lzss_huff+sub_block")
+        .arg("-i").arg(&txt)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("hamlet_act_1.txt.lzw.lzh"));
+    let packed = temp_dir.path().join("hamlet_act_1.txt.lzw.lzh");
+    let framed = std::fs::read(&packed)?;
+    assert_eq!(*framed.last().unwrap(),0);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test").arg("-m").arg("lzw+lzss_huff+sub_block")
+        .arg("-i").arg(&packed)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("every stage decoded"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw+lzss_huff+sub_block")
+        .arg("-i").arg(&packed)
+        .arg("-o").arg(temp_dir.path().join("hamlet.txt"))
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&txt)?,std::fs::read(temp_dir.path().join("hamlet.txt"))?);
+    // expanding in the wrong order fails
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("sub_block+lzss_huff+lzw")
+        .arg("-i").arg(&packed)
+        .arg("-o").arg(temp_dir.path().join("wrong.txt"))
+        .assert()
+        .failure();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw+squeeze")
+        .arg("-i").arg(&txt)
+        .arg("-o").arg(temp_dir.path().join("squeezed"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("squeeze is not a method"));
+    Ok(())
+}
+
+#[test]
+fn concatenated_members() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let packed = temp_dir.path().join("plays.lzh");
+    let manifest = temp_dir.path().join("plays.toml");
+    let parts = temp_dir.path().join("parts");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt")).arg(Path::new("tests").join("tempest_act_5.txt"))
+        .arg("-o").arg(&packed)
+        .arg("--members").arg(&manifest)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("compressed 2 files"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(&packed)
+        .arg("-o").arg(temp_dir.path().join("plays.txt"))
+        .assert()
+        .success();
+    let mut both = std::fs::read(Path::new("tests").join("hamlet_act_1.txt"))?;
+    both.append(&mut std::fs::read(Path::new("tests").join("tempest_act_5.txt"))?);
+    assert_eq!(both,std::fs::read(temp_dir.path().join("plays.txt"))?);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(&packed)
+        .arg("--members").arg(&manifest)
+        .arg("-d").arg(&parts)
+        .assert()
+        .success();
+    for name in ["hamlet_act_1.txt","tempest_act_5.txt"] {
+        assert_eq!(std::fs::read(Path::new("tests").join(name))?,std::fs::read(parts.join(name))?);
+    }
+    // members that do not fit the data are rejected
+    std::fs::write(&manifest,"[[member]]\nname = \"all\"\noffset = 0\nsize = 1000\n")?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(&packed)
+        .arg("--members").arg(&manifest)
+        .arg("-d").arg(&parts)
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains("members add up to 1000"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt")).arg(Path::new("tests").join("tempest_act_5.txt"))
+        .assert()
+        .code(2);
+    Ok(())
+}
+
+#[test]
+fn exit_codes() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let out = temp_dir.path().join("out");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--lookahead").arg("18")
+        .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(2)
+        .stderr(predicates::str::ends_with("error[usage]: --lookahead does not apply to lzw\n"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(3)
+        .stderr(predicates::str::is_match("^error\\[format\\]: .*hamlet_act_1.txt: file format mismatch\n$")?);
+    let mut bad_crc = std::fs::read(Path::new("tests").join("td105.adv.td0"))?;
+    bad_crc[5] ^= 1;
+    std::fs::write(temp_dir.path().join("bad_crc.td0"),&bad_crc)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(temp_dir.path().join("bad_crc.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(4)
+        .stderr(predicates::str::contains("error[checksum]: "));
+    let compressed = std::fs::read(Path::new("tests").join("hamlet_act_1.lzh"))?;
+    std::fs::write(temp_dir.path().join("short.lzh"),&compressed[0..1000])?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test").arg("-m").arg("lzss_huff")
+        .arg("-i").arg(temp_dir.path().join("short.lzh"))
+        .assert()
+        .code(5)
+        .stderr(predicates::str::ends_with("error[truncated]: 1 of 1 failed\n"));
+    let compressed = std::fs::read(Path::new("tests").join("td105.adv.td0"))?;
+    std::fs::write(temp_dir.path().join("short.td0"),&compressed[0..compressed.len()-100])?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(temp_dir.path().join("short.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(5)
+        .stderr(predicates::str::contains("error[truncated]: "));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(temp_dir.path().join("missing.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(6)
+        .stderr(predicates::str::contains("error[io]: "));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--max-size").arg("1k")
+        .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+        .arg("-o").arg(&out)
+        .assert()
+        .code(7)
+        .stderr(predicates::str::contains("error[too-large]: "));
+    Ok(())
+}
+
+#[test]
+fn parallel_jobs() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let out_dir = temp_dir.path().join("normal");
+    let bogus = temp_dir.path().join("bogus.td0");
+    std::fs::write(&bogus,"not a disk image")?;
+    // a bad file is reported without stopping the others
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td*.adv.td0")).arg(&bogus)
+        .arg("-d").arg(&out_dir)
+        .arg("--jobs").arg("3")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("2 succeeded, 1 failed"))
+        .stderr(predicates::str::is_match("  failed: .*bogus.td0")?);
+    let v1 = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    let v2 = std::fs::read(out_dir.join("td105.norm.td0"))?;
+    compare_slices("td0",&v1,&v2);
+    let v1 = std::fs::read(Path::new("tests").join("td215.norm.td0"))?;
+    let v2 = std::fs::read(out_dir.join("td215.norm.td0"))?;
+    compare_slices("td0",&v1,&v2);
+    Ok(())
+}
+
+#[test]
+fn overwrite_control() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let original = Path::new("tests").join("td105.adv.td0");
+    let out_path = temp_dir.path().join("td105.td0");
+    std::fs::write(&out_path,"keep me")?;
+    // cannot ask since stdin is not a terminal
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(&original)
+        .arg("-o").arg(&out_path)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("use --force"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(&original)
+        .arg("-o").arg(&out_path)
+        .arg("--no-clobber")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&out_path)?,b"keep me");
+    // a failed conversion leaves the destination alone, and no temporary file behind
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(&out_path)
+        .arg("--force")
+        .assert()
+        .failure();
+    assert_eq!(std::fs::read(&out_path)?,b"keep me");
+    assert_eq!(std::fs::read_dir(temp_dir.path())?.count(),1);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(&original)
+        .arg("-o").arg(&out_path)
+        .arg("--force")
+        .assert()
+        .success();
+    let v1 = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    compare_slices("td0",&v1,&std::fs::read(&out_path)?);
+    // in a batch, skipped files are not failures
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td*.adv.td0"))
+        .arg("-d").arg(temp_dir.path())
+        .arg("-n")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td*.adv.td0"))
+        .arg("-d").arg(temp_dir.path())
+        .arg("-n")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("0 succeeded, 0 failed, 2 skipped"));
+    Ok(())
+}
+
+#[test]
+fn inspect_tokens() -> STDRESULT {
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("inspect")
+        .arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+        .arg("--json")
+        .assert()
+        .success()
+        .stdout(predicates::str::starts_with("{\"bit\":0,\"pos\":0,\"kind\":\"header\",\"value\":12288}\n"))
+        .stderr(predicates::str::contains("Teledisk 1.1 image"))
+        .stderr(predicates::str::contains("expands to 53206"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("inspect")
+        .arg("-m").arg("lzss_huff")
+        .arg("-i").arg(Path::new("tests").join("tempest_act_5.lzh"))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("literal 41 'A'"))
+        .stdout(predicates::str::contains("match offset"));
+    // the tokens before an error are still shown
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("inspect")
+        .arg("-m").arg("lzw")
+        .arg("-i").arg(Path::new("tests").join("tempest_act_5.lzh"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("stopped after"));
+    Ok(())
+}
+
+#[test]
+fn td0_comment() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = temp_dir.path().join("td105.td0");
+    std::fs::copy(Path::new("tests").join("td105.adv.td0"),&image)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("comment")
+        .arg("-i").arg(&image)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("no comment"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("comment")
+        .arg("-i").arg(&image)
+        .arg("--set").arg("imaged by hand\nsecond line")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("comment")
+        .arg("-i").arg(&image)
+        .assert()
+        .success()
+        .stdout(predicates::str::ends_with("\nimaged by hand\nsecond line\n"));
+    // the image still expands
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("test").arg("-m").arg("td0")
+        .arg("-i").arg(&image)
+        .assert()
+        .success();
+    let stripped = temp_dir.path().join("stripped.td0");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("comment")
+        .arg("-i").arg(&image)
+        .arg("-o").arg(&stripped)
+        .arg("--strip")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&stripped)?,std::fs::read(Path::new("tests").join("td105.adv.td0"))?);
+    Ok(())
+}
+
+#[test]
+fn td0_fix_crc() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = temp_dir.path().join("td105.td0");
+    let mut mangled = std::fs::read(Path::new("tests").join("td105.adv.td0"))?;
+    mangled[10] ^= 0xff;
+    std::fs::write(&image,&mangled)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("fix-crc")
+        .arg("-i").arg(&image)
+        .arg("--sectors")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("header CRC is"))
+        .stdout(predicates::str::contains("1 CRC mismatches"));
+    assert_eq!(std::fs::read(&image)?,mangled);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("fix-crc")
+        .arg("-i").arg(&image)
+        .assert()
+        .success();
+    assert_eq!(std::fs::read(&image)?,std::fs::read(Path::new("tests").join("td105.adv.td0"))?);
+    Ok(())
+}
+
+#[test]
+fn td0_split() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = std::fs::read(Path::new("tests").join("td215.adv.td0"))?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("split")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(temp_dir.path().join("set.td0"))
+        .arg("--size").arg("10k")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("volume 3 of 3"));
+    let mut joined = Vec::new();
+    for (sequence,name) in ["set.td0","set.td1","set.td2"].iter().enumerate() {
+        let volume = std::fs::read(temp_dir.path().join(name))?;
+        assert!(volume.len() <= 10240);
+        assert_eq!(volume[2],sequence as u8);
+        joined.extend_from_slice(&volume[12..]);
+    }
+    assert_eq!(joined,image[12..]);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("split")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(temp_dir.path().join("small.td0"))
+        .arg("--size").arg("1k")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("file too large"));
+    Ok(())
+}
+
+#[test]
+fn td0_salvage() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = temp_dir.path().join("damaged.td0");
+    let rescued = temp_dir.path().join("rescued.td0");
+    let mut mangled = std::fs::read(Path::new("tests").join("td105.norm.td0"))?;
+    mangled[26] ^= 0xff;
+    std::fs::write(&image,&mangled)?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("salvage")
+        .arg("-i").arg(&image)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("is unreadable"))
+        .stdout(predicates::str::contains("1 damaged regions"));
+    assert!(!rescued.exists());
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("salvage")
+        .arg("-i").arg(&image)
+        .arg("-o").arg(&rescued)
+        .arg("--fill").arg("229")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("fix-crc")
+        .arg("-i").arg(&rescued)
+        .arg("--sectors")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("0 CRC mismatches"));
+    Ok(())
+}
+
+#[test]
+fn td0_recover() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let image = temp_dir.path().join("cut.td0");
+    let whole = temp_dir.path().join("whole.td0");
+    let compressed = std::fs::read(Path::new("tests").join("td105.adv.td0"))?;
+    std::fs::write(&image,&compressed[0..compressed.len()/2])?;
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("recover")
+        .arg("-i").arg(&image)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("image is truncated"))
+        .stdout(predicates::str::contains("padded 0 tracks"));
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("recover")
+        .arg("-i").arg(&image)
+        .arg("-o").arg(&whole)
+        .arg("--cylinders").arg("80")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("td0").arg("recover")
+        .arg("-i").arg(&whole)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("image is complete"));
+    Ok(())
+}
+
+#[test]
+fn low_memory() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let out = temp_dir.path().join("hamlet.lzw");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw").arg("--low-memory")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(&out)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzss_huff").arg("--low-memory")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg("-")
+        .assert()
+        .success();
+    let mut cmd = assert_cmd::Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("lzw").arg("--low-memory")
+        .arg("-i").arg("-")
+        .arg("-o").arg(temp_dir.path().join("hamlet.txt"))
+        .write_stdin(std::fs::read(&out)?)
+        .assert()
+        .code(2)
+        .stderr("error[usage]: expanding stdin holds all of the data in memory, which --low-memory does not allow\n");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("compress").arg("-m").arg("lzw+sub_block").arg("--low-memory")
+        .arg("-i").arg(Path::new("tests").join("hamlet_act_1.txt"))
+        .arg("-o").arg(temp_dir.path().join("hamlet.blk"))
+        .assert()
+        .code(2);
+    assert!(!temp_dir.path().join("hamlet.blk").exists());
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--low-memory").arg("--trim-padding")
+        .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+        .arg("-o").arg(temp_dir.path().join("td105.td0"))
+        .assert()
+        .code(2)
+        .stderr("error[usage]: --trim-padding holds all of the data in memory, which --low-memory does not allow\n");
+    for pad in ["--pad-to","--pad-min"] {
+        let mut cmd = Command::cargo_bin("retrocompressor")?;
+        cmd.arg("expand").arg("-m").arg("td0").arg("--low-memory").arg(pad).arg("1024")
+            .arg("-i").arg(Path::new("tests").join("td105.adv.td0"))
+            .arg("-o").arg(temp_dir.path().join("td105.td0"))
+            .assert()
+            .code(2)
+            .stderr("error[usage]: padding the image holds all of the data in memory, which --low-memory does not allow\n");
+    }
+    Ok(())
+}
+
+#[test]
+fn force_bad_header_crc() -> STDRESULT {
+    let temp_dir = tempfile::tempdir()?;
+    let mut bad_crc = std::fs::read(Path::new("tests").join("td215.adv.td0"))?;
+    bad_crc[10] ^= 0xff;
+    std::fs::write(temp_dir.path().join("bad_crc.td0"),&bad_crc)?;
+    let good = temp_dir.path().join("good.norm.td0");
+    let forced = temp_dir.path().join("forced.norm.td0");
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(Path::new("tests").join("td215.adv.td0"))
+        .arg("-o").arg(&good)
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0")
+        .arg("-i").arg(temp_dir.path().join("bad_crc.td0"))
+        .arg("-o").arg(&forced)
+        .assert()
+        .code(4);
+    let mut cmd = Command::cargo_bin("retrocompressor")?;
+    cmd.arg("expand").arg("-m").arg("td0").arg("--force")
+        .arg("-i").arg(temp_dir.path().join("bad_crc.td0"))
+        .arg("-o").arg(&forced)
+        .assert()
+        .success();
+    // the damaged CRC is replaced by the correct one
+    assert_eq!(std::fs::read(&forced)?,std::fs::read(&good)?);
+    Ok(())
+}